@@ -0,0 +1,342 @@
+//! Derive macros for the `RespReadable` and `RespWritable` traits of the
+//! `resp` crate, which re-exports them with its `derive` feature.
+//!
+//! A struct is encoded as an array of its fields in declaration order, or
+//! with `#[resp(map)]` as a map from field names to values. Fields of type
+//! `Option<T>` are written and read as null when missing, and
+//! `#[resp(rename = "...")]` changes the key of a field in a map.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{
+    Data, DeriveInput, Error, Fields, GenericArgument, GenericParam, Generics, Ident, Index,
+    Lifetime, LifetimeParam, LitByteStr, LitStr, Member, PathArguments, Result, Type,
+    parse_macro_input, parse_quote, spanned::Spanned,
+};
+
+#[proc_macro_derive(RespWritable, attributes(resp))]
+pub fn derive_writable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    Struct::from_input(&input)
+        .map(|s| s.writable())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(RespReadable, attributes(resp))]
+pub fn derive_readable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    Struct::from_input(&input)
+        .and_then(|s| s.readable())
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+// ===========================================================
+// Struct
+// ===========================================================
+
+struct Struct<'i> {
+    ident: &'i Ident,
+    generics: &'i Generics,
+
+    /// Whether the struct is encoded as a map rather than an array
+    map: bool,
+    fields: Vec<Field<'i>>,
+}
+
+struct Field<'i> {
+    member: Member,
+    ty: &'i Type,
+
+    /// Key of the field in a map
+    key: String,
+}
+
+impl<'i> Struct<'i> {
+    fn from_input(input: &'i DeriveInput) -> Result<Struct<'i>> {
+        let Data::Struct(data) = &input.data else {
+            return Err(Error::new(
+                Span::call_site(),
+                "RESP derives only support structs",
+            ));
+        };
+
+        let mut map = false;
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("resp"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("map") {
+                    map = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown resp attribute, expected `map`"))
+                }
+            })?;
+        }
+        if map && !matches!(data.fields, Fields::Named(_)) {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "#[resp(map)] needs a struct with named fields",
+            ));
+        }
+
+        let mut fields = Vec::with_capacity(data.fields.len());
+        for (i, field) in data.fields.iter().enumerate() {
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(Index::from(i)),
+            };
+            let mut key = field.ident.as_ref().map(Ident::to_string);
+            for attr in field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("resp"))
+            {
+                attr.parse_nested_meta(|meta| {
+                    if !meta.path.is_ident("rename") {
+                        return Err(meta.error("unknown resp attribute, expected `rename`"));
+                    }
+                    if !map {
+                        return Err(meta.error("`rename` only applies to #[resp(map)] structs"));
+                    }
+                    key = Some(meta.value()?.parse::<LitStr>()?.value());
+                    Ok(())
+                })?;
+            }
+            fields.push(Field {
+                member,
+                ty: &field.ty,
+                key: key.unwrap_or_default(),
+            });
+        }
+
+        Ok(Struct {
+            ident: &input.ident,
+            generics: &input.generics,
+            map,
+            fields,
+        })
+    }
+
+    fn writable(&self) -> TokenStream2 {
+        let ident = self.ident;
+        let mut generics = self.generics.clone();
+        for param in generics.type_params_mut() {
+            param.bounds.push(parse_quote!(::resp::types::RespWritable));
+        }
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        let len = self.fields.len();
+        let (header, header_len) = if self.map {
+            (
+                quote!(writer.write_map_header(#len)?;),
+                quote!(::resp::derive::map_header_len(#len, protocol)),
+            )
+        } else {
+            (
+                quote!(writer.write_array_header(#len)?;),
+                quote!(::resp::derive::array_header_len(#len)),
+            )
+        };
+
+        // The key of each field goes right before its value in a map. The
+        // calls for a field are spanned on its type, for errors about a
+        // type that isn't writable to point at it.
+        let mut writes = Vec::with_capacity(len * 2);
+        let mut lens = Vec::with_capacity(len * 2);
+        for field in self.fields.iter() {
+            if self.map {
+                let key = &field.key;
+                writes.push(quote!(::resp::types::RespWritable::write(&#key, writer)?;));
+                lens.push(quote!(::resp::types::RespWritable::encoded_len(&#key, protocol)));
+            }
+            let member = &field.member;
+            let span = field.ty.span();
+            writes.push(quote_spanned! {span=>
+                ::resp::types::RespWritable::write(&self.#member, writer)?;
+            });
+            lens.push(quote_spanned! {span=>
+                ::resp::types::RespWritable::encoded_len(&self.#member, protocol)
+            });
+        }
+
+        quote! {
+            impl #impl_generics ::resp::types::RespWritable for #ident #ty_generics #where_clause {
+                fn write<__B: ::resp::writer::OutBuf>(
+                    &self,
+                    writer: &mut ::resp::writer::RespWriter<'_, __B>,
+                ) -> ::resp::writer::WriteResult {
+                    #header
+                    #(#writes)*
+                    Ok(())
+                }
+
+                fn encoded_len(&self, protocol: ::resp::writer::ProtocolVersion) -> usize {
+                    #header_len #(+ #lens)*
+                }
+            }
+        }
+    }
+
+    fn readable(&self) -> Result<TokenStream2> {
+        let ident = self.ident;
+        let mut generics = self.generics.clone();
+
+        // Borrowed fields borrow from the input of the parser, so the
+        // lifetime of the struct, if it has one, is that of the input
+        let mut lifetimes = generics.lifetimes();
+        let lifetime = match (lifetimes.next(), lifetimes.next()) {
+            (None, _) => None,
+            (Some(param), None) => Some(param.lifetime.clone()),
+            (Some(_), Some(param)) => {
+                return Err(Error::new_spanned(
+                    param,
+                    "RespReadable can't be derived for a struct with more than one lifetime",
+                ));
+            }
+        };
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        let lifetime = lifetime.unwrap_or_else(|| {
+            let lifetime = Lifetime::new("'__resp", Span::call_site());
+            generics.params.insert(
+                0,
+                GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+            );
+            lifetime
+        });
+        for param in generics.type_params_mut() {
+            param
+                .bounds
+                .push(parse_quote!(::resp::types::RespReadable<#lifetime>));
+        }
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+        let body = if self.map {
+            self.parse_map()
+        } else {
+            self.parse_array()
+        };
+        let can_parse = if self.map {
+            quote!(tag == b'%' || tag == b'*')
+        } else {
+            quote!(tag == b'*')
+        };
+
+        Ok(quote! {
+            impl #impl_generics ::resp::types::RespReadable<#lifetime> for #ident #ty_generics
+            #where_clause
+            {
+                fn parse(
+                    parser: &mut ::resp::parser::RespParser<#lifetime>,
+                ) -> ::resp::parser::ParseResult<Self> {
+                    #body
+                }
+
+                fn can_parse(tag: u8) -> bool {
+                    #can_parse
+                }
+            }
+        })
+    }
+
+    fn parse_array(&self) -> TokenStream2 {
+        let ident = self.ident;
+        let len = self.fields.len();
+        let members = self.fields.iter().map(|field| &field.member);
+        let values = self.fields.iter().map(|field| parse_field(field.ty));
+        let parser = parser_arg(len);
+
+        quote! {
+            ::resp::derive::parse_array(parser, #len, |#parser| {
+                Ok(#ident {
+                    #(
+                        #members: #values,
+                    )*
+                })
+            })
+        }
+    }
+
+    fn parse_map(&self) -> TokenStream2 {
+        let ident = self.ident;
+        let members: Vec<_> = self.fields.iter().map(|field| &field.member).collect();
+        let vars: Vec<_> = (0..self.fields.len())
+            .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+            .collect();
+        let keys = self
+            .fields
+            .iter()
+            .map(|field| LitByteStr::new(field.key.as_bytes(), Span::call_site()));
+        let values = self.fields.iter().map(|field| parse_field(field.ty));
+        let parser = parser_arg(self.fields.len());
+        let finish = self.fields.iter().zip(&vars).map(|(field, var)| {
+            if option_inner(field.ty).is_some() {
+                quote!(#var.flatten())
+            } else {
+                quote!(::resp::derive::required(parser, #var)?)
+            }
+        });
+
+        quote! {
+            #(
+                let mut #vars = None;
+            )*
+            ::resp::derive::parse_map(parser, |#parser, key| {
+                let known = match key {
+                    #(
+                        #keys => {
+                            #vars = Some(#values);
+                            true
+                        }
+                    )*
+                    _ => false,
+                };
+                Ok(known)
+            })?;
+
+            Ok(#ident {
+                #(
+                    #members: #finish,
+                )*
+            })
+        }
+    }
+}
+
+/// The parser argument of the closure parsing the fields, which goes unused
+/// without any
+fn parser_arg(len: usize) -> TokenStream2 {
+    if len == 0 { quote!(_) } else { quote!(parser) }
+}
+
+/// The expression parsing a field of type `ty`
+fn parse_field(ty: &Type) -> TokenStream2 {
+    match option_inner(ty) {
+        Some(inner) => quote!(::resp::derive::parse_nullable::<#inner>(parser)?),
+        None => quote!(<#ty as ::resp::types::RespReadable>::parse(parser)?),
+    }
+}
+
+/// The `T` of a field of type `Option<T>`. The type is only recognized by
+/// its name, like serde does, since macros can't resolve paths.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(inner)) if args.args.len() == 1 => Some(inner),
+        _ => None,
+    }
+}
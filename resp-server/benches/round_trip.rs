@@ -0,0 +1,233 @@
+//! Parse, dispatch and serialize round trips of whole requests, using
+//! in-memory buffers only. Run with `cargo bench -p resp-server`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use bytes::{Bytes, BytesMut};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use resp::writer::{RespWriter, WriteBuf};
+use resp_server::{ClientState, Database, Entry, KvStore, config::Config, handle_request};
+
+/// Counts allocations, so that the benchmarks can report how many a round
+/// trip takes besides how long it takes
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn request(args: &[&[u8]]) -> Vec<u8> {
+    let mut req = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        req.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        req.extend_from_slice(arg);
+        req.extend_from_slice(b"\r\n");
+    }
+    req
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let large = vec![b'A'; 1024 * 1024];
+    // What value compression is for, a JSON document of a few kilobytes
+    let json = Bytes::from(r#"{"id": 12345, "name": "resp", "tags": ["a", "b", "c"]}"#.repeat(64));
+    let kv_store = KvStore::from([
+        (
+            Bytes::from_static(b"key"),
+            Entry::new(Bytes::from_static(b"value")),
+        ),
+        (
+            Bytes::from_static(b"large"),
+            Entry::new(Bytes::from(large.clone())),
+        ),
+        (Bytes::from_static(b"json"), Entry::new(json.clone())),
+        (
+            Bytes::from_static(b"json_compressed"),
+            Entry::compressed(json, 0),
+        ),
+    ]);
+    let config = Config::default();
+    let db = Arc::new(Database::new(kv_store, &config));
+    let parser_config = config.parser_config();
+
+    let cases = [
+        ("set", request(&[b"SET", b"key", b"value"])),
+        ("get", request(&[b"GET", b"key"])),
+        ("get_1mb", request(&[b"GET", b"large"])),
+        ("set_1mb", request(&[b"SET", b"large", &large])),
+        // The cost of decompressing on the GET path
+        ("get_json", request(&[b"GET", b"json"])),
+        (
+            "get_json_compressed",
+            request(&[b"GET", b"json_compressed"]),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("round_trip");
+    for (name, req) in cases {
+        let mut write_buf = WriteBuf::new(Vec::new());
+        let mut client = ClientState::new(None);
+        let mut round_trip = || {
+            write_buf.clear();
+            let req_buf = BytesMut::from(black_box(&req[..]));
+            handle_request(
+                req_buf,
+                &parser_config,
+                &mut RespWriter::new(&mut write_buf),
+                &db,
+                &mut client,
+            );
+        };
+
+        // The first round trip grows the output buffer, later ones reuse it
+        round_trip();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        round_trip();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!("round_trip/{}: {} allocations", name, allocations);
+
+        group.throughput(Throughput::Elements(1));
+        group.bench_function(name, |b| b.iter(&mut round_trip));
+    }
+    group.finish();
+}
+
+/// A pipeline of SETs as a connection handles one batch: every reply is
+/// appended to the same buffer, which is cleared rather than reallocated
+/// for the next batch
+fn bench_pipeline(c: &mut Criterion) {
+    const PIPELINE: usize = 10_000;
+
+    let config = Config::default();
+    let db = Arc::new(Database::new(KvStore::new(), &config));
+    let parser_config = config.parser_config();
+    let requests: Vec<Vec<u8>> = (0..PIPELINE)
+        .map(|i| request(&[b"SET", format!("key:{}", i).as_bytes(), b"value"]))
+        .collect();
+
+    let mut write_buf = WriteBuf::new(Vec::new());
+    let mut client = ClientState::new(None);
+    let mut pipeline = || {
+        write_buf.clear();
+        let mut writer = RespWriter::new(&mut write_buf);
+        for req in &requests {
+            let req_buf = BytesMut::from(black_box(&req[..]));
+            handle_request(req_buf, &parser_config, &mut writer, &db, &mut client);
+        }
+    };
+
+    pipeline();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    pipeline();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "pipeline/set_10k: {} allocations, {:.1} per request",
+        allocations,
+        allocations as f64 / PIPELINE as f64
+    );
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(PIPELINE as u64));
+    group.bench_function("set_10k", |b| b.iter(&mut pipeline));
+    group.finish();
+}
+
+/// Sends the request `req` makes for each thread from 1 to 8 threads at
+/// once, all on `db`
+fn bench_concurrent(
+    c: &mut Criterion,
+    name: &str,
+    db: &Arc<Database>,
+    req: impl Fn(usize) -> Vec<u8>,
+) {
+    const PER_THREAD: usize = 10_000;
+
+    let parser_config = db.config().parser_config();
+    let mut group = c.benchmark_group(name);
+    for threads in [1, 2, 4, 8] {
+        let reqs: Vec<Vec<u8>> = (0..threads).map(&req).collect();
+        group.throughput(Throughput::Elements((threads * PER_THREAD) as u64));
+        group.bench_function(format!("{}_threads", threads), |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for req in &reqs {
+                        let parser_config = &parser_config;
+                        scope.spawn(move || {
+                            let mut write_buf = WriteBuf::new(Vec::new());
+                            let mut client = ClientState::new(None);
+                            for _ in 0..PER_THREAD {
+                                write_buf.clear();
+                                let req_buf = BytesMut::from(black_box(&req[..]));
+                                handle_request(
+                                    req_buf,
+                                    parser_config,
+                                    &mut RespWriter::new(&mut write_buf),
+                                    db,
+                                    &mut client,
+                                );
+                            }
+                        });
+                    }
+                });
+            })
+        });
+    }
+    group.finish();
+}
+
+/// GETs of one key from several threads at once. They only share the read
+/// lock of its shard, so the throughput should grow with the number of
+/// threads up to the number of cores.
+fn bench_concurrent_get(c: &mut Criterion) {
+    let kv_store = KvStore::from([(
+        Bytes::from_static(b"key"),
+        Entry::new(Bytes::from_static(b"value")),
+    )]);
+    let db = Arc::new(Database::new(kv_store, &Config::default()));
+    bench_concurrent(c, "concurrent_get", &db, |_| request(&[b"GET", b"key"]));
+}
+
+/// SETs from several threads at once, each of its own key. Keys in
+/// different shards are written under different locks, so the throughput
+/// should grow with the number of threads as well, if less than for GETs
+/// since some of the keys share a shard.
+fn bench_concurrent_set(c: &mut Criterion) {
+    let db = Arc::new(Database::new(KvStore::new(), &Config::default()));
+    bench_concurrent(c, "concurrent_set", &db, |thread| {
+        let key = format!("key:{}", thread);
+        request(&[b"SET", key.as_bytes(), b"value"])
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_round_trip,
+    bench_pipeline,
+    bench_concurrent_get,
+    bench_concurrent_set
+);
+criterion_main!(benches);
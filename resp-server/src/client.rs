@@ -0,0 +1,319 @@
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use bytes::Bytes;
+use log::warn;
+use parking_lot::Mutex;
+use resp::writer::ProtocolVersion;
+use tokio_util::sync::CancellationToken;
+
+use crate::tracking::PushSender;
+
+// ===========================================================
+// ClientInfo
+// ===========================================================
+
+/// The part of a client that other connections see through the registry,
+/// for CLIENT LIST and CLIENT KILL
+#[derive(Debug)]
+pub(crate) struct ClientInfo {
+    /// Server-assigned id, never reused while the server runs
+    pub(crate) id: u64,
+
+    /// Address of the peer, `None` if the kernel failed to report it, as it
+    /// may for a socket that has already been reset
+    pub(crate) addr: Option<SocketAddr>,
+
+    connected_at: Instant,
+    name: Mutex<Option<Bytes>>,
+    last_command: Mutex<LastCommand>,
+
+    /// Index of the selected database. There is only database 0 until
+    /// SELECT exists.
+    db: AtomicUsize,
+
+    /// Cancelled to make the connection close
+    killed: CancellationToken,
+}
+
+/// The command a client ran last, and when
+#[derive(Debug)]
+struct LastCommand {
+    name: Option<&'static str>,
+    at: Instant,
+}
+
+impl ClientInfo {
+    pub(crate) fn name(&self) -> Option<Bytes> {
+        self.name.lock().clone()
+    }
+
+    pub(crate) fn db(&self) -> usize {
+        self.db.load(Ordering::Relaxed)
+    }
+
+    /// Makes the connection close once the reply to the request it is
+    /// handling, if any, has been sent
+    pub(crate) fn kill(&self) {
+        self.killed.cancel();
+    }
+
+    /// The line CLIENT LIST reports for the client
+    pub(crate) fn describe(&self) -> String {
+        let addr = self
+            .addr
+            .map_or_else(|| "?".to_string(), |addr| addr.to_string());
+        let name = self.name().unwrap_or_default();
+        let (command, idle) = {
+            let last = self.last_command.lock();
+            (last.name.unwrap_or("NULL"), last.at.elapsed())
+        };
+        format!(
+            "id={} addr={} name={} age={} idle={} db={} cmd={}\n",
+            self.id,
+            addr,
+            String::from_utf8_lossy(&name),
+            self.connected_at.elapsed().as_secs(),
+            idle.as_secs(),
+            self.db(),
+            command
+        )
+    }
+}
+
+// ===========================================================
+// ClientState
+// ===========================================================
+
+/// Everything the server keeps about a connection. It is created when the
+/// client connects and handed to every command the client runs, which is
+/// where per-connection settings belong.
+pub struct ClientState {
+    info: Arc<ClientInfo>,
+
+    /// Protocol replies are encoded in. Every connection starts out
+    /// speaking RESP2.
+    pub(crate) protocol: ProtocolVersion,
+
+    /// Where messages the client didn't ask for go, such as the
+    /// invalidations of CLIENT TRACKING. `None` outside of a connection.
+    pushes: Option<PushSender>,
+}
+
+impl ClientState {
+    /// State of a new client, which gets the next free id
+    pub fn new(addr: Option<SocketAddr>) -> ClientState {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+        ClientState::with_id(NEXT_ID.fetch_add(1, Ordering::Relaxed), addr)
+    }
+
+    fn with_id(id: u64, addr: Option<SocketAddr>) -> ClientState {
+        let now = Instant::now();
+        ClientState {
+            info: Arc::new(ClientInfo {
+                id,
+                addr,
+                connected_at: now,
+                name: Mutex::new(None),
+                last_command: Mutex::new(LastCommand {
+                    name: None,
+                    at: now,
+                }),
+                db: AtomicUsize::new(0),
+                killed: CancellationToken::new(),
+            }),
+            protocol: ProtocolVersion::Resp2,
+            pushes: None,
+        }
+    }
+
+    /// State of a client that connected from `peer_addr`. Failing to get
+    /// the address is logged, the client is served without one.
+    pub(crate) fn connected(peer_addr: io::Result<SocketAddr>) -> ClientState {
+        let client = ClientState::new(peer_addr.as_ref().ok().copied());
+        if let Err(err) = peer_addr {
+            warn!(
+                "Failed to get peer address of connection {}: {}",
+                client, err
+            );
+        }
+        client
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.info.id
+    }
+
+    pub(crate) fn addr(&self) -> Option<SocketAddr> {
+        self.info.addr
+    }
+
+    pub(crate) fn info(&self) -> &Arc<ClientInfo> {
+        &self.info
+    }
+
+    /// Records that the client is running `command`, for the `cmd` and
+    /// `idle` fields of CLIENT LIST
+    pub(crate) fn record_command(&self, command: &'static str) {
+        *self.info.last_command.lock() = LastCommand {
+            name: Some(command),
+            at: Instant::now(),
+        };
+    }
+
+    pub(crate) fn set_name(&mut self, name: Option<Bytes>) {
+        *self.info.name.lock() = name;
+    }
+
+    pub(crate) fn pushes(&self) -> Option<&PushSender> {
+        self.pushes.as_ref()
+    }
+
+    pub(crate) fn set_pushes(&mut self, pushes: Option<PushSender>) {
+        self.pushes = pushes;
+    }
+
+    pub(crate) fn is_killed(&self) -> bool {
+        self.info.killed.is_cancelled()
+    }
+
+    /// Completes once the client has been killed
+    pub(crate) async fn killed(&self) {
+        self.info.killed.cancelled().await
+    }
+
+    /// Goes back to the state of a freshly connected client, for RESET. The
+    /// id, address and age stay, as does the registration.
+    pub(crate) fn reset(&mut self) {
+        self.set_name(None);
+        self.info.db.store(0, Ordering::Relaxed);
+        self.protocol = ProtocolVersion::Resp2;
+    }
+}
+
+impl fmt::Display for ClientState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr() {
+            Some(addr) => write!(f, "#{} ({})", self.id(), addr),
+            None => write!(f, "#{} (unknown peer)", self.id()),
+        }
+    }
+}
+
+// ===========================================================
+// Clients
+// ===========================================================
+
+/// Registry of the connected clients
+#[derive(Default)]
+pub(crate) struct Clients {
+    clients: Mutex<BTreeMap<u64, Arc<ClientInfo>>>,
+}
+
+impl Clients {
+    /// Registers `client` until the returned guard is dropped, unless
+    /// `max_clients` clients are registered already
+    pub(crate) fn register(
+        &self,
+        client: &ClientState,
+        max_clients: usize,
+    ) -> Option<Registration<'_>> {
+        let mut clients = self.clients.lock();
+        if clients.len() >= max_clients {
+            return None;
+        }
+
+        clients.insert(client.id(), client.info().clone());
+        Some(Registration {
+            clients: self,
+            id: client.id(),
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.clients.lock().len()
+    }
+
+    /// Every registered client, in the order they connected
+    pub(crate) fn list(&self) -> Vec<Arc<ClientInfo>> {
+        self.clients.lock().values().cloned().collect()
+    }
+}
+
+/// Keeps a client registered, see [`Clients::register`]
+pub(crate) struct Registration<'a> {
+    clients: &'a Clients,
+    id: u64,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.clients.clients.lock().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+
+        let inputs = [
+            ClientState::with_id(5, Some(addr)),
+            ClientState::with_id(6, None),
+        ];
+        let expects = ["#5 (127.0.0.1:50000)", "#6 (unknown peer)"];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i].to_string(), expects[i]);
+        }
+
+        let first = ClientState::connected(Ok(addr));
+        let second = ClientState::connected(Err(io::ErrorKind::NotConnected.into()));
+        assert_eq!(second.addr(), None);
+        assert!(second.id() > first.id());
+    }
+
+    #[test]
+    fn test_registry() {
+        let clients = Clients::default();
+        let mut first = ClientState::new(None);
+        let second = ClientState::new(None);
+
+        let registered = clients.register(&first, 2).unwrap();
+        {
+            let _registered = clients.register(&second, 2).unwrap();
+            let ids: Vec<_> = clients.list().iter().map(|info| info.id).collect();
+            assert_eq!(ids, [first.id(), second.id()]);
+            assert!(clients.register(&ClientState::new(None), 2).is_none());
+        }
+        let ids: Vec<_> = clients.list().iter().map(|info| info.id).collect();
+        assert_eq!(ids, [first.id()]);
+
+        // The registry sees changes to the state
+        first.set_name(Some(Bytes::from("worker")));
+        first.protocol = ProtocolVersion::Resp3;
+        assert_eq!(clients.list()[0].name(), Some(Bytes::from("worker")));
+        first.info().kill();
+        assert!(first.is_killed());
+
+        first.reset();
+        assert_eq!(clients.list()[0].name(), None);
+        assert_eq!(first.protocol, ProtocolVersion::Resp2);
+
+        drop(registered);
+        assert!(clients.list().is_empty());
+    }
+}
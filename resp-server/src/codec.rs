@@ -0,0 +1,70 @@
+use bytes::BytesMut;
+use resp::codec::{Decoder, FrameError, RespCommandCodec};
+
+use crate::config::Config;
+
+/// Splits the input stream into complete requests with `RespCommandCodec`,
+/// under the protocol limits from the configuration. The requests are
+/// parsed later, with their arguments borrowed from the frame rather than
+/// copied.
+pub struct RequestCodec {
+    inner: RespCommandCodec,
+}
+
+impl RequestCodec {
+    pub fn new(config: &Config) -> RequestCodec {
+        RequestCodec {
+            inner: RespCommandCodec::with_limits(config.command_limits()),
+        }
+    }
+}
+
+impl Decoder for RequestCodec {
+    type Item = BytesMut;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, FrameError> {
+        self.inner.decode_frame(src)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_limits() {
+        let config = Config {
+            proto_max_bulk_len: 16,
+            proto_max_multibulk_len: 4,
+            proto_inline_max_size: 32,
+            client_query_buffer_limit: 64,
+            ..Config::default()
+        };
+
+        let inputs: [&[u8]; 5] = [
+            b"*1\r\n$16\r\nAAAAAAAAAAAAAAAA\r\n",
+            b"*1\r\n$17\r\n",
+            b"*5\r\n",
+            &[b'A'; 33],
+            b"*4\r\n$16\r\nAAAAAAAAAAAAAAAA\r\n$16\r\nAAAAAAAAAAAAAAAA\r\n$16\r\nAAAAAAAAAAAAAAAA\r\n",
+        ];
+        let expects = [
+            "Ok(27)",
+            "Protocol error: invalid bulk length",
+            "Protocol error: invalid multibulk length",
+            "Protocol error: too big inline request",
+            "query buffer of 73 bytes exceeds the limit",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut codec = RequestCodec::new(&config);
+            let res = match codec.decode(&mut BytesMut::from(inputs[i])) {
+                Ok(frame) => format!("Ok({})", frame.unwrap().len()),
+                Err(err) => err.to_string(),
+            };
+            assert_eq!(res, expects[i]);
+        }
+    }
+}
@@ -0,0 +1,914 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::Ordering,
+    time::Instant,
+};
+
+use bytes::Bytes;
+use log::info;
+use resp::{
+    command::CommandName,
+    types::{RespValue, RespWritable},
+    writer::{OutBuf, RespWriter, WriteResult},
+};
+use tracing::info_span;
+
+use crate::{
+    client::ClientState,
+    config::ConfigError,
+    db::Database,
+    error::CommandError,
+    latency::Histogram,
+    lazyfree::Displaced,
+    reply::Reply,
+    store::{Entry, KvStore, Store},
+};
+
+mod connection;
+mod hash;
+mod keyspace;
+mod scripting;
+mod server;
+mod string;
+
+// ===========================================================
+// Flag
+// ===========================================================
+
+/// Properties of a command, reported by COMMAND and used to decide what a
+/// client is allowed to run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Flag {
+    /// May modify the store
+    Write,
+
+    /// Never modifies the store, so it only takes the shared read lock and
+    /// runs concurrently with other readers. A readonly command that needs
+    /// to mutate (e.g. purging a stale key) must not do so through the read
+    /// guard; it has to retake the write lock.
+    ReadOnly,
+
+    /// Administrative command, such as SHUTDOWN
+    Admin,
+
+    /// Runs in constant or logarithmic time
+    Fast,
+}
+
+impl Flag {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Flag::Write => "write",
+            Flag::ReadOnly => "readonly",
+            Flag::Admin => "admin",
+            Flag::Fast => "fast",
+        }
+    }
+}
+
+// ===========================================================
+// KeySpec
+// ===========================================================
+
+/// Which arguments of a command are keys, as in Redis: the position of the
+/// first and the last key and the step between keys. A negative last
+/// position counts from the end, -1 being the last argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct KeySpec {
+    pub(crate) first: i64,
+    pub(crate) last: i64,
+    pub(crate) step: i64,
+}
+
+impl KeySpec {
+    pub(crate) const NONE: KeySpec = KeySpec {
+        first: 0,
+        last: 0,
+        step: 0,
+    };
+
+    /// The first argument is the only key
+    pub(crate) const SINGLE: KeySpec = KeySpec {
+        first: 1,
+        last: 1,
+        step: 1,
+    };
+
+    /// Positions of the keys among `argc` arguments, including the command
+    /// name. Positions past the arguments are left out.
+    pub(crate) fn positions(self, argc: usize) -> impl Iterator<Item = usize> {
+        let argc = argc as i64;
+        let last = if self.last < 0 {
+            argc + self.last
+        } else {
+            self.last.min(argc - 1)
+        };
+        // Without keys the range is empty
+        let (first, last) = if self.first > 0 {
+            (self.first, last)
+        } else {
+            (1, 0)
+        };
+
+        (first..=last)
+            .step_by(self.step.max(1) as usize)
+            .map(|pos| pos as usize)
+    }
+}
+
+// ===========================================================
+// CommandSpec
+// ===========================================================
+
+/// Runs a command whose arity has already been checked. `args` includes the
+/// command name.
+pub(crate) type Handler<S> = fn(&mut Ctx<'_, S>, &[&[u8]]) -> Reply;
+
+/// Everything the server knows about a command
+pub(crate) struct CommandSpec<S: Store> {
+    /// Lowercase name the command is registered under
+    pub(crate) name: &'static str,
+
+    /// Number of arguments including the command name. As in Redis, a
+    /// negative arity means at least `-arity` arguments.
+    pub(crate) arity: i64,
+
+    pub(crate) flags: &'static [Flag],
+    pub(crate) keys: KeySpec,
+    pub(crate) handler: Handler<S>,
+}
+
+// Derived Clone and Copy would require them of the store
+impl<S: Store> Clone for CommandSpec<S> {
+    fn clone(&self) -> CommandSpec<S> {
+        *self
+    }
+}
+
+impl<S: Store> Copy for CommandSpec<S> {}
+
+impl<S: Store> CommandSpec<S> {
+    pub(crate) fn accepts(&self, argc: usize) -> bool {
+        if self.arity >= 0 {
+            argc as i64 == self.arity
+        } else {
+            argc as i64 >= -self.arity
+        }
+    }
+}
+
+// ===========================================================
+// CommandTable
+// ===========================================================
+
+/// Specs of every command the server implements
+fn specs<S: Store>() -> Vec<CommandSpec<S>> {
+    [
+        string::commands(),
+        keyspace::commands(),
+        hash::commands(),
+        server::commands(),
+        connection::commands(),
+        scripting::commands(),
+    ]
+    .concat()
+}
+
+/// Checks that the `rename-command` directives can be applied the way
+/// [`CommandTable::new`] applies them, so that the configuration can be
+/// refused before anything is built from it
+pub(crate) fn check_renames(renames: &[(String, String)]) -> Result<(), ConfigError> {
+    let mut names: HashSet<Bytes> = specs::<KvStore>()
+        .iter()
+        .map(|spec| Bytes::copy_from_slice(&CommandName::new(spec.name.as_bytes())))
+        .collect();
+
+    for (name, new_name) in renames {
+        if !names.remove(&*CommandName::new(name.as_bytes())) {
+            return Err(ConfigError::RenameUnknownCommand { name: name.clone() });
+        }
+        let key = Bytes::copy_from_slice(&CommandName::new(new_name.as_bytes()));
+        if !new_name.is_empty() && !names.insert(key) {
+            return Err(ConfigError::RenameTaken {
+                name: name.clone(),
+                new_name: new_name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A registered command along with what is recorded about its calls
+pub(crate) struct Command<S: Store> {
+    /// Name the command is registered under, which differs from the name in
+    /// its spec if it was renamed
+    pub(crate) name: Bytes,
+
+    pub(crate) spec: CommandSpec<S>,
+
+    /// Execution time of every call
+    pub(crate) latency: Histogram,
+}
+
+/// The commands the server knows, built once at startup. This is the only
+/// place commands are registered, dispatch and COMMAND both read from it.
+/// Commands are keyed by their name in uppercase.
+pub(crate) struct CommandTable<S: Store> {
+    commands: HashMap<Bytes, Command<S>>,
+}
+
+impl<S: Store> CommandTable<S> {
+    /// Registers every command, applying the `rename-command` directives in
+    /// order. A rename of an unknown command, or to a name that is taken,
+    /// fails.
+    pub(crate) fn new(renames: &[(String, String)]) -> Result<CommandTable<S>, ConfigError> {
+        let mut commands: HashMap<Bytes, Command<S>> = specs()
+            .into_iter()
+            .map(|spec| {
+                let key = Bytes::copy_from_slice(&CommandName::new(spec.name.as_bytes()));
+                let command = Command {
+                    name: Bytes::from_static(spec.name.as_bytes()),
+                    spec,
+                    latency: Histogram::default(),
+                };
+                (key, command)
+            })
+            .collect();
+
+        for (name, new_name) in renames {
+            let Some(mut command) = commands.remove(&*CommandName::new(name.as_bytes())) else {
+                return Err(ConfigError::RenameUnknownCommand { name: name.clone() });
+            };
+            if commands.contains_key(&*CommandName::new(new_name.as_bytes())) {
+                return Err(ConfigError::RenameTaken {
+                    name: name.clone(),
+                    new_name: new_name.clone(),
+                });
+            }
+
+            if new_name.is_empty() {
+                info!("Disabled command '{}'", name);
+            } else {
+                info!("Renamed command '{}' to '{}'", name, new_name);
+                command.name = Bytes::from(new_name.clone());
+                let key = Bytes::copy_from_slice(&CommandName::new(new_name.as_bytes()));
+                commands.insert(key, command);
+            }
+        }
+
+        Ok(CommandTable { commands })
+    }
+
+    /// Looks up a command by name, ignoring case
+    pub(crate) fn command(&self, name: &[u8]) -> Option<&Command<S>> {
+        self.commands.get(&*CommandName::new(name))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Command<S>> {
+        self.commands.values()
+    }
+
+    /// Every command that has been called since the last reset, by name
+    pub(crate) fn called(&self) -> Vec<&Command<S>> {
+        let mut called: Vec<_> = self
+            .commands
+            .values()
+            .filter(|command| command.latency.count() > 0)
+            .collect();
+        called.sort_by_key(|command| &command.name);
+        called
+    }
+
+    pub(crate) fn reset_latency(&self) {
+        self.commands
+            .values()
+            .for_each(|command| command.latency.reset());
+    }
+}
+
+// ===========================================================
+// Ctx
+// ===========================================================
+
+/// What a handler works with besides its arguments. Handlers take the
+/// database lock themselves and release it before returning, so that the
+/// reply is serialized without blocking other connections.
+pub(crate) struct Ctx<'a, S: Store> {
+    pub(crate) db: &'a Database<S>,
+
+    /// The connection running the command
+    pub(crate) client: &'a mut ClientState,
+
+    /// Values removed from or overwritten in the store. They are freed after
+    /// the handler returns, rather than while it holds the lock.
+    displaced: Vec<Displaced<S>>,
+
+    reply: bool,
+}
+
+impl<'a, S: Store> Ctx<'a, S> {
+    fn new(db: &'a Database<S>, client: &'a mut ClientState) -> Ctx<'a, S> {
+        Ctx {
+            db,
+            client,
+            displaced: Vec::new(),
+            reply: true,
+        }
+    }
+
+    pub(crate) fn displace(&mut self, displaced: Displaced<S>) {
+        self.displaced.push(displaced);
+    }
+
+    /// Removes `key` if it has expired, as Redis does when an expired key is
+    /// looked up, rather than leaving it in the store until it is written
+    /// again. Checked under the read lock first, so that a plain miss
+    /// doesn't take the write lock.
+    pub(crate) fn remove_expired(&mut self, key: &[u8]) {
+        if !self
+            .db
+            .kv_store
+            .read(key)
+            .entry(key)
+            .is_some_and(Entry::is_expired)
+        {
+            return;
+        }
+
+        // It may have been written again in between
+        let removed = {
+            let mut store = self.db.kv_store.write(key);
+            if store.entry(key).is_some_and(Entry::is_expired) {
+                store.remove(key)
+            } else {
+                None
+            }
+        };
+        if let Some(entry) = removed {
+            self.db.stats.expired_keys.fetch_add(1, Ordering::Relaxed);
+            self.db.tracking.invalidate(&[key]);
+            self.displace(Displaced::Deleted(entry.value));
+        }
+    }
+
+    /// Drops whatever the handler returns instead of sending it
+    pub(crate) fn suppress_reply(&mut self) {
+        self.reply = false;
+    }
+}
+
+// ===========================================================
+// Dispatch
+// ===========================================================
+
+/// Runs the handler of `spec`, keeping clients that track keys informed. The
+/// keys a read touches are remembered before it looks at them, so that a
+/// write landing in between invalidates them, needlessly at worst, rather
+/// than going unnoticed. The keys a write touched are invalidated once it
+/// is done.
+fn run_handler<S: Store>(ctx: &mut Ctx<'_, S>, spec: &CommandSpec<S>, args: &[&[u8]]) -> Reply {
+    let keys = || -> Vec<&[u8]> {
+        spec.keys
+            .positions(args.len())
+            .map(|position| args[position])
+            .collect()
+    };
+
+    if ctx.db.tracking.is_active() && spec.flags.contains(&Flag::ReadOnly) {
+        let max_keys = ctx.db.config().tracking_table_max_keys;
+        ctx.db.tracking.remember(ctx.client.id(), &keys(), max_keys);
+    }
+
+    let reply = (spec.handler)(ctx, args);
+    if ctx.db.tracking.is_active()
+        && spec.flags.contains(&Flag::Write)
+        && !matches!(reply, Reply::Value(RespValue::Error(_)))
+    {
+        ctx.db.tracking.invalidate(&keys());
+    }
+    reply
+}
+
+/// Runs the command in `args`, which must not be empty, and writes its
+/// reply. Unknown commands and wrong arities are rejected before any handler
+/// runs.
+pub(crate) fn dispatch<S: Store, B: OutBuf>(
+    args: &[&[u8]],
+    db: &Database<S>,
+    writer: &mut RespWriter<'_, B>,
+    client: &mut ClientState,
+) -> WriteResult {
+    let Some(command) = db.commands.command(args[0]) else {
+        let err = CommandError::UnknownCommand {
+            name: Bytes::copy_from_slice(args[0]),
+            args: args[1..]
+                .iter()
+                .map(|arg| Bytes::copy_from_slice(arg))
+                .collect(),
+        };
+        return RespValue::from(err).write(writer);
+    };
+    let spec = &command.spec;
+    client.record_command(spec.name);
+
+    if !spec.accepts(args.len()) {
+        let err = CommandError::WrongArity { command: spec.name };
+        return RespValue::from(err).write(writer);
+    }
+
+    let _span = info_span!("command", cmd = spec.name).entered();
+    info!(
+        "Handle: {:?}",
+        args.iter()
+            .map(|arg| arg.escape_ascii().to_string())
+            .collect::<Vec<_>>()
+    );
+
+    // Execution time covers freeing what the command displaced, but not
+    // serializing the reply
+    let started = Instant::now();
+    let protocol = client.protocol;
+    let mut ctx = Ctx::new(db, client);
+    let reply = run_handler(&mut ctx, spec, args);
+    if spec.flags.contains(&Flag::Write) && !matches!(reply, Reply::Value(RespValue::Error(_))) {
+        db.persistence.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+    if !ctx.displaced.is_empty() {
+        let config = db.config();
+        for displaced in ctx.displaced {
+            db.lazyfree.free(displaced, &config);
+        }
+    }
+    command.latency.record(started.elapsed());
+    db.stats
+        .total_commands_processed
+        .fetch_add(1, Ordering::Relaxed);
+
+    // HELLO switches protocols, and replies in the one it switched to
+    if ctx.client.protocol != protocol {
+        writer.set_protocol(ctx.client.protocol);
+    }
+    if ctx.reply {
+        reply.write(writer)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, mpsc},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use resp::{
+        command::MAX_NAME_LEN,
+        parser::RespParser,
+        types::RespReadable,
+        writer::{WriteBuf, WriteError},
+    };
+
+    use super::*;
+    use crate::{
+        client::ClientState,
+        config::Config,
+        store::KvStore,
+        test_util::{self, args, database, dispatch, request, run},
+        tracking::{TrackingMode, push_queue},
+    };
+
+    #[test]
+    fn test_flags() {
+        let commands = CommandTable::<KvStore>::new(&[]).unwrap();
+
+        let inputs = ["get", "set", "del", "flushdb", "shutdown"];
+        let expects = [
+            (true, false),
+            (false, true),
+            (false, true),
+            (false, true),
+            (false, false),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let spec = &commands.command(inputs[i].as_bytes()).unwrap().spec;
+            assert_eq!(
+                (
+                    spec.flags.contains(&Flag::ReadOnly),
+                    spec.flags.contains(&Flag::Write),
+                ),
+                expects[i]
+            );
+        }
+        assert!(
+            commands
+                .command(b"shutdown")
+                .unwrap()
+                .spec
+                .flags
+                .contains(&Flag::Admin)
+        );
+    }
+
+    #[test]
+    fn test_key_positions() {
+        let keys = |first, last, step| KeySpec { first, last, step };
+        let inputs = [
+            (KeySpec::NONE, 3),
+            (KeySpec::SINGLE, 2),
+            (KeySpec::SINGLE, 1),
+            (keys(1, -1, 1), 4),
+            (keys(1, -1, 2), 5),
+            (keys(2, -2, 1), 5),
+            (keys(2, 2, 1), 2),
+        ];
+        let expects: [&[usize]; 7] = [&[], &[1], &[], &[1, 2, 3], &[1, 3], &[2, 3], &[]];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (spec, argc) = inputs[i];
+            assert_eq!(spec.positions(argc).collect::<Vec<_>>(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let commands = CommandTable::<KvStore>::new(&[]).unwrap();
+
+        let long = "g".repeat(MAX_NAME_LEN + 1);
+        let inputs = ["get", "GET", "gEt", "", "foo", long.as_str()];
+        let expects = [Some("get"), Some("get"), Some("get"), None, None, None];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let command = commands.command(inputs[i].as_bytes());
+            assert_eq!(command.map(|command| command.spec.name), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_arity() {
+        let db = database(&[]);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"GET"],
+            &[b"GET", b"a", b"b"],
+            &[b"SET", b"key"],
+            &[b"del"],
+            &[b"FLUSHDB", b"ASYNC"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR wrong number of arguments for 'get' command\r\n",
+            b"-ERR wrong number of arguments for 'get' command\r\n",
+            b"-ERR wrong number of arguments for 'set' command\r\n",
+            b"-ERR wrong number of arguments for 'del' command\r\n",
+            b"+OK\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let db = database(&[]);
+        assert_eq!(
+            dispatch(&request(&[b"foo", b"a", b"b"]), &db),
+            b"-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_command() {
+        let config = Config::from_args([
+            "--rename-command",
+            "flushall",
+            "",
+            "--rename-command",
+            "config",
+            "admin-config",
+        ])
+        .unwrap();
+        let db = Arc::new(Database::new(
+            test_util::store::<KvStore>(&[("key", "value")]),
+            &config,
+        ));
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"FLUSHALL"],
+            &[b"CONFIG", b"SET", b"protected-mode", b"no"],
+            &[b"ADMIN-CONFIG", b"SET", b"protected-mode", b"no"],
+            &[b"GET", b"key"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR unknown command 'FLUSHALL', with args beginning with: \r\n",
+            b"-ERR unknown command 'CONFIG', with args beginning with: 'SET' 'protected-mode' \
+              'no' \r\n",
+            b"+OK\r\n",
+            b"$5\r\nvalue\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+        assert!(!db.config().protected_mode);
+        assert_eq!(db.kv_store.len(), 1);
+
+        // COMMAND lists the new names only
+        let mut names: Vec<&[u8]> = db
+            .commands
+            .iter()
+            .map(|command| &command.name[..])
+            .collect();
+        names.sort();
+        let mut expected: Vec<&[u8]> = CommandTable::<KvStore>::new(&[])
+            .unwrap()
+            .iter()
+            .map(|command| command.spec.name.as_bytes())
+            .filter(|name| *name != b"flushall" && *name != b"config")
+            .chain([&b"admin-config"[..]])
+            .collect();
+        expected.sort();
+        assert_eq!(names, expected);
+
+        // A rename that can't be applied is a configuration error
+        let inputs: &[&[&str]] = &[
+            &["--rename-command", "nosuchcommand", "x"],
+            &["--rename-command", "get", "set"],
+            &[
+                "--rename-command",
+                "get",
+                "",
+                "--rename-command",
+                "get",
+                "x",
+            ],
+        ];
+        let expects = &[
+            ConfigError::RenameUnknownCommand {
+                name: "nosuchcommand".into(),
+            },
+            ConfigError::RenameTaken {
+                name: "get".into(),
+                new_name: "set".into(),
+            },
+            ConfigError::RenameUnknownCommand { name: "get".into() },
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(Config::from_args(inputs[i]).unwrap_err(), expects[i]);
+        }
+    }
+
+    /// GET, with another client setting the key between reading the value
+    /// and returning it
+    fn racing_get(ctx: &mut Ctx<'_, KvStore>, args: &[&[u8]]) -> Reply {
+        let get = ctx.db.commands.command(b"GET").unwrap().spec.handler;
+        let reply = get(ctx, args);
+        run(&[b"SET", args[1], b"new"], ctx.db);
+        reply
+    }
+
+    #[test]
+    fn test_tracking_write_between_read_and_reply() {
+        let db = database(&[("key", "old")]);
+        let mut client = ClientState::new(None);
+        let (tx, mut rx) = push_queue(client.info().clone());
+        db.tracking.enable(client.id(), TrackingMode::Default, tx);
+        let spec = CommandSpec {
+            name: "get",
+            arity: 2,
+            flags: &[Flag::ReadOnly],
+            keys: KeySpec::SINGLE,
+            handler: racing_get,
+        };
+
+        // The client is sent the old value, so it has to hear about the new
+        // one
+        let reply = run_handler(&mut Ctx::new(&db, &mut client), &spec, &[b"GET", b"key"]);
+        assert!(matches!(reply, Reply::Bulk(_)));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespValue::Push(vec![
+                RespValue::from("invalidate"),
+                RespValue::Array(vec![RespValue::from("key")])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_readers_do_not_block_each_other() {
+        let db = database(&[("key", "value")]);
+
+        // Hold a read lock for the whole test; a GET on another thread must
+        // still complete because it only needs shared access.
+        let _guard = db.kv_store.read(b"key");
+
+        let (tx, rx) = mpsc::channel();
+        let reader_db = db.clone();
+        thread::spawn(move || {
+            tx.send(run(&args(&[b"GET", b"key"]), &reader_db)).unwrap();
+        });
+
+        let res = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(res, b"$5\r\nvalue\r\n");
+    }
+
+    #[test]
+    fn test_writers_to_other_shards_do_not_block() {
+        let db = database(&[("key", "value")]);
+
+        // Hold the write lock of the shard holding "key", and find a key in
+        // another shard, whose lock is free
+        let _guard = db.kv_store.write(b"key");
+        let other = (0..)
+            .map(|i| format!("other:{}", i))
+            .find(|key| db.kv_store.try_write(key.as_bytes()).is_some())
+            .unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let writer_db = db.clone();
+        thread::spawn(move || {
+            let set = run(&args(&[b"SET", other.as_bytes(), b"v"]), &writer_db);
+            let get = run(&args(&[b"GET", other.as_bytes()]), &writer_db);
+            tx.send((set, get)).unwrap();
+        });
+
+        let res = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(res, (b"+OK\r\n".to_vec(), b"$1\r\nv\r\n".to_vec()));
+    }
+
+    #[test]
+    fn test_lock_released_before_reply() {
+        let big = "A".repeat(8 * 1024 * 1024);
+        let db = database(&[("big", &big)]);
+        let commands = CommandTable::<KvStore>::new(&[]).unwrap();
+
+        let mut client = ClientState::new(None);
+        let mut ctx = Ctx::new(&db, &mut client);
+        let get = commands.command(b"get").unwrap().spec.handler;
+        let reply = get(&mut ctx, &args(&[b"GET", b"big"]));
+        assert!(db.kv_store.try_write(b"big").is_some());
+        assert_eq!(reply, Reply::Bulk(Bytes::from(big.clone())));
+
+        let del = commands.command(b"del").unwrap().spec.handler;
+        let reply = del(&mut ctx, &args(&[b"DEL", b"big"]));
+        assert!(db.kv_store.try_write(b"big").is_some());
+        assert_eq!(reply, Reply::Integer(1));
+        assert!(matches!(
+            ctx.displaced.as_slice(),
+            [Displaced::Deleted(value)] if value.stored_len() == big.len()
+        ));
+
+        let mut ctx = Ctx::new(&db, &mut client);
+        let reply = del(&mut ctx, &args(&[b"DEL", b"big"]));
+        assert_eq!(reply, Reply::Integer(0));
+        assert!(ctx.displaced.is_empty());
+    }
+
+    #[test]
+    fn test_command_names_are_case_insensitive() {
+        let db = database(&[]);
+
+        // Only the name is folded, keys and values keep their case, and an
+        // unknown name is echoed as sent
+        let inputs: &[&[&[u8]]] = &[
+            &[b"sEt", b"Key", b"Value"],
+            &[b"get", b"Key"],
+            &[b"GET", b"key"],
+            &[b"Exists", b"Key", b"KEY"],
+            &[b"dEl", b"key"],
+            &[b"dEl", b"Key"],
+            &[b"Get", b"Key"],
+            &[b"FoO", b"Bar"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"+OK\r\n",
+            b"$5\r\nValue\r\n",
+            b"$-1\r\n",
+            b":1\r\n",
+            b":0\r\n",
+            b":1\r\n",
+            b"$-1\r\n",
+            b"-ERR unknown command 'FoO', with args beginning with: 'Bar' \r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_values_round_trip() {
+        // Deterministic pseudo-random blob with the bytes that matter for
+        // framing sprinkled in
+        let mut state: u32 = 0x2545_f491;
+        let mut blob: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        blob.extend_from_slice(b"\r\n\0\r\0\n\x00\xff\xfe");
+        let key = b"\xff\r\nkey\0";
+
+        let db = database(&[]);
+        assert_eq!(dispatch(&request(&[b"SET", key, &blob]), &db), b"+OK\r\n");
+
+        let mut expected = format!("${}\r\n", blob.len()).into_bytes();
+        expected.extend_from_slice(&blob);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(dispatch(&request(&[b"GET", key]), &db), expected);
+
+        let mut parser = RespParser::new(&expected);
+        assert_eq!(RespValue::parse(&mut parser), Ok(RespValue::from(blob)));
+    }
+
+    #[test]
+    fn test_write_error_is_returned() {
+        let db = database(&[("key", &"A".repeat(1024))]);
+
+        let mut write_buf = WriteBuf::with_limit(Vec::new(), 64);
+        let mut writer = RespWriter::new(&mut write_buf);
+        let mut client = ClientState::new(None);
+        assert!(matches!(
+            super::dispatch(&args(&[b"GET", b"key"]), &db, &mut writer, &mut client),
+            Err(WriteError::BufferFull { limit: 64, .. })
+        ));
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_concurrent_get() {
+        const OPS_PER_THREAD: usize = 200_000;
+
+        let db = database(&[("key", "value")]);
+        for threads in [1, 2, 4, 8] {
+            let start = Instant::now();
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    let db = db.clone();
+                    thread::spawn(move || {
+                        let get = args(&[b"GET", b"key"]);
+                        for _ in 0..OPS_PER_THREAD {
+                            run(&get, &db);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            let elapsed = start.elapsed();
+            let ops = (threads * OPS_PER_THREAD) as f64 / elapsed.as_secs_f64();
+            println!("{} reader thread(s): {:.0} GET/s", threads, ops);
+        }
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_get_large_value() {
+        const VALUE_LEN: usize = 1024 * 1024;
+        const ITERATIONS: usize = 2_000;
+
+        let value = "A".repeat(VALUE_LEN);
+        let db = database(&[("key", &value)]);
+
+        let get = args(&[b"GET", b"key"]);
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let reply = run(&get, &db);
+            assert_eq!(reply.len(), VALUE_LEN + 12);
+        }
+
+        let elapsed = start.elapsed();
+        let mb = (ITERATIONS * VALUE_LEN) as f64 / (1024.0 * 1024.0);
+        println!(
+            "GET of a 1MB value: {:.1} us/op, {:.0} MB/s",
+            elapsed.as_micros() as f64 / ITERATIONS as f64,
+            mb / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,651 @@
+use bytes::Bytes;
+use resp::{
+    command::{Keyword, scan_options},
+    parser::{read_i64, read_u64},
+    types::RespValue,
+    writer::ProtocolVersion,
+};
+
+use super::{CommandSpec, Ctx, Flag, KeySpec};
+use crate::{
+    client::ClientInfo, error::CommandError, reply::Reply, store::Store, tracking::TrackingMode,
+};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "client",
+            arity: -2,
+            flags: &[],
+            keys: KeySpec::NONE,
+            handler: client,
+        },
+        CommandSpec {
+            name: "echo",
+            arity: 2,
+            flags: &[Flag::Fast],
+            keys: KeySpec::NONE,
+            handler: echo,
+        },
+        CommandSpec {
+            name: "hello",
+            arity: -1,
+            flags: &[Flag::Fast],
+            keys: KeySpec::NONE,
+            handler: hello,
+        },
+        CommandSpec {
+            name: "ping",
+            arity: -1,
+            flags: &[Flag::Fast],
+            keys: KeySpec::NONE,
+            handler: ping,
+        },
+        CommandSpec {
+            name: "reset",
+            arity: 1,
+            flags: &[Flag::Fast],
+            keys: KeySpec::NONE,
+            handler: reset,
+        },
+    ]
+}
+
+/// ID, GETNAME, SETNAME, LIST, KILL and TRACKING
+fn client<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_, sub] if sub.eq_ignore_ascii_case(b"ID") => ctx.client.id().into(),
+        [_, sub] if sub.eq_ignore_ascii_case(b"GETNAME") => ctx.client.info().name().into(),
+        [_, sub, name] if sub.eq_ignore_ascii_case(b"SETNAME") => {
+            if !is_valid_name(name) {
+                return CommandError::InvalidClientName.into();
+            }
+            ctx.client
+                .set_name(Some(Bytes::copy_from_slice(name)).filter(|name| !name.is_empty()));
+            Reply::Ok
+        }
+        [_, sub] if sub.eq_ignore_ascii_case(b"LIST") => {
+            let clients = ctx.db.clients.list();
+            clients
+                .iter()
+                .map(|info| info.describe())
+                .collect::<String>()
+                .into()
+        }
+        [_, sub, filters @ ..] if sub.eq_ignore_ascii_case(b"KILL") => client_kill(ctx, filters),
+        [_, sub, switch, options @ ..] if sub.eq_ignore_ascii_case(b"TRACKING") => {
+            client_tracking(ctx, switch, options)
+        }
+        [_, sub, ..]
+            if [&b"ID"[..], b"GETNAME", b"SETNAME", b"LIST", b"TRACKING"]
+                .iter()
+                .any(|known| sub.eq_ignore_ascii_case(known)) =>
+        {
+            CommandError::Syntax.into()
+        }
+        [_, sub, ..] => CommandError::UnknownSubcommand {
+            command: "client",
+            subcommand: Bytes::copy_from_slice(sub),
+        }
+        .into(),
+        _ => unreachable!("arity is checked before dispatch"),
+    }
+}
+
+/// Names show up in CLIENT LIST, where they must stay one word
+fn is_valid_name(name: &[u8]) -> bool {
+    name.iter().all(|c| (b'!'..=b'~').contains(c))
+}
+
+const CLIENT_KILL_FILTERS: &[Keyword] = &[
+    Keyword::with_value("ID"),
+    Keyword::with_value("ADDR"),
+    Keyword::with_value("SKIPME"),
+];
+
+/// CLIENT KILL addr kills the client connected from `addr` and fails if
+/// there is none. CLIENT KILL [ID id] [ADDR addr] [SKIPME yes|no] kills
+/// every client matching all the filters, other than the caller unless
+/// SKIPME is no, and replies with their number.
+fn client_kill<S: Store>(ctx: &mut Ctx<'_, S>, filters: &[&[u8]]) -> Reply {
+    let clients = ctx.db.clients.list();
+    let has_addr = |info: &ClientInfo, addr: &[u8]| {
+        info.addr
+            .is_some_and(|own| own.to_string().as_bytes() == addr)
+    };
+
+    if let [addr] = filters {
+        let Some(info) = clients.iter().find(|info| has_addr(info, addr)) else {
+            return CommandError::NoSuchClient.into();
+        };
+        info.kill();
+        return Reply::Ok;
+    }
+
+    let Ok(options) = scan_options(filters, CLIENT_KILL_FILTERS) else {
+        return CommandError::Syntax.into();
+    };
+    let id = match options.value("ID").map(read_u64) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return CommandError::NotAnInteger.into(),
+    };
+    let addr = options.value("ADDR");
+    let skip_me = match options.value("SKIPME") {
+        None => true,
+        Some(value) if value.eq_ignore_ascii_case(b"YES") => true,
+        Some(value) if value.eq_ignore_ascii_case(b"NO") => false,
+        Some(_) => return CommandError::Syntax.into(),
+    };
+
+    let killed = clients
+        .iter()
+        .filter(|info| id.is_none_or(|id| info.id == id))
+        .filter(|info| addr.is_none_or(|addr| has_addr(info, addr)))
+        .filter(|info| !skip_me || info.id != ctx.client.id())
+        .inspect(|info| info.kill())
+        .count();
+    killed.into()
+}
+
+/// CLIENT TRACKING ON|OFF [BCAST] [PREFIX prefix ...] has the caller told
+/// about changes to keys it may have cached, through RESP3 push messages.
+/// By default it hears about the keys it read, once each until it reads them
+/// again, and with BCAST about every key starting with one of the prefixes.
+/// Turning it on again replaces the mode. The REDIRECT, OPTIN, OPTOUT and
+/// NOLOOP options of Redis aren't supported.
+fn client_tracking<S: Store>(ctx: &mut Ctx<'_, S>, switch: &[u8], options: &[&[u8]]) -> Reply {
+    let on = if switch.eq_ignore_ascii_case(b"ON") {
+        true
+    } else if switch.eq_ignore_ascii_case(b"OFF") {
+        false
+    } else {
+        return CommandError::Syntax.into();
+    };
+
+    let mut bcast = false;
+    let mut prefixes = Vec::new();
+    let mut options = options;
+    loop {
+        match options {
+            [] => break,
+            [option, rest @ ..] if option.eq_ignore_ascii_case(b"BCAST") => {
+                bcast = true;
+                options = rest;
+            }
+            [option, prefix, rest @ ..] if option.eq_ignore_ascii_case(b"PREFIX") => {
+                prefixes.push(Bytes::copy_from_slice(prefix));
+                options = rest;
+            }
+            _ => return CommandError::Syntax.into(),
+        }
+    }
+
+    if !on {
+        ctx.db.tracking.disable(ctx.client.id());
+        return Reply::Ok;
+    }
+    if !prefixes.is_empty() && !bcast {
+        return CommandError::PrefixNeedsBcast.into();
+    }
+    if ctx.client.protocol != ProtocolVersion::Resp3 {
+        return CommandError::TrackingNeedsResp3.into();
+    }
+    // Only commands run outside of a connection have nowhere to push to
+    let Some(pushes) = ctx.client.pushes().cloned() else {
+        return CommandError::TrackingNeedsResp3.into();
+    };
+
+    let mode = if bcast {
+        TrackingMode::Broadcast { prefixes }
+    } else {
+        TrackingMode::Default
+    };
+    ctx.db.tracking.enable(ctx.client.id(), mode, pushes);
+    Reply::Ok
+}
+
+/// HELLO [protover [AUTH username password] [SETNAME clientname]] switches
+/// the connection to RESP2 or RESP3 and describes the server. Without a
+/// version the protocol stays as it is. There are no users besides the
+/// default one, which takes any password, as in Redis without requirepass.
+fn hello<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let protocol = match args.get(1).map(|arg| read_i64(arg)) {
+        None => ctx.client.protocol,
+        Some(Ok(2)) => ProtocolVersion::Resp2,
+        Some(Ok(3)) => ProtocolVersion::Resp3,
+        Some(Ok(_)) => return CommandError::NoProto.into(),
+        Some(Err(_)) => return CommandError::InvalidProtocolVersion.into(),
+    };
+
+    let mut name = None;
+    let mut options = args.get(2..).unwrap_or_default();
+    loop {
+        match options {
+            [] => break,
+            [option, user, _password, rest @ ..] if option.eq_ignore_ascii_case(b"AUTH") => {
+                if !user.eq_ignore_ascii_case(b"default") {
+                    return CommandError::WrongPass.into();
+                }
+                options = rest;
+            }
+            [option, value, rest @ ..] if option.eq_ignore_ascii_case(b"SETNAME") => {
+                name = Some(*value);
+                options = rest;
+            }
+            [option, ..] => {
+                return CommandError::HelloOption {
+                    option: Bytes::copy_from_slice(option),
+                }
+                .into();
+            }
+        }
+    }
+    if let Some(name) = name {
+        if !is_valid_name(name) {
+            return CommandError::InvalidClientName.into();
+        }
+        ctx.client
+            .set_name(Some(Bytes::copy_from_slice(name)).filter(|name| !name.is_empty()));
+    }
+
+    // Invalidations can't be sent over RESP2
+    if protocol == ProtocolVersion::Resp2 {
+        ctx.db.tracking.disable(ctx.client.id());
+    }
+    ctx.client.protocol = protocol;
+    let proto = match protocol {
+        ProtocolVersion::Resp2 => 2,
+        ProtocolVersion::Resp3 => 3,
+    };
+    let id = i64::try_from(ctx.client.id()).unwrap_or(i64::MAX);
+    Reply::Value(RespValue::Map(vec![
+        (RespValue::from("server"), RespValue::from("redis")),
+        (
+            RespValue::from("version"),
+            RespValue::from(env!("CARGO_PKG_VERSION")),
+        ),
+        (RespValue::from("proto"), RespValue::Integer(proto)),
+        (RespValue::from("id"), RespValue::Integer(id)),
+        (RespValue::from("mode"), RespValue::from("standalone")),
+        (RespValue::from("role"), RespValue::from("master")),
+        (RespValue::from("modules"), RespValue::Array(vec![])),
+    ]))
+}
+
+/// ECHO message. Replies the message as a bulk string, byte for byte.
+fn echo<S: Store>(_ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    Bytes::copy_from_slice(args[1]).into()
+}
+
+/// PING [message]. Replies PONG, or the message as a bulk string.
+fn ping<S: Store>(_ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_] => Reply::Value(RespValue::Simple("PONG".to_string())),
+        [_, message] => Bytes::copy_from_slice(message).into(),
+        _ => CommandError::WrongArity { command: "ping" }.into(),
+    }
+}
+
+/// Puts the connection back into the state it had when it connected, which
+/// so far means dropping its name, turning tracking off and going back to
+/// RESP2
+fn reset<S: Store>(ctx: &mut Ctx<'_, S>, _args: &[&[u8]]) -> Reply {
+    ctx.db.tracking.disable(ctx.client.id());
+    ctx.client.reset();
+    Reply::Value(RespValue::Simple("RESET".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use crate::{
+        client::ClientState,
+        test_util::{args, database, run_as},
+        tracking::push_queue,
+    };
+    use resp::{
+        types::RespWritable,
+        writer::{ProtocolVersion, RespWriter, WriteBuf},
+    };
+
+    #[test]
+    fn test_client() {
+        let db = database(&[]);
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let mut client = ClientState::new(Some(addr));
+        let id = client.id();
+        let _registration = db.clients.register(&client, usize::MAX);
+        let line = format!(
+            "id={} addr=127.0.0.1:50000 name=worker-1 age=0 idle=0 db=0 cmd=client\n",
+            id
+        );
+        let list = format!("${}\r\n{}\r\n", line.len(), line);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"CLIENT", b"GETNAME"],
+            &[b"CLIENT", b"SETNAME", b"worker-1"],
+            &[b"client", b"getname"],
+            &[b"CLIENT", b"LIST"],
+            &[b"CLIENT", b"SETNAME", b"two words"],
+            &[b"CLIENT", b"SETNAME", b"line\r\n"],
+            &[b"CLIENT", b"GETNAME"],
+            &[b"CLIENT", b"SETNAME", b""],
+            &[b"CLIENT", b"GETNAME"],
+            &[b"CLIENT", b"SETNAME"],
+            &[b"CLIENT", b"FOO"],
+            &[b"CLIENT", b"SETNAME", b"again"],
+            &[b"RESET"],
+            &[b"CLIENT", b"GETNAME"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"$-1\r\n",
+            b"+OK\r\n",
+            b"$8\r\nworker-1\r\n",
+            list.as_bytes(),
+            b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+            b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+            b"$8\r\nworker-1\r\n",
+            b"+OK\r\n",
+            b"$-1\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR unknown subcommand 'FOO'. Try CLIENT HELP.\r\n",
+            b"+OK\r\n",
+            b"+RESET\r\n",
+            b"$-1\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = run_as(&args(inputs[i]), &db, &mut client);
+            assert_eq!(
+                String::from_utf8_lossy(&reply),
+                String::from_utf8_lossy(expects[i]),
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        client.protocol = ProtocolVersion::Resp3;
+        run_as(&args(&[b"RESET"]), &db, &mut client);
+        assert_eq!(client.protocol, ProtocolVersion::Resp2);
+
+        let reply = run_as(&args(&[b"CLIENT", b"ID"]), &db, &mut client);
+        assert_eq!(reply, format!(":{}\r\n", id).as_bytes());
+    }
+
+    #[test]
+    fn test_client_tracking() {
+        let db = database(&[("a", "1")]);
+        let mut client = ClientState::new(None);
+        let (tx, mut rx) = push_queue(client.info().clone());
+        let mut pushed = || {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::with_protocol(&mut write_buf, ProtocolVersion::Resp3);
+            while let Ok(push) = rx.try_recv() {
+                push.write(&mut writer).unwrap();
+            }
+            write_buf.get().clone()
+        };
+
+        // Invalidations have no way to reach a RESP2 client
+        let reply = run_as(&args(&[b"CLIENT", b"TRACKING", b"ON"]), &db, &mut client);
+        assert_eq!(
+            String::from_utf8_lossy(&reply),
+            "-ERR Client tracking takes RESP3, switch with HELLO 3\r\n"
+        );
+        client.set_pushes(Some(tx));
+        client.protocol = ProtocolVersion::Resp3;
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"CLIENT", b"TRACKING"],
+            &[b"CLIENT", b"TRACKING", b"MAYBE"],
+            &[b"CLIENT", b"TRACKING", b"ON", b"PREFIX", b"a"],
+            &[b"CLIENT", b"TRACKING", b"ON", b"BCAST", b"PREFIX"],
+            &[b"CLIENT", b"TRACKING", b"ON", b"NOLOOP"],
+            &[b"CLIENT", b"TRACKING", b"ON"],
+            &[b"GET", b"a"],
+            &[b"SET", b"a", b"2"],
+            &[b"SET", b"a", b"3"],
+            &[b"GET", b"a"],
+            &[b"GET", b"b"],
+            &[b"DEL", b"a", b"b"],
+            &[b"CLIENT", b"TRACKING", b"ON", b"BCAST", b"PREFIX", b"user:"],
+            &[b"SET", b"user:1", b"x"],
+            &[b"SET", b"other", b"x"],
+            &[b"FLUSHDB"],
+            &[b"CLIENT", b"TRACKING", b"OFF"],
+            &[b"SET", b"user:1", b"x"],
+        ];
+        let expects: &[(&[u8], &[u8])] = &[
+            (b"-ERR syntax error\r\n", b""),
+            (b"-ERR syntax error\r\n", b""),
+            (
+                b"-ERR PREFIX option requires BCAST mode to be enabled\r\n",
+                b"",
+            ),
+            (b"-ERR syntax error\r\n", b""),
+            (b"-ERR syntax error\r\n", b""),
+            (b"+OK\r\n", b""),
+            (b"$1\r\n1\r\n", b""),
+            (b"+OK\r\n", b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\na\r\n"),
+            (b"+OK\r\n", b""),
+            (b"$1\r\n3\r\n", b""),
+            (b"_\r\n", b""),
+            (
+                b":1\r\n",
+                b">2\r\n$10\r\ninvalidate\r\n*2\r\n$1\r\na\r\n$1\r\nb\r\n",
+            ),
+            (b"+OK\r\n", b""),
+            (
+                b"+OK\r\n",
+                b">2\r\n$10\r\ninvalidate\r\n*1\r\n$6\r\nuser:1\r\n",
+            ),
+            (b"+OK\r\n", b""),
+            (b"+OK\r\n", b">2\r\n$10\r\ninvalidate\r\n_\r\n"),
+            (b"+OK\r\n", b""),
+            (b"+OK\r\n", b""),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = run_as(&args(inputs[i]), &db, &mut client);
+            assert_eq!(
+                String::from_utf8_lossy(&reply),
+                String::from_utf8_lossy(expects[i].0),
+                "{:?}",
+                inputs[i]
+            );
+            assert_eq!(
+                String::from_utf8_lossy(&pushed()),
+                String::from_utf8_lossy(expects[i].1),
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        // Going back to RESP2 turns tracking off, as does RESET
+        run_as(&args(&[b"CLIENT", b"TRACKING", b"ON"]), &db, &mut client);
+        assert_eq!(db.tracking.len(), 1);
+        run_as(&args(&[b"HELLO", b"2"]), &db, &mut client);
+        assert_eq!(db.tracking.len(), 0);
+
+        client.protocol = ProtocolVersion::Resp3;
+        run_as(&args(&[b"CLIENT", b"TRACKING", b"ON"]), &db, &mut client);
+        run_as(&args(&[b"RESET"]), &db, &mut client);
+        assert_eq!(db.tracking.len(), 0);
+    }
+
+    #[test]
+    fn test_ping_echo() {
+        let db = database(&[]);
+        let mut client = ClientState::new(None);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"PING"],
+            &[b"ping", b"hello"],
+            &[b"PING", b""],
+            &[b"PING", b"a", b"b"],
+            &[b"ECHO", b"bin\x00\r\n\xff"],
+            &[b"ECHO"],
+            &[b"ECHO", b"a", b"b"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"+PONG\r\n",
+            b"$5\r\nhello\r\n",
+            b"$0\r\n\r\n",
+            b"-ERR wrong number of arguments for 'ping' command\r\n",
+            b"$7\r\nbin\x00\r\n\xff\r\n",
+            b"-ERR wrong number of arguments for 'echo' command\r\n",
+            b"-ERR wrong number of arguments for 'echo' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = run_as(&args(inputs[i]), &db, &mut client);
+            assert_eq!(reply, expects[i], "{:?}", inputs[i]);
+        }
+    }
+
+    #[test]
+    fn test_hello() {
+        let db = database(&[]);
+        let mut client = ClientState::new(None);
+        let hello = |proto: u8, id: u64| {
+            let id = id.to_string();
+            let version = env!("CARGO_PKG_VERSION");
+            format!(
+                "$6\r\nserver\r\n$5\r\nredis\r\n$7\r\nversion\r\n${}\r\n{}\r\n\
+                 $5\r\nproto\r\n:{}\r\n$2\r\nid\r\n:{}\r\n\
+                 $4\r\nmode\r\n$10\r\nstandalone\r\n$4\r\nrole\r\n$6\r\nmaster\r\n\
+                 $7\r\nmodules\r\n*0\r\n",
+                version.len(),
+                version,
+                proto,
+                id
+            )
+        };
+        let resp2 = format!("*14\r\n{}", hello(2, client.id()));
+        let resp3 = format!("%7\r\n{}", hello(3, client.id()));
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"HELLO"],
+            &[b"HELLO", b"4"],
+            &[b"HELLO", b"1"],
+            &[b"HELLO", b"three"],
+            &[b"HELLO", b"3", b"AUTH", b"default"],
+            &[b"HELLO", b"3", b"FOO"],
+            &[b"HELLO", b"3", b"AUTH", b"admin", b"secret"],
+            &[b"HELLO", b"3", b"SETNAME", b"two words"],
+            &[b"GET", b"missing"],
+            &[
+                b"hello", b"3", b"auth", b"default", b"secret", b"setname", b"cli",
+            ],
+            &[b"GET", b"missing"],
+            &[b"CLIENT", b"GETNAME"],
+            &[b"HELLO"],
+            &[b"HELLO", b"2"],
+            &[b"GET", b"missing"],
+            &[b"HELLO", b"3"],
+            &[b"RESET"],
+            &[b"GET", b"missing"],
+        ];
+        let expects: &[&[u8]] = &[
+            resp2.as_bytes(),
+            b"-NOPROTO unsupported protocol version\r\n",
+            b"-NOPROTO unsupported protocol version\r\n",
+            b"-ERR Protocol version is not an integer or out of range\r\n",
+            b"-ERR Syntax error in HELLO option 'AUTH'\r\n",
+            b"-ERR Syntax error in HELLO option 'FOO'\r\n",
+            b"-WRONGPASS invalid username-password pair or user is disabled.\r\n",
+            b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+            b"$-1\r\n",
+            resp3.as_bytes(),
+            b"_\r\n",
+            b"$3\r\ncli\r\n",
+            resp3.as_bytes(),
+            resp2.as_bytes(),
+            b"$-1\r\n",
+            resp3.as_bytes(),
+            b"+RESET\r\n",
+            b"$-1\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = run_as(&args(inputs[i]), &db, &mut client);
+            assert_eq!(
+                String::from_utf8_lossy(&reply),
+                String::from_utf8_lossy(expects[i]),
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_client_kill() {
+        let db = database(&[]);
+        let addrs: Vec<SocketAddr> = ["127.0.0.1:50001", "127.0.0.1:50002", "127.0.0.1:50003"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let mut clients: Vec<_> = addrs
+            .iter()
+            .map(|addr| ClientState::new(Some(*addr)))
+            .collect();
+        let _registrations: Vec<_> = clients
+            .iter()
+            .map(|client| db.clients.register(client, usize::MAX))
+            .collect();
+        let second = clients[1].id().to_string();
+
+        // Run by the first client
+        let inputs: &[&[&[u8]]] = &[
+            &[b"CLIENT", b"KILL", b"127.0.0.1:1"],
+            &[b"CLIENT", b"KILL", b"ID", b"0"],
+            &[b"CLIENT", b"KILL", b"ID", b"abc"],
+            &[b"CLIENT", b"KILL", b"ID", b"1", b"ADDR"],
+            &[b"CLIENT", b"KILL", b"FOO", b"bar"],
+            &[b"CLIENT", b"KILL", b"SKIPME", b"maybe"],
+            &[b"CLIENT", b"KILL", b"ID", second.as_bytes()],
+            &[b"CLIENT", b"KILL", b"ADDR", b"127.0.0.1:50001"],
+            &[b"CLIENT", b"KILL", b"127.0.0.1:50003"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR No such client\r\n",
+            b":0\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR syntax error\r\n",
+            b":1\r\n",
+            b":0\r\n",
+            b"+OK\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = run_as(&args(inputs[i]), &db, &mut clients[0]);
+            assert_eq!(reply, expects[i], "{:?}", inputs[i]);
+        }
+        let killed: Vec<_> = clients.iter().map(ClientState::is_killed).collect();
+        assert_eq!(killed, [false, true, true]);
+
+        let reply = run_as(
+            &args(&[
+                b"CLIENT",
+                b"KILL",
+                b"ADDR",
+                b"127.0.0.1:50001",
+                b"SKIPME",
+                b"no",
+            ]),
+            &db,
+            &mut clients[0],
+        );
+        assert_eq!(reply, b":1\r\n");
+        assert!(clients[0].is_killed());
+    }
+}
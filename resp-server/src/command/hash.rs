@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use resp::types::IntoResp;
+
+use super::{CommandSpec, Ctx, Flag, KeySpec};
+use crate::{
+    error::CommandError,
+    lazyfree::Displaced,
+    reply::Reply,
+    store::{Entry, Store, Value},
+};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "hdel",
+            arity: -3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: hdel,
+        },
+        CommandSpec {
+            name: "hget",
+            arity: 3,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: hget,
+        },
+        CommandSpec {
+            name: "hgetall",
+            arity: 2,
+            flags: &[Flag::ReadOnly],
+            keys: KeySpec::SINGLE,
+            handler: hgetall,
+        },
+        CommandSpec {
+            name: "hset",
+            arity: -4,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: hset,
+        },
+    ]
+}
+
+/// Reads the hash under `key` with `read`, under the read lock. A missing
+/// key reads as `None`, and is removed if it has only expired.
+fn read_hash<S: Store, T>(
+    ctx: &mut Ctx<'_, S>,
+    key: &[u8],
+    read: impl FnOnce(&HashMap<Bytes, Bytes>) -> T,
+) -> Result<Option<T>, CommandError> {
+    let read = {
+        let store = ctx.db.kv_store.read(key);
+        let entry = store.get(key);
+        ctx.db.stats.record_lookup(entry.is_some());
+        match entry.map(|entry| &entry.value) {
+            Some(Value::Hash(fields)) => Some(read(fields)),
+            Some(_) => return Err(CommandError::WrongType),
+            None => None,
+        }
+    };
+    if read.is_none() {
+        ctx.remove_expired(key);
+    }
+    Ok(read)
+}
+
+fn hget<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match read_hash(ctx, args[1], |fields| fields.get(args[2]).cloned()) {
+        Ok(value) => value.flatten().into(),
+        Err(err) => err.into(),
+    }
+}
+
+/// HGETALL key. Replies the fields and their values in no particular order,
+/// as a map in RESP3 and as a flat array in RESP2.
+fn hgetall<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match read_hash(ctx, args[1], HashMap::clone) {
+        Ok(fields) => Reply::Value(fields.unwrap_or_default().into_resp()),
+        Err(err) => err.into(),
+    }
+}
+
+/// HSET key field value [field value ...]. Replies the number of fields
+/// that were added rather than updated. A missing key starts out as an
+/// empty hash, keeping no expire time if it had only expired.
+fn hset<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    // The arity only says there is at least one pair
+    if args.len() % 2 != 0 {
+        return CommandError::WrongArity { command: "hset" }.into();
+    }
+    let pairs = args[2..].chunks_exact(2).map(|pair| {
+        (
+            Bytes::copy_from_slice(pair[0]),
+            Bytes::copy_from_slice(pair[1]),
+        )
+    });
+
+    let (added, expired) = {
+        let mut store = ctx.db.kv_store.write(args[1]);
+        let expired = if store.entry(args[1]).is_some_and(Entry::is_expired) {
+            store.remove(args[1])
+        } else {
+            None
+        };
+
+        let added = match store.value_mut(args[1]) {
+            Some(Value::Hash(fields)) => pairs
+                .filter(|(field, value)| fields.insert(field.clone(), value.clone()).is_none())
+                .count(),
+            Some(_) => return CommandError::WrongType.into(),
+            None => {
+                let fields: HashMap<Bytes, Bytes> = pairs.collect();
+                let added = fields.len();
+                store.insert(
+                    Bytes::copy_from_slice(args[1]),
+                    Entry::new(Value::Hash(fields)),
+                );
+                added
+            }
+        };
+        (added, expired)
+    };
+    if let Some(entry) = expired {
+        ctx.displace(Displaced::Deleted(entry.value));
+    }
+
+    added.into()
+}
+
+/// HDEL key field [field ...]. Replies the number of fields removed. A hash
+/// left without fields is removed, as in Redis.
+fn hdel<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let (removed, emptied) = {
+        let mut store = ctx.db.kv_store.write(args[1]);
+        let removed = match store.get(args[1]).map(|entry| &entry.value) {
+            Some(Value::Hash(_)) => {
+                let Some(Value::Hash(fields)) = store.value_mut(args[1]) else {
+                    unreachable!("the hash was just looked up");
+                };
+                args[2..]
+                    .iter()
+                    .filter(|field| fields.remove(**field).is_some())
+                    .count()
+            }
+            Some(_) => return CommandError::WrongType.into(),
+            None => 0,
+        };
+
+        let is_empty = matches!(
+            store.entry(args[1]).map(|entry| &entry.value),
+            Some(Value::Hash(fields)) if fields.is_empty()
+        );
+        let emptied = if is_empty {
+            store.remove(args[1])
+        } else {
+            None
+        };
+        (removed, emptied)
+    };
+    if let Some(entry) = emptied {
+        ctx.displace(Displaced::Deleted(entry.value));
+    }
+
+    removed.into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, SystemTime},
+    };
+
+    use resp::{parser::RespParser, types::RespReadable, writer::ProtocolVersion};
+
+    use crate::{
+        client::ClientState,
+        test_util::{args, database, dispatch, request, run_as},
+    };
+
+    #[test]
+    fn test_hset_hget_hdel() {
+        let db = database(&[("string", "v")]);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"HSET", b"user", b"name", b"ada", b"lang", b"en"],
+            &[b"HSET", b"user", b"lang", b"fr", b"age", b"36"],
+            &[b"HGET", b"user", b"lang"],
+            &[b"HGET", b"user", b"missing"],
+            &[b"HGET", b"missing", b"lang"],
+            &[b"HSET", b"user", b"name"],
+            &[b"HSET", b"user", b"a", b"1", b"b"],
+            &[b"HDEL", b"user", b"name", b"missing", b"name"],
+            &[b"HDEL", b"missing", b"name"],
+            &[b"EXISTS", b"user"],
+            &[b"HDEL", b"user", b"lang", b"age"],
+            &[b"EXISTS", b"user"],
+            &[b"HGET", b"string", b"field"],
+            &[b"HSET", b"string", b"field", b"v"],
+            &[b"HDEL", b"string", b"field"],
+            &[b"HGETALL", b"string"],
+            &[b"HSET", b"hash", b"field", b"v"],
+            &[b"GET", b"hash"],
+            &[b"APPEND", b"hash", b"v"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":2\r\n",
+            b":1\r\n",
+            b"$2\r\nfr\r\n",
+            b"$-1\r\n",
+            b"$-1\r\n",
+            b"-ERR wrong number of arguments for 'hset' command\r\n",
+            b"-ERR wrong number of arguments for 'hset' command\r\n",
+            b":1\r\n",
+            b":0\r\n",
+            b":1\r\n",
+            b":2\r\n",
+            b":0\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b":1\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_hgetall() {
+        let db = database(&[]);
+        dispatch(
+            &request(&[b"HSET", b"user", b"name", b"ada", b"crlf\r\n", b"\xff"]),
+            &db,
+        );
+
+        // A flat array of fields and values in RESP2
+        let reply = dispatch(&request(&[b"HGETALL", b"user"]), &db);
+        let mut parser = RespParser::new(&reply);
+        let flat = Vec::<&[u8]>::parse(&mut parser).unwrap();
+        let pairs: HashMap<&[u8], &[u8]> = flat.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        assert_eq!(flat.len(), 4);
+        assert_eq!(
+            pairs,
+            HashMap::from([(&b"name"[..], &b"ada"[..]), (b"crlf\r\n", b"\xff")])
+        );
+
+        // A map in RESP3
+        let mut client = ClientState::new(None);
+        client.protocol = ProtocolVersion::Resp3;
+        let reply = run_as(&args(&[b"HGETALL", b"user"]), &db, &mut client);
+        assert!(reply.starts_with(b"%2\r\n"), "{:?}", reply);
+
+        assert_eq!(
+            dispatch(&request(&[b"HGETALL", b"missing"]), &db),
+            b"*0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_hset_replaces_expired() {
+        let db = database(&[]);
+        dispatch(&request(&[b"HSET", b"hash", b"old", b"v"]), &db);
+        let past = SystemTime::now() - Duration::from_secs(1);
+        db.kv_store.write(b"hash").set_expiry(b"hash", Some(past));
+
+        assert_eq!(
+            dispatch(&request(&[b"HSET", b"hash", b"new", b"v"]), &db),
+            b":1\r\n"
+        );
+        assert_eq!(
+            dispatch(&request(&[b"HGET", b"hash", b"old"]), &db),
+            b"$-1\r\n"
+        );
+        assert_eq!(dispatch(&request(&[b"TTL", b"hash"]), &db), b":-1\r\n");
+    }
+}
@@ -0,0 +1,1220 @@
+use std::{
+    mem,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use resp::{
+    command::{Keyword, scan_options},
+    parser::{ParseErrorKind, read_i64, read_u64},
+    types::RespValue,
+};
+
+use super::{CommandSpec, Ctx, Flag, KeySpec};
+use crate::{
+    error::CommandError,
+    lazyfree::Displaced,
+    reply::Reply,
+    store::{Entry, Store},
+};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "del",
+            arity: -2,
+            flags: &[Flag::Write],
+            keys: KeySpec {
+                first: 1,
+                last: -1,
+                step: 1,
+            },
+            handler: del,
+        },
+        CommandSpec {
+            name: "exists",
+            arity: -2,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec {
+                first: 1,
+                last: -1,
+                step: 1,
+            },
+            handler: exists,
+        },
+        CommandSpec {
+            name: "expire",
+            arity: -3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: expire,
+        },
+        CommandSpec {
+            name: "expireat",
+            arity: -3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: expireat,
+        },
+        CommandSpec {
+            name: "flushall",
+            arity: -1,
+            flags: &[Flag::Write],
+            keys: KeySpec::NONE,
+            handler: flushdb,
+        },
+        CommandSpec {
+            name: "flushdb",
+            arity: -1,
+            flags: &[Flag::Write],
+            keys: KeySpec::NONE,
+            handler: flushdb,
+        },
+        CommandSpec {
+            name: "keys",
+            arity: 2,
+            flags: &[Flag::ReadOnly],
+            keys: KeySpec::NONE,
+            handler: keys,
+        },
+        CommandSpec {
+            name: "memory",
+            arity: -2,
+            flags: &[Flag::ReadOnly],
+            keys: KeySpec {
+                first: 2,
+                last: 2,
+                step: 1,
+            },
+            handler: memory,
+        },
+        CommandSpec {
+            name: "persist",
+            arity: 2,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: persist,
+        },
+        CommandSpec {
+            name: "pexpire",
+            arity: -3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: pexpire,
+        },
+        CommandSpec {
+            name: "pexpireat",
+            arity: -3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: pexpireat,
+        },
+        CommandSpec {
+            name: "pttl",
+            arity: 2,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: pttl,
+        },
+        CommandSpec {
+            name: "scan",
+            arity: -2,
+            flags: &[Flag::ReadOnly],
+            keys: KeySpec::NONE,
+            handler: scan,
+        },
+        CommandSpec {
+            name: "ttl",
+            arity: 2,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: ttl,
+        },
+    ]
+}
+
+/// DEL key [key ...]. Replies the number of keys that existed. The keys
+/// are removed together with the shards holding them all locked, so no
+/// client sees some of them gone and others not.
+fn del<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let deleted = ctx.db.del(&args[1..]);
+
+    // An expired key is gone already as far as the client can tell, but its
+    // value still has to be freed
+    let count = deleted.iter().filter(|entry| !entry.is_expired()).count();
+    for entry in deleted {
+        ctx.displace(Displaced::Deleted(entry.value));
+    }
+    count.into()
+}
+
+/// EXISTS key [key ...]. A key given more than once is counted as many
+/// times, as in Redis.
+fn exists<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    ctx.db.exists(&args[1..]).into()
+}
+
+fn expire<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    expire_generic(ctx, args, "expire", 1000, true)
+}
+
+fn expireat<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    expire_generic(ctx, args, "expireat", 1000, false)
+}
+
+fn pexpire<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    expire_generic(ctx, args, "pexpire", 1, true)
+}
+
+fn pexpireat<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    expire_generic(ctx, args, "pexpireat", 1, false)
+}
+
+/// PERSIST key. Replies 1 if the key had an expire time and no longer has
+/// one, and 0 if it has none or doesn't exist.
+fn persist<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let persisted = {
+        let mut store = ctx.db.kv_store.write(args[1]);
+        match store.get(args[1]) {
+            Some(entry) => entry.expires_at.is_some() && store.set_expiry(args[1], None),
+            None => false,
+        }
+    };
+    if !persisted {
+        ctx.remove_expired(args[1]);
+    }
+    persisted.into()
+}
+
+fn ttl<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    ttl_generic(ctx, args, false)
+}
+
+fn pttl<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    ttl_generic(ctx, args, true)
+}
+
+/// TTL key and PTTL key. Replies the time the key has left to live, -1 if
+/// it has no expire time and -2 if it doesn't exist. TTL rounds to the
+/// nearest second, as Redis does.
+fn ttl_generic<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]], millis: bool) -> Reply {
+    let left = {
+        let store = ctx.db.kv_store.read(args[1]);
+        store.get(args[1]).map(|entry| {
+            entry.expires_at.map(|expires_at| {
+                expires_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .as_millis()
+            })
+        })
+    };
+
+    match left {
+        None => {
+            ctx.remove_expired(args[1]);
+            Reply::Integer(-2)
+        }
+        Some(None) => Reply::Integer(-1),
+        Some(Some(left)) => {
+            let left = if millis { left } else { (left + 500) / 1000 };
+            i64::try_from(left).unwrap_or(i64::MAX).into()
+        }
+    }
+}
+
+/// The NX, XX, GT and LT options of the EXPIRE commands
+#[derive(Clone, Copy, Debug, Default)]
+struct ExpireCondition {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireCondition {
+    /// Parses the options in the order and with the errors of Redis: the
+    /// first unknown option is reported by name, then NX with any other,
+    /// then GT with LT
+    fn parse(options: &[&[u8]]) -> Result<ExpireCondition, CommandError> {
+        let mut condition = ExpireCondition::default();
+        for option in options {
+            if option.eq_ignore_ascii_case(b"NX") {
+                condition.nx = true;
+            } else if option.eq_ignore_ascii_case(b"XX") {
+                condition.xx = true;
+            } else if option.eq_ignore_ascii_case(b"GT") {
+                condition.gt = true;
+            } else if option.eq_ignore_ascii_case(b"LT") {
+                condition.lt = true;
+            } else {
+                return Err(CommandError::UnsupportedOption {
+                    option: Bytes::copy_from_slice(option),
+                });
+            }
+        }
+
+        if condition.nx && (condition.xx || condition.gt || condition.lt) {
+            return Err(CommandError::IncompatibleOptions {
+                options: "NX and XX, GT or LT",
+            });
+        }
+        if condition.gt && condition.lt {
+            return Err(CommandError::IncompatibleOptions {
+                options: "GT and LT",
+            });
+        }
+        Ok(condition)
+    }
+
+    /// Whether the expire time can go from `current` to `when`. A key
+    /// without one counts as expiring later than any time.
+    fn allows(&self, current: Option<i64>, when: i64) -> bool {
+        !(self.nx && current.is_some()
+            || self.xx && current.is_none()
+            || self.gt && current.is_none_or(|current| when <= current)
+            || self.lt && current.is_some_and(|current| when >= current))
+    }
+}
+
+/// Milliseconds since the epoch, negative before it
+fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => i64::try_from(since.as_millis()).unwrap_or(i64::MAX),
+        Err(err) => i64::try_from(err.duration().as_millis()).map_or(i64::MIN, |until| -until),
+    }
+}
+
+/// EXPIRE key time [NX | XX | GT | LT] and its variants. `scale` turns the
+/// time into milliseconds, and a relative time counts from now. Replies 1
+/// if the expire time was set, and 0 if the key doesn't exist or the
+/// condition kept it from changing. A time in the past deletes the key.
+fn expire_generic<S: Store>(
+    ctx: &mut Ctx<'_, S>,
+    args: &[&[u8]],
+    command: &'static str,
+    scale: i64,
+    relative: bool,
+) -> Reply {
+    // Redis checks the options before the time
+    let condition = match ExpireCondition::parse(&args[3..]) {
+        Ok(condition) => condition,
+        Err(err) => return err.into(),
+    };
+    let Ok(time) = read_i64(args[2]) else {
+        return CommandError::NotAnInteger.into();
+    };
+
+    let now = unix_millis(SystemTime::now());
+    let when = time.checked_mul(scale).and_then(|when| {
+        if relative {
+            when.checked_add(now)
+        } else {
+            Some(when)
+        }
+    });
+    let Some(when) = when else {
+        return CommandError::InvalidExpireTime { command }.into();
+    };
+
+    let deleted = {
+        let mut store = ctx.db.kv_store.write(args[1]);
+        let Some(entry) = store.get(args[1]) else {
+            return false.into();
+        };
+        if !condition.allows(entry.expires_at.map(unix_millis), when) {
+            return false.into();
+        }
+
+        if when <= now {
+            store.remove(args[1])
+        } else {
+            store.set_expiry(
+                args[1],
+                Some(UNIX_EPOCH + Duration::from_millis(when as u64)),
+            );
+            None
+        }
+    };
+    if let Some(entry) = deleted {
+        ctx.displace(Displaced::Deleted(entry.value));
+    }
+
+    true.into()
+}
+
+/// FLUSHDB [ASYNC | SYNC], and FLUSHALL, which is the same with database 0
+/// the only one
+fn flushdb<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let lazy = match args {
+        [_] => None,
+        [_, arg] if arg.eq_ignore_ascii_case(b"ASYNC") => Some(true),
+        [_, arg] if arg.eq_ignore_ascii_case(b"SYNC") => Some(false),
+        _ => return CommandError::Syntax.into(),
+    };
+
+    let stores = ctx.db.kv_store.write_all().replace(S::default());
+    ctx.db.tracking.invalidate_all();
+    ctx.displace(Displaced::Flushed { stores, lazy });
+
+    Reply::Ok
+}
+
+/// KEYS pattern. Replies every key matching the pattern in no particular
+/// order. The keys are collected a shard at a time under its lock and
+/// matched once it is released, as SCAN does, but the whole keyspace is
+/// still copied in one go, so SCAN is the one to use on a large store.
+fn keys<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let mut keys = Vec::new();
+    ctx.db.kv_store.scan(|key, entry| {
+        if !entry.is_expired() {
+            keys.push(key.clone());
+        }
+    });
+
+    keys.retain(|key| glob_match(args[1], key));
+    keys.into()
+}
+
+/// Only USAGE for now. It counts the key, the value as stored, so
+/// compressed if it is, and the entry itself, but not the overhead of the
+/// map holding it. SAMPLES is accepted for compatibility, a string value
+/// has nothing to sample.
+fn memory<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let key = match args {
+        [_, sub, key] if sub.eq_ignore_ascii_case(b"USAGE") => key,
+        [_, sub, key, option, _]
+            if sub.eq_ignore_ascii_case(b"USAGE") && option.eq_ignore_ascii_case(b"SAMPLES") =>
+        {
+            key
+        }
+        [_, sub, ..] if sub.eq_ignore_ascii_case(b"USAGE") => {
+            return CommandError::Syntax.into();
+        }
+        [_, sub, ..] => {
+            return CommandError::UnknownSubcommand {
+                command: "memory",
+                subcommand: Bytes::copy_from_slice(sub),
+            }
+            .into();
+        }
+        _ => unreachable!("arity is checked before dispatch"),
+    };
+
+    let store = ctx.db.kv_store.read(key);
+    let usage = store
+        .get(key)
+        .map(|entry| key.len() + entry.value.stored_len() + mem::size_of::<Entry>());
+    usage.into()
+}
+
+/// Keys returned by a SCAN call without COUNT
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+const SCAN_OPTIONS: &[Keyword] = &[Keyword::with_value("MATCH"), Keyword::with_value("COUNT")];
+
+/// SCAN cursor [MATCH pattern] [COUNT count]. The cursor is the position
+/// the next call continues from, every key before it has been returned.
+/// Keys present for the whole scan are thus returned exactly once, whatever
+/// is inserted or removed meanwhile, and the scan ends once the cursor
+/// passes the last position. The keyspace keeps the keys ordered by
+/// position, so a call costs about COUNT keys whatever the size of the
+/// store.
+fn scan<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let Ok(cursor) = read_u64(args[1]) else {
+        return CommandError::InvalidCursor.into();
+    };
+
+    let Ok(options) = scan_options(&args[2..], SCAN_OPTIONS) else {
+        return CommandError::Syntax.into();
+    };
+    let pattern = options.value("MATCH");
+    let count = match options.value("COUNT").map(read_u64) {
+        None => SCAN_DEFAULT_COUNT,
+        Some(Ok(count)) if count >= 1 => usize::try_from(count).unwrap_or(usize::MAX),
+        Some(Ok(_)) => return CommandError::Syntax.into(),
+        Some(Err(err)) if matches!(err.kind(), ParseErrorKind::NegativeInteger { .. }) => {
+            return CommandError::Syntax.into();
+        }
+        Some(Err(_)) => return CommandError::NotAnInteger.into(),
+    };
+
+    let (keys, next) = ctx.db.kv_store.scan_from(cursor, count);
+    let keys = keys
+        .into_iter()
+        .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key)))
+        .map(RespValue::from)
+        .collect();
+    Reply::Value(RespValue::from(vec![
+        next.to_string().into(),
+        RespValue::Array(keys),
+    ]))
+}
+
+/// Matches `string` against a glob-style pattern as Redis does: `*`, `?`,
+/// `[...]` sets with ranges and `^` negation, and `\` escapes
+fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => string.is_empty(),
+        Some((b'*', rest)) => {
+            // Consecutive stars match the same as one
+            let rest = &rest[rest.iter().take_while(|c| **c == b'*').count()..];
+            (0..=string.len()).any(|skip| glob_match(rest, &string[skip..]))
+        }
+        Some((b'?', rest)) => !string.is_empty() && glob_match(rest, &string[1..]),
+        Some((b'[', rest)) => {
+            let Some((c, string_rest)) = string.split_first() else {
+                return false;
+            };
+            let (matched, rest) = match_set(rest, *c);
+            matched && glob_match(rest, string_rest)
+        }
+        Some((b'\\', [escaped, rest @ ..])) => {
+            string.first() == Some(escaped) && glob_match(rest, &string[1..])
+        }
+        Some((c, rest)) => string.first() == Some(c) && glob_match(rest, &string[1..]),
+    }
+}
+
+/// Matches `c` against the set following a `[`, returning whether it
+/// matched and the pattern after the set. An unterminated set extends to
+/// the end of the pattern.
+fn match_set(mut set: &[u8], c: u8) -> (bool, &[u8]) {
+    let negate = set.first() == Some(&b'^');
+    if negate {
+        set = &set[1..];
+    }
+
+    let mut matched = false;
+    loop {
+        match set {
+            [] => break,
+            [b']', rest @ ..] => {
+                set = rest;
+                break;
+            }
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                set = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end {
+                    (*start, *end)
+                } else {
+                    (*end, *start)
+                };
+                matched |= (low..=high).contains(&c);
+                set = rest;
+            }
+            [member, rest @ ..] => {
+                matched |= *member == c;
+                set = rest;
+            }
+        }
+    }
+
+    (matched != negate, set)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread,
+    };
+
+    use bytes::Bytes;
+    use resp::{parser::RespParser, types::RespReadable};
+
+    use super::*;
+    use crate::{
+        config::Config,
+        db::Database,
+        store::KvStore,
+        test_util::{database, dispatch, request},
+    };
+
+    /// Seconds until `key` expires, rounded, if it exists and has a TTL
+    fn ttl(db: &Database, key: &[u8]) -> Option<Option<u64>> {
+        let store = db.kv_store.read(key);
+        let entry = store.get(key)?;
+        Some(entry.expires_at.map(|expires_at| {
+            let left = expires_at.duration_since(SystemTime::now()).unwrap();
+            left.as_secs_f64().round() as u64
+        }))
+    }
+
+    #[test]
+    fn test_expire_conditions() {
+        // Options, then the TTL of the key before and the new one asked for
+        let inputs: &[(&str, Option<u64>, u64)] = &[
+            ("", None, 50),
+            ("", Some(100), 50),
+            ("", Some(100), 200),
+            ("NX", None, 50),
+            ("NX", Some(100), 50),
+            ("NX", Some(100), 200),
+            ("XX", None, 50),
+            ("XX", Some(100), 50),
+            ("XX", Some(100), 200),
+            ("GT", None, 50),
+            ("GT", Some(100), 50),
+            ("GT", Some(100), 200),
+            ("LT", None, 50),
+            ("LT", Some(100), 50),
+            ("LT", Some(100), 200),
+            ("XX GT", None, 50),
+            ("XX GT", Some(100), 50),
+            ("XX GT", Some(100), 200),
+            ("lt xx", None, 50),
+            ("lt xx", Some(100), 50),
+            ("lt xx", Some(100), 200),
+            ("NX NX", None, 50),
+            ("GT GT", Some(100), 200),
+        ];
+        // The reply, then the TTL after
+        let expects: &[(&[u8], Option<u64>)] = &[
+            (b":1\r\n", Some(50)),
+            (b":1\r\n", Some(50)),
+            (b":1\r\n", Some(200)),
+            (b":1\r\n", Some(50)),
+            (b":0\r\n", Some(100)),
+            (b":0\r\n", Some(100)),
+            (b":0\r\n", None),
+            (b":1\r\n", Some(50)),
+            (b":1\r\n", Some(200)),
+            // No TTL is later than any time
+            (b":0\r\n", None),
+            (b":0\r\n", Some(100)),
+            (b":1\r\n", Some(200)),
+            (b":1\r\n", Some(50)),
+            (b":1\r\n", Some(50)),
+            (b":0\r\n", Some(100)),
+            (b":0\r\n", None),
+            (b":0\r\n", Some(100)),
+            (b":1\r\n", Some(200)),
+            (b":0\r\n", None),
+            (b":1\r\n", Some(50)),
+            (b":0\r\n", Some(100)),
+            (b":1\r\n", Some(50)),
+            (b":1\r\n", Some(200)),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (options, before, time) = inputs[i];
+            let db = database(&[("key", "v")]);
+            if let Some(before) = before {
+                let before = before.to_string();
+                dispatch(&request(&[b"EXPIRE", b"key", before.as_bytes()]), &db);
+            }
+
+            let time = time.to_string();
+            let mut args: Vec<&[u8]> = vec![b"EXPIRE", b"key", time.as_bytes()];
+            args.extend(options.split_whitespace().map(str::as_bytes));
+            assert_eq!(
+                dispatch(&request(&args), &db),
+                expects[i].0,
+                "{:?}",
+                inputs[i]
+            );
+            assert_eq!(ttl(&db, b"key"), Some(expects[i].1), "{:?}", inputs[i]);
+        }
+    }
+
+    #[test]
+    fn test_expire() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        // Rounded to the nearest second, as `ttl` is
+        let in_a_minute = ((now.as_millis() + 60_500) / 1000).to_string();
+        let in_a_minute_ms = (now.as_millis() + 60_000).to_string();
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"PEXPIRE", b"key", b"60000"],
+            &[b"EXPIREAT", b"key", in_a_minute.as_bytes()],
+            &[b"PEXPIREAT", b"key", in_a_minute_ms.as_bytes()],
+            &[b"EXPIRE", b"missing", b"60"],
+            &[b"EXPIRE", b"expired", b"60"],
+            &[b"EXPIRE", b"key", b"0"],
+            &[b"EXPIRE", b"key", b"-10", b"NX"],
+            &[b"EXPIREAT", b"key", b"1"],
+            &[b"EXPIRE", b"key", b"ten"],
+            &[b"EXPIRE", b"key", b"ten", b"KEEPTTL"],
+            &[b"EXPIRE", b"key", b"60", b"NX", b"XX"],
+            &[b"EXPIRE", b"key", b"60", b"GT", b"NX"],
+            &[b"EXPIRE", b"key", b"60", b"NX", b"LT"],
+            &[b"EXPIRE", b"key", b"60", b"GT", b"LT"],
+            &[b"EXPIRE", b"key", b"60", b"GT", b"LT", b"FOO"],
+            &[b"EXPIRE", b"key", b"9223372036854775"],
+            &[b"PEXPIRE", b"key", b"9223372036854775807"],
+            &[b"EXPIREAT", b"key", b"-9223372036854776"],
+            &[b"EXPIRE", b"key"],
+        ];
+        // The reply, then the TTL of the key after
+        let expects: &[(&[u8], Option<Option<u64>>)] = &[
+            (b":1\r\n", Some(Some(60))),
+            (b":1\r\n", Some(Some(60))),
+            (b":1\r\n", Some(Some(60))),
+            (b":0\r\n", Some(None)),
+            (b":0\r\n", Some(None)),
+            // A time in the past deletes the key
+            (b":1\r\n", None),
+            (b":1\r\n", None),
+            (b":1\r\n", None),
+            (
+                b"-ERR value is not an integer or out of range\r\n",
+                Some(None),
+            ),
+            (b"-ERR Unsupported option KEEPTTL\r\n", Some(None)),
+            (
+                b"-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR GT and LT options at the same time are not compatible\r\n",
+                Some(None),
+            ),
+            (b"-ERR Unsupported option FOO\r\n", Some(None)),
+            (
+                b"-ERR invalid expire time in 'expire' command\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR invalid expire time in 'pexpire' command\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR invalid expire time in 'expireat' command\r\n",
+                Some(None),
+            ),
+            (
+                b"-ERR wrong number of arguments for 'expire' command\r\n",
+                Some(None),
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = database(&[("key", "v"), ("expired", "v")]);
+            db.kv_store
+                .write(b"expired")
+                .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i].0,
+                "{:?}",
+                inputs[i]
+            );
+            assert_eq!(ttl(&db, b"key"), expects[i].1, "{:?}", inputs[i]);
+        }
+
+        // GT and LT are strict, the same expire time is neither
+        let db = database(&[("key", "v")]);
+        let pexpireat = |option: &[u8]| {
+            let args: &[&[u8]] = &[b"PEXPIREAT", b"key", in_a_minute_ms.as_bytes(), option];
+            dispatch(&request(args), &db)
+        };
+        assert_eq!(pexpireat(b"NX"), b":1\r\n");
+        assert_eq!(pexpireat(b"GT"), b":0\r\n");
+        assert_eq!(pexpireat(b"LT"), b":0\r\n");
+        assert_eq!(pexpireat(b"XX"), b":1\r\n");
+    }
+
+    #[test]
+    fn test_persist() {
+        let db = database(&[("key", "v"), ("plain", "v"), ("expired", "v")]);
+        dispatch(&request(&[b"EXPIRE", b"key", b"60"]), &db);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"PERSIST", b"key"],
+            &[b"TTL", b"key"],
+            &[b"PERSIST", b"key"],
+            &[b"PERSIST", b"plain"],
+            &[b"PERSIST", b"missing"],
+            &[b"PERSIST", b"expired"],
+            &[b"TTL", b"expired"],
+            &[b"PERSIST"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":1\r\n",
+            b":-1\r\n",
+            b":0\r\n",
+            b":0\r\n",
+            b":0\r\n",
+            b":0\r\n",
+            b":-2\r\n",
+            b"-ERR wrong number of arguments for 'persist' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        // Persisted keys are no longer counted as expiring
+        assert_eq!((db.kv_store.len(), db.kv_store.expires()), (2, 0));
+    }
+
+    #[test]
+    fn test_del() {
+        let db = database(&[("a", "1"), ("b", "2"), ("c", "3"), ("expired", "4")]);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"DEL", b"a", b"missing", b"b"],
+            &[b"DEL", b"a", b"b"],
+            &[b"DEL", b"c", b"c"],
+            &[b"DEL", b"expired"],
+            &[b"DEL"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":2\r\n",
+            b":0\r\n",
+            b":1\r\n",
+            b":0\r\n",
+            b"-ERR wrong number of arguments for 'del' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+        // The expired key was removed all the same
+        assert!(db.kv_store.is_empty());
+    }
+
+    #[test]
+    fn test_exists() {
+        let db = database(&[("a", "1"), ("b", "2"), ("expired", "3")]);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"EXISTS", b"a"],
+            &[b"EXISTS", b"missing"],
+            &[b"EXISTS", b"a", b"b", b"missing"],
+            &[b"EXISTS", b"a", b"a", b"a"],
+            &[b"EXISTS", b"expired"],
+            &[b"EXISTS"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":1\r\n",
+            b":0\r\n",
+            b":2\r\n",
+            b":3\r\n",
+            b":0\r\n",
+            b"-ERR wrong number of arguments for 'exists' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_ttl() {
+        let db = database(&[("persistent", "v"), ("expiring", "v"), ("expired", "v")]);
+        {
+            // A little over 100s, which TTL rounds down
+            db.kv_store.write(b"expiring").set_expiry(
+                b"expiring",
+                Some(SystemTime::now() + Duration::from_millis(100_400)),
+            );
+            db.kv_store
+                .write(b"expired")
+                .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+        }
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"TTL", b"persistent"],
+            &[b"PTTL", b"persistent"],
+            &[b"TTL", b"missing"],
+            &[b"PTTL", b"missing"],
+            &[b"TTL", b"expired"],
+            &[b"TTL", b"expiring"],
+            &[b"TTL"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":-1\r\n",
+            b":-1\r\n",
+            b":-2\r\n",
+            b":-2\r\n",
+            b":-2\r\n",
+            b":100\r\n",
+            b"-ERR wrong number of arguments for 'ttl' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        let reply = dispatch(&request(&[b"PTTL", b"expiring"]), &db);
+        let millis: i64 = str::from_utf8(&reply[1..reply.len() - 2])
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((99_000..=100_400).contains(&millis), "{}", millis);
+
+        // Looking the expired key up removed it
+        assert!(db.kv_store.read(b"expired").entry(b"expired").is_none());
+    }
+
+    #[test]
+    fn test_flushdb() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"FLUSHDB"],
+            &[b"FLUSHDB", b"ASYNC"],
+            &[b"flushdb", b"sync"],
+            &[b"FLUSHDB", b"LATER"],
+            &[b"FLUSHDB", b"ASYNC", b"SYNC"],
+            &[b"FLUSHALL"],
+            &[b"flushall", b"async"],
+            &[b"FLUSHALL", b"LATER"],
+        ];
+        let expects: &[(&[u8], bool)] = &[
+            (b"+OK\r\n", true),
+            (b"+OK\r\n", true),
+            (b"+OK\r\n", true),
+            (b"-ERR syntax error\r\n", false),
+            (b"-ERR syntax error\r\n", false),
+            (b"+OK\r\n", true),
+            (b"+OK\r\n", true),
+            (b"-ERR syntax error\r\n", false),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = database(&[("a", "1"), ("b", "2")]);
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i].0);
+            assert_eq!(db.kv_store.is_empty(), expects[i].1);
+        }
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let overhead = mem::size_of::<Entry>();
+        let json = r#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100);
+        let config = Config::from_args(["--value-compression", "yes"]).unwrap();
+        let db = Arc::new(Database::new(KvStore::new(), &config));
+        dispatch(&request(&[b"SET", b"key", b"value"]), &db);
+        dispatch(&request(&[b"SET", b"json", json.as_bytes()]), &db);
+        let compressed = db.get_str(b"json").unwrap().unwrap().stored_len();
+        assert!(compressed < json.len() / 5);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"MEMORY", b"USAGE", b"key"],
+            &[b"memory", b"usage", b"key", b"samples", b"5"],
+            &[b"MEMORY", b"USAGE", b"json"],
+            &[b"MEMORY", b"USAGE", b"missing"],
+            &[b"MEMORY", b"USAGE", b"key", b"LATER"],
+            &[b"MEMORY", b"DOCTOR"],
+        ];
+        let expects = [
+            format!(":{}\r\n", 3 + 5 + overhead),
+            format!(":{}\r\n", 3 + 5 + overhead),
+            format!(":{}\r\n", 4 + compressed + overhead),
+            "$-1\r\n".to_string(),
+            "-ERR syntax error\r\n".to_string(),
+            "-ERR unknown subcommand 'DOCTOR'. Try MEMORY HELP.\r\n".to_string(),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i].as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let inputs: &[(&[u8], &[u8])] = &[
+            (b"*", b""),
+            (b"*", b"anything"),
+            (b"user:*", b"user:1"),
+            (b"user:*", b"users"),
+            (b"h?llo", b"hello"),
+            (b"h?llo", b"hllo"),
+            (b"h*llo", b"heeeello"),
+            (b"h**o", b"ho"),
+            (b"h[ae]llo", b"hallo"),
+            (b"h[ae]llo", b"hillo"),
+            (b"h[^e]llo", b"hallo"),
+            (b"h[^e]llo", b"hello"),
+            (b"h[a-b]llo", b"hbllo"),
+            (b"h[b-a]llo", b"hbllo"),
+            (b"h[a-b]llo", b"hcllo"),
+            (b"h\\*llo", b"h*llo"),
+            (b"h\\*llo", b"hello"),
+            (b"[\\]]", b"]"),
+            (b"[abc", b"b"),
+            (b"a*b*c", b"aXbYbZc"),
+        ];
+        let expects = [
+            true, true, true, false, true, false, true, true, true, false, true, false, true, true,
+            false, true, false, true, true, true,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (pattern, string) = inputs[i];
+            assert_eq!(glob_match(pattern, string), expects[i], "{:?}", inputs[i]);
+        }
+    }
+
+    /// Scans the whole keyspace, returning every key and the number of calls
+    fn scan_all(db: &Arc<Database>, options: &[&[u8]]) -> (Vec<Bytes>, usize) {
+        let mut keys = Vec::new();
+        let mut cursor = "0".to_string();
+        for calls in 1.. {
+            let mut args: Vec<&[u8]> = vec![b"SCAN", cursor.as_bytes()];
+            args.extend_from_slice(options);
+            let reply = dispatch(&request(&args), db);
+
+            let mut parser = RespParser::new(&reply);
+            let reply = Vec::<RespValue>::try_from(RespValue::parse(&mut parser).unwrap()).unwrap();
+            let [next, batch] = <[RespValue; 2]>::try_from(reply).unwrap();
+            let batch = Vec::<RespValue>::try_from(batch).unwrap();
+            keys.extend(
+                batch
+                    .into_iter()
+                    .map(|key| Bytes::from(Vec::<u8>::try_from(key).unwrap())),
+            );
+
+            cursor = String::try_from(next).unwrap();
+            if cursor == "0" {
+                return (keys, calls);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_scan() {
+        let names: Vec<String> = (0..100).map(|i| format!("key:{}", i)).collect();
+        let entries: Vec<(&str, &str)> = names.iter().map(|name| (name.as_str(), "v")).collect();
+        let db = database(&entries);
+
+        let inputs: &[&[&[u8]]] = &[&[], &[b"COUNT", b"7"], &[b"count", b"1000"]];
+        let expects = [10, 15, 1];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (mut keys, calls) = scan_all(&db, inputs[i]);
+            keys.sort();
+            let mut expected: Vec<Bytes> =
+                names.iter().map(|name| Bytes::from(name.clone())).collect();
+            expected.sort();
+            assert_eq!(keys, expected);
+            assert_eq!(calls, expects[i]);
+        }
+
+        // MATCH filters each batch, so batches may come back empty
+        let (mut keys, _) = scan_all(&db, &[b"MATCH", b"key:1?", b"COUNT", b"3"]);
+        keys.sort();
+        let expected: Vec<Bytes> = (10..20)
+            .map(|i| Bytes::from(format!("key:{}", i)))
+            .collect();
+        assert_eq!(keys, expected);
+
+        assert_eq!(scan_all(&database(&[]), &[]), (vec![], 1));
+    }
+
+    #[test]
+    fn test_keys() {
+        let db = database(&[
+            ("hello", "v"),
+            ("hallo", "v"),
+            ("hxllo", "v"),
+            ("h*llo", "v"),
+            ("expired", "v"),
+        ]);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+        let inputs: &[&[u8]] = &[b"*", b"h[ae]llo", b"h\\*llo", b"h?llo", b"nothing*"];
+        let expects: &[&[&[u8]]] = &[
+            &[b"h*llo", b"hallo", b"hello", b"hxllo"],
+            &[b"hallo", b"hello"],
+            &[b"h*llo"],
+            &[b"h*llo", b"hallo", b"hello", b"hxllo"],
+            &[],
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let reply = dispatch(&request(&[b"KEYS", inputs[i]]), &db);
+            let mut parser = RespParser::new(&reply);
+            let mut keys = Vec::<&[u8]>::parse(&mut parser).unwrap();
+            keys.sort();
+            assert_eq!(keys, expects[i], "{:?}", inputs[i]);
+        }
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"SCAN", b"x"],
+            &[b"SCAN", b"-1"],
+            &[b"SCAN", b"0", b"COUNT", b"0"],
+            &[b"SCAN", b"0", b"COUNT", b"many"],
+            &[b"SCAN", b"0", b"COUNT", b"-5"],
+            &[b"SCAN", b"18446744073709551616"],
+            &[b"SCAN", b"0", b"MATCH"],
+            &[b"SCAN", b"0", b"TYPE", b"string"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR invalid cursor\r\n",
+            b"-ERR invalid cursor\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR invalid cursor\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR syntax error\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        let db = database(&[]);
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_scan_under_churn() {
+        let names: Vec<String> = (0..1000).map(|i| format!("stable:{}", i)).collect();
+        let entries: Vec<(&str, &str)> = names.iter().map(|name| (name.as_str(), "v")).collect();
+        let db = database(&entries);
+
+        // Keys come and go while the scans run, rehashing the map along the
+        // way
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (db, done) = (db.clone(), done.clone());
+            thread::spawn(move || {
+                let mut i = 0u64;
+                while !done.load(Ordering::Relaxed) {
+                    let key = format!("churn:{}", i % 5000);
+                    dispatch(&request(&[b"SET", key.as_bytes(), b"v"]), &db);
+                    let old = format!("churn:{}", (i + 2500) % 5000);
+                    dispatch(&request(&[b"DEL", old.as_bytes()]), &db);
+                    i += 1;
+                }
+            })
+        };
+
+        for _ in 0..5 {
+            let (keys, _) = scan_all(&db, &[b"COUNT", b"50"]);
+            let seen: HashSet<Bytes> = keys.into_iter().collect();
+            let missed: Vec<&String> = names
+                .iter()
+                .filter(|name| !seen.contains(name.as_bytes()))
+                .collect();
+            assert!(missed.is_empty(), "missed {:?}", missed);
+        }
+
+        done.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_keyspace_counts_under_churn() {
+        let db = database(&[]);
+
+        // Keys are set, given an expire time, cleared of it by being set
+        // again and deleted from several threads at once. Some of the
+        // expire times are in the past, which deletes the key, and some
+        // are only set if there is none yet.
+        let writers: Vec<_> = (0..4u64)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..5000u64 {
+                        let key = format!("key:{}", (i * 7 + t * 13) % 200);
+                        let key = key.as_bytes();
+                        let ttl = (i % 5) as i64 * 10_000 - 10_000;
+                        let ttl = ttl.to_string();
+                        let args: &[&[u8]] = match (i / 3 + t) % 4 {
+                            0 => &[b"SET", key, b"v"],
+                            1 => &[b"PEXPIRE", key, ttl.as_bytes()],
+                            2 => &[b"DEL", key],
+                            _ => &[b"PEXPIRE", key, b"50000", b"NX"],
+                        };
+                        dispatch(&request(args), &db);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        // So that the count being checked isn't zero
+        for i in 0..10 {
+            let key = format!("expiring:{}", i);
+            dispatch(&request(&[b"SET", key.as_bytes(), b"v"]), &db);
+            dispatch(&request(&[b"EXPIRE", key.as_bytes(), b"100"]), &db);
+        }
+
+        let store = &db.kv_store;
+        let (mut keys, mut expires, mut sum) = (0, 0, 0);
+        store.scan(|_, entry| {
+            keys += 1;
+            if let Some(expires_at) = entry.expires_at {
+                expires += 1;
+                sum += expires_at.duration_since(UNIX_EPOCH).unwrap().as_millis();
+            }
+        });
+        assert_eq!(store.len(), keys);
+        assert_eq!(store.expires(), expires);
+        let mut live = 0;
+        store.scan(|_, entry| live += usize::from(!entry.is_expired()));
+        assert_eq!(store.scan_from(0, usize::MAX).0.len(), live);
+
+        let now = SystemTime::now();
+        let avg_ttl = (sum / expires as u128) as u64;
+        let avg_ttl =
+            avg_ttl.saturating_sub(now.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64);
+        assert_eq!(store.avg_ttl(now), avg_ttl);
+    }
+}
@@ -0,0 +1,201 @@
+use bytes::Bytes;
+use resp::{parser::read_i64, types::RespValue};
+
+use super::{CommandSpec, Ctx, KeySpec};
+use crate::{error::CommandError, lazyfree::Displaced, reply::Reply, store::Store};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "script",
+            arity: -2,
+            flags: &[],
+            keys: KeySpec::NONE,
+            handler: script,
+        },
+        // The keys follow the number of keys, which a key spec can't
+        // describe
+        CommandSpec {
+            name: "evalsha",
+            arity: -3,
+            flags: &[],
+            keys: KeySpec::NONE,
+            handler: evalsha,
+        },
+    ]
+}
+
+/// SCRIPT LOAD, EXISTS and FLUSH. FLUSH frees the scripts like FLUSHDB frees
+/// the store, ASYNC and SYNC overriding `lazyfree-lazy-user-flush`.
+fn script<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_, sub, body] if sub.eq_ignore_ascii_case(b"LOAD") => ctx.db.scripts.load(body).into(),
+        [_, sub, ..] if sub.eq_ignore_ascii_case(b"LOAD") => CommandError::WrongArity {
+            command: "script|load",
+        }
+        .into(),
+        [_, sub, shas @ ..] if sub.eq_ignore_ascii_case(b"EXISTS") => {
+            if shas.is_empty() {
+                return CommandError::WrongArity {
+                    command: "script|exists",
+                }
+                .into();
+            }
+            let exists = shas
+                .iter()
+                .map(|sha| RespValue::from(i64::from(ctx.db.scripts.contains(sha))))
+                .collect();
+            RespValue::Array(exists).into()
+        }
+        [_, sub, modifier @ ..] if sub.eq_ignore_ascii_case(b"FLUSH") => {
+            let lazy = match modifier {
+                [] => None,
+                [arg] if arg.eq_ignore_ascii_case(b"ASYNC") => Some(true),
+                [arg] if arg.eq_ignore_ascii_case(b"SYNC") => Some(false),
+                _ => return CommandError::Syntax.into(),
+            };
+            let scripts = ctx.db.scripts.flush();
+            ctx.displace(Displaced::ScriptsFlushed { scripts, lazy });
+            Reply::Ok
+        }
+        [_, sub, ..] => CommandError::UnknownSubcommand {
+            command: "script",
+            subcommand: Bytes::copy_from_slice(sub),
+        }
+        .into(),
+        _ => unreachable!("arity is checked before dispatch"),
+    }
+}
+
+/// EVALSHA sha numkeys [key...] [arg...]. The arguments are checked and the
+/// script looked up as in Redis, so that clients relying on NOSCRIPT to
+/// fall back to EVAL see it, but there is no engine to run a cached script.
+fn evalsha<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let Ok(numkeys) = read_i64(args[2]) else {
+        return CommandError::NotAnInteger.into();
+    };
+    if numkeys > (args.len() - 3) as i64 {
+        return CommandError::InvalidNumKeys {
+            reason: "Number of keys can't be greater than number of args",
+        }
+        .into();
+    }
+    if numkeys < 0 {
+        return CommandError::InvalidNumKeys {
+            reason: "Number of keys can't be negative",
+        }
+        .into();
+    }
+
+    match ctx.db.scripts.get(args[1]) {
+        Some(_) => CommandError::NoScriptEngine.into(),
+        None => CommandError::NoScript.into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::{database, dispatch, request};
+
+    const SHA: &[u8] = b"e0e1f9fabfc9d4800c877a703b823ac0578ff8db";
+
+    #[test]
+    fn test_script() {
+        let db = database(&[]);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"SCRIPT", b"EXISTS", SHA, b"missing"],
+            &[b"SCRIPT", b"LOAD", b"return 1"],
+            &[b"script", b"load", b"return 1"],
+            &[b"SCRIPT", b"EXISTS", SHA, b"missing"],
+            &[
+                b"SCRIPT",
+                b"EXISTS",
+                b"E0E1F9FABFC9D4800C877A703B823AC0578FF8DB",
+            ],
+            // Neither of these touch the cache
+            &[b"CONFIG", b"RESETSTAT"],
+            &[b"FLUSHDB"],
+            &[b"SCRIPT", b"EXISTS", SHA],
+            &[b"SCRIPT", b"FLUSH", b"LATER"],
+            &[b"SCRIPT", b"FLUSH", b"ASYNC"],
+            &[b"SCRIPT", b"EXISTS", SHA],
+            &[b"SCRIPT", b"LOAD", b"return 1"],
+            &[b"SCRIPT", b"FLUSH"],
+            &[b"SCRIPT", b"EXISTS", SHA],
+            &[b"SCRIPT", b"LOAD"],
+            &[b"SCRIPT", b"EXISTS"],
+            &[b"SCRIPT", b"DEBUG", b"YES"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"*2\r\n:0\r\n:0\r\n",
+            b"$40\r\ne0e1f9fabfc9d4800c877a703b823ac0578ff8db\r\n",
+            b"$40\r\ne0e1f9fabfc9d4800c877a703b823ac0578ff8db\r\n",
+            b"*2\r\n:1\r\n:0\r\n",
+            b"*1\r\n:1\r\n",
+            b"+OK\r\n",
+            b"+OK\r\n",
+            b"*1\r\n:1\r\n",
+            b"-ERR syntax error\r\n",
+            b"+OK\r\n",
+            b"*1\r\n:0\r\n",
+            b"$40\r\ne0e1f9fabfc9d4800c877a703b823ac0578ff8db\r\n",
+            b"+OK\r\n",
+            b"*1\r\n:0\r\n",
+            b"-ERR wrong number of arguments for 'script|load' command\r\n",
+            b"-ERR wrong number of arguments for 'script|exists' command\r\n",
+            b"-ERR unknown subcommand 'DEBUG'. Try SCRIPT HELP.\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_evalsha() {
+        let db = database(&[]);
+        dispatch(&request(&[b"SCRIPT", b"LOAD", b"return 1"]), &db);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[
+                b"EVALSHA",
+                b"ffffffffffffffffffffffffffffffffffffffff",
+                b"0",
+            ],
+            &[b"EVALSHA", b"short", b"1", b"key"],
+            &[b"EVALSHA", SHA, b"0"],
+            &[b"EVALSHA", SHA, b"1", b"key", b"arg"],
+            &[b"EVALSHA", SHA, b"2", b"key"],
+            &[b"EVALSHA", SHA, b"-1"],
+            &[b"EVALSHA", SHA, b"one"],
+            &[b"EVALSHA", SHA],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-NOSCRIPT No matching script. Please use EVAL.\r\n",
+            b"-NOSCRIPT No matching script. Please use EVAL.\r\n",
+            b"-ERR This server caches scripts but can't run them\r\n",
+            b"-ERR This server caches scripts but can't run them\r\n",
+            b"-ERR Number of keys can't be greater than number of args\r\n",
+            b"-ERR Number of keys can't be negative\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR wrong number of arguments for 'evalsha' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+}
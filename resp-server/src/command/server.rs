@@ -0,0 +1,920 @@
+use std::{
+    str,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use log::{error, info};
+use resp::types::RespValue;
+
+use super::{Command, CommandSpec, Ctx, Flag, KeySpec};
+use crate::{
+    error::CommandError, latency::format_usec, lazyfree::Displaced, reply::Reply,
+    snapshot::Snapshot, store::Store,
+};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "info",
+            arity: -1,
+            flags: &[],
+            keys: KeySpec::NONE,
+            handler: info,
+        },
+        CommandSpec {
+            name: "shutdown",
+            arity: -1,
+            flags: &[Flag::Admin],
+            keys: KeySpec::NONE,
+            handler: shutdown,
+        },
+        CommandSpec {
+            name: "command",
+            arity: -1,
+            flags: &[],
+            keys: KeySpec::NONE,
+            handler: command,
+        },
+        CommandSpec {
+            name: "config",
+            arity: -2,
+            flags: &[Flag::Admin],
+            keys: KeySpec::NONE,
+            handler: config,
+        },
+        CommandSpec {
+            name: "debug",
+            arity: -2,
+            flags: &[Flag::Admin],
+            keys: KeySpec::NONE,
+            handler: debug,
+        },
+        CommandSpec {
+            name: "latency",
+            arity: -2,
+            flags: &[Flag::Admin],
+            keys: KeySpec::NONE,
+            handler: latency,
+        },
+        CommandSpec {
+            name: "save",
+            arity: 1,
+            flags: &[Flag::Admin],
+            keys: KeySpec::NONE,
+            handler: save,
+        },
+    ]
+}
+
+fn info<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let section = match args {
+        [_] => None,
+        [_, section] => Some(*section),
+        _ => return CommandError::Syntax.into(),
+    };
+
+    // Server information doesn't live in the store, so no lock
+    ctx.db.info(section).into()
+}
+
+/// SHUTDOWN [NOSAVE | SAVE] [FORCE]. Without either, the snapshot is saved
+/// if there are save points. It is saved before the server starts stopping,
+/// and if that fails the server keeps running, unless FORCE is given. Writes
+/// other connections make while they wind down are saved once they are done.
+fn shutdown<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let mut save = None;
+    let mut force = false;
+    for arg in &args[1..] {
+        if arg.eq_ignore_ascii_case(b"NOSAVE") && save != Some(true) {
+            save = Some(false);
+        } else if arg.eq_ignore_ascii_case(b"SAVE") && save != Some(false) {
+            save = Some(true);
+        } else if arg.eq_ignore_ascii_case(b"FORCE") {
+            force = true;
+        } else {
+            return CommandError::Syntax.into();
+        }
+    }
+
+    let persistence = &ctx.db.persistence;
+    if save.unwrap_or_else(|| !ctx.db.config().save.is_empty()) {
+        match ctx.db.save() {
+            Ok(_) => persistence.shutdown_saved.store(true, Ordering::Relaxed),
+            Err(err) if force => {
+                error!("Error trying to save the DB, exiting anyway: {}", err);
+                save = Some(false);
+            }
+            Err(err) => {
+                error!("Error trying to save the DB, can't exit: {}", err);
+                return CommandError::ShutdownFailed.into();
+            }
+        }
+    }
+    if save.is_some() {
+        *persistence.shutdown_save.lock() = save;
+    }
+
+    // Like in Redis there is no reply, the connection is closed once the
+    // server stops
+    info!("User requested shutdown...");
+    ctx.db.shutdown.cancel();
+    ctx.suppress_reply();
+    Reply::Null
+}
+
+/// Saves the snapshot, holding up this client but not the others
+fn save<S: Store>(ctx: &mut Ctx<'_, S>, _args: &[&[u8]]) -> Reply {
+    match ctx.db.save() {
+        Ok(_) => Reply::Ok,
+        Err(err) => {
+            error!("SAVE failed: {}", err);
+            CommandError::SaveFailed.into()
+        }
+    }
+}
+
+/// Describes a command as `[name, arity, [flags...], first key, last key,
+/// step]`, under the name it is registered with
+fn describe<S: Store>(command: &Command<S>) -> RespValue {
+    let keys = command.spec.keys;
+    RespValue::from(vec![
+        command.name.clone().into(),
+        command.spec.arity.into(),
+        RespValue::Array(
+            command
+                .spec
+                .flags
+                .iter()
+                .map(|flag| RespValue::Simple(flag.name().to_string()))
+                .collect(),
+        ),
+        keys.first.into(),
+        keys.last.into(),
+        keys.step.into(),
+    ])
+}
+
+/// COMMAND GETKEYS command [arg...] lists the keys a call would access
+fn getkeys<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let Some(command) = args.first().and_then(|name| ctx.db.commands.command(name)) else {
+        return CommandError::GetKeys {
+            reason: "Invalid command specified",
+        }
+        .into();
+    };
+    if !command.spec.accepts(args.len()) {
+        return CommandError::GetKeys {
+            reason: "Invalid number of arguments specified for command",
+        }
+        .into();
+    }
+
+    let keys: Vec<Vec<u8>> = command
+        .spec
+        .keys
+        .positions(args.len())
+        .map(|pos| args[pos].to_vec())
+        .collect();
+    if keys.is_empty() {
+        return CommandError::GetKeys {
+            reason: "The command has no key arguments",
+        }
+        .into();
+    }
+    keys.into()
+}
+
+fn command<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_] => ctx
+            .db
+            .commands
+            .iter()
+            .map(describe)
+            .collect::<Vec<_>>()
+            .into(),
+        [_, sub] if sub.eq_ignore_ascii_case(b"COUNT") => ctx.db.commands.len().into(),
+        // There are no docs to give, but redis-cli asks for them on connect
+        [_, sub, ..] if sub.eq_ignore_ascii_case(b"DOCS") => RespValue::Map(Vec::new()).into(),
+        [_, sub, call @ ..] if sub.eq_ignore_ascii_case(b"GETKEYS") => getkeys(ctx, call),
+        [_, sub, ..] => CommandError::UnknownSubcommand {
+            command: "command",
+            subcommand: Bytes::copy_from_slice(sub),
+        }
+        .into(),
+        [] => unreachable!("arity is checked before dispatch"),
+    }
+}
+
+fn config<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_, sub, pairs @ ..] if sub.eq_ignore_ascii_case(b"SET") => config_set(ctx, pairs),
+        [_, sub] if sub.eq_ignore_ascii_case(b"RESETSTAT") => {
+            ctx.db.reset_stats();
+            Reply::Ok
+        }
+        [_, sub, _, ..] if sub.eq_ignore_ascii_case(b"RESETSTAT") => CommandError::WrongArity {
+            command: "config|resetstat",
+        }
+        .into(),
+        [_, sub, ..] => CommandError::UnknownSubcommand {
+            command: "config",
+            subcommand: Bytes::copy_from_slice(sub),
+        }
+        .into(),
+        _ => unreachable!("arity is checked before dispatch"),
+    }
+}
+
+/// Sets one or more `parameter value` pairs. Every pair is validated before
+/// any is applied, so a failing CONFIG SET changes nothing.
+fn config_set<S: Store>(ctx: &mut Ctx<'_, S>, pairs: &[&[u8]]) -> Reply {
+    if pairs.is_empty() || pairs.len() % 2 != 0 {
+        return CommandError::WrongArity {
+            command: "config|set",
+        }
+        .into();
+    }
+
+    let mut protected_mode = None;
+    for pair in pairs.chunks(2) {
+        let (parameter, value) = (pair[0], pair[1]);
+        if !parameter.eq_ignore_ascii_case(b"protected-mode") {
+            return CommandError::UnknownConfig {
+                parameter: Bytes::copy_from_slice(parameter),
+            }
+            .into();
+        }
+
+        protected_mode = match value.to_ascii_lowercase().as_slice() {
+            b"yes" => Some(true),
+            b"no" => Some(false),
+            _ => {
+                return CommandError::InvalidConfig {
+                    parameter: Bytes::copy_from_slice(parameter),
+                    reason: "argument must be 'yes' or 'no'",
+                }
+                .into();
+            }
+        };
+    }
+
+    if let Some(enabled) = protected_mode {
+        ctx.db
+            .update_config(|config| config.protected_mode = enabled);
+    }
+    Reply::Ok
+}
+
+fn parse_float(arg: &[u8]) -> Result<f64, CommandError> {
+    str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse::<f64>().ok())
+        .filter(|value| value.is_finite())
+        .ok_or(CommandError::NotAFloat)
+}
+
+/// SLEEP, which blocks the thread running the command like it blocks the
+/// whole server in Redis, RELOAD, and CHANGE-REPL-ID as a no-op
+fn debug<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match args {
+        [_, sub] if sub.eq_ignore_ascii_case(b"RELOAD") => reload(ctx, Snapshot::to_bytes),
+        [_, sub, ..] if sub.eq_ignore_ascii_case(b"RELOAD") => CommandError::Syntax.into(),
+        // There is no replication, so no replication ID to change. Accepted
+        // so that scripts written for Redis keep working.
+        [_, sub] if sub.eq_ignore_ascii_case(b"CHANGE-REPL-ID") => Reply::Ok,
+        [_, sub, seconds] if sub.eq_ignore_ascii_case(b"SLEEP") => {
+            let duration = match parse_float(seconds).map(Duration::try_from_secs_f64) {
+                Ok(Ok(duration)) => duration,
+                _ => return CommandError::NotAFloat.into(),
+            };
+            thread::sleep(duration);
+            Reply::Ok
+        }
+        [_, sub, ..] if sub.eq_ignore_ascii_case(b"SLEEP") => CommandError::WrongArity {
+            command: "debug|sleep",
+        }
+        .into(),
+        [_, sub, ..] => CommandError::UnknownSubcommand {
+            command: "debug",
+            subcommand: Bytes::copy_from_slice(sub),
+        }
+        .into(),
+        _ => unreachable!("arity is checked before dispatch"),
+    }
+}
+
+/// DEBUG RELOAD saves the store with `save` and loads it back in its place,
+/// for tests to exercise the snapshot format. Every shard stays locked
+/// until the store is replaced. Unlike in Redis the snapshot
+/// stays in memory rather than going through the file. Keys that have
+/// expired are dropped, as when loading at startup. The store is only
+/// replaced once the snapshot has been decoded in full, so a failure leaves
+/// it untouched.
+fn reload<S: Store>(ctx: &mut Ctx<'_, S>, save: impl FnOnce(&Snapshot) -> Vec<u8>) -> Reply {
+    let config = ctx.db.config();
+    let previous = {
+        let mut shards = ctx.db.kv_store.write_all();
+        let saved = Snapshot::of_stores(&shards.stores());
+        let mut snapshot = match Snapshot::decode(&save(&saved)) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                error!("DEBUG RELOAD failed: {}", err);
+                return CommandError::ReloadFailed.into();
+            }
+        };
+
+        snapshot.compress(&config);
+        let reloaded = snapshot.into_store(SystemTime::now());
+        shards.replace(reloaded)
+    };
+    ctx.db.tracking.invalidate_all();
+    ctx.displace(Displaced::Flushed {
+        stores: previous,
+        lazy: Some(false),
+    });
+
+    Reply::Ok
+}
+
+/// Percentiles reported when LATENCY PERCENTILES isn't given any
+const DEFAULT_PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+/// LATENCY PERCENTILES command [percentile...] replies with the execution
+/// time in microseconds below which each percentile of the calls fell, nil
+/// for a command that hasn't been called since the last reset. Percentiles
+/// are given as `p99.9` or `99.9`.
+fn latency<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let (name, percentiles) = match args {
+        [_, sub, name, percentiles @ ..] if sub.eq_ignore_ascii_case(b"PERCENTILES") => {
+            (name, percentiles)
+        }
+        [_, sub] if sub.eq_ignore_ascii_case(b"PERCENTILES") => {
+            return CommandError::WrongArity {
+                command: "latency|percentiles",
+            }
+            .into();
+        }
+        [_, sub, ..] => {
+            return CommandError::UnknownSubcommand {
+                command: "latency",
+                subcommand: Bytes::copy_from_slice(sub),
+            }
+            .into();
+        }
+        _ => unreachable!("arity is checked before dispatch"),
+    };
+
+    let mut requested = Vec::with_capacity(percentiles.len());
+    for arg in percentiles {
+        let number = match arg {
+            [b'p' | b'P', number @ ..] => number,
+            _ => arg,
+        };
+        match parse_float(number) {
+            Ok(percentile) if (0.0..=100.0).contains(&percentile) => requested.push(percentile),
+            Ok(_) => return CommandError::Syntax.into(),
+            Err(err) => return err.into(),
+        }
+    }
+    if requested.is_empty() {
+        requested.extend(DEFAULT_PERCENTILES);
+    }
+
+    let command = ctx.db.commands.command(name);
+    requested
+        .iter()
+        .map(|percentile| {
+            command
+                .and_then(|command| command.latency.percentile(*percentile))
+                .map(format_usec)
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, atomic::Ordering},
+        time::UNIX_EPOCH,
+    };
+
+    use resp::{parser::RespParser, resp_array, types::RespReadable};
+
+    use super::*;
+    use crate::{
+        client::ClientState,
+        config::Config,
+        db::Database,
+        store::KvStore,
+        test_util::{database, dispatch, request},
+    };
+
+    #[test]
+    fn test_info() {
+        let db = database(&[]);
+        db.stats
+            .client_output_buffer_limit_disconnections
+            .fetch_add(2, Ordering::Relaxed);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"INFO"],
+            &[b"INFO", b"stats"],
+            &[b"INFO", b"MEMORY"],
+            &[b"INFO", b"default"],
+            &[b"INFO", b"keyspace"],
+        ];
+        // Outside of a runtime the Server section leaves out the runtime
+        let server = format!(
+            "# Server\r\nredis_version:{}\r\nprocess_id:{}\r\nuptime_in_seconds:0\r\n",
+            env!("CARGO_PKG_VERSION"),
+            std::process::id()
+        );
+        let clients = "# Clients\r\nconnected_clients:0\r\ntracking_clients:0\r\n";
+        let memory = "# Memory\r\nnumber_of_cached_scripts:0\r\nlazyfree_pending_objects:0\r\n";
+        // Every INFO before counts as a command processed
+        let stats = |processed: usize| {
+            format!(
+                "# Stats\r\ntotal_connections_received:0\r\ntotal_commands_processed:{}\r\n\
+                 expired_keys:0\r\nkeyspace_hits:0\r\nkeyspace_misses:0\r\n\
+                 client_output_buffer_limit_disconnections:2\r\n",
+                processed
+            )
+        };
+        // Empty, so without a line for db0
+        let keyspace = "# Keyspace\r\n";
+        let all = |processed| {
+            [
+                server.as_str(),
+                clients,
+                memory,
+                &stats(processed),
+                keyspace,
+            ]
+            .join("\r\n")
+        };
+        let expects = [
+            all(0),
+            stats(1),
+            memory.to_string(),
+            all(3),
+            keyspace.to_string(),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let expected = format!("${}\r\n{}\r\n", expects[i].len(), expects[i]);
+            assert_eq!(dispatch(&request(inputs[i]), &db), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_info_stats() {
+        let db = database(&[("a", "1"), ("b", "2")]);
+        dispatch(&request(&[b"HSET", b"hash", b"field", b"v"]), &db);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"GET", b"a"],
+            &[b"GET", b"missing"],
+            &[b"GET", b"b"],
+            &[b"GET", b"hash"],
+            &[b"MGET", b"a", b"missing", b"b"],
+            &[b"HGET", b"hash", b"field"],
+            &[b"HGET", b"missing", b"field"],
+            &[b"SET", b"c", b"3"],
+            &[b"GET"],
+            &[b"NOSUCHCOMMAND"],
+        ];
+        for input in inputs {
+            dispatch(&request(input), &db);
+        }
+
+        let reply = dispatch(&request(&[b"INFO", b"stats"]), &db);
+        let reply = String::from_utf8(reply).unwrap();
+        let field = |name: &str| {
+            reply
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .unwrap()
+                .to_string()
+        };
+        // HSET and the calls above, but not the wrong arity or the unknown
+        // command
+        assert_eq!(field("total_commands_processed"), "9");
+        // A key of another type still counts as a hit
+        assert_eq!(field("keyspace_hits"), "6");
+        assert_eq!(field("keyspace_misses"), "3");
+
+        dispatch(&request(&[b"CONFIG", b"RESETSTAT"]), &db);
+        let reply = dispatch(&request(&[b"INFO", b"stats"]), &db);
+        let reply = String::from_utf8(reply).unwrap();
+        assert!(
+            reply.contains("total_commands_processed:1\r\n"),
+            "{}",
+            reply
+        );
+        assert!(reply.contains("keyspace_hits:0\r\n"), "{}", reply);
+    }
+
+    #[test]
+    fn test_info_keyspace() {
+        let db = database(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        dispatch(&request(&[b"PEXPIRE", b"a", b"100000"]), &db);
+        dispatch(&request(&[b"PEXPIRE", b"b", b"300000"]), &db);
+
+        let reply = dispatch(&request(&[b"INFO", b"keyspace"]), &db);
+        let reply = String::from_utf8(reply).unwrap();
+        let line = reply.lines().find(|line| line.starts_with("db0:")).unwrap();
+        let (counts, avg_ttl) = line.rsplit_once(",avg_ttl=").unwrap();
+        assert_eq!(counts, "db0:keys=3,expires=2");
+        let avg_ttl: u64 = avg_ttl.parse().unwrap();
+        assert!((190_000..=200_000).contains(&avg_ttl), "{}", avg_ttl);
+
+        dispatch(&request(&[b"FLUSHDB"]), &db);
+        let reply = dispatch(&request(&[b"INFO", b"keyspace"]), &db);
+        assert_eq!(reply, b"$12\r\n# Keyspace\r\n\r\n");
+    }
+
+    #[test]
+    fn test_shutdown_options() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"SHUTDOWN"],
+            &[b"shutdown", b"nosave"],
+            &[b"SHUTDOWN", b"NOSAVE", b"FORCE"],
+            &[b"SHUTDOWN", b"SAVE"],
+            &[b"SHUTDOWN", b"SAVE", b"NOSAVE"],
+            &[b"SHUTDOWN", b"LATER"],
+        ];
+        let expects: &[(&[u8], bool)] = &[
+            (b"", true),
+            (b"", true),
+            (b"", true),
+            (b"", true),
+            (b"-ERR syntax error\r\n", false),
+            (b"-ERR syntax error\r\n", false),
+        ];
+
+        let dir =
+            std::env::temp_dir().join(format!("resp-server-test-{}-shutdown", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config::from_args(["--dir", dir.to_str().unwrap()]).unwrap();
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = Arc::new(Database::new(KvStore::default(), &config));
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i].0);
+            assert_eq!(db.shutdown.is_cancelled(), expects[i].1);
+        }
+
+        // SHUTDOWN SAVE saved before stopping, so the server only saves
+        // again if something is written meanwhile
+        assert!(config.snapshot_path().exists());
+        let db = Arc::new(Database::new(KvStore::default(), &config));
+        dispatch(&request(&[b"SHUTDOWN", b"SAVE"]), &db);
+        assert!(!db.persistence.needs_final_save(&config));
+        dispatch(&request(&[b"SET", b"late", b"v"]), &db);
+        assert!(db.persistence.needs_final_save(&config));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_save_failure() {
+        // A file where the directory should be, which nothing can be saved
+        // into
+        let dir =
+            std::env::temp_dir().join(format!("resp-server-test-{}-not-a-dir", std::process::id()));
+        std::fs::write(&dir, b"").unwrap();
+        let config =
+            Config::from_args(["--dir", dir.to_str().unwrap(), "--save", "3600", "1"]).unwrap();
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"SHUTDOWN"],
+            &[b"SHUTDOWN", b"SAVE"],
+            &[b"SHUTDOWN", b"FORCE"],
+            &[b"SHUTDOWN", b"SAVE", b"FORCE"],
+            &[b"SHUTDOWN", b"NOSAVE"],
+        ];
+        let expects: &[(&[u8], bool)] = &[
+            (b"-ERR Errors trying to SHUTDOWN. Check logs.\r\n", false),
+            (b"-ERR Errors trying to SHUTDOWN. Check logs.\r\n", false),
+            (b"", true),
+            (b"", true),
+            (b"", true),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = Arc::new(Database::new(KvStore::default(), &config));
+            dispatch(&request(&[b"SET", b"key", b"v"]), &db);
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i].0,
+                "{:?}",
+                inputs[i]
+            );
+            assert_eq!(db.shutdown.is_cancelled(), expects[i].1);
+            // Once it has failed, exiting doesn't try again
+            if db.shutdown.is_cancelled() {
+                assert!(!db.persistence.needs_final_save(&config));
+            }
+        }
+
+        std::fs::remove_file(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_command() {
+        let db = database(&[]);
+
+        let count = format!(":{}\r\n", db.commands.len());
+        assert_eq!(
+            dispatch(&request(&[b"command", b"count"]), &db),
+            count.as_bytes()
+        );
+        assert_eq!(dispatch(&request(&[b"COMMAND", b"DOCS"]), &db), b"*0\r\n");
+        assert_eq!(
+            dispatch(&request(&[b"COMMAND", b"DOCS", b"get"]), &db),
+            b"*0\r\n"
+        );
+        assert_eq!(
+            dispatch(&request(&[b"COMMAND", b"FOO"]), &db),
+            b"-ERR unknown subcommand 'FOO'. Try COMMAND HELP.\r\n"
+        );
+
+        let reply = dispatch(&request(&[b"COMMAND"]), &db);
+        let mut parser = RespParser::new(&reply);
+        let RespValue::Array(mut commands) = RespValue::parse(&mut parser).unwrap() else {
+            panic!("COMMAND did not reply with an array");
+        };
+        assert_eq!(commands.len(), db.commands.len());
+
+        commands.retain(|command| matches!(command, RespValue::Array(info) if info[0] == "get"));
+        assert_eq!(
+            commands,
+            [resp_array![
+                "get",
+                2,
+                RespValue::Array(vec![
+                    RespValue::Simple("readonly".to_string()),
+                    RespValue::Simple("fast".to_string()),
+                ]),
+                1,
+                1,
+                1,
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_command_getkeys() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"COMMAND", b"GETKEYS", b"GET", b"key"],
+            &[b"COMMAND", b"GETKEYS", b"set", b"key", b"value"],
+            &[b"COMMAND", b"GETKEYS", b"MEMORY", b"USAGE", b"key"],
+            &[b"COMMAND", b"GETKEYS", b"GET"],
+            &[b"COMMAND", b"GETKEYS", b"FLUSHDB"],
+            &[b"COMMAND", b"GETKEYS", b"FOO", b"key"],
+            &[b"COMMAND", b"GETKEYS"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"*1\r\n$3\r\nkey\r\n",
+            b"*1\r\n$3\r\nkey\r\n",
+            b"*1\r\n$3\r\nkey\r\n",
+            b"-ERR Invalid number of arguments specified for command\r\n",
+            b"-ERR The command has no key arguments\r\n",
+            b"-ERR Invalid command specified\r\n",
+            b"-ERR Invalid command specified\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        let db = database(&[]);
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_config_set() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"CONFIG", b"SET", b"protected-mode", b"no"],
+            &[b"config", b"set", b"PROTECTED-MODE", b"YES"],
+            &[
+                b"CONFIG",
+                b"SET",
+                b"protected-mode",
+                b"no",
+                b"protected-mode",
+                b"yes",
+            ],
+            &[b"CONFIG", b"SET", b"protected-mode", b"maybe"],
+            &[
+                b"CONFIG",
+                b"SET",
+                b"protected-mode",
+                b"no",
+                b"maxmemory",
+                b"1mb",
+            ],
+            &[b"CONFIG", b"SET", b"protected-mode"],
+            &[b"CONFIG", b"SET"],
+            &[b"CONFIG", b"REWRITE"],
+            &[b"CONFIG"],
+        ];
+        let expects: &[(&[u8], bool)] = &[
+            (b"+OK\r\n", false),
+            (b"+OK\r\n", true),
+            (b"+OK\r\n", true),
+            (
+                b"-ERR CONFIG SET failed (possibly related to argument 'protected-mode') - \
+                  argument must be 'yes' or 'no'\r\n",
+                true,
+            ),
+            (
+                b"-ERR Unknown option or number of arguments for CONFIG SET - 'maxmemory'\r\n",
+                true,
+            ),
+            (
+                b"-ERR wrong number of arguments for 'config|set' command\r\n",
+                true,
+            ),
+            (
+                b"-ERR wrong number of arguments for 'config|set' command\r\n",
+                true,
+            ),
+            (
+                b"-ERR unknown subcommand 'REWRITE'. Try CONFIG HELP.\r\n",
+                true,
+            ),
+            (
+                b"-ERR wrong number of arguments for 'config' command\r\n",
+                true,
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = database(&[]);
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i].0);
+            assert_eq!(db.config().protected_mode, expects[i].1);
+        }
+    }
+
+    /// Parses a LATENCY PERCENTILES reply into microseconds
+    fn percentiles(db: &Arc<Database>, args: &[&[u8]]) -> Vec<Option<f64>> {
+        let reply = dispatch(&request(args), db);
+        let mut parser = RespParser::new(&reply);
+        let values = Vec::<RespValue>::try_from(RespValue::parse(&mut parser).unwrap()).unwrap();
+        values
+            .into_iter()
+            .map(|value| Option::<f64>::try_from(value).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let db = database(&[]);
+        let p99: &[&[u8]] = &[b"LATENCY", b"PERCENTILES", b"debug", b"p99"];
+        assert_eq!(percentiles(&db, p99), [None]);
+
+        assert_eq!(
+            dispatch(&request(&[b"DEBUG", b"SLEEP", b"0"]), &db),
+            b"+OK\r\n"
+        );
+        let fast = percentiles(&db, p99)[0].unwrap();
+
+        assert_eq!(
+            dispatch(&request(&[b"DEBUG", b"SLEEP", b"0.05"]), &db),
+            b"+OK\r\n"
+        );
+        let slow = percentiles(&db, p99)[0].unwrap();
+        assert!(slow >= 50_000.0 && slow > fast, "{} -> {}", fast, slow);
+
+        // Percentiles default to p50, p99 and p99.9
+        let all = percentiles(&db, &[b"LATENCY", b"PERCENTILES", b"DEBUG"]);
+        assert_eq!(all.len(), 3);
+        assert!(all.iter().all(Option::is_some));
+
+        let info = dispatch(&request(&[b"INFO", b"latencystats"]), &db);
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("# Latencystats\r\n"), "{}", info);
+        assert!(
+            info.contains("latency_percentiles_usec_debug:p50="),
+            "{}",
+            info
+        );
+
+        assert_eq!(
+            dispatch(&request(&[b"CONFIG", b"RESETSTAT"]), &db),
+            b"+OK\r\n"
+        );
+        assert_eq!(percentiles(&db, p99), [None]);
+    }
+
+    #[test]
+    fn test_debug_reload() {
+        let json = r#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100);
+        let expires_at = UNIX_EPOCH + Duration::from_millis(4_000_000_000_000);
+
+        let inputs: [&[&str]; 2] = [&[], &["--value-compression", "yes"]];
+        let expects = [false, true];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            let db = Arc::new(Database::new(KvStore::new(), &config));
+            dispatch(&request(&[b"SET", b"json", json.as_bytes()]), &db);
+            dispatch(&request(&[b"SET", b"expiring", b"v"]), &db);
+            dispatch(&request(&[b"SET", b"expired", b"v"]), &db);
+            db.kv_store
+                .write(b"expiring")
+                .set_expiry(b"expiring", Some(expires_at));
+            db.kv_store
+                .write(b"expired")
+                .set_expiry(b"expired", Some(UNIX_EPOCH));
+
+            assert_eq!(dispatch(&request(&[b"DEBUG", b"RELOAD"]), &db), b"+OK\r\n");
+
+            let value = db.get_str(b"json").unwrap().unwrap();
+            assert_eq!(value.is_compressed(), expects[i]);
+            assert_eq!(value.data(), json);
+            assert_eq!(db.kv_store.len(), 2);
+            assert_eq!(
+                db.kv_store
+                    .read(b"expiring")
+                    .entry(b"expiring")
+                    .unwrap()
+                    .expires_at,
+                Some(expires_at)
+            );
+        }
+    }
+
+    #[test]
+    fn test_reload_failure() {
+        let db = database(&[("a", "1"), ("b", "2")]);
+        let mut client = ClientState::new(None);
+        let mut ctx = Ctx::new(&db, &mut client);
+
+        // A snapshot cut short doesn't decode
+        let reply = reload(&mut ctx, |snapshot| {
+            let data = snapshot.to_bytes();
+            data[..data.len() / 2].to_vec()
+        });
+        let Reply::Value(RespValue::Error(err)) = reply else {
+            panic!("{:?}", reply);
+        };
+        assert!(
+            err.to_string()
+                .starts_with("ERR Error trying to load the RDB dump"),
+            "{}",
+            err
+        );
+        assert!(ctx.displaced.is_empty());
+        assert_eq!(db.kv_store.len(), 2);
+        assert_eq!(dispatch(&request(&[b"GET", b"a"]), &db), b"$1\r\n1\r\n");
+
+        assert_eq!(
+            dispatch(&request(&[b"DEBUG", b"CHANGE-REPL-ID"]), &db),
+            b"+OK\r\n"
+        );
+    }
+
+    #[test]
+    fn test_latency_errors() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"LATENCY", b"PERCENTILES", b"get", b"101"],
+            &[b"LATENCY", b"PERCENTILES", b"get", b"pfoo"],
+            &[b"LATENCY", b"PERCENTILES"],
+            &[b"LATENCY", b"HISTORY"],
+            &[b"DEBUG", b"SLEEP", b"-1"],
+            &[b"DEBUG", b"SLEEP", b"inf"],
+            &[b"DEBUG", b"SLEEP"],
+            &[b"DEBUG", b"SEGFAULT"],
+            &[b"DEBUG", b"RELOAD", b"NOSAVE"],
+            &[b"CONFIG", b"RESETSTAT", b"now"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR syntax error\r\n",
+            b"-ERR value is not a valid float\r\n",
+            b"-ERR wrong number of arguments for 'latency|percentiles' command\r\n",
+            b"-ERR unknown subcommand 'HISTORY'. Try LATENCY HELP.\r\n",
+            b"-ERR value is not a valid float\r\n",
+            b"-ERR value is not a valid float\r\n",
+            b"-ERR wrong number of arguments for 'debug|sleep' command\r\n",
+            b"-ERR unknown subcommand 'SEGFAULT'. Try DEBUG HELP.\r\n",
+            b"-ERR syntax error\r\n",
+            b"-ERR wrong number of arguments for 'config|resetstat' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        let db = database(&[]);
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+    }
+}
@@ -0,0 +1,716 @@
+use std::time::{Duration, SystemTime};
+
+use bytes::{Bytes, BytesMut};
+use resp::{
+    command::{Keyword, scan_options},
+    parser::read_i64,
+};
+
+use super::{CommandSpec, Ctx, Flag, KeySpec};
+use crate::{
+    config::Config,
+    error::CommandError,
+    lazyfree::Displaced,
+    reply::Reply,
+    store::{Entry, Store, Str, Value},
+};
+
+pub(super) fn commands<S: Store>() -> Vec<CommandSpec<S>> {
+    vec![
+        CommandSpec {
+            name: "append",
+            arity: 3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: append,
+        },
+        CommandSpec {
+            name: "decr",
+            arity: 2,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: decr,
+        },
+        CommandSpec {
+            name: "decrby",
+            arity: 3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: decrby,
+        },
+        CommandSpec {
+            name: "get",
+            arity: 2,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: get,
+        },
+        CommandSpec {
+            name: "incr",
+            arity: 2,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: incr,
+        },
+        CommandSpec {
+            name: "incrby",
+            arity: 3,
+            flags: &[Flag::Write, Flag::Fast],
+            keys: KeySpec::SINGLE,
+            handler: incrby,
+        },
+        CommandSpec {
+            name: "mget",
+            arity: -2,
+            flags: &[Flag::ReadOnly, Flag::Fast],
+            keys: KeySpec {
+                first: 1,
+                last: -1,
+                step: 1,
+            },
+            handler: mget,
+        },
+        CommandSpec {
+            name: "mset",
+            arity: -3,
+            flags: &[Flag::Write],
+            keys: KeySpec {
+                first: 1,
+                last: -1,
+                step: 2,
+            },
+            handler: mset,
+        },
+        CommandSpec {
+            name: "set",
+            arity: -3,
+            flags: &[Flag::Write],
+            keys: KeySpec::SINGLE,
+            handler: set,
+        },
+    ]
+}
+
+fn get<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    // Cloning `Bytes` only bumps a reference count, the value itself is
+    // decompressed and copied into the output buffer after the lock is
+    // released
+    match ctx.db.get_str(args[1]) {
+        Ok(Some(value)) => value.data().into(),
+        Ok(None) => {
+            ctx.remove_expired(args[1]);
+            Reply::Null
+        }
+        Err(err) => err.into(),
+    }
+}
+
+/// EX and PX exclude each other, but either may be repeated with the last
+/// one counting, as in Redis
+const SET_OPTIONS: &[Keyword] = &[
+    Keyword::with_value("EX").in_group(0),
+    Keyword::with_value("PX").in_group(0),
+];
+
+/// SET key value [EX seconds | PX milliseconds]. Without either, the key
+/// loses whatever expire time it had.
+fn set<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let Ok(options) = scan_options(&args[3..], SET_OPTIONS) else {
+        return CommandError::Syntax.into();
+    };
+    let expires_at = match (options.value("EX"), options.value("PX")) {
+        (Some(seconds), _) => Some(expire_time(seconds, 1000)),
+        (_, Some(millis)) => Some(expire_time(millis, 1)),
+        _ => None,
+    };
+    let expires_at = match expires_at.transpose() {
+        Ok(expires_at) => expires_at,
+        Err(err) => return err.into(),
+    };
+
+    // Compressing happens before taking the lock
+    let mut entry = string_entry(&ctx.db.config(), Bytes::copy_from_slice(args[2]));
+    entry.expires_at = expires_at;
+
+    let overwritten = ctx.db.set(Bytes::copy_from_slice(args[1]), entry);
+    if let Some(entry) = overwritten {
+        ctx.displace(Displaced::Overwritten(entry.value));
+    }
+
+    Reply::Ok
+}
+
+/// MGET key [key ...]. Replies the strings in the order of the keys, with
+/// null for a missing key or a value of another type. All of them are read
+/// with the shards holding them locked at once, so that an MSET is seen
+/// either in full or not at all, and decompressed once the locks are
+/// released, as for GET.
+fn mget<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let values: Vec<Option<Str>> = {
+        let shards = ctx.db.kv_store.read_keys(args[1..].iter().copied());
+        args[1..]
+            .iter()
+            .map(|key| {
+                let entry = shards.shard(key).get(key);
+                ctx.db.stats.record_lookup(entry.is_some());
+                let entry = entry?;
+                entry.last_access.touch();
+                match &entry.value {
+                    Value::Str(value) => Some(value.clone()),
+                    _ => None,
+                }
+            })
+            .collect()
+    };
+
+    values
+        .into_iter()
+        .map(|value| value.map(|value| value.data()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// MSET key value [key value ...]. All the keys are set with the shards
+/// holding them locked at once, so no reader sees only some of them, and
+/// they lose whatever expire time they had.
+fn mset<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    // The arity only says there is at least one pair
+    if args.len() % 2 == 0 {
+        return CommandError::WrongArity { command: "mset" }.into();
+    }
+
+    let config = ctx.db.config();
+    let entries: Vec<(Bytes, Entry)> = args[1..]
+        .chunks_exact(2)
+        .map(|pair| {
+            (
+                Bytes::copy_from_slice(pair[0]),
+                string_entry(&config, Bytes::copy_from_slice(pair[1])),
+            )
+        })
+        .collect();
+
+    let overwritten: Vec<Entry> = {
+        let mut shards = ctx
+            .db
+            .kv_store
+            .write_keys(args[1..].iter().step_by(2).copied());
+        entries
+            .into_iter()
+            .filter_map(|(key, entry)| shards.shard_mut(&key).insert(key, entry))
+            .collect()
+    };
+    for entry in overwritten {
+        ctx.displace(Displaced::Overwritten(entry.value));
+    }
+
+    Reply::Ok
+}
+
+/// APPEND key value. Replies the length of the string once appended to, a
+/// missing key counting as empty. The key keeps its expire time.
+fn append<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    let config = ctx.db.config();
+    let (len, overwritten) = {
+        let mut store = ctx.db.kv_store.write(args[1]);
+        let (current, expires_at) = match current_str(&**store, args[1]) {
+            Ok(current) => current,
+            Err(err) => return err.into(),
+        };
+        let current = current.unwrap_or_default();
+        let len = current.len() + args[2].len();
+        if len > config.proto_max_bulk_len {
+            return CommandError::StringTooLong.into();
+        }
+
+        // The new value depends on the old one, so unlike for SET it is
+        // compressed under the lock
+        let mut value = BytesMut::with_capacity(len);
+        value.extend_from_slice(&current);
+        value.extend_from_slice(args[2]);
+        let mut entry = string_entry(&config, value.freeze());
+        entry.expires_at = expires_at;
+        (len, store.insert(Bytes::copy_from_slice(args[1]), entry))
+    };
+    if let Some(entry) = overwritten {
+        ctx.displace(Displaced::Overwritten(entry.value));
+    }
+
+    len.into()
+}
+
+fn incr<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    incr_by(ctx, args[1], 1)
+}
+
+fn decr<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    incr_by(ctx, args[1], -1)
+}
+
+fn incrby<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match read_i64(args[2]) {
+        Ok(delta) => incr_by(ctx, args[1], delta),
+        Err(_) => CommandError::NotAnInteger.into(),
+    }
+}
+
+fn decrby<S: Store>(ctx: &mut Ctx<'_, S>, args: &[&[u8]]) -> Reply {
+    match read_i64(args[2]) {
+        // Its negation doesn't fit
+        Ok(i64::MIN) => CommandError::Overflow {
+            reason: "decrement would overflow",
+        }
+        .into(),
+        Ok(delta) => incr_by(ctx, args[1], -delta),
+        Err(_) => CommandError::NotAnInteger.into(),
+    }
+}
+
+/// Adds `delta` to the integer stored as a string under `key`, a missing key
+/// counting as 0, and replies the result. The key keeps its expire time.
+fn incr_by<S: Store>(ctx: &mut Ctx<'_, S>, key: &[u8], delta: i64) -> Reply {
+    let (value, overwritten) = {
+        let mut store = ctx.db.kv_store.write(key);
+        let (current, expires_at) = match current_str(&**store, key) {
+            Ok(current) => current,
+            Err(err) => return err.into(),
+        };
+        let current = match current.map(|current| read_i64(&current)) {
+            None => 0,
+            Some(Ok(current)) => current,
+            Some(Err(_)) => return CommandError::NotAnInteger.into(),
+        };
+        let Some(value) = current.checked_add(delta) else {
+            return CommandError::Overflow {
+                reason: "increment or decrement would overflow",
+            }
+            .into();
+        };
+
+        let mut entry = Entry::new(Bytes::from(value.to_string()));
+        entry.expires_at = expires_at;
+        (value, store.insert(Bytes::copy_from_slice(key), entry))
+    };
+    if let Some(entry) = overwritten {
+        ctx.displace(Displaced::Overwritten(entry.value));
+    }
+
+    value.into()
+}
+
+/// The contents and expire time of the string under `key`, for commands
+/// that rewrite it, or WRONGTYPE for a value of another type. A missing key
+/// has neither.
+fn current_str<S: Store>(
+    store: &S,
+    key: &[u8],
+) -> Result<(Option<Bytes>, Option<SystemTime>), CommandError> {
+    let Some(entry) = store.get(key) else {
+        return Ok((None, None));
+    };
+    match &entry.value {
+        Value::Str(value) => Ok((Some(value.data()), entry.expires_at)),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+/// An entry for a string, compressed if the configuration says so
+fn string_entry(config: &Config, value: Bytes) -> Entry {
+    if config.value_compression {
+        Entry::compressed(value, config.value_compression_threshold)
+    } else {
+        Entry::new(value)
+    }
+}
+
+/// When a key set to live for `time`, in units of `scale` milliseconds,
+/// expires. The time has to be positive.
+fn expire_time(time: &[u8], scale: u64) -> Result<SystemTime, CommandError> {
+    let Ok(time) = read_i64(time) else {
+        return Err(CommandError::NotAnInteger);
+    };
+    u64::try_from(time)
+        .ok()
+        .filter(|time| *time > 0)
+        .and_then(|time| time.checked_mul(scale))
+        .and_then(|millis| SystemTime::now().checked_add(Duration::from_millis(millis)))
+        .ok_or(CommandError::InvalidExpireTime { command: "set" })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use bytes::Bytes;
+
+    use crate::{
+        config::Config,
+        db::Database,
+        store::{Entry, KvStore, Store, Value},
+        test_util::{database, dispatch, request},
+    };
+
+    #[test]
+    fn test_wrong_type() {
+        let db = database(&[]);
+        let list = Value::List(VecDeque::from([Bytes::from("a")]));
+        db.kv_store
+            .write(b"list")
+            .insert(Bytes::from("list"), Entry::new(list));
+
+        // Only GET cares about the type, SET replaces whatever is there
+        let inputs: &[&[&[u8]]] = &[
+            &[b"GET", b"list"],
+            &[b"EXPIRE", b"list", b"60"],
+            &[b"SET", b"list", b"v"],
+            &[b"GET", b"list"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b":1\r\n",
+            b"+OK\r\n",
+            b"$1\r\nv\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(inputs[i]), &db), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_set_expiry() {
+        // Each SET is followed by a TTL of the key
+        let inputs: &[&[&[u8]]] = &[
+            &[b"SET", b"k", b"v"],
+            &[b"SET", b"k", b"v", b"EX", b"100"],
+            &[b"SET", b"k", b"v", b"px", b"100000"],
+            &[b"SET", b"k", b"v", b"EX", b"10", b"EX", b"100"],
+            &[b"SET", b"k", b"v", b"EX", b"0"],
+            &[b"SET", b"k", b"v", b"PX", b"-5"],
+            &[b"SET", b"k", b"v", b"EX", b"9223372036854775807"],
+            &[b"SET", b"k", b"v", b"EX", b"ten"],
+            &[b"SET", b"k", b"v", b"EX", b"10", b"PX", b"100"],
+            &[b"SET", b"k", b"v", b"EX"],
+            &[b"SET", b"k", b"v", b"KEEPTTL"],
+        ];
+        let expects: &[(&[u8], &[u8])] = &[
+            (b"+OK\r\n", b":-1\r\n"),
+            (b"+OK\r\n", b":100\r\n"),
+            (b"+OK\r\n", b":100\r\n"),
+            (b"+OK\r\n", b":100\r\n"),
+            (b"-ERR invalid expire time in 'set' command\r\n", b":-2\r\n"),
+            (b"-ERR invalid expire time in 'set' command\r\n", b":-2\r\n"),
+            (b"-ERR invalid expire time in 'set' command\r\n", b":-2\r\n"),
+            (
+                b"-ERR value is not an integer or out of range\r\n",
+                b":-2\r\n",
+            ),
+            (b"-ERR syntax error\r\n", b":-2\r\n"),
+            (b"-ERR syntax error\r\n", b":-2\r\n"),
+            (b"-ERR syntax error\r\n", b":-2\r\n"),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let db = database(&[]);
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i].0,
+                "{:?}",
+                inputs[i]
+            );
+            assert_eq!(
+                dispatch(&request(&[b"TTL", b"k"]), &db),
+                expects[i].1,
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        // A plain SET clears the expire time
+        let db = database(&[]);
+        dispatch(&request(&[b"SET", b"k", b"v", b"EX", b"100"]), &db);
+        dispatch(&request(&[b"SET", b"k", b"w"]), &db);
+        assert_eq!(dispatch(&request(&[b"TTL", b"k"]), &db), b":-1\r\n");
+    }
+
+    #[test]
+    fn test_incr() {
+        let db = database(&[("text", "abc"), ("max", "9223372036854775807")]);
+        db.kv_store.write(b"list").insert(
+            Bytes::from("list"),
+            Entry::new(Value::List(VecDeque::new())),
+        );
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"INCR", b"counter"],
+            &[b"INCR", b"counter"],
+            &[b"INCRBY", b"counter", b"10"],
+            &[b"DECR", b"counter"],
+            &[b"DECRBY", b"counter", b"-5"],
+            &[b"DECRBY", b"counter", b"20"],
+            &[b"GET", b"counter"],
+            &[b"DECR", b"new"],
+            &[b"INCR", b"text"],
+            &[b"INCRBY", b"counter", b"ten"],
+            &[b"INCRBY", b"counter", b"99999999999999999999"],
+            &[b"INCR", b"max"],
+            &[b"INCRBY", b"counter", b"9223372036854775807"],
+            &[b"DECRBY", b"counter", b"-9223372036854775808"],
+            &[b"GET", b"counter"],
+            &[b"INCR", b"list"],
+            &[b"INCR"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":1\r\n",
+            b":2\r\n",
+            b":12\r\n",
+            b":11\r\n",
+            b":16\r\n",
+            b":-4\r\n",
+            b"$2\r\n-4\r\n",
+            b":-1\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR value is not an integer or out of range\r\n",
+            b"-ERR increment or decrement would overflow\r\n",
+            b":9223372036854775803\r\n",
+            b"-ERR decrement would overflow\r\n",
+            b"$19\r\n9223372036854775803\r\n",
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            b"-ERR wrong number of arguments for 'incr' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_append() {
+        let db = database(&[("key", "abc")]);
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"APPEND", b"key", b"def"],
+            &[b"GET", b"key"],
+            &[b"APPEND", b"new", b""],
+            &[b"EXISTS", b"new"],
+            &[b"APPEND", b"new", b"\x00\r\n"],
+            &[b"GET", b"new"],
+        ];
+        let expects: &[&[u8]] = &[
+            b":6\r\n",
+            b"$6\r\nabcdef\r\n",
+            b":0\r\n",
+            b":1\r\n",
+            b":3\r\n",
+            b"$3\r\n\x00\r\n\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+
+        // Limited like the strings a client can send
+        let config = Config::from_args(["--proto-max-bulk-len", "1mb"]).unwrap();
+        let db = Arc::new(Database::new(KvStore::new(), &config));
+        let half = vec![b'a'; 512 * 1024];
+        let append = request(&[b"APPEND", b"key", &half]);
+        assert_eq!(dispatch(&append, &db), b":524288\r\n");
+        assert_eq!(dispatch(&append, &db), b":1048576\r\n");
+        assert_eq!(
+            dispatch(&request(&[b"APPEND", b"key", b"a"]), &db),
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
+    }
+
+    #[test]
+    fn test_mget_mset() {
+        let db = database(&[("a", "1"), ("expired", "v")]);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+        db.kv_store.write(b"list").insert(
+            Bytes::from("list"),
+            Entry::new(Value::List(VecDeque::new())),
+        );
+
+        let inputs: &[&[&[u8]]] = &[
+            &[b"MGET", b"a", b"missing", b"expired", b"list", b"a"],
+            &[b"MSET", b"a", b"2", b"b", b"3", b"c"],
+            &[b"MGET", b"a", b"b", b"c"],
+            &[b"MSET", b"b", b"3", b"a", b"4", b"list", b"5", b"b", b"6"],
+            &[b"MGET", b"list", b"b", b"a"],
+            &[b"MSET", b"a"],
+            &[b"MGET"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"*5\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$-1\r\n$1\r\n1\r\n",
+            b"-ERR wrong number of arguments for 'mset' command\r\n",
+            b"*3\r\n$1\r\n1\r\n$-1\r\n$-1\r\n",
+            b"+OK\r\n",
+            b"*3\r\n$1\r\n5\r\n$1\r\n6\r\n$1\r\n4\r\n",
+            b"-ERR wrong number of arguments for 'mset' command\r\n",
+            b"-ERR wrong number of arguments for 'mget' command\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                dispatch(&request(inputs[i]), &db),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_mset_is_atomic() {
+        // A reader never sees the keys set by one MSET mixed with another's,
+        // though there are enough keys for them to be spread over several
+        // shards
+        let keys: Vec<String> = (0..32).map(|i| format!("key:{}", i)).collect();
+        let db = database(&[]);
+        let writer = {
+            let db = Arc::clone(&db);
+            let keys = keys.clone();
+            std::thread::spawn(move || {
+                for i in 0..1000 {
+                    let value = i.to_string();
+                    let mut args: Vec<&[u8]> = vec![b"MSET"];
+                    for key in &keys {
+                        args.extend([key.as_bytes(), value.as_bytes()]);
+                    }
+                    assert_eq!(dispatch(&request(&args), &db), b"+OK\r\n");
+                }
+            })
+        };
+        let mut args: Vec<&[u8]> = vec![b"MGET"];
+        args.extend(keys.iter().map(|key| key.as_bytes()));
+        while !writer.is_finished() {
+            let reply = dispatch(&request(&args), &db);
+            let lines: Vec<&[u8]> = reply.split(|&b| b == b'\n').collect();
+            // The first line is the array header, then every value has a
+            // length line and a line of its own
+            assert!(
+                lines[1..lines.len() - 1]
+                    .chunks(2)
+                    .all(|value| value == &lines[1..3])
+            );
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_rewrites_keep_ttl() {
+        let db = database(&[("counter", "1"), ("text", "a")]);
+        for key in [&b"counter"[..], b"text"] {
+            dispatch(&request(&[b"EXPIRE", key, b"100"]), &db);
+        }
+        dispatch(&request(&[b"INCR", b"counter"]), &db);
+        dispatch(&request(&[b"APPEND", b"text", b"b"]), &db);
+
+        for key in [&b"counter"[..], b"text"] {
+            assert_eq!(dispatch(&request(&[b"TTL", key]), &db), b":100\r\n");
+        }
+        assert_eq!(db.kv_store.expires(), 2);
+    }
+
+    #[test]
+    fn test_get_removes_expired() {
+        let db = database(&[("expired", "v"), ("live", "v")]);
+        db.kv_store
+            .write(b"expired")
+            .set_expiry(b"expired", Some(SystemTime::now() - Duration::from_secs(1)));
+
+        let inputs: [&[u8]; 3] = [b"expired", b"live", b"missing"];
+        let expects: [&[u8]; 3] = [b"$-1\r\n", b"$1\r\nv\r\n", b"$-1\r\n"];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(dispatch(&request(&[b"GET", inputs[i]]), &db), expects[i]);
+        }
+        assert!(db.kv_store.read(b"expired").entry(b"expired").is_none());
+        assert_eq!((db.kv_store.len(), db.kv_store.expires()), (1, 0));
+    }
+
+    #[test]
+    fn test_value_compression() {
+        let json = r#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100);
+
+        let inputs: &[&[&str]] = &[
+            &[],
+            &["--value-compression", "yes"],
+            &[
+                "--value-compression",
+                "yes",
+                "--value-compression-threshold",
+                "1mb",
+            ],
+        ];
+        let expects = [false, true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            let db = Arc::new(Database::new(KvStore::new(), &config));
+            let set = request(&[b"SET", b"json", json.as_bytes()]);
+            assert_eq!(dispatch(&set, &db), b"+OK\r\n");
+
+            let value = db.get_str(b"json").unwrap().unwrap();
+            assert_eq!(value.is_compressed(), expects[i]);
+            assert_eq!(value.data(), Bytes::from(json.clone()));
+
+            let reply = format!("${}\r\n{}\r\n", json.len(), json);
+            assert_eq!(
+                dispatch(&request(&[b"GET", b"json"]), &db),
+                reply.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_append_compressed() {
+        let json = r#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100);
+        let more = r#"{"id": 2, "tags": ["c"]}"#.repeat(10);
+
+        let config = Config::from_args(&["--value-compression", "yes"]).unwrap();
+        let db = Arc::new(Database::new(KvStore::new(), &config));
+        dispatch(&request(&[b"SET", b"json", json.as_bytes()]), &db);
+        assert!(db.get_str(b"json").unwrap().unwrap().is_compressed());
+
+        let expected = format!("{}{}", json, more);
+        assert_eq!(
+            dispatch(&request(&[b"APPEND", b"json", more.as_bytes()]), &db),
+            format!(":{}\r\n", expected.len()).as_bytes()
+        );
+        let reply = format!("${}\r\n{}\r\n", expected.len(), expected);
+        assert_eq!(
+            dispatch(&request(&[b"GET", b"json"]), &db),
+            reply.as_bytes()
+        );
+
+        // The appended value is compressed again rather than stored as is
+        let value = db.get_str(b"json").unwrap().unwrap();
+        assert!(value.is_compressed());
+        assert!(value.stored_len() < expected.len());
+    }
+}
@@ -0,0 +1,1538 @@
+use std::{
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use resp::{
+    codec::CommandLimits,
+    parser::{ErrorRecovery, ParserConfig},
+};
+
+use crate::command;
+
+// ===========================================================
+// ConfigError
+// ===========================================================
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    UnknownOption {
+        name: String,
+    },
+    MissingValue {
+        name: String,
+    },
+    InvalidValue {
+        name: String,
+        value: String,
+    },
+
+    /// The config file could not be read
+    Read {
+        path: String,
+        reason: String,
+    },
+
+    /// A directive in the config file is invalid
+    Line {
+        line: usize,
+        err: Box<ConfigError>,
+    },
+
+    /// `rename-command` of a command that doesn't exist
+    RenameUnknownCommand {
+        name: String,
+    },
+
+    /// `rename-command` to the name another command has
+    RenameTaken {
+        name: String,
+        new_name: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownOption { name } => write!(f, "unknown option '{}'", name),
+            ConfigError::MissingValue { name } => write!(f, "missing value for '{}'", name),
+            ConfigError::InvalidValue { name, value } => {
+                write!(f, "invalid value '{}' for '{}'", value, name)
+            }
+            ConfigError::Read { path, reason } => write!(f, "can't read '{}': {}", path, reason),
+            ConfigError::Line { line, err } => write!(f, "line {}: {}", line, err),
+            ConfigError::RenameUnknownCommand { name } => {
+                write!(f, "can't rename '{}': no such command", name)
+            }
+            ConfigError::RenameTaken { name, new_name } => write!(
+                f,
+                "can't rename '{}' to '{}': a command with that name exists",
+                name, new_name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub type ConfigResult<T = ()> = Result<T, ConfigError>;
+
+// ===========================================================
+// BindAddr
+// ===========================================================
+
+/// Address the server listens on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BindAddr {
+    pub ip: IpAddr,
+
+    /// Set by a leading `-`, failing to bind this address is only logged
+    pub optional: bool,
+}
+
+impl BindAddr {
+    /// Parses an address in the form Redis accepts in `bind`, where `*` and
+    /// `::*` stand for every IPv4 and IPv6 address respectively
+    pub fn parse(addr: &str) -> Option<BindAddr> {
+        let (optional, addr) = match addr.strip_prefix('-') {
+            Some(addr) => (true, addr),
+            None => (false, addr),
+        };
+
+        let ip = match addr {
+            "*" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            "::*" => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            addr => addr.parse().ok()?,
+        };
+
+        Some(BindAddr { ip, optional })
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.optional {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", self.ip)
+    }
+}
+
+// ===========================================================
+// ClientClass, OutputBufferLimit
+// ===========================================================
+
+/// Kind of client for the purpose of output buffer limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientClass {
+    Normal,
+    Replica,
+    Pubsub,
+}
+
+impl ClientClass {
+    fn parse(name: &str) -> Option<ClientClass> {
+        match name.to_ascii_lowercase().as_str() {
+            "normal" => Some(ClientClass::Normal),
+            "replica" | "slave" => Some(ClientClass::Replica),
+            "pubsub" => Some(ClientClass::Pubsub),
+            _ => None,
+        }
+    }
+}
+
+/// Limits on the output pending to be sent to a client. A client is
+/// disconnected as soon as its pending output exceeds `hard`, or once it has
+/// stayed above `soft` for `soft_seconds`. Zero disables a limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl OutputBufferLimits {
+    pub fn get(&self, class: ClientClass) -> &OutputBufferLimit {
+        match class {
+            ClientClass::Normal => &self.normal,
+            ClientClass::Replica => &self.replica,
+            ClientClass::Pubsub => &self.pubsub,
+        }
+    }
+
+    fn get_mut(&mut self, class: ClientClass) -> &mut OutputBufferLimit {
+        match class {
+            ClientClass::Normal => &mut self.normal,
+            ClientClass::Replica => &mut self.replica,
+            ClientClass::Pubsub => &mut self.pubsub,
+        }
+    }
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> OutputBufferLimits {
+        OutputBufferLimits {
+            normal: OutputBufferLimit::default(),
+            replica: OutputBufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+// ===========================================================
+// SavePoint
+// ===========================================================
+
+/// Save the snapshot once `seconds` have passed since the last save, if
+/// there have been at least `changes` writes since
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+// ===========================================================
+// LogFormat, LogLevel
+// ===========================================================
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human-readable line per event
+    #[default]
+    Text,
+
+    /// One JSON object per event, for log aggregators
+    Json,
+}
+
+impl LogFormat {
+    fn parse(name: &str) -> Option<LogFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+/// Verbosity of the log, in the levels Redis uses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    #[default]
+    Notice,
+    Warning,
+    Nothing,
+}
+
+impl LogLevel {
+    fn parse(name: &str) -> Option<LogLevel> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "verbose" => Some(LogLevel::Verbose),
+            "notice" => Some(LogLevel::Notice),
+            "warning" => Some(LogLevel::Warning),
+            "nothing" => Some(LogLevel::Nothing),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+            LogLevel::Notice => "notice",
+            LogLevel::Warning => "warning",
+            LogLevel::Nothing => "nothing",
+        }
+    }
+
+    /// The level as a `RUST_LOG` style filter. There is no level between
+    /// debug and info, so verbose and notice are the same.
+    pub fn filter(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose | LogLevel::Notice => "info",
+            LogLevel::Warning => "warn",
+            LogLevel::Nothing => "off",
+        }
+    }
+}
+
+// ===========================================================
+// Config
+// ===========================================================
+
+/// Server configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Addresses to listen on, every one of them gets its own listener
+    pub bind: Vec<BindAddr>,
+
+    /// Whether `bind` was set explicitly rather than left at its default
+    pub bind_configured: bool,
+
+    /// Only serve clients on the loopback interface unless `bind` was
+    /// configured explicitly
+    pub protected_mode: bool,
+
+    /// Port to listen on
+    pub port: u16,
+
+    /// Maximum number of connected clients, any more are refused
+    pub maxclients: usize,
+
+    /// Seconds a client may stay idle before its connection is closed, 0
+    /// for never
+    pub timeout: u64,
+
+    /// Seconds a shutdown waits for connections to send their last replies
+    /// before closing them regardless
+    pub shutdown_timeout: u64,
+
+    /// How many times a second background tasks run, such as removing keys
+    /// that expired without being looked up
+    pub hz: u32,
+
+    /// Most keys remembered for clients tracking the keys they read, 0 for
+    /// no limit. Beyond it keys are forgotten, and invalidated for the
+    /// clients that read them.
+    pub tracking_table_max_keys: usize,
+
+    /// Capacity the per-connection reply buffer is shrunk back to once a
+    /// reply has made it grow beyond this size
+    pub reply_buffer_high_water: usize,
+
+    /// Maximum length of a single bulk string in a request
+    pub proto_max_bulk_len: usize,
+
+    /// Maximum number of arguments in a multibulk request
+    pub proto_max_multibulk_len: usize,
+
+    /// Maximum length of an inline request or of a length header line
+    pub proto_inline_max_size: usize,
+
+    /// Maximum number of bytes buffered for a request that is not complete
+    /// yet
+    pub client_query_buffer_limit: usize,
+
+    /// Idle time in seconds before TCP keepalive probes are sent to a
+    /// client, 0 disables keepalive
+    pub tcp_keepalive: u64,
+
+    /// Limits on pending output, per client class
+    pub client_output_buffer_limit: OutputBufferLimits,
+
+    /// Free large values removed by DEL in the background
+    pub lazyfree_lazy_user_del: bool,
+
+    /// Free large values overwritten by the server in the background
+    pub lazyfree_lazy_server_del: bool,
+
+    /// Make FLUSHDB without a modifier behave like FLUSHDB ASYNC
+    pub lazyfree_lazy_user_flush: bool,
+
+    /// Store string values compressed once they are longer than
+    /// `value_compression_threshold`
+    pub value_compression: bool,
+
+    pub value_compression_threshold: usize,
+
+    /// Directory the snapshot is saved to and loaded from
+    pub dir: PathBuf,
+
+    /// File name of the snapshot within `dir`
+    pub dbfilename: String,
+
+    /// When to save the snapshot on its own. Without any, it is only saved
+    /// by SAVE.
+    pub save: Vec<SavePoint>,
+
+    /// Commands to register under another name, in the order given. An
+    /// empty new name disables the command.
+    pub rename_command: Vec<(String, String)>,
+
+    /// Format of the log output
+    pub log_format: LogFormat,
+
+    /// Verbosity of the log, unless overridden by `RUST_LOG`
+    pub loglevel: LogLevel,
+
+    /// File the log is appended to instead of being written to stdout
+    pub logfile: Option<PathBuf>,
+
+    /// Log every request and reply as an annotated dump of its RESP, at the
+    /// trace level
+    pub trace_protocol: bool,
+
+    /// File the pid is written to once the server listens
+    pub pidfile: Option<PathBuf>,
+
+    /// Number of runtime worker threads, 1 runs everything on the current
+    /// thread. One per core if not set.
+    pub io_threads: Option<usize>,
+
+    /// Arguments the configuration was built from, including the path of
+    /// the config file if any. A reload builds the configuration from them
+    /// again.
+    pub command_line: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec![BindAddr {
+                ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                optional: false,
+            }],
+            bind_configured: false,
+            protected_mode: true,
+            port: 6379,
+            maxclients: 10000,
+            timeout: 0,
+            shutdown_timeout: 10,
+            hz: 10,
+            tracking_table_max_keys: 1_000_000,
+            reply_buffer_high_water: 64 * 1024,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            proto_inline_max_size: 64 * 1024,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            tcp_keepalive: 300,
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            lazyfree_lazy_user_del: false,
+            lazyfree_lazy_server_del: false,
+            lazyfree_lazy_user_flush: false,
+            value_compression: false,
+            value_compression_threshold: 1024,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.snap".to_string(),
+            save: Vec::new(),
+            rename_command: Vec::new(),
+            log_format: LogFormat::Text,
+            loglevel: LogLevel::Notice,
+            logfile: None,
+            trace_protocol: false,
+            pidfile: None,
+            io_threads: None,
+            command_line: Vec::new(),
+        }
+    }
+}
+
+/// Directives that only take effect at startup, a reload keeps their values
+const STATIC_DIRECTIVES: &[&str] = &[
+    "bind",
+    "port",
+    "io-threads",
+    "log-format",
+    "logfile",
+    "pidfile",
+    "rename-command",
+];
+
+impl Config {
+    /// Builds the configuration from command line arguments given in the
+    /// same form as Redis accepts them, e.g. `--tcp-keepalive 60` or
+    /// `--bind 127.0.0.1 ::1`. If the first argument is not an option, it
+    /// is the path of a config file, which the options override.
+    pub fn from_args<I>(args: I) -> ConfigResult<Config>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut config = Config::default();
+
+        let command_line: Vec<String> = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_string())
+            .collect();
+        let mut args: Vec<&str> = command_line.iter().map(String::as_str).collect();
+
+        if let Some(path) = args.first().filter(|arg| !arg.starts_with("--")) {
+            config.load_file(Path::new(path))?;
+            args.remove(0);
+        }
+
+        let mut pos = 0;
+        while pos < args.len() {
+            let Some(name) = args[pos].strip_prefix("--") else {
+                return Err(ConfigError::UnknownOption {
+                    name: args[pos].to_string(),
+                });
+            };
+
+            let values = &args[pos + 1..];
+            let count = values
+                .iter()
+                .position(|value| value.starts_with("--"))
+                .unwrap_or(values.len());
+            config.set(name, &values[..count])?;
+            pos += count + 1;
+        }
+
+        // Renames are what keeps dangerous commands out of reach, so one
+        // that can't be applied stops the server from starting
+        command::check_renames(&config.rename_command)?;
+
+        config.command_line = command_line;
+        Ok(config)
+    }
+
+    /// Applies the directives of a config file in the Redis format, one
+    /// directive per line followed by its values
+    fn load_file(&mut self, path: &Path) -> ConfigResult {
+        let contents = fs::read_to_string(path).map_err(|err| ConfigError::Read {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let in_line = |err| ConfigError::Line {
+                line: i + 1,
+                err: Box::new(err),
+            };
+            let words = split_words(line).ok_or_else(|| {
+                in_line(ConfigError::InvalidValue {
+                    name: line.to_string(),
+                    value: "unbalanced quotes".to_string(),
+                })
+            })?;
+            let values: Vec<&str> = words[1..].iter().map(String::as_str).collect();
+            self.set(&words[0], &values).map_err(in_line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Where the snapshot is saved to and loaded from
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// Limits for parsing requests, from the proto-* directives. Requests
+    /// are flat, so nesting keeps its default limit.
+    pub fn parser_config(&self) -> ParserConfig {
+        ParserConfig {
+            max_line_len: self.proto_inline_max_size,
+            max_bulk_len: self.proto_max_bulk_len,
+            max_array_len: self.proto_max_multibulk_len,
+            max_streamed_len: self.proto_max_bulk_len,
+            // What follows a malformed request can't be trusted to be the
+            // start of the next one
+            on_error: ErrorRecovery::Close,
+            ..ParserConfig::default()
+        }
+    }
+
+    /// Limits for framing requests, from the proto-* directives and the
+    /// query buffer limit
+    pub fn command_limits(&self) -> CommandLimits {
+        CommandLimits {
+            max_bulk_len: self.proto_max_bulk_len,
+            max_multibulk_len: self.proto_max_multibulk_len,
+            max_inline_len: self.proto_inline_max_size,
+            max_buffer_len: self.client_query_buffer_limit,
+        }
+    }
+
+    /// Every directive with its value as it would be written in a config
+    /// file
+    pub fn directives(&self) -> Vec<(&'static str, String)> {
+        let bind: Vec<String> = self.bind.iter().map(BindAddr::to_string).collect();
+        let limits = &self.client_output_buffer_limit;
+        let output_limits = [
+            ("normal", limits.normal),
+            ("replica", limits.replica),
+            ("pubsub", limits.pubsub),
+        ]
+        .iter()
+        .map(|(class, limit)| {
+            format!(
+                "{} {} {} {}",
+                class, limit.hard, limit.soft, limit.soft_seconds
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+        let renames = self
+            .rename_command
+            .iter()
+            .map(|(name, new_name)| format!("{} \"{}\"", name, new_name))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let save = self
+            .save
+            .iter()
+            .map(|point| format!("{} {}", point.seconds, point.changes))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+        let path = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map_or(String::new(), |path| path.display().to_string())
+        };
+
+        vec![
+            ("bind", bind.join(" ")),
+            ("port", self.port.to_string()),
+            ("protected-mode", yes_no(self.protected_mode)),
+            ("maxclients", self.maxclients.to_string()),
+            ("timeout", self.timeout.to_string()),
+            ("shutdown-timeout", self.shutdown_timeout.to_string()),
+            ("hz", self.hz.to_string()),
+            (
+                "tracking-table-max-keys",
+                self.tracking_table_max_keys.to_string(),
+            ),
+            ("proto-max-bulk-len", self.proto_max_bulk_len.to_string()),
+            (
+                "proto-max-multibulk-len",
+                self.proto_max_multibulk_len.to_string(),
+            ),
+            (
+                "proto-inline-max-size",
+                self.proto_inline_max_size.to_string(),
+            ),
+            (
+                "client-query-buffer-limit",
+                self.client_query_buffer_limit.to_string(),
+            ),
+            ("tcp-keepalive", self.tcp_keepalive.to_string()),
+            ("client-output-buffer-limit", output_limits),
+            (
+                "lazyfree-lazy-user-del",
+                yes_no(self.lazyfree_lazy_user_del),
+            ),
+            (
+                "lazyfree-lazy-server-del",
+                yes_no(self.lazyfree_lazy_server_del),
+            ),
+            (
+                "lazyfree-lazy-user-flush",
+                yes_no(self.lazyfree_lazy_user_flush),
+            ),
+            ("value-compression", yes_no(self.value_compression)),
+            (
+                "value-compression-threshold",
+                self.value_compression_threshold.to_string(),
+            ),
+            ("dir", self.dir.display().to_string()),
+            ("dbfilename", self.dbfilename.clone()),
+            ("save", save),
+            ("rename-command", renames),
+            ("log-format", self.log_format.name().to_string()),
+            ("loglevel", self.loglevel.name().to_string()),
+            ("logfile", path(&self.logfile)),
+            ("trace-protocol", yes_no(self.trace_protocol)),
+            ("pidfile", path(&self.pidfile)),
+            (
+                "io-threads",
+                self.io_threads
+                    .map_or(String::new(), |threads| threads.to_string()),
+            ),
+        ]
+    }
+
+    /// Merges a freshly loaded configuration into this one for a reload.
+    /// Directives that can change at runtime take their new values, the
+    /// others keep their current ones. Every difference is logged.
+    pub fn reloaded(&self, mut loaded: Config) -> Config {
+        let changes = self.directives().into_iter().zip(loaded.directives());
+        for ((name, current), (_, value)) in changes {
+            if current == value {
+                continue;
+            }
+
+            if STATIC_DIRECTIVES.contains(&name) {
+                warn!(
+                    "Not reloading {}: it can't be changed without a restart, keeping '{}'",
+                    name, current
+                );
+            } else {
+                info!("Reloaded {}: '{}' -> '{}'", name, current, value);
+            }
+        }
+
+        loaded.bind = self.bind.clone();
+        loaded.bind_configured = self.bind_configured;
+        loaded.port = self.port;
+        loaded.io_threads = self.io_threads;
+        loaded.log_format = self.log_format;
+        loaded.logfile = self.logfile.clone();
+        loaded.pidfile = self.pidfile.clone();
+        loaded.rename_command = self.rename_command.clone();
+        loaded
+    }
+
+    /// Sets a single configuration directive by its Redis name
+    pub fn set(&mut self, name: &str, values: &[&str]) -> ConfigResult {
+        if values.is_empty() {
+            return Err(ConfigError::MissingValue {
+                name: name.to_string(),
+            });
+        }
+
+        match name.to_ascii_lowercase().as_str() {
+            "bind" => {
+                self.bind = values
+                    .iter()
+                    .map(|value| BindAddr::parse(value).ok_or_else(|| invalid_value(name, value)))
+                    .collect::<ConfigResult<_>>()?;
+                self.bind_configured = true;
+            }
+            "protected-mode" => self.protected_mode = parse_bool(name, single(name, values)?)?,
+            "port" => self.port = parse(name, single(name, values)?)?,
+            "maxclients" => {
+                let value = single(name, values)?;
+                let maxclients = parse(name, value)?;
+                if maxclients == 0 {
+                    return Err(invalid_value(name, value));
+                }
+                self.maxclients = maxclients;
+            }
+            "timeout" => self.timeout = parse(name, single(name, values)?)?,
+            "shutdown-timeout" => self.shutdown_timeout = parse(name, single(name, values)?)?,
+            "hz" => {
+                let value = single(name, values)?;
+                let hz = parse(name, value)?;
+                if !(1..=500).contains(&hz) {
+                    return Err(invalid_value(name, value));
+                }
+                self.hz = hz;
+            }
+            "tracking-table-max-keys" => {
+                self.tracking_table_max_keys = parse(name, single(name, values)?)?
+            }
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = parse_memory(name, single(name, values)?)?
+            }
+            "proto-max-multibulk-len" => {
+                self.proto_max_multibulk_len = parse(name, single(name, values)?)?
+            }
+            "proto-inline-max-size" => {
+                self.proto_inline_max_size = parse_memory(name, single(name, values)?)?
+            }
+            "client-query-buffer-limit" => {
+                self.client_query_buffer_limit = parse_memory(name, single(name, values)?)?
+            }
+            "tcp-keepalive" => self.tcp_keepalive = parse(name, single(name, values)?)?,
+            "lazyfree-lazy-user-del" => {
+                self.lazyfree_lazy_user_del = parse_bool(name, single(name, values)?)?
+            }
+            "lazyfree-lazy-server-del" => {
+                self.lazyfree_lazy_server_del = parse_bool(name, single(name, values)?)?
+            }
+            "lazyfree-lazy-user-flush" => {
+                self.lazyfree_lazy_user_flush = parse_bool(name, single(name, values)?)?
+            }
+            "value-compression" => {
+                self.value_compression = parse_bool(name, single(name, values)?)?
+            }
+            "value-compression-threshold" => {
+                self.value_compression_threshold = parse_memory(name, single(name, values)?)?
+            }
+            "dir" => self.dir = PathBuf::from(single(name, values)?),
+            // A name only, the directory is `dir`
+            "dbfilename" => {
+                let value = single(name, values)?;
+                if value.is_empty() || value.contains(['/', '\\']) {
+                    return Err(invalid_value(name, value));
+                }
+                self.dbfilename = value.to_string();
+            }
+            // Pairs of seconds and changes, an empty value disables saving
+            "save" => {
+                if let [""] = values {
+                    self.save = Vec::new();
+                } else if values.len() % 2 != 0 {
+                    return Err(invalid_value(name, &values.join(" ")));
+                } else {
+                    self.save = values
+                        .chunks(2)
+                        .map(|pair| {
+                            Ok(SavePoint {
+                                seconds: parse(name, pair[0])?,
+                                changes: parse(name, pair[1])?,
+                            })
+                        })
+                        .collect::<ConfigResult<_>>()?;
+                }
+            }
+            // Can be given several times, each adds a rename
+            "rename-command" => match values {
+                [name, new_name] => self
+                    .rename_command
+                    .push((name.to_ascii_lowercase(), new_name.to_ascii_lowercase())),
+                _ => return Err(invalid_value(name, &values.join(" "))),
+            },
+            "io-threads" => {
+                let value = single(name, values)?;
+                let threads = parse(name, value)?;
+                if threads == 0 {
+                    return Err(invalid_value(name, value));
+                }
+                self.io_threads = Some(threads);
+            }
+            "loglevel" => {
+                let value = single(name, values)?;
+                self.loglevel = LogLevel::parse(value).ok_or_else(|| invalid_value(name, value))?
+            }
+            "log-format" => {
+                let value = single(name, values)?;
+                self.log_format =
+                    LogFormat::parse(value).ok_or_else(|| invalid_value(name, value))?
+            }
+            // As in Redis, an empty path means stdout
+            "logfile" => self.logfile = path(single(name, values)?),
+            "pidfile" => self.pidfile = path(single(name, values)?),
+            "trace-protocol" => self.trace_protocol = parse_bool(name, single(name, values)?)?,
+            "client-output-buffer-limit" => {
+                // Given as one or more `<class> <hard> <soft> <soft seconds>`
+                // groups, classes that are not mentioned keep their limits
+                if values.len() % 4 != 0 {
+                    return Err(invalid_value(name, &values.join(" ")));
+                }
+
+                let mut limits = self.client_output_buffer_limit;
+                for group in values.chunks(4) {
+                    let class = ClientClass::parse(group[0])
+                        .ok_or_else(|| invalid_value(name, &group.join(" ")))?;
+                    *limits.get_mut(class) = OutputBufferLimit {
+                        hard: parse_memory(name, group[1])?,
+                        soft: parse_memory(name, group[2])?,
+                        soft_seconds: parse(name, group[3])?,
+                    };
+                }
+                self.client_output_buffer_limit = limits;
+            }
+            _ => {
+                return Err(ConfigError::UnknownOption {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a config file line into words. Words can be quoted with double
+/// quotes, which allow escapes, or single quotes, which don't. Returns `None`
+/// for an unterminated quote.
+fn split_words(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Some(words);
+        };
+
+        let mut word = String::new();
+        match first {
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => word.push('\n'),
+                        'r' => word.push('\r'),
+                        't' => word.push('\t'),
+                        c => word.push(c),
+                    },
+                    c => word.push(c),
+                }
+            },
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    c => word.push(c),
+                }
+            },
+            c => {
+                word.push(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Returns the only value of a directive that takes exactly one
+fn single<'a>(name: &str, values: &[&'a str]) -> ConfigResult<&'a str> {
+    match values {
+        [value] => Ok(value),
+        _ => Err(invalid_value(name, &values.join(" "))),
+    }
+}
+
+fn invalid_value(name: &str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> ConfigResult<T> {
+    value.parse().map_err(|_| invalid_value(name, value))
+}
+
+fn path(value: &str) -> Option<PathBuf> {
+    (!value.is_empty()).then(|| PathBuf::from(value))
+}
+
+fn parse_bool(name: &str, value: &str) -> ConfigResult<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(invalid_value(name, value)),
+    }
+}
+
+/// Parses a memory amount with an optional unit, where `k`, `m` and `g` are
+/// powers of 1000 and `kb`, `mb` and `gb` are powers of 1024
+fn parse_memory(name: &str, value: &str) -> ConfigResult<usize> {
+    let lower = value.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: usize = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(invalid_value(name, value)),
+    };
+
+    parse::<usize>(name, digits)
+        .map_err(|_| invalid_value(name, value))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| invalid_value(name, value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_memory() {
+        let inputs = [
+            "1024", "1k", "1kb", "512mb", "1gb", "2G", "12b", "", "mb", "1tb", "-1",
+        ];
+        let expects: &[Option<usize>] = &[
+            Some(1024),
+            Some(1000),
+            Some(1024),
+            Some(512 * 1024 * 1024),
+            Some(1024 * 1024 * 1024),
+            Some(2 * 1000 * 1000 * 1000),
+            Some(12),
+            None,
+            None,
+            None,
+            None,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(parse_memory("test", inputs[i]).ok(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        let inputs = ["yes", "no", "YES", "No", "true", "1", ""];
+        let expects = [
+            Some(true),
+            Some(false),
+            Some(true),
+            Some(false),
+            None,
+            None,
+            None,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(parse_bool("test", inputs[i]).ok(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args([
+            "--tcp-keepalive",
+            "60",
+            "--proto-max-bulk-len",
+            "1mb",
+            "--trace-protocol",
+            "yes",
+        ])
+        .unwrap();
+        assert_eq!(config.tcp_keepalive, 60);
+        assert_eq!(config.proto_max_bulk_len, 1024 * 1024);
+        assert!(config.trace_protocol);
+
+        assert_eq!(
+            Config::from_args(["--tcp-keepalive"]).unwrap_err(),
+            ConfigError::MissingValue {
+                name: "tcp-keepalive".to_string()
+            }
+        );
+        assert_eq!(
+            Config::from_args(["--tcp-keepalive", "--proto-max-bulk-len", "1mb"]).unwrap_err(),
+            ConfigError::MissingValue {
+                name: "tcp-keepalive".to_string()
+            }
+        );
+        assert_eq!(
+            Config::from_args(["--tcp-keepalive", "soon"]).unwrap_err(),
+            ConfigError::InvalidValue {
+                name: "tcp-keepalive".to_string(),
+                value: "soon".to_string()
+            }
+        );
+        assert_eq!(
+            Config::from_args(["--no-such-option", "1"]).unwrap_err(),
+            ConfigError::UnknownOption {
+                name: "no-such-option".to_string()
+            }
+        );
+        assert_eq!(
+            Config::from_args(["--tcp-keepalive", "60", "120"]).unwrap_err(),
+            ConfigError::InvalidValue {
+                name: "tcp-keepalive".to_string(),
+                value: "60 120".to_string()
+            }
+        );
+        // Only the first argument can be a config file
+        assert!(matches!(
+            Config::from_args(["tcp-keepalive"]).unwrap_err(),
+            ConfigError::Read { path, .. } if path == "tcp-keepalive"
+        ));
+    }
+
+    #[test]
+    fn test_split_words() {
+        let inputs = [
+            "port 6379",
+            "  bind   127.0.0.1 ::1  ",
+            r#"logfile "/var/log/redis server.log""#,
+            r#"logfile "tab\there \"quoted\"""#,
+            r"logfile '/tmp/a\b'",
+            r#"logfile """#,
+            r#"logfile "unterminated"#,
+            "logfile 'unterminated",
+        ];
+        let expects: &[Option<&[&str]>] = &[
+            Some(&["port", "6379"]),
+            Some(&["bind", "127.0.0.1", "::1"]),
+            Some(&["logfile", "/var/log/redis server.log"]),
+            Some(&["logfile", "tab\there \"quoted\""]),
+            Some(&["logfile", r"/tmp/a\b"]),
+            Some(&["logfile", ""]),
+            None,
+            None,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let words = split_words(inputs[i]);
+            let expected = expects[i].map(|words| words.iter().map(|word| word.to_string()));
+            assert_eq!(words, expected.map(Iterator::collect));
+        }
+    }
+
+    /// Config file in the temp directory, removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> TempFile {
+            let path = std::env::temp_dir().join(format!(
+                "resp-server-test-{}-{}.conf",
+                std::process::id(),
+                name
+            ));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_config_file() {
+        let file = TempFile::new(
+            "load",
+            "# A comment\n\nport 7000\n\ttcp-keepalive 60\nbind 127.0.0.1 \"-::1\"\nloglevel warning\n",
+        );
+
+        let config = Config::from_args([file.path()]).unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.tcp_keepalive, 60);
+        assert_eq!(
+            config.bind,
+            [
+                BindAddr::parse("127.0.0.1").unwrap(),
+                BindAddr::parse("-::1").unwrap()
+            ]
+        );
+        assert!(config.bind_configured);
+        assert_eq!(config.loglevel, LogLevel::Warning);
+        assert_eq!(config.command_line, [file.path()]);
+
+        // Options on the command line override the file
+        let config = Config::from_args([file.path(), "--port", "7001"]).unwrap();
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.tcp_keepalive, 60);
+
+        let inputs = [
+            "port 7000\nno-such-option 1\n",
+            "port\n",
+            "logfile \"/tmp\n",
+        ];
+        let expects = [
+            (
+                2,
+                ConfigError::UnknownOption {
+                    name: "no-such-option".to_string(),
+                },
+            ),
+            (
+                1,
+                ConfigError::MissingValue {
+                    name: "port".to_string(),
+                },
+            ),
+            (
+                1,
+                ConfigError::InvalidValue {
+                    name: "logfile \"/tmp".to_string(),
+                    value: "unbalanced quotes".to_string(),
+                },
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let file = TempFile::new("invalid", inputs[i]);
+            let (line, err) = expects[i].clone();
+            assert_eq!(
+                Config::from_args([file.path()]).unwrap_err(),
+                ConfigError::Line {
+                    line,
+                    err: Box::new(err)
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_reloaded() {
+        let current = Config::from_args(["--port", "7000", "--pidfile", "/tmp/a.pid"]).unwrap();
+        let loaded = Config::from_args([
+            "--port",
+            "7001",
+            "--bind",
+            "0.0.0.0",
+            "--tcp-keepalive",
+            "60",
+            "--loglevel",
+            "debug",
+            "--lazyfree-lazy-user-del",
+            "yes",
+        ])
+        .unwrap();
+        let config = current.reloaded(loaded);
+
+        let inputs = [
+            "port",
+            "bind",
+            "pidfile",
+            "tcp-keepalive",
+            "loglevel",
+            "lazyfree-lazy-user-del",
+        ];
+        let expects = ["7000", "127.0.0.1", "/tmp/a.pid", "60", "debug", "yes"];
+
+        let directives: HashMap<_, _> = config.directives().into_iter().collect();
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(directives[inputs[i]], expects[i], "{}", inputs[i]);
+        }
+        assert!(!config.bind_configured);
+
+        // None of the static directives changes, whatever the new value
+        let changed = Config::from_args([
+            "--bind",
+            "0.0.0.0",
+            "--port",
+            "1",
+            "--io-threads",
+            "2",
+            "--log-format",
+            "json",
+            "--logfile",
+            "/tmp/b.log",
+            "--pidfile",
+            "/tmp/b.pid",
+        ])
+        .unwrap();
+        let config = current.reloaded(changed);
+        let kept: HashMap<_, _> = current.directives().into_iter().collect();
+        for (name, value) in config.directives() {
+            if STATIC_DIRECTIVES.contains(&name) {
+                assert_eq!(value, kept[name], "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_io_threads() {
+        let inputs: &[&[&str]] = &[&[], &["--io-threads", "1"], &["--io-threads", "8"]];
+        let expects = [None, Some(1), Some(8)];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            assert_eq!(config.io_threads, expects[i]);
+        }
+
+        assert_eq!(
+            Config::from_args(["--io-threads", "0"]).unwrap_err(),
+            ConfigError::InvalidValue {
+                name: "io-threads".to_string(),
+                value: "0".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_maxclients() {
+        let inputs: &[&[&str]] = &[&[], &["--maxclients", "1"], &["--maxclients", "50000"]];
+        let expects = [10000, 1, 50000];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            assert_eq!(config.maxclients, expects[i]);
+        }
+
+        for value in ["0", "-1", "many"] {
+            assert_eq!(
+                Config::from_args(["--maxclients", value]).unwrap_err(),
+                ConfigError::InvalidValue {
+                    name: "maxclients".to_string(),
+                    value: value.to_string()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_hz() {
+        let inputs: &[&[&str]] = &[&[], &["--hz", "1"], &["--hz", "500"]];
+        let expects = [10, 1, 500];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            assert_eq!(config.hz, expects[i]);
+        }
+
+        for value in ["0", "501", "-1"] {
+            assert_eq!(
+                Config::from_args(["--hz", value]).unwrap_err(),
+                ConfigError::InvalidValue {
+                    name: "hz".to_string(),
+                    value: value.to_string()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_rename_command() {
+        let inputs: &[&[&str]] = &[
+            &[],
+            &["--rename-command", "FLUSHDB", ""],
+            &[
+                "--rename-command",
+                "flushdb",
+                "",
+                "--rename-command",
+                "config",
+                "Admin-Config",
+            ],
+        ];
+        let expects: &[&[(&str, &str)]] = &[
+            &[],
+            &[("flushdb", "")],
+            &[("flushdb", ""), ("config", "admin-config")],
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            let renames: Vec<(&str, &str)> = config
+                .rename_command
+                .iter()
+                .map(|(name, new_name)| (name.as_str(), new_name.as_str()))
+                .collect();
+            assert_eq!(renames, expects[i]);
+        }
+
+        assert_eq!(
+            Config::from_args(["--rename-command", "flushdb"]).unwrap_err(),
+            ConfigError::InvalidValue {
+                name: "rename-command".to_string(),
+                value: "flushdb".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let point = |seconds, changes| SavePoint { seconds, changes };
+        let inputs: &[&[&str]] = &[
+            &[],
+            &["--save", "3600", "1", "300", "100"],
+            &["--save", "60", "10", "--save", ""],
+            &["--dir", "/var/lib/resp-server", "--dbfilename", "data.snap"],
+        ];
+        let expects = [
+            (vec![], "./dump.snap"),
+            (vec![point(3600, 1), point(300, 100)], "./dump.snap"),
+            (vec![], "./dump.snap"),
+            (vec![], "/var/lib/resp-server/data.snap"),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            assert_eq!(config.save, expects[i].0);
+            assert_eq!(config.snapshot_path(), PathBuf::from(expects[i].1));
+        }
+
+        let inputs: &[&[&str]] = &[
+            &["--save", "3600"],
+            &["--save", "soon", "1"],
+            &["--dbfilename", "../dump.snap"],
+            &["--dbfilename", ""],
+        ];
+        for args in inputs {
+            assert!(
+                matches!(
+                    Config::from_args(*args),
+                    Err(ConfigError::InvalidValue { .. })
+                ),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        let inputs: &[&[&str]] = &[
+            &[],
+            &["--logfile", "/var/log/resp-server.log"],
+            &["--logfile", ""],
+            &["--pidfile", "/var/run/resp-server.pid"],
+        ];
+        let expects = [
+            (None, None),
+            (Some("/var/log/resp-server.log"), None),
+            (None, None),
+            (None, Some("/var/run/resp-server.pid")),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(inputs[i]).unwrap();
+            assert_eq!(config.logfile, expects[i].0.map(PathBuf::from));
+            assert_eq!(config.pidfile, expects[i].1.map(PathBuf::from));
+        }
+    }
+
+    #[test]
+    fn test_log_format() {
+        let inputs = ["text", "json", "JSON", "logfmt"];
+        let expects = [
+            Ok(LogFormat::Text),
+            Ok(LogFormat::Json),
+            Ok(LogFormat::Json),
+            Err(ConfigError::InvalidValue {
+                name: "log-format".to_string(),
+                value: "logfmt".to_string(),
+            }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config::from_args(["--log-format", inputs[i]]);
+            assert_eq!(config.map(|config| config.log_format), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_bind_addr_parse() {
+        let inputs = [
+            "127.0.0.1",
+            "::1",
+            "-::1",
+            "*",
+            "-::*",
+            "10.0.0.5",
+            "localhost",
+            "-",
+            "",
+        ];
+        let expects = [
+            Some(("127.0.0.1", false)),
+            Some(("::1", false)),
+            Some(("::1", true)),
+            Some(("0.0.0.0", false)),
+            Some(("::", true)),
+            Some(("10.0.0.5", false)),
+            None,
+            None,
+            None,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let expected = expects[i].map(|(ip, optional)| BindAddr {
+                ip: ip.parse().unwrap(),
+                optional,
+            });
+            assert_eq!(BindAddr::parse(inputs[i]), expected);
+        }
+    }
+
+    #[test]
+    fn test_bind_from_args() {
+        let config = Config::from_args(["--bind", "127.0.0.1", "-::1", "--port", "7000"]).unwrap();
+        assert_eq!(
+            config.bind,
+            [
+                BindAddr::parse("127.0.0.1").unwrap(),
+                BindAddr::parse("-::1").unwrap()
+            ]
+        );
+        assert_eq!(config.port, 7000);
+        assert!(config.bind_configured);
+        assert!(
+            !Config::from_args(["--port", "7000"])
+                .unwrap()
+                .bind_configured
+        );
+
+        assert_eq!(
+            Config::from_args(["--bind", "127.0.0.1", "nowhere"]).unwrap_err(),
+            ConfigError::InvalidValue {
+                name: "bind".to_string(),
+                value: "nowhere".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_client_output_buffer_limit() {
+        let config = Config::from_args([
+            "--client-output-buffer-limit",
+            "normal",
+            "1mb",
+            "512kb",
+            "10",
+            "pubsub",
+            "0",
+            "0",
+            "0",
+        ])
+        .unwrap();
+        let limits = config.client_output_buffer_limit;
+        assert_eq!(
+            *limits.get(ClientClass::Normal),
+            OutputBufferLimit {
+                hard: 1024 * 1024,
+                soft: 512 * 1024,
+                soft_seconds: 10
+            }
+        );
+        assert_eq!(
+            *limits.get(ClientClass::Pubsub),
+            OutputBufferLimit::default()
+        );
+        assert_eq!(limits.replica, OutputBufferLimits::default().replica);
+
+        let inputs: &[&[&str]] = &[
+            &["normal", "1mb", "512kb"],
+            &["master", "0", "0", "0"],
+            &["normal", "lots", "0", "0"],
+            &["normal", "0", "0", "0", "pubsub", "1mb"],
+        ];
+        let expects = [
+            "normal 1mb 512kb",
+            "master 0 0 0",
+            "lots",
+            "normal 0 0 0 pubsub 1mb",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                Config::default()
+                    .set("client-output-buffer-limit", inputs[i])
+                    .unwrap_err(),
+                ConfigError::InvalidValue {
+                    name: "client-output-buffer-limit".to_string(),
+                    value: expects[i].to_string()
+                }
+            );
+        }
+    }
+}
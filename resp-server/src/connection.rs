@@ -0,0 +1,1711 @@
+use std::{
+    future,
+    io::{self, IoSlice},
+    iter, mem,
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use futures::FutureExt;
+use log::{Level, debug, error, log_enabled, trace, warn};
+use resp::{
+    codec::{FrameError, FramedRead},
+    command::{self as command_line, CommandLine},
+    parser::{ParserConfig, RespParser},
+    trace::Trace,
+    types::{
+        BulkString, CommandFormatError, RespError, RespReadable, RespValue, RespValueRef,
+        RespWritable,
+    },
+    writer::{OutBuf, RespWriter, SegmentedBuf, WriteBuf, WriteError, WriteResult},
+};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::mpsc,
+    time::Instant,
+};
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, field, info_span};
+
+use crate::{
+    client::ClientState,
+    codec::RequestCodec,
+    command,
+    config::{ClientClass, Config, OutputBufferLimit},
+    db::Database,
+    store::Store,
+    tracking::{PushReceiver, push_queue},
+};
+
+// ===========================================================
+// Protected mode
+// ===========================================================
+
+/// Sent to clients refused by protected mode before closing the connection
+const DENIED_ERR: &[u8] = b"-DENIED Redis is running in protected mode because protected mode \
+is enabled and no password is set for the default user. In this mode connections are only \
+accepted from the loopback interface. If you want to connect from external computers to Redis \
+you may adopt one of the following solutions: 1) Just disable protected mode sending the \
+command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from \
+the same host the server is running, however MAKE SURE Redis is not publicly accessible from \
+internet if you do so. 2) If you started the server manually just for testing, restart it with \
+the '--protected-mode no' option. 3) Configure the addresses to listen on explicitly with \
+'--bind'. NOTE: You only need to do one of the above things in order for the server to start \
+accepting connections from the outside.\r\n";
+
+/// Whether protected mode refuses a client. There are no passwords yet, so
+/// only an explicitly configured bind address lifts it. A peer whose address
+/// is unknown is refused.
+fn is_denied(peer: Option<IpAddr>, config: &Config) -> bool {
+    if !config.protected_mode || config.bind_configured {
+        return false;
+    }
+
+    !peer.is_some_and(|ip| ip.to_canonical().is_loopback())
+}
+
+/// Sent to clients beyond `maxclients` before closing the connection
+const MAX_CLIENTS_ERR: &[u8] = b"-ERR max number of clients reached\r\n";
+
+// ===========================================================
+// Requests
+// ===========================================================
+
+/// Sent when not even an error reply can be serialized, so that the client
+/// always gets a well-formed answer.
+const FALLBACK_ERR: &[u8] = b"-ERR internal error while writing the reply\r\n";
+
+/// Replaces whatever has been written for the current request, which starts
+/// at `start` in the reply buffer, with an error reply. Replies to earlier
+/// requests in the same batch are kept. If the error can't be serialized
+/// either, the preformatted fallback frame is written instead.
+fn write_err<B: OutBuf>(err: RespError, writer: &mut RespWriter<'_, B>, start: usize) {
+    error!("{}", err);
+    writer.buffer().truncate(start);
+
+    if let Err(err) = err.write(writer) {
+        error!("Failed to write error response: {}", err);
+        writer.buffer().truncate(start);
+        writer.buffer().get_mut().extend_from_slice(FALLBACK_ERR);
+    }
+}
+
+/// Handles a single complete request, appending its reply to the reply
+/// buffer. This is everything a connection does with a request after framing
+/// it, without any I/O.
+///
+/// Returns false if the request is malformed in a way that leaves the rest
+/// of the stream unreadable. The connection has to be closed once the error
+/// reply has been sent. Errors of the command itself never close it.
+pub fn handle_request<S: Store, B: OutBuf>(
+    req_buf: BytesMut,
+    parser_config: &ParserConfig,
+    writer: &mut RespWriter<'_, B>,
+    db: &Arc<Database<S>>,
+    client: &mut ClientState,
+) -> bool {
+    let start = writer.buffer().len();
+
+    // Anything that isn't a multibulk request is an inline command, a line
+    // the codec framed with its LF, which may or may not follow a CR
+    if req_buf.first() != Some(&b'*') {
+        let line = req_buf.strip_suffix(b"\n").unwrap_or(&req_buf);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let words = match command_line::split_inline(line) {
+            Ok(words) => words,
+            Err(err) => {
+                write_err(RespError::protocol(err), writer, start);
+                return false;
+            }
+        };
+        let Ok(command) = CommandLine::new(&words) else {
+            return true;
+        };
+        let args: Vec<&[u8]> = iter::once(command.name)
+            .chain(command.args.iter().map(BulkString::as_bytes))
+            .collect();
+        return run_command(&args, db, writer, client, start);
+    }
+
+    // The arguments are borrowed from the request, handlers copy the ones
+    // they keep
+    let mut parser = RespParser::new_with_config(&req_buf, *parser_config);
+    let request = match RespValueRef::parse(&mut parser) {
+        Ok(request) => request,
+        Err(err) => {
+            write_err(RespError::protocol(err.kind()), writer, start);
+            return !err.kind().is_fatal();
+        }
+    };
+
+    let args: Vec<&[u8]> = match request.as_command() {
+        Ok((name, args)) => iter::once(name)
+            .chain(args.iter().filter_map(RespValueRef::as_bytes))
+            .collect(),
+        // Empty requests are sent by some clients as keep-alives, they are
+        // ignored without a reply
+        Err(CommandFormatError::Empty) => return true,
+        // The request was parsed whole, so the next one can still be read
+        Err(err) => {
+            write_err(RespError::protocol(err), writer, start);
+            return true;
+        }
+    };
+
+    run_command(&args, db, writer, client, start)
+}
+
+/// Runs a command and writes its reply, or an error if the reply can't be
+/// written. A reply that doesn't fit within the output buffer limit of the
+/// client is dropped instead, and false is returned for the connection to
+/// be closed, as in Redis.
+fn run_command<S: Store, B: OutBuf>(
+    args: &[&[u8]],
+    db: &Arc<Database<S>>,
+    writer: &mut RespWriter<'_, B>,
+    client: &mut ClientState,
+    start: usize,
+) -> bool {
+    match command::dispatch(args, db, writer, client) {
+        Ok(()) => true,
+        Err(WriteError::BufferFull { needed, limit }) => {
+            warn!(
+                "Closing connection {}: output buffer limit of {} bytes reached, {} needed",
+                client, limit, needed
+            );
+            writer.buffer().truncate(start);
+            db.stats
+                .client_output_buffer_limit_disconnections
+                .fetch_add(1, Ordering::Relaxed);
+            false
+        }
+        Err(err) => {
+            write_err(
+                RespError::err(format!("Failed to write response: {}", err)),
+                writer,
+                start,
+            );
+            true
+        }
+    }
+}
+
+/// Creates a reply buffer that can't grow beyond the hard output buffer
+/// limit, since replies past it would never be sent
+fn reply_buf(data: SegmentedBuf, limit: &OutputBufferLimit) -> WriteBuf<SegmentedBuf> {
+    match limit.hard {
+        0 => WriteBuf::new(data),
+        hard => WriteBuf::with_limit(data, hard),
+    }
+}
+
+/// Prepares the per-connection reply buffer for the next request. The buffer
+/// keeps its capacity, unless a large reply made it grow beyond `high_water`,
+/// in which case it is shrunk back so that a single huge reply doesn't pin
+/// that memory for the lifetime of the connection.
+fn reset_write_buf<B: OutBuf>(write_buf: &mut WriteBuf<B>, high_water: usize) {
+    write_buf.clear();
+    if write_buf.capacity() > high_water {
+        write_buf.shrink_to(high_water);
+    }
+}
+
+// ===========================================================
+// Connection
+// ===========================================================
+
+enum FlushError {
+    /// The client exceeded its output buffer limits and has to be
+    /// disconnected
+    OutputBufferLimit,
+
+    Io(io::Error),
+}
+
+/// Number of reply batches that can be queued for the writer of a
+/// connection. Once the queue is full, requests are no longer read until the
+/// writer catches up.
+const REPLY_QUEUE_LEN: usize = 16;
+
+/// The writer of the connection is gone, so nothing more can be sent
+#[derive(Debug)]
+pub(crate) struct ConnectionClosed;
+
+/// Sending half of the reply queue of a connection. Replies are queued
+/// already serialized, in the order the client has to receive them, with
+/// large values still shared with the database.
+#[derive(Clone)]
+pub(crate) struct ReplySender {
+    frames: mpsc::Sender<SegmentedBuf>,
+
+    /// Bytes queued or handed to the writer but not yet sent, which is what
+    /// the output buffer limits apply to
+    pending: Arc<AtomicUsize>,
+    hard_limit: usize,
+
+    /// Cancelled once the hard limit is exceeded, which stops the writer
+    /// without sending what is still queued
+    over_limit: CancellationToken,
+}
+
+impl ReplySender {
+    /// Queues `frame`, waiting while the queue is full. Fails once the writer
+    /// has stopped, or if queuing the frame would exceed the hard output
+    /// buffer limit, which stops the writer as well.
+    pub(crate) async fn send(&self, frame: SegmentedBuf) -> Result<(), ConnectionClosed> {
+        let pending = self.pending.fetch_add(frame.len(), Ordering::Relaxed) + frame.len();
+        if self.hard_limit > 0 && pending > self.hard_limit {
+            self.over_limit.cancel();
+            return Err(ConnectionClosed);
+        }
+
+        self.frames.send(frame).await.map_err(|_| ConnectionClosed)
+    }
+
+    /// Resolves once the writer has stopped
+    async fn closed(&self) {
+        self.frames.closed().await
+    }
+}
+
+/// Receiving half of the reply queue, owned by the writer task
+struct ReplyQueue {
+    frames: mpsc::Receiver<SegmentedBuf>,
+    pending: Arc<AtomicUsize>,
+    limit: OutputBufferLimit,
+    over_limit: CancellationToken,
+
+    /// Sent frames small enough to be reused as reply buffers go back to the
+    /// reader through this
+    spares: mpsc::Sender<SegmentedBuf>,
+    high_water: usize,
+}
+
+/// Creates the reply queue of a connection, along with the receiver of the
+/// buffers the writer is done with
+fn reply_queue(
+    limit: OutputBufferLimit,
+    high_water: usize,
+) -> (ReplySender, ReplyQueue, mpsc::Receiver<SegmentedBuf>) {
+    let (frames_tx, frames_rx) = mpsc::channel(REPLY_QUEUE_LEN);
+    let (spares_tx, spares_rx) = mpsc::channel(REPLY_QUEUE_LEN);
+    let pending = Arc::new(AtomicUsize::new(0));
+    let over_limit = CancellationToken::new();
+
+    let sender = ReplySender {
+        frames: frames_tx,
+        pending: pending.clone(),
+        hard_limit: limit.hard,
+        over_limit: over_limit.clone(),
+    };
+    let queue = ReplyQueue {
+        frames: frames_rx,
+        pending,
+        limit,
+        over_limit,
+        spares: spares_tx,
+        high_water,
+    };
+    (sender, queue, spares_rx)
+}
+
+/// Sends queued replies to the client until every sender is gone and the
+/// queue has drained, enforcing the output buffer limits. Output beyond the
+/// hard limit is never sent, and output above the soft limit has to drain
+/// below it within the configured time.
+async fn write_replies(mut out: OwnedWriteHalf, mut queue: ReplyQueue) -> Result<(), FlushError> {
+    let limit = queue.limit;
+    let mut above_soft_since = None;
+
+    loop {
+        let mut frame = tokio::select! {
+            biased;
+            _ = queue.over_limit.cancelled() => return Err(FlushError::OutputBufferLimit),
+            frame = queue.frames.recv() => match frame {
+                Some(frame) => frame,
+                None => return Ok(()),
+            },
+        };
+
+        let mut slices = frame.as_io_slices();
+        let mut data = &mut slices[..];
+        while !data.is_empty() {
+            let pending = queue.pending.load(Ordering::Relaxed);
+            let deadline = if limit.soft > 0 && pending > limit.soft {
+                let since = *above_soft_since.get_or_insert_with(Instant::now);
+                Some(since + Duration::from_secs(limit.soft_seconds))
+            } else {
+                above_soft_since = None;
+                None
+            };
+
+            // A write that completes right away wins over an expired deadline
+            let written = tokio::select! {
+                biased;
+                _ = queue.over_limit.cancelled() => return Err(FlushError::OutputBufferLimit),
+                res = out.write_vectored(data) => res.map_err(FlushError::Io)?,
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() => return Err(FlushError::OutputBufferLimit),
+            };
+            if written == 0 {
+                return Err(FlushError::Io(io::ErrorKind::WriteZero.into()));
+            }
+            IoSlice::advance_slices(&mut data, written);
+            queue.pending.fetch_sub(written, Ordering::Relaxed);
+        }
+
+        // Spares don't hold on to the values they shared
+        frame.truncate(0);
+        if frame.capacity() <= queue.high_water {
+            // The reader allocates a new buffer when there is no spare left
+            let _ = queue.spares.try_send(frame);
+        }
+    }
+}
+
+/// Waits for the next request, `None` once the client disconnects or is
+/// killed, the writer stops, the server shuts down or no request arrives
+/// within `idle_timeout`. Push messages arriving meanwhile are sent right
+/// away, nothing else is waiting to be sent.
+async fn next_request(
+    transport: &mut FramedRead<OwnedReadHalf, RequestCodec>,
+    client: &ClientState,
+    replies: &ReplySender,
+    pushes: &mut PushReceiver,
+    shutdown: &CancellationToken,
+    idle_timeout: Option<Duration>,
+) -> Option<Result<BytesMut, FrameError>> {
+    let idle = async {
+        match idle_timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => future::pending().await,
+        }
+    };
+    tokio::pin!(idle);
+    loop {
+        tokio::select! {
+            // Requests that are already buffered must not win over shutdown
+            biased;
+            _ = shutdown.cancelled() => return None,
+            _ = client.killed() => return None,
+            _ = replies.closed() => return None,
+            next = transport.next() => return next,
+            Some(push) = pushes.recv() => {
+                let mut write_buf = WriteBuf::new(SegmentedBuf::new());
+                let mut writer = RespWriter::with_protocol(&mut write_buf, client.protocol);
+                if write_pushes(push, pushes, &mut writer).is_err()
+                    || replies.send(write_buf.into_inner()).await.is_err()
+                {
+                    return None;
+                }
+            }
+            _ = &mut idle => {
+                debug!("Closing idle client {}", client);
+                return None;
+            }
+        }
+    }
+}
+
+/// Writes `first` and the other push messages queued for the client
+fn write_pushes<B: OutBuf>(
+    first: RespValue,
+    pushes: &mut PushReceiver,
+    writer: &mut RespWriter<'_, B>,
+) -> WriteResult {
+    first.write(writer)?;
+    while let Ok(push) = pushes.try_recv() {
+        push.write(writer)?;
+    }
+    Ok(())
+}
+
+/// Serves a connection until the client disconnects or the server shuts
+/// down. Everything logged meanwhile is recorded in a span carrying the
+/// connection id and peer address.
+pub(crate) async fn handle_connection<S: Store>(
+    stream: TcpStream,
+    db: &Arc<Database<S>>,
+    config: &Config,
+) {
+    let client = ClientState::connected(stream.peer_addr());
+    let span = info_span!(
+        "connection",
+        id = client.id(),
+        peer = client.addr().map(field::display)
+    );
+
+    serve(stream, client, db, config).instrument(span).await
+}
+
+async fn serve<S: Store>(
+    stream: TcpStream,
+    mut client: ClientState,
+    db: &Arc<Database<S>>,
+    config: &Config,
+) {
+    debug!("Peer connected {}", client);
+    if is_denied(client.addr().map(|addr| addr.ip()), config) {
+        debug!("Refusing {} in protected mode", client);
+        let mut stream = stream;
+        if let Err(err) = stream.write_all(DENIED_ERR).await {
+            error!("Failed to send response: {:?}", err);
+        }
+        return;
+    }
+
+    let Some(_registration) = db.clients.register(&client, config.maxclients) else {
+        debug!("Refusing {}, maxclients reached", client);
+        let mut stream = stream;
+        if let Err(err) = stream.write_all(MAX_CLIENTS_ERR).await {
+            error!("Failed to send response: {:?}", err);
+        }
+        return;
+    };
+    db.stats
+        .total_connections_received
+        .fetch_add(1, Ordering::Relaxed);
+
+    // Requests are read here while a separate task sends the replies, so a
+    // client that is slow to read doesn't hold up handling its pipeline
+    let (reader, out) = stream.into_split();
+    let mut transport = FramedRead::new(reader, RequestCodec::new(config));
+    let parser_config = config.parser_config();
+    let idle_timeout = (config.timeout > 0).then(|| Duration::from_secs(config.timeout));
+
+    let output_limit = *config.client_output_buffer_limit.get(ClientClass::Normal);
+    let (replies, queue, mut spares) = reply_queue(output_limit, config.reply_buffer_high_water);
+    let writer = tokio::spawn(write_replies(out, queue).in_current_span());
+
+    let (pushes_tx, mut pushes) = push_queue(client.info().clone());
+    client.set_pushes(Some(pushes_tx));
+
+    let mut write_buf = reply_buf(SegmentedBuf::new(), &output_limit);
+    let mut next = next_request(
+        &mut transport,
+        &client,
+        &replies,
+        &mut pushes,
+        &db.shutdown,
+        idle_timeout,
+    )
+    .await;
+    while let Some(result) = next {
+        let mut writer = RespWriter::with_protocol(&mut write_buf, client.protocol);
+        let closing = match result {
+            Ok(req_buf) => {
+                if trace_protocol(config) {
+                    trace!("Received from {}\n{}", client, Trace::new(&req_buf));
+                }
+                !handle_request(req_buf, &parser_config, &mut writer, db, &mut client)
+            }
+            Err(FrameError::Protocol(msg)) => {
+                // The stream can't be framed reliably anymore, so the
+                // connection is closed after telling the client why
+                let start = writer.buffer().len();
+                write_err(RespError::protocol(msg), &mut writer, start);
+                true
+            }
+            Err(err) => {
+                error!("Closing connection {}: {}", client, err);
+                true
+            }
+        };
+
+        // Replies to pipelined requests are collected and queued together
+        // once every request that has already arrived has been handled, or
+        // the buffer grows too large. Once the server is shutting down or the
+        // client has been killed, the rest of the batch is dropped.
+        let full = write_buf.len() >= config.reply_buffer_high_water;
+        next = if closing || full || db.shutdown.is_cancelled() || client.is_killed() {
+            None
+        } else {
+            transport.next().now_or_never().flatten()
+        };
+        if next.is_some() {
+            continue;
+        }
+
+        // Push messages go after the replies of the batch, so that an
+        // invalidation doesn't overtake the reply with the value it
+        // invalidates
+        if let Ok(push) = pushes.try_recv() {
+            let mut writer = RespWriter::with_protocol(&mut write_buf, client.protocol);
+            if write_pushes(push, &mut pushes, &mut writer).is_err() {
+                break;
+            }
+        }
+        if !write_buf.is_empty() {
+            let spare = spares.try_recv().unwrap_or_default();
+            let frame = mem::replace(&mut write_buf, reply_buf(spare, &output_limit)).into_inner();
+            if trace_protocol(config) {
+                trace!("Sending to {}\n{}", client, Trace::new(&frame.to_vec()));
+            }
+            if replies.send(frame).await.is_err() {
+                break;
+            }
+        }
+        reset_write_buf(&mut write_buf, config.reply_buffer_high_water);
+
+        if closing {
+            break;
+        }
+        next = next_request(
+            &mut transport,
+            &client,
+            &replies,
+            &mut pushes,
+            &db.shutdown,
+            idle_timeout,
+        )
+        .await;
+    }
+    db.tracking.disable(client.id());
+
+    // Whatever has been queued is still sent before the connection closes
+    drop(replies);
+    match writer.await {
+        Ok(Ok(())) => {}
+        Ok(Err(FlushError::OutputBufferLimit)) => {
+            warn!("Closing connection {}: output buffer limit reached", client);
+            db.stats
+                .client_output_buffer_limit_disconnections
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(Err(FlushError::Io(err))) => error!("Failed to send response: {:?}", err),
+        Err(err) => error!("Writer of connection {} failed: {}", client, err),
+    }
+
+    debug!("Peer disconnected {}", client);
+}
+
+/// Whether requests and replies are dumped to the log, which takes
+/// `trace-protocol` and the trace level to be enabled
+fn trace_protocol(config: &Config) -> bool {
+    config.trace_protocol && log_enabled!(Level::Trace)
+}
+
+/// Applies per-connection socket options. Failures only degrade latency or
+/// dead peer detection, so they are logged and the connection is kept.
+pub(crate) fn configure_socket(stream: &TcpStream, config: &Config) {
+    if let Err(err) = stream.set_nodelay(true) {
+        warn!("Failed to set TCP_NODELAY: {:?}", err);
+    }
+
+    if config.tcp_keepalive > 0 {
+        let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(config.tcp_keepalive));
+        if let Err(err) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+            warn!("Failed to enable TCP keepalive: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::AtomicUsize,
+        time::Instant,
+    };
+
+    use resp::{types::RespValue, writer::ProtocolVersion};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::test_util::{connect, database, expect_reply, read_to_close, request, run};
+
+    /// Counts allocations so that benchmarks can report allocation rates
+    struct CountingAlloc;
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+
+    #[test]
+    fn test_write_err_fallback() {
+        let mut write_buf = WriteBuf::with_limit(Vec::new(), 32);
+        let mut writer = RespWriter::new(&mut write_buf);
+
+        // A partially written reply is discarded, earlier replies are kept
+        writer.write_value(&RespValue::Integer(1)).unwrap();
+        writer.write_u8(b'$').unwrap();
+        write_err(RespError::err("short"), &mut writer, 4);
+        assert_eq!(writer.buffer().get(), b":1\r\n-ERR short\r\n");
+
+        let err = RespError::err("a message that does not fit into the buffer");
+        write_err(err, &mut writer, 4);
+        assert_eq!(writer.buffer().get(), &[b":1\r\n", FALLBACK_ERR].concat());
+    }
+
+    #[test]
+    fn test_reply_encoding_per_protocol() {
+        let db = database(&[("key", "value")]);
+
+        let inputs = [
+            (ProtocolVersion::Resp2, request(&[b"GET", b"missing"])),
+            (ProtocolVersion::Resp3, request(&[b"GET", b"missing"])),
+            (ProtocolVersion::Resp2, request(&[b"GET", b"key"])),
+            (ProtocolVersion::Resp3, request(&[b"GET", b"key"])),
+        ];
+        let expects: &[&[u8]] = &[b"$-1\r\n", b"_\r\n", b"$5\r\nvalue\r\n", b"$5\r\nvalue\r\n"];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (protocol, req) = &inputs[i];
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::with_protocol(&mut write_buf, *protocol);
+            let mut client = ClientState::new(None);
+            handle_request(
+                BytesMut::from(&req[..]),
+                &ParserConfig::default(),
+                &mut writer,
+                &db,
+                &mut client,
+            );
+            assert_eq!(write_buf.get(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_reply_buffer_limit() {
+        let db = database(&[("key", "value")]);
+        let mut client = ClientState::new(None);
+
+        // Two replies of 11 bytes fill the buffer exactly, a third one is
+        // dropped and closes the connection
+        let mut write_buf = WriteBuf::with_limit(Vec::new(), 22);
+        let mut writer = RespWriter::new(&mut write_buf);
+        let inputs = [
+            request(&[b"GET", b"key"]),
+            request(&[b"GET", b"key"]),
+            request(&[b"GET", b"key"]),
+        ];
+        let expects = [true, true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let open = handle_request(
+                BytesMut::from(&inputs[i][..]),
+                &ParserConfig::default(),
+                &mut writer,
+                &db,
+                &mut client,
+            );
+            assert_eq!(open, expects[i]);
+        }
+        assert_eq!(write_buf.get(), b"$5\r\nvalue\r\n$5\r\nvalue\r\n");
+        assert_eq!(
+            db.stats
+                .client_output_buffer_limit_disconnections
+                .load(Ordering::Relaxed),
+            1
+        );
+
+        // A reply that runs into the limit half way is dropped whole
+        let mut write_buf = WriteBuf::with_limit(Vec::new(), 16);
+        let mut writer = RespWriter::new(&mut write_buf);
+        let req = request(&[b"COMMAND", b"INFO", b"GET", b"SET"]);
+        let open = handle_request(
+            BytesMut::from(&req[..]),
+            &ParserConfig::default(),
+            &mut writer,
+            &db,
+            &mut client,
+        );
+        assert!(!open);
+        assert!(write_buf.is_empty());
+    }
+
+    #[test]
+    fn test_payload_with_line_breaks() {
+        let db = database(&[]);
+        let values: [&[u8]; 4] = [b"a\r\nb", b"a\rb", b"a\nb", b"\r\n*1\r\n$4\r\nPING\r\n"];
+
+        for value in values {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            let mut client = ClientState::new(None);
+            for req in [request(&[b"SET", b"k", value]), request(&[b"GET", b"k"])] {
+                handle_request(
+                    BytesMut::from(&req[..]),
+                    &ParserConfig::default(),
+                    &mut writer,
+                    &db,
+                    &mut client,
+                );
+            }
+
+            let expected = [b"+OK\r\n".to_vec(), request(&[value])[4..].to_vec()].concat();
+            assert_eq!(write_buf.get(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_request_shape() {
+        let db = database(&[]);
+        let inputs: [&[u8]; 8] = [
+            b"*2\r\n$3\r\nGET\r\n:1\r\n",
+            b"*1\r\n:1\r\n",
+            b"*0\r\n",
+            b"*-1\r\n",
+            b"*?\r\n$3\r\nGET\r\n$1\r\nk\r\n.\r\n",
+            // Inline commands
+            b"get 'k'\r\n",
+            b"  \r\n",
+            b":1\r\n",
+        ];
+        let expects: [&[u8]; 8] = [
+            b"-ERR Protocol error: expected '$', got ':'\r\n",
+            b"-ERR Protocol error: expected '$', got ':'\r\n",
+            b"",
+            b"",
+            b"$-1\r\n",
+            b"$-1\r\n",
+            b"",
+            b"-ERR unknown command ':1', with args beginning with: \r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            let mut client = ClientState::new(None);
+            // The request was read whole, so the connection stays open
+            assert!(handle_request(
+                BytesMut::from(inputs[i]),
+                &ParserConfig::default(),
+                &mut writer,
+                &db,
+                &mut client
+            ));
+            assert_eq!(write_buf.get(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let db = database(&[]);
+        let config = Config {
+            proto_max_bulk_len: 4,
+            proto_max_multibulk_len: 2,
+            ..Config::default()
+        };
+        let inputs = [
+            request(&[b"GET", b"k"]),
+            request(&[b"GET", b"k", b"v"]),
+            request(&[b"GET", b"key12"]),
+        ];
+        let expects: [(bool, &[u8]); 3] = [
+            (true, b"$-1\r\n"),
+            (
+                false,
+                b"-ERR Protocol error: aggregate of 3 elements exceeds the limit of 2\r\n",
+            ),
+            (
+                false,
+                b"-ERR Protocol error: bulk string of 5 bytes exceeds the limit of 4\r\n",
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            let mut client = ClientState::new(None);
+            let open = handle_request(
+                BytesMut::from(&inputs[i][..]),
+                &config.parser_config(),
+                &mut writer,
+                &db,
+                &mut client,
+            );
+            assert_eq!((open, &write_buf.get()[..]), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_error_reply_injection() {
+        let db = database(&[]);
+        let inputs = [
+            request(&[b"FOO\r\n+OK", b"k"]),
+            request(&[b"FOO", b"k\r\n+OK"]),
+            request(&[b"CONFIG", b"SET", b"k\r\n+OK", b"1"]),
+        ];
+
+        for req in inputs {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            let mut client = ClientState::new(None);
+            handle_request(
+                BytesMut::from(&req[..]),
+                &ParserConfig::default(),
+                &mut writer,
+                &db,
+                &mut client,
+            );
+
+            // A single error reply, whatever the client sent
+            let mut parser = RespParser::new(write_buf.get());
+            let reply = RespValue::parse(&mut parser).unwrap();
+            assert!(reply.as_error().is_some(), "{:?}", reply);
+            assert!(parser.remaining().is_empty(), "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn test_reset_write_buf() {
+        let mut write_buf = WriteBuf::new(Vec::new());
+        write_buf.push_bytes(&[0; 512]).unwrap();
+        let capacity = write_buf.capacity();
+
+        reset_write_buf(&mut write_buf, 1024);
+        assert!(write_buf.is_empty());
+        assert_eq!(write_buf.capacity(), capacity);
+
+        write_buf.push_bytes(&[0; 64 * 1024]).unwrap();
+        reset_write_buf(&mut write_buf, 1024);
+        assert!(write_buf.is_empty());
+        assert!(write_buf.capacity() <= 1024);
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_reply_buffer_allocations() {
+        const REQUESTS: usize = 100_000;
+
+        let db = database(&[("key", "value")]);
+        let req = request(&[b"GET", b"key"]);
+        let parse = || {
+            let mut parser = RespParser::new(&req);
+            Vec::<&[u8]>::parse(&mut parser).unwrap()
+        };
+
+        let mut client = ClientState::new(None);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..REQUESTS {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            command::dispatch(&parse(), &db, &mut writer, &mut client).unwrap();
+        }
+        let fresh = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+        let mut write_buf = WriteBuf::new(Vec::new());
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..REQUESTS {
+            reset_write_buf(&mut write_buf, Config::default().reply_buffer_high_water);
+            let mut writer = RespWriter::new(&mut write_buf);
+            command::dispatch(&parse(), &db, &mut writer, &mut client).unwrap();
+        }
+        let reused = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+        println!(
+            "allocations per GET: {:.2} with a fresh buffer, {:.2} with a reused buffer",
+            fresh as f64 / REQUESTS as f64,
+            reused as f64 / REQUESTS as f64
+        );
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_request_parse_allocations() {
+        const REQUESTS: usize = 100_000;
+
+        let req = request(&[b"SET", b"key", b"value"]);
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..REQUESTS {
+            let mut parser = RespParser::new(&req);
+            std::hint::black_box(Vec::<resp::types::BulkString>::parse(&mut parser).unwrap());
+        }
+        let owned = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..REQUESTS {
+            let mut parser = RespParser::new(&req);
+            std::hint::black_box(Vec::<&[u8]>::parse(&mut parser).unwrap());
+        }
+        let borrowed = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+        println!(
+            "allocations per SET request: {:.2} owned, {:.2} borrowed",
+            owned as f64 / REQUESTS as f64,
+            borrowed as f64 / REQUESTS as f64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_empty_request_is_ignored() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        client.write_all(b"*0\r\n").await.unwrap();
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$-1\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_inline_line_endings() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        // Inline commands may end with a lone LF, while the lengths of a
+        // multibulk request still decide where its arguments end
+        let pipeline = [
+            &b"sEt key a\n"[..],
+            b"GET key\r\n",
+            &request(&[b"SET", b"key", b"a\nb"]),
+            b"GET key\n",
+            b"  \n",
+            b"del key\n",
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+        expect_reply(
+            &mut client,
+            b"+OK\r\n$1\r\na\r\n+OK\r\n$3\r\na\nb\r\n:1\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_inline_quoted_args() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        // As typed into telnet, with quotes and escapes for what a word
+        // can't hold
+        client
+            .write_all(
+                b"SET greeting \"hello world\"\r\nGET greeting\r\nSET 'a b' \"\\x00\\n\"\r\n",
+            )
+            .await
+            .unwrap();
+        expect_reply(&mut client, b"+OK\r\n$11\r\nhello world\r\n+OK\r\n").await;
+
+        client.write_all(&request(&[b"GET", b"a b"])).await.unwrap();
+        expect_reply(&mut client, b"$2\r\n\x00\n\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_unknown_command_without_args() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        client.write_all(&request(&[b"FOO"])).await.unwrap();
+        expect_reply(
+            &mut client,
+            b"-ERR unknown command 'FOO', with args beginning with: \r\n",
+        )
+        .await;
+
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$-1\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_replies_keep_order() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        let pipeline = [
+            request(&[b"SET", b"key", b"value"]),
+            request(&[b"FOO"]),
+            request(&[b"GET"]),
+            request(&[b"GET", b"key"]),
+            request(&[b"DEL", b"key"]),
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+        expect_reply(
+            &mut client,
+            &[
+                &b"+OK\r\n"[..],
+                b"-ERR unknown command 'FOO', with args beginning with: \r\n",
+                b"-ERR wrong number of arguments for 'get' command\r\n",
+                b"$5\r\nvalue\r\n",
+                b":1\r\n",
+            ]
+            .concat(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_many_pipelined_requests_in_one_write() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        let mut pipeline = Vec::new();
+        let mut replies = Vec::new();
+        for i in 0..1000 {
+            pipeline.extend_from_slice(&request(&[b"INCR", b"counter"]));
+            replies.extend_from_slice(format!(":{}\r\n", i + 1).as_bytes());
+        }
+        client.write_all(&pipeline).await.unwrap();
+        expect_reply(&mut client, &replies).await;
+    }
+
+    #[tokio::test]
+    async fn test_request_split_across_writes() {
+        let mut client = connect(database(&[]), Config::default()).await;
+        client.set_nodelay(true).unwrap();
+
+        // Every byte in a write of its own, so that the server sees the
+        // requests in as many pieces as the network lets through
+        let pipeline = [
+            request(&[b"SET", b"key", b"a\r\nb"]),
+            b"GET key\r\n".to_vec(),
+        ]
+        .concat();
+        for byte in pipeline {
+            client.write_all(&[byte]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        expect_reply(&mut client, b"+OK\r\n$4\r\na\r\nb\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_large_values_between_pipelined_replies() {
+        let mut client = connect(database(&[]), Config::default()).await;
+        let value = vec![b'v'; 256 * 1024];
+        client
+            .write_all(&request(&[b"SET", b"key", &value]))
+            .await
+            .unwrap();
+        expect_reply(&mut client, b"+OK\r\n").await;
+
+        // Sent from the stored value, in between inline replies
+        let pipeline = [
+            request(&[b"GET", b"key"]),
+            request(&[b"GET", b"missing"]),
+            request(&[b"GET", b"key"]),
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+        let bulk = [format!("${}\r\n", value.len()).as_bytes(), &value, b"\r\n"].concat();
+        expect_reply(&mut client, &[&bulk[..], b"$-1\r\n", &bulk].concat()).await;
+    }
+
+    #[tokio::test]
+    async fn test_protocol_error_after_pipelined_replies() {
+        let mut client = connect(database(&[("key", "value")]), Config::default()).await;
+
+        let mut pipeline = request(&[b"GET", b"key"]);
+        pipeline.extend_from_slice(b"*1\r\n+GET\r\n");
+        client.write_all(&pipeline).await.unwrap();
+        assert_eq!(
+            read_to_close(&mut client).await,
+            b"$5\r\nvalue\r\n-ERR Protocol error: expected '$', got '+'\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_request_closes_connection() {
+        // Frames the codec accepts, each followed by a GET that is only
+        // served if the connection stays open
+        let inputs: &[&[u8]] = &[
+            b"*1\r\n$3\r\nGET\r\n",
+            b"*1\r\n$3\r\nGETXY",
+            b"GET \"key\r\n",
+            b"*-2\r\n",
+        ];
+        let expects: &[&[u8]] = &[
+            b"-ERR wrong number of arguments for 'get' command\r\n$-1\r\n",
+            b"-ERR Protocol error: missing CRLF\r\n",
+            b"-ERR Protocol error: unbalanced quotes in request\r\n",
+            b"-ERR Protocol error: invalid length -2\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut client = connect(database(&[]), Config::default()).await;
+
+            let mut pipeline = inputs[i].to_vec();
+            pipeline.extend_from_slice(&request(&[b"GET", b"key"]));
+            client.write_all(&pipeline).await.unwrap();
+            client.shutdown().await.unwrap();
+            assert_eq!(read_to_close(&mut client).await, expects[i]);
+        }
+    }
+
+    fn output_limit(hard: usize, soft: usize, soft_seconds: u64) -> Config {
+        let mut config = Config::default();
+        config.client_output_buffer_limit.normal = OutputBufferLimit {
+            hard,
+            soft,
+            soft_seconds,
+        };
+        config
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_hard_limit() {
+        let db = database(&[("small", "value"), ("large", &"A".repeat(4096))]);
+        let mut client = connect(db.clone(), output_limit(1024, 0, 0)).await;
+
+        client
+            .write_all(&request(&[b"GET", b"small"]))
+            .await
+            .unwrap();
+        expect_reply(&mut client, b"$5\r\nvalue\r\n").await;
+
+        client
+            .write_all(&request(&[b"GET", b"large"]))
+            .await
+            .unwrap();
+        assert_eq!(read_to_close(&mut client).await, b"");
+        assert_eq!(
+            db.stats
+                .client_output_buffer_limit_disconnections
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_soft_limit() {
+        // Large enough to fill the socket buffers of a client that doesn't
+        // read
+        let db = database(&[("key", &"A".repeat(64 * 1024 * 1024))]);
+        let mut client = connect(db.clone(), output_limit(0, 1024, 0)).await;
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+
+        let disconnected = async {
+            while db
+                .stats
+                .client_output_buffer_limit_disconnections
+                .load(Ordering::Relaxed)
+                == 0
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), disconnected)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queued_replies_survive_half_close() {
+        // A tiny reply buffer queues every reply on its own
+        let config = Config {
+            reply_buffer_high_water: 1,
+            ..Config::default()
+        };
+        let mut client = connect(database(&[("key", "value")]), config).await;
+
+        let pipeline = vec![request(&[b"GET", b"key"]); 100].concat();
+        client.write_all(&pipeline).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        assert_eq!(
+            read_to_close(&mut client).await,
+            b"$5\r\nvalue\r\n".repeat(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_reader_does_not_block_requests() {
+        let db = database(&[("key", &"A".repeat(64 * 1024 * 1024))]);
+        let mut client = connect(db.clone(), Config::default()).await;
+
+        // The reply to the GET can't be sent while the client doesn't read,
+        // the SET after it is still handled
+        let pipeline = [
+            request(&[b"GET", b"key"]),
+            request(&[b"SET", b"other", b"value"]),
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+
+        let handled = async {
+            while db
+                .kv_store
+                .read(b"other")
+                .get(b"other".as_slice())
+                .is_none()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), handled)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let db = database(&[("key", "value")]);
+        let mut idle = connect(db.clone(), Config::default()).await;
+        let mut client = connect(db.clone(), Config::default()).await;
+
+        // Requests pipelined after SHUTDOWN are not served
+        let pipeline = [
+            request(&[b"GET", b"key"]),
+            request(&[b"SHUTDOWN", b"NOSAVE"]),
+            request(&[b"GET", b"key"]),
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+        assert_eq!(read_to_close(&mut client).await, b"$5\r\nvalue\r\n");
+        assert!(db.shutdown.is_cancelled());
+
+        assert_eq!(read_to_close(&mut idle).await, b"");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let db = database(&[("key", "value")]);
+        let config = Config {
+            timeout: 1,
+            ..Config::default()
+        };
+        let mut client = connect(db.clone(), config).await;
+        let mut busy = connect(db.clone(), Config::default()).await;
+
+        // A request restarts the timer
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$5\r\nvalue\r\n").await;
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$5\r\nvalue\r\n").await;
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), read_to_close(&mut client));
+        assert_eq!(closed.await.unwrap(), b"");
+
+        // No timeout by default
+        busy.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut busy, b"$5\r\nvalue\r\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_client_kill() {
+        use tokio::io::AsyncReadExt;
+
+        let db = database(&[("key", "value")]);
+        let mut victim = connect(db.clone(), Config::default()).await;
+        let mut client = connect(db.clone(), Config::default()).await;
+
+        victim
+            .write_all(&request(&[b"CLIENT", b"ID"]))
+            .await
+            .unwrap();
+        let mut id = [0; 32];
+        let len = victim.read(&mut id).await.unwrap();
+        let id = &id[1..len - 2];
+
+        client
+            .write_all(&request(&[b"CLIENT", b"KILL", b"ID", id]))
+            .await
+            .unwrap();
+        expect_reply(&mut client, b":1\r\n").await;
+        assert_eq!(read_to_close(&mut victim).await, b"");
+
+        // Killing itself, the reply is still sent but the rest of the
+        // pipeline isn't served
+        let addr = client.local_addr().unwrap().to_string();
+        let pipeline = [
+            request(&[b"CLIENT", b"KILL", b"ID", b"0", b"SKIPME", b"no"]),
+            request(&[
+                b"CLIENT",
+                b"KILL",
+                b"ADDR",
+                addr.as_bytes(),
+                b"SKIPME",
+                b"no",
+            ]),
+            request(&[b"GET", b"key"]),
+        ]
+        .concat();
+        client.write_all(&pipeline).await.unwrap();
+        assert_eq!(read_to_close(&mut client).await, b":0\r\n:1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_client_list() {
+        use resp::parser::parse_complete;
+        use tokio::io::AsyncReadExt;
+
+        async fn client_list(client: &mut TcpStream) -> Vec<String> {
+            client
+                .write_all(&request(&[b"CLIENT", b"LIST"]))
+                .await
+                .unwrap();
+            let mut reply = Vec::new();
+            loop {
+                let mut chunk = [0; 1024];
+                let len = client.read(&mut chunk).await.unwrap();
+                assert_ne!(len, 0, "closed after {:?}", reply);
+                reply.extend_from_slice(&chunk[..len]);
+                if let Ok(list) = parse_complete::<&[u8]>(&reply) {
+                    let list = std::str::from_utf8(list).unwrap();
+                    return list.lines().map(str::to_string).collect();
+                }
+            }
+        }
+
+        let db = database(&[]);
+        let mut named = connect(db.clone(), Config::default()).await;
+        let mut other = connect(db.clone(), Config::default()).await;
+        named
+            .write_all(&request(&[b"CLIENT", b"SETNAME", b"worker-1"]))
+            .await
+            .unwrap();
+        expect_reply(&mut named, b"+OK\r\n").await;
+        other.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut other, b"$-1\r\n").await;
+
+        // One line each, in the order they connected
+        let list = client_list(&mut named).await;
+        assert_eq!(list.len(), 2, "{:?}", list);
+        let addrs = [named.local_addr().unwrap(), other.local_addr().unwrap()];
+        let expects = [("worker-1", "client"), ("", "get")];
+        for i in 0..list.len() {
+            let fields: Vec<&str> = list[i].split(' ').collect();
+            assert_eq!(fields[1], format!("addr={}", addrs[i]));
+            assert_eq!(fields[2], format!("name={}", expects[i].0));
+            assert_eq!(fields[6], format!("cmd={}", expects[i].1));
+        }
+        assert!(
+            db.info(Some(b"clients"))
+                .contains("connected_clients:2\r\n")
+        );
+        assert_eq!(
+            db.stats.total_connections_received.load(Ordering::Relaxed),
+            2
+        );
+
+        // A client that goes away without a word is gone from the list
+        drop(other);
+        let gone = async {
+            while client_list(&mut named).await.len() != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), gone)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_tracking() {
+        use tokio::io::AsyncReadExt;
+
+        let db = database(&[("k", "v")]);
+        let mut client = connect(db.clone(), Config::default()).await;
+        let mut req = request(&[b"HELLO", b"3"]);
+        req.extend(request(&[b"CLIENT", b"TRACKING", b"ON"]));
+        client.write_all(&req).await.unwrap();
+        let mut res = Vec::new();
+        while !res.ends_with(b"+OK\r\n") {
+            let mut buf = [0; 1024];
+            let n = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0, "closed after {:?}", res.escape_ascii().to_string());
+            res.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(db.tracking.len(), 1);
+
+        client.write_all(&request(&[b"GET", b"k"])).await.unwrap();
+        expect_reply(&mut client, b"$1\r\nv\r\n").await;
+
+        // Written by another client while this one waits for requests
+        run(&[b"SET", b"k", b"w"], &db);
+        expect_reply(&mut client, b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n").await;
+
+        // Not read again since, so there is nothing more to invalidate
+        run(&[b"SET", b"k", b"x"], &db);
+        client.write_all(&request(&[b"PING"])).await.unwrap();
+        expect_reply(&mut client, b"+PONG\r\n").await;
+
+        // Tracking ends with the connection
+        drop(client);
+        let gone = async {
+            while db.tracking.is_active() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), gone)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_truncated_request() {
+        // An empty frame is nothing to answer, and keeps the connection open
+        let db = database(&[]);
+        let mut client = ClientState::new(None);
+        let mut write_buf = WriteBuf::new(Vec::new());
+        let mut writer = RespWriter::new(&mut write_buf);
+        let open = handle_request(
+            BytesMut::new(),
+            &ParserConfig::default(),
+            &mut writer,
+            &db,
+            &mut client,
+        );
+        assert!(open);
+        assert!(write_buf.is_empty());
+
+        // A client that closes part way through a request is let go without
+        // a reply, whichever byte it stopped at
+        let frames = [request(&[b"GET", b"key"]), b"GET key\r\n".to_vec()];
+        for frame in frames {
+            for len in 0..frame.len() {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+                    .await
+                    .unwrap();
+                let (accepted, _) = listener.accept().await.unwrap();
+                let db = db.clone();
+                let server = tokio::spawn(async move {
+                    handle_connection(accepted, &db, &Config::default()).await;
+                });
+
+                stream.write_all(&frame[..len]).await.unwrap();
+                stream.shutdown().await.unwrap();
+                assert_eq!(read_to_close(&mut stream).await, b"", "{:?}", &frame[..len]);
+                server.await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_bulk_closes_connection() {
+        let config = Config {
+            proto_max_bulk_len: 1024,
+            ..Config::default()
+        };
+        let mut client = connect(database(&[]), config).await;
+
+        // A value of exactly the limit is accepted
+        let value = vec![b'v'; 1024];
+        client
+            .write_all(&request(&[b"SET", b"key", &value]))
+            .await
+            .unwrap();
+        client
+            .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$1025\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_to_close(&mut client).await,
+            b"+OK\r\n-ERR Protocol error: invalid bulk length\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_huge_declared_bulk_closes_connection() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        client.write_all(b"*1\r\n$9999999999\r\n").await.unwrap();
+        assert_eq!(
+            read_to_close(&mut client).await,
+            b"-ERR Protocol error: invalid bulk length\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_huge_multibulk_closes_connection() {
+        let mut client = connect(database(&[]), Config::default()).await;
+
+        client.write_all(b"*1000000000\r\n").await.unwrap();
+        assert_eq!(
+            read_to_close(&mut client).await,
+            b"-ERR Protocol error: invalid multibulk length\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_buffer_limit_closes_connection() {
+        let config = Config {
+            client_query_buffer_limit: 4096,
+            ..Config::default()
+        };
+        let mut client = connect(database(&[]), config).await;
+
+        // A valid, but never ending request
+        let mut req = b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$100000\r\n".to_vec();
+        req.extend_from_slice(&[b'A'; 8192]);
+        client.write_all(&req).await.unwrap();
+        assert_eq!(read_to_close(&mut client).await, b"");
+    }
+
+    #[tokio::test]
+    async fn test_configure_socket() {
+        let inputs = [300, 0];
+        let expects = [true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config {
+                tcp_keepalive: inputs[i],
+                ..Config::default()
+            };
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap());
+            let (_client, accepted) = tokio::join!(client, listener.accept());
+            let (stream, _) = accepted.unwrap();
+            configure_socket(&stream, &config);
+
+            assert!(stream.nodelay().unwrap());
+            assert_eq!(SockRef::from(&stream).keepalive().unwrap(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_protected_mode() {
+        let inputs = [
+            (Some("127.0.0.1"), true, false),
+            (Some("::1"), true, false),
+            (Some("::ffff:127.0.0.1"), true, false),
+            (Some("10.0.0.5"), true, false),
+            (Some("fe80::1"), true, false),
+            (None, true, false),
+            (Some("10.0.0.5"), false, false),
+            (Some("10.0.0.5"), true, true),
+        ];
+        let expects = [false, false, false, true, true, true, false, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (peer, protected_mode, bind_configured) = inputs[i];
+            let config = Config {
+                protected_mode,
+                bind_configured,
+                ..Config::default()
+            };
+            let peer = peer.map(|ip| ip.parse().unwrap());
+            assert_eq!(is_denied(peer, &config), expects[i]);
+        }
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_large_reply_throughput() {
+        use tokio::io::AsyncReadExt;
+
+        const VALUE_LEN: usize = 10 * 1024 * 1024;
+        const ITERATIONS: usize = 50;
+
+        let db = database(&[("key", &"A".repeat(VALUE_LEN))]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, &db, &Config::default()).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let req = request(&[b"GET", b"key"]);
+        let mut reply = vec![0; format!("${}\r\n", VALUE_LEN).len() + VALUE_LEN + 2];
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            client.write_all(&req).await.unwrap();
+            client.read_exact(&mut reply).await.unwrap();
+        }
+
+        let elapsed = start.elapsed();
+        let mb = (ITERATIONS * VALUE_LEN) as f64 / (1024.0 * 1024.0);
+        println!(
+            "GET of a 10MB value: {:.2} ms/op, {:.0} MB/s",
+            elapsed.as_secs_f64() * 1000.0 / ITERATIONS as f64,
+            mb / elapsed.as_secs_f64()
+        );
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_get_latency() {
+        use tokio::io::AsyncReadExt;
+
+        const ITERATIONS: usize = 20_000;
+
+        for nodelay in [false, true] {
+            let db = database(&[("key", "value")]);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                stream.set_nodelay(nodelay).unwrap();
+                handle_connection(stream, &db, &Config::default()).await;
+            });
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.set_nodelay(nodelay).unwrap();
+            let req = request(&[b"GET", b"key"]);
+            let mut reply = [0; b"$5\r\nvalue\r\n".len()];
+
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                client.write_all(&req).await.unwrap();
+                client.read_exact(&mut reply).await.unwrap();
+            }
+
+            let elapsed = start.elapsed();
+            println!(
+                "GET round trip with TCP_NODELAY {}: {:.1} us/op",
+                if nodelay { "on" } else { "off" },
+                elapsed.as_micros() as f64 / ITERATIONS as f64
+            );
+        }
+    }
+
+    /// Run with `cargo test --release -p resp-server -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_pipelined_get() {
+        use tokio::io::AsyncReadExt;
+
+        const PIPELINE: usize = 64;
+        const BATCHES: usize = 5_000;
+
+        let mut client = connect(database(&[("key", "value")]), Config::default()).await;
+        let req = request(&[b"GET", b"key"]).repeat(PIPELINE);
+        let mut reply = b"$5\r\nvalue\r\n".repeat(PIPELINE);
+
+        let start = Instant::now();
+        for _ in 0..BATCHES {
+            client.write_all(&req).await.unwrap();
+            client.read_exact(&mut reply).await.unwrap();
+        }
+
+        let elapsed = start.elapsed();
+        println!(
+            "Pipelined GET (-P {}): {:.0} GET/s",
+            PIPELINE,
+            (BATCHES * PIPELINE) as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
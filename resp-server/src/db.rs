@@ -0,0 +1,626 @@
+use std::{
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use log::info;
+use parking_lot::{Mutex, RwLock};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::Clients,
+    command::CommandTable,
+    config::{Config, ConfigResult},
+    error::CommandError,
+    latency::format_usec,
+    lazyfree::{Displaced, LazyFree},
+    script::ScriptCache,
+    snapshot::{Snapshot, SnapshotError},
+    store::{Entry, KvStore, Shards, Store, Str, Value},
+    tracking::Tracking,
+};
+
+/// Keys removed under one write lock by the active expiry
+const ACTIVE_EXPIRE_BATCH: usize = 20;
+
+// ===========================================================
+// Database
+// ===========================================================
+
+/// Data and server-wide state shared by every connection
+pub struct Database<S: Store = KvStore> {
+    pub(crate) kv_store: Shards<S>,
+    pub(crate) lazyfree: LazyFree,
+    pub(crate) stats: Stats,
+    pub(crate) persistence: Persistence,
+    pub(crate) commands: CommandTable<S>,
+    pub(crate) clients: Clients,
+    pub(crate) scripts: ScriptCache,
+    pub(crate) tracking: Tracking,
+
+    /// Current configuration. It is replaced as a whole when it changes, so
+    /// whoever holds a snapshot never sees a half applied change.
+    config: RwLock<Arc<Config>>,
+
+    /// Cancelled to stop the server, either by a signal or by SHUTDOWN
+    pub(crate) shutdown: CancellationToken,
+
+    /// When the database was created, for the uptime reported by INFO
+    started: Instant,
+
+    /// Shard the active expiry continues from, so that runs cut short by
+    /// their budget don't leave the last shards waiting
+    expire_shard: AtomicUsize,
+}
+
+impl<S: Store> Database<S> {
+    /// Panics if the `rename-command` directives of `config` can't be
+    /// applied, which [`Config::from_args`] and [`ServerBuilder::build`]
+    /// already refuse
+    ///
+    /// [`ServerBuilder::build`]: crate::ServerBuilder::build
+    pub fn new(kv_store: S, config: &Config) -> Database<S> {
+        Database {
+            kv_store: Shards::new(kv_store),
+            lazyfree: LazyFree::new(),
+            stats: Stats::default(),
+            persistence: Persistence::new(),
+            commands: CommandTable::new(&config.rename_command)
+                .expect("rename-command directives are checked with the configuration"),
+            clients: Clients::default(),
+            scripts: ScriptCache::default(),
+            tracking: Tracking::default(),
+            config: RwLock::new(Arc::new(config.clone())),
+            shutdown: CancellationToken::new(),
+            started: Instant::now(),
+            expire_shard: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    pub(crate) fn set_config(&self, config: Config) {
+        *self.config.write() = Arc::new(config);
+    }
+
+    /// Changes the configuration through a copy of the current one
+    pub(crate) fn update_config(&self, update: impl FnOnce(&mut Config)) {
+        let mut current = self.config.write();
+        let mut config = Config::clone(&current);
+        update(&mut config);
+        *current = Arc::new(config);
+    }
+
+    /// Builds the configuration again from the command line the current one
+    /// was built from, re-reading its config file, and switches to it.
+    /// Directives that can't change at runtime keep their values.
+    pub fn reload_config(&self) -> ConfigResult<Arc<Config>> {
+        let loaded = Config::from_args(&self.config().command_line)?;
+
+        let mut current = self.config.write();
+        let config = Arc::new(current.reloaded(loaded));
+        *current = config.clone();
+        Ok(config)
+    }
+
+    /// Saves the store as a snapshot to the configured path, returning the
+    /// number of keys saved. Every shard is locked while the entries are
+    /// copied, so that the snapshot is of one point in time, encoding and
+    /// writing the file happen after.
+    pub fn save(&self) -> Result<usize, SnapshotError> {
+        let _saving = self.persistence.saving.lock();
+        let path = self.config().snapshot_path();
+        let (snapshot, dirty) = {
+            let shards = self.kv_store.read_all();
+            (
+                Snapshot::of_stores(&shards.stores()),
+                self.persistence.dirty.load(Ordering::Relaxed),
+            )
+        };
+
+        Snapshot::write(&path, &snapshot.to_bytes())?;
+        // Writes made while saving count towards the next save
+        self.persistence.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        self.persistence
+            .last_save
+            .store(unix_time(SystemTime::now()), Ordering::Relaxed);
+        info!(
+            "DB saved on disk: {} keys to {}",
+            snapshot.entries.len(),
+            path.display()
+        );
+        Ok(snapshot.entries.len())
+    }
+
+    /// Removes keys that have expired at `now` without being looked up, a
+    /// shard after the other and in batches so that a write lock is only
+    /// held briefly at a time, until none are left or `budget` is spent.
+    /// The next call continues from the shard this one stopped at. Returns
+    /// the number of keys removed.
+    pub(crate) fn remove_expired_keys(&self, now: SystemTime, budget: Duration) -> usize {
+        let start = Instant::now();
+        let config = self.config();
+        let mut removed = 0;
+        let shards: Vec<_> = self.kv_store.iter().collect();
+        let first = self.expire_shard.load(Ordering::Relaxed) % shards.len();
+        let mut index = first;
+        loop {
+            let (keys, expired): (Vec<_>, Vec<_>) = {
+                let mut store = shards[index].write();
+                store
+                    .expired_keys(now, ACTIVE_EXPIRE_BATCH)
+                    .into_iter()
+                    .filter_map(|key| store.remove(&key).map(|entry| (key, entry)))
+                    .unzip()
+            };
+            self.tracking
+                .invalidate(&keys.iter().map(|key| &key[..]).collect::<Vec<_>>());
+            removed += expired.len();
+            let done = expired.len() < ACTIVE_EXPIRE_BATCH;
+            for entry in expired {
+                self.lazyfree
+                    .free(Displaced::<S>::Deleted(entry.value), &config);
+            }
+
+            if done {
+                index = (index + 1) % shards.len();
+                if index == first {
+                    break;
+                }
+            }
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        self.expire_shard.store(index, Ordering::Relaxed);
+
+        self.stats
+            .expired_keys
+            .fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// The string under `key`, unless it has expired, or WRONGTYPE for a
+    /// value of another type. A compressed string is returned as such, to be
+    /// decompressed once the lock is released.
+    pub(crate) fn get_str(&self, key: &[u8]) -> Result<Option<Str>, CommandError> {
+        let store = self.kv_store.read(key);
+        let entry = store.get(key);
+        self.stats.record_lookup(entry.is_some());
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        entry.last_access.touch();
+        match &entry.value {
+            Value::Str(value) => Ok(Some(value.clone())),
+            _ => Err(CommandError::WrongType),
+        }
+    }
+
+    /// Counts the keys that exist and haven't expired, a key given more
+    /// than once as many times
+    pub(crate) fn exists(&self, keys: &[&[u8]]) -> usize {
+        let shards = self.kv_store.read_keys(keys.iter().copied());
+        keys.iter()
+            .filter(|key| shards.shard(key).get(key).is_some())
+            .count()
+    }
+
+    /// Stores `entry` under `key`, returning the entry it replaced, if any,
+    /// for the caller to free once the lock is released
+    pub(crate) fn set(&self, key: Bytes, entry: Entry) -> Option<Entry> {
+        self.kv_store.write(&key).insert(key, entry)
+    }
+
+    /// Removes `keys` with the shards holding them all locked, returning
+    /// the entries removed. Those that had expired are returned as well,
+    /// since they still have to be freed.
+    pub(crate) fn del(&self, keys: &[&[u8]]) -> Vec<Entry> {
+        let mut shards = self.kv_store.write_keys(keys.iter().copied());
+        keys.iter()
+            .filter_map(|key| shards.shard_mut(key).remove(key))
+            .collect()
+    }
+
+    /// Renders the requested INFO section, the default sections if `section`
+    /// is `None`. Unknown sections are empty, as in Redis.
+    pub(crate) fn info(&self, section: Option<&[u8]>) -> String {
+        // Name, fields and whether the section is one of the default ones
+        let sections = [
+            ("Server", self.server_info(), true),
+            (
+                "Clients",
+                format!(
+                    "connected_clients:{}\r\ntracking_clients:{}\r\n",
+                    self.clients.len(),
+                    self.tracking.len()
+                ),
+                true,
+            ),
+            (
+                "Memory",
+                format!(
+                    "number_of_cached_scripts:{}\r\nlazyfree_pending_objects:{}\r\n",
+                    self.scripts.len(),
+                    self.lazyfree.pending_objects()
+                ),
+                true,
+            ),
+            ("Stats", self.stats.info(), true),
+            ("Latencystats", self.latency_info(), false),
+            ("Keyspace", self.keyspace_info(), true),
+        ];
+
+        let is = |name: &[u8]| section.is_some_and(|section| section.eq_ignore_ascii_case(name));
+        let all = is(b"all") || is(b"everything");
+        let default = section.is_none() || is(b"default");
+
+        sections
+            .iter()
+            .filter(|(name, _, is_default)| all || (default && *is_default) || is(name.as_bytes()))
+            .map(|(name, fields, _)| format!("# {}\r\n{}", name, fields))
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
+
+    /// Counts of the keys in the database, from counters kept by the store
+    /// rather than a scan. There is only database 0, and like in Redis it is
+    /// left out while empty.
+    fn keyspace_info(&self) -> String {
+        let store = &self.kv_store;
+        if store.is_empty() {
+            return String::new();
+        }
+        format!(
+            "db0:keys={},expires={},avg_ttl={}\r\n",
+            store.len(),
+            store.expires(),
+            store.avg_ttl(SystemTime::now())
+        )
+    }
+
+    /// Latency percentiles of every command called since the last reset
+    fn latency_info(&self) -> String {
+        self.commands
+            .called()
+            .iter()
+            .map(|command| {
+                let percentiles = [("p50", 50.0), ("p99", 99.0), ("p99.9", 99.9)]
+                    .iter()
+                    .filter_map(|(name, percentile)| {
+                        let nanos = command.latency.percentile(*percentile)?;
+                        Some(format!("{}={}", name, format_usec(nanos)))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(
+                    "latency_percentiles_usec_{}:{}\r\n",
+                    String::from_utf8_lossy(&command.name),
+                    percentiles
+                )
+            })
+            .collect()
+    }
+
+    /// Resets the counters reported by INFO and the latency histograms, for
+    /// CONFIG RESETSTAT
+    pub(crate) fn reset_stats(&self) {
+        self.stats.reset();
+        self.commands.reset_latency();
+    }
+
+    /// Describes the server process and the runtime it runs on
+    fn server_info(&self) -> String {
+        format!(
+            "redis_version:{}\r\nprocess_id:{}\r\nuptime_in_seconds:{}\r\n{}",
+            env!("CARGO_PKG_VERSION"),
+            process::id(),
+            self.started.elapsed().as_secs(),
+            runtime_info()
+        )
+    }
+}
+
+/// Describes the runtime the server runs on, whichever way it was built.
+/// Empty outside of a runtime.
+fn runtime_info() -> String {
+    let Ok(handle) = Handle::try_current() else {
+        return String::new();
+    };
+
+    let flavor = match handle.runtime_flavor() {
+        RuntimeFlavor::CurrentThread => "current_thread",
+        RuntimeFlavor::MultiThread => "multi_thread",
+        _ => "unknown",
+    };
+    format!(
+        "tokio_runtime:{}\r\nio_threads:{}\r\n",
+        flavor,
+        handle.metrics().num_workers()
+    )
+}
+
+// ===========================================================
+// Persistence
+// ===========================================================
+
+/// State of the snapshot saves
+pub(crate) struct Persistence {
+    /// Writes since the last save
+    pub(crate) dirty: AtomicU64,
+
+    /// When the last save completed, in unix seconds. Starting counts as a
+    /// save, as in Redis.
+    pub(crate) last_save: AtomicU64,
+
+    /// Whether a shutdown saves regardless of the save points, set by
+    /// SHUTDOWN SAVE or NOSAVE. Otherwise it saves if any are configured.
+    pub(crate) shutdown_save: Mutex<Option<bool>>,
+
+    /// Set once SHUTDOWN has saved the snapshot itself, so that the final
+    /// save only happens if something was written since
+    pub(crate) shutdown_saved: AtomicBool,
+
+    /// Held by the save in progress, saves share the temporary file
+    saving: Mutex<()>,
+}
+
+impl Persistence {
+    fn new() -> Persistence {
+        Persistence {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(unix_time(SystemTime::now())),
+            shutdown_save: Mutex::new(None),
+            shutdown_saved: AtomicBool::new(false),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Whether a save point of `config` has been reached at `now`
+    pub(crate) fn is_due(&self, config: &Config, now: SystemTime) -> bool {
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        let elapsed = unix_time(now).saturating_sub(self.last_save.load(Ordering::Relaxed));
+        config
+            .save
+            .iter()
+            .any(|point| dirty >= point.changes && elapsed >= point.seconds)
+    }
+
+    /// Whether the server saves before it exits
+    pub(crate) fn saves_on_shutdown(&self, config: &Config) -> bool {
+        self.shutdown_save.lock().unwrap_or(!config.save.is_empty())
+    }
+
+    /// Whether the server still has to save before it exits, which it
+    /// doesn't if SHUTDOWN saved and nothing was written since
+    pub(crate) fn needs_final_save(&self, config: &Config) -> bool {
+        self.saves_on_shutdown(config)
+            && (!self.shutdown_saved.load(Ordering::Relaxed)
+                || self.dirty.load(Ordering::Relaxed) > 0)
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// ===========================================================
+// Stats
+// ===========================================================
+
+/// Server-wide counters reported by INFO
+#[derive(Default)]
+pub(crate) struct Stats {
+    /// Connections accepted past the protected mode and maxclients checks
+    pub(crate) total_connections_received: AtomicU64,
+
+    /// Commands run, not counting unknown commands or a wrong arity
+    pub(crate) total_commands_processed: AtomicU64,
+
+    /// Keys removed because they expired, whether looked up or not
+    pub(crate) expired_keys: AtomicU64,
+
+    /// Reads of a key that found it, whatever its type, or didn't
+    pub(crate) keyspace_hits: AtomicU64,
+    pub(crate) keyspace_misses: AtomicU64,
+
+    pub(crate) client_output_buffer_limit_disconnections: AtomicU64,
+}
+
+impl Stats {
+    /// Counts a read of a key towards the hits or the misses
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counters(&self) -> [(&'static str, &AtomicU64); 6] {
+        [
+            (
+                "total_connections_received",
+                &self.total_connections_received,
+            ),
+            ("total_commands_processed", &self.total_commands_processed),
+            ("expired_keys", &self.expired_keys),
+            ("keyspace_hits", &self.keyspace_hits),
+            ("keyspace_misses", &self.keyspace_misses),
+            (
+                "client_output_buffer_limit_disconnections",
+                &self.client_output_buffer_limit_disconnections,
+            ),
+        ]
+    }
+
+    fn reset(&self) {
+        for (_, counter) in self.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn info(&self) -> String {
+        self.counters()
+            .iter()
+            .map(|(name, counter)| format!("{}:{}\r\n", name, counter.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use bytes::Bytes;
+    use tokio::runtime::Builder;
+
+    use super::*;
+    use crate::store::Entry;
+
+    #[test]
+    fn test_remove_expired_keys() {
+        let db = Database::new(KvStore::default(), &Config::default());
+        let now = SystemTime::now();
+        for i in 0..50 {
+            let expires_at = now - Duration::from_secs(i);
+            db.set(
+                Bytes::from(format!("expired:{}", i)),
+                Entry {
+                    expires_at: Some(expires_at),
+                    ..Entry::new(Bytes::from("v"))
+                },
+            );
+        }
+        db.set(
+            Bytes::from("expiring"),
+            Entry {
+                expires_at: Some(now + Duration::from_secs(60)),
+                ..Entry::new(Bytes::from("v"))
+            },
+        );
+        db.set(Bytes::from("key"), Entry::new(Bytes::from("v")));
+
+        // Without a budget a single batch is removed, from a single shard
+        let first = db.remove_expired_keys(now, Duration::ZERO);
+        assert!(first <= ACTIVE_EXPIRE_BATCH, "{}", first);
+        assert_eq!(db.kv_store.len(), 52 - first);
+
+        // Each run continues from the shard the last one stopped at, so runs
+        // without a budget get through every shard in the end
+        let mut removed = first;
+        for _ in 0..64 {
+            removed += db.remove_expired_keys(now, Duration::ZERO);
+        }
+        assert_eq!(removed, 50);
+        assert_eq!(db.remove_expired_keys(now, Duration::MAX), 0);
+        assert_eq!((db.kv_store.len(), db.kv_store.expires()), (2, 1));
+        assert_eq!(db.stats.expired_keys.load(Ordering::Relaxed), 50);
+    }
+
+    #[test]
+    fn test_save_points() {
+        let config = Config::from_args(["--save", "3600", "1", "60", "100"]).unwrap();
+        let persistence = Persistence::new();
+        let last_save = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        persistence
+            .last_save
+            .store(unix_time(last_save), Ordering::Relaxed);
+
+        // Writes since the last save and seconds since
+        let inputs = [
+            (0, 7200),
+            (1, 3599),
+            (1, 3600),
+            (99, 600),
+            (100, 60),
+            (100, 59),
+        ];
+        let expects = [false, false, true, false, true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (dirty, elapsed) = inputs[i];
+            persistence.dirty.store(dirty, Ordering::Relaxed);
+            let now = last_save + Duration::from_secs(elapsed);
+            assert_eq!(
+                persistence.is_due(&config, now),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+            assert!(!persistence.is_due(&Config::default(), now));
+        }
+
+        assert!(persistence.saves_on_shutdown(&config));
+        assert!(!persistence.saves_on_shutdown(&Config::default()));
+        *persistence.shutdown_save.lock() = Some(false);
+        assert!(!persistence.saves_on_shutdown(&config));
+    }
+
+    #[test]
+    fn test_runtime_info() {
+        let inputs = [
+            Builder::new_current_thread().build().unwrap(),
+            Builder::new_multi_thread()
+                .worker_threads(3)
+                .build()
+                .unwrap(),
+        ];
+        let expects = [
+            "tokio_runtime:current_thread\r\nio_threads:1\r\n",
+            "tokio_runtime:multi_thread\r\nio_threads:3\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i].block_on(async { runtime_info() }), expects[i]);
+        }
+        assert_eq!(runtime_info(), "");
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let path = env::temp_dir().join(format!("resp-server-test-{}-reload.conf", process::id()));
+        fs::write(&path, "port 7000\ntcp-keepalive 300\n").unwrap();
+        let config = Config::from_args([path.to_str().unwrap()]).unwrap();
+        let db = Database::new(KvStore::new(), &config);
+
+        let before = db.config();
+        fs::write(&path, "port 7001\ntcp-keepalive 60\nprotected-mode no\n").unwrap();
+        let reloaded = db.reload_config().unwrap();
+
+        // A snapshot taken before is left alone
+        assert_eq!(
+            (before.port, before.tcp_keepalive, before.protected_mode),
+            (7000, 300, true)
+        );
+        assert_eq!(
+            (
+                reloaded.port,
+                reloaded.tcp_keepalive,
+                reloaded.protected_mode
+            ),
+            (7000, 60, false)
+        );
+        assert!(Arc::ptr_eq(&reloaded, &db.config()));
+
+        // A file that doesn't load changes nothing
+        fs::write(&path, "tcp-keepalive soon\n").unwrap();
+        assert!(db.reload_config().is_err());
+        assert!(Arc::ptr_eq(&reloaded, &db.config()));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
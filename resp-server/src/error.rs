@@ -0,0 +1,411 @@
+use std::{error, fmt};
+
+use bytes::Bytes;
+use resp::types::{ErrorCode, RespError, RespValue};
+
+// ===========================================================
+// CommandError
+// ===========================================================
+
+/// Longest part of a client-supplied name or argument list that is echoed
+/// back in an error, as in Redis
+const MAX_ECHOED_LEN: usize = 128;
+
+/// Error strings are a single line, so line breaks in client data are
+/// replaced
+fn sanitize(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).replace(['\r', '\n'], " ")
+}
+
+/// Error reply of a command, rendered in the same format as Redis so that
+/// clients can rely on the error prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    UnknownCommand {
+        name: Bytes,
+        args: Vec<Bytes>,
+    },
+
+    UnknownSubcommand {
+        command: &'static str,
+        subcommand: Bytes,
+    },
+
+    WrongArity {
+        command: &'static str,
+    },
+
+    /// The key holds a value of a different type than the command works on
+    WrongType,
+
+    /// An argument or a stored value is not a 64 bit signed integer
+    NotAnInteger,
+
+    /// An argument is not a finite floating point number
+    NotAFloat,
+
+    Syntax,
+
+    InvalidCursor,
+
+    /// An option keyword the command doesn't know, for commands that report
+    /// it by name rather than as a syntax error
+    UnsupportedOption {
+        option: Bytes,
+    },
+
+    /// Options that exclude each other, e.g. "GT and LT"
+    IncompatibleOptions {
+        options: &'static str,
+    },
+
+    /// An expire time that overflows once converted to milliseconds since
+    /// the epoch
+    InvalidExpireTime {
+        command: &'static str,
+    },
+
+    /// An increment that takes an integer out of the 64 bit range
+    Overflow {
+        reason: &'static str,
+    },
+
+    /// APPEND past the largest string a client could send
+    StringTooLong,
+
+    /// The number of keys of EVALSHA is negative or more than there are
+    /// arguments
+    InvalidNumKeys {
+        reason: &'static str,
+    },
+
+    /// EVALSHA of a script that isn't cached, which clients answer by
+    /// sending the script again with EVAL
+    NoScript,
+
+    /// EVALSHA of a cached script, which there is no engine to run
+    NoScriptEngine,
+
+    /// DEBUG RELOAD of a snapshot that can't be decoded
+    ReloadFailed,
+
+    /// SAVE that failed to write the snapshot
+    SaveFailed,
+
+    /// SHUTDOWN whose save failed, without FORCE to exit anyway
+    ShutdownFailed,
+
+    /// CLIENT SETNAME of a name that isn't a single printable word
+    InvalidClientName,
+
+    /// CLIENT KILL of an address no client is connected from
+    NoSuchClient,
+
+    /// CLIENT TRACKING ON over RESP2, which has no push messages to carry
+    /// invalidations
+    TrackingNeedsResp3,
+
+    /// CLIENT TRACKING ON with prefixes but without BCAST
+    PrefixNeedsBcast,
+
+    /// HELLO with a protocol version that isn't an integer
+    InvalidProtocolVersion,
+
+    /// HELLO with a protocol version other than 2 or 3. Clients fall back
+    /// to RESP2 on the NOPROTO code.
+    NoProto,
+
+    /// HELLO with an option it doesn't know or without its values
+    HelloOption {
+        option: Bytes,
+    },
+
+    /// AUTH of a user other than the default one, which is the only user
+    WrongPass,
+
+    /// COMMAND GETKEYS of a call it can't tell the keys of
+    GetKeys {
+        reason: &'static str,
+    },
+
+    /// CONFIG SET of a parameter that doesn't exist or can't be changed at
+    /// runtime
+    UnknownConfig {
+        parameter: Bytes,
+    },
+
+    InvalidConfig {
+        parameter: Bytes,
+        reason: &'static str,
+    },
+}
+
+/// Echoes at most `MAX_ECHOED_LEN` bytes of `data`
+fn echoed(data: &[u8]) -> String {
+    sanitize(&data[..data.len().min(MAX_ECHOED_LEN)])
+}
+
+impl From<&CommandError> for RespError {
+    fn from(err: &CommandError) -> RespError {
+        match err {
+            CommandError::UnknownCommand { name, args } => {
+                let mut args_echoed = Vec::new();
+                for arg in args.iter() {
+                    if args_echoed.len() >= MAX_ECHOED_LEN {
+                        break;
+                    }
+
+                    let len = arg.len().min(MAX_ECHOED_LEN - args_echoed.len());
+                    args_echoed.push(b'\'');
+                    args_echoed.extend_from_slice(&arg[..len]);
+                    args_echoed.extend_from_slice(b"' ");
+                }
+
+                RespError::err(format!(
+                    "unknown command '{}', with args beginning with: {}",
+                    echoed(name),
+                    sanitize(&args_echoed)
+                ))
+            }
+            CommandError::UnknownSubcommand {
+                command,
+                subcommand,
+            } => RespError::err(format!(
+                "unknown subcommand '{}'. Try {} HELP.",
+                echoed(subcommand),
+                command.to_uppercase()
+            )),
+            CommandError::WrongArity { command } => RespError::wrong_arity(command),
+            CommandError::WrongType => RespError::wrong_type(),
+            CommandError::NotAnInteger => RespError::not_an_integer(),
+            CommandError::NotAFloat => RespError::not_a_float(),
+            CommandError::Syntax => RespError::syntax(),
+            CommandError::InvalidCursor => RespError::err("invalid cursor"),
+            CommandError::UnsupportedOption { option } => {
+                RespError::err(format!("Unsupported option {}", echoed(option)))
+            }
+            CommandError::IncompatibleOptions { options } => RespError::err(format!(
+                "{} options at the same time are not compatible",
+                options
+            )),
+            CommandError::InvalidExpireTime { command } => {
+                RespError::err(format!("invalid expire time in '{}' command", command))
+            }
+            CommandError::Overflow { reason } => RespError::err(*reason),
+            CommandError::StringTooLong => {
+                RespError::err("string exceeds maximum allowed size (proto-max-bulk-len)")
+            }
+            CommandError::InvalidNumKeys { reason } => RespError::err(*reason),
+            CommandError::NoScript => {
+                RespError::new(ErrorCode::NoScript, "No matching script. Please use EVAL.")
+            }
+            CommandError::NoScriptEngine => {
+                RespError::err("This server caches scripts but can't run them")
+            }
+            CommandError::ReloadFailed => {
+                RespError::err("Error trying to load the RDB dump, check server logs.")
+            }
+            CommandError::SaveFailed => {
+                RespError::err("Error trying to save the snapshot, check server logs.")
+            }
+            CommandError::ShutdownFailed => {
+                RespError::err("Errors trying to SHUTDOWN. Check logs.")
+            }
+            CommandError::InvalidClientName => RespError::err(
+                "Client names cannot contain spaces, newlines or special characters.",
+            ),
+            CommandError::NoSuchClient => RespError::err("No such client"),
+            CommandError::TrackingNeedsResp3 => {
+                RespError::err("Client tracking takes RESP3, switch with HELLO 3")
+            }
+            CommandError::PrefixNeedsBcast => {
+                RespError::err("PREFIX option requires BCAST mode to be enabled")
+            }
+            CommandError::InvalidProtocolVersion => {
+                RespError::err("Protocol version is not an integer or out of range")
+            }
+            CommandError::NoProto => {
+                RespError::new(ErrorCode::NoProto, "unsupported protocol version")
+            }
+            CommandError::HelloOption { option } => {
+                RespError::err(format!("Syntax error in HELLO option '{}'", echoed(option)))
+            }
+            CommandError::WrongPass => RespError::new(
+                ErrorCode::WrongPass,
+                "invalid username-password pair or user is disabled.",
+            ),
+            CommandError::GetKeys { reason } => RespError::err(*reason),
+            CommandError::UnknownConfig { parameter } => RespError::err(format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                echoed(parameter)
+            )),
+            CommandError::InvalidConfig { parameter, reason } => RespError::err(format!(
+                "CONFIG SET failed (possibly related to argument '{}') - {}",
+                echoed(parameter),
+                reason
+            )),
+        }
+    }
+}
+
+impl From<CommandError> for RespError {
+    fn from(err: CommandError) -> RespError {
+        RespError::from(&err)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        RespError::from(self).fmt(f)
+    }
+}
+
+impl error::Error for CommandError {}
+
+impl From<CommandError> for RespValue {
+    fn from(err: CommandError) -> RespValue {
+        RespValue::Error(err.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use resp::{
+        types::RespWritable,
+        writer::{RespWriter, WriteBuf},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_wire_format() {
+        let long = Bytes::from(vec![b'a'; 200]);
+        let inputs = [
+            CommandError::UnknownCommand {
+                name: Bytes::from("FOO"),
+                args: vec![],
+            },
+            CommandError::UnknownCommand {
+                name: Bytes::from("foo"),
+                args: vec![Bytes::from("a"), Bytes::from("b")],
+            },
+            CommandError::UnknownCommand {
+                name: Bytes::from("FOO"),
+                args: vec![Bytes::from("line\r\nbreak")],
+            },
+            CommandError::UnknownCommand {
+                name: Bytes::from("FOO"),
+                args: vec![long.clone(), Bytes::from("b")],
+            },
+            CommandError::UnknownCommand {
+                name: long,
+                args: vec![],
+            },
+            CommandError::UnknownSubcommand {
+                command: "command",
+                subcommand: Bytes::from("foo"),
+            },
+            CommandError::WrongArity { command: "get" },
+            CommandError::WrongType,
+            CommandError::NotAnInteger,
+            CommandError::NotAFloat,
+            CommandError::Syntax,
+            CommandError::InvalidCursor,
+            CommandError::UnsupportedOption {
+                option: Bytes::from("KEEPTTL"),
+            },
+            CommandError::IncompatibleOptions {
+                options: "GT and LT",
+            },
+            CommandError::InvalidExpireTime { command: "expire" },
+            CommandError::Overflow {
+                reason: "increment or decrement would overflow",
+            },
+            CommandError::StringTooLong,
+            CommandError::InvalidNumKeys {
+                reason: "Number of keys can't be negative",
+            },
+            CommandError::NoScript,
+            CommandError::NoScriptEngine,
+            CommandError::ReloadFailed,
+            CommandError::SaveFailed,
+            CommandError::ShutdownFailed,
+            CommandError::InvalidClientName,
+            CommandError::NoSuchClient,
+            CommandError::TrackingNeedsResp3,
+            CommandError::PrefixNeedsBcast,
+            CommandError::InvalidProtocolVersion,
+            CommandError::NoProto,
+            CommandError::HelloOption {
+                option: Bytes::from("FOO"),
+            },
+            CommandError::WrongPass,
+            CommandError::GetKeys {
+                reason: "The command has no key arguments",
+            },
+            CommandError::UnknownConfig {
+                parameter: Bytes::from("maxmemory"),
+            },
+            CommandError::InvalidConfig {
+                parameter: Bytes::from("protected-mode"),
+                reason: "argument must be 'yes' or 'no'",
+            },
+        ];
+        let expects = [
+            "-ERR unknown command 'FOO', with args beginning with: \r\n".to_string(),
+            "-ERR unknown command 'foo', with args beginning with: 'a' 'b' \r\n".to_string(),
+            "-ERR unknown command 'FOO', with args beginning with: 'line  break' \r\n".to_string(),
+            format!(
+                "-ERR unknown command 'FOO', with args beginning with: '{}' \r\n",
+                "a".repeat(128)
+            ),
+            format!(
+                "-ERR unknown command '{}', with args beginning with: \r\n",
+                "a".repeat(128)
+            ),
+            "-ERR unknown subcommand 'foo'. Try COMMAND HELP.\r\n".to_string(),
+            "-ERR wrong number of arguments for 'get' command\r\n".to_string(),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_string(),
+            "-ERR value is not an integer or out of range\r\n".to_string(),
+            "-ERR value is not a valid float\r\n".to_string(),
+            "-ERR syntax error\r\n".to_string(),
+            "-ERR invalid cursor\r\n".to_string(),
+            "-ERR Unsupported option KEEPTTL\r\n".to_string(),
+            "-ERR GT and LT options at the same time are not compatible\r\n".to_string(),
+            "-ERR invalid expire time in 'expire' command\r\n".to_string(),
+            "-ERR increment or decrement would overflow\r\n".to_string(),
+            "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n".to_string(),
+            "-ERR Number of keys can't be negative\r\n".to_string(),
+            "-NOSCRIPT No matching script. Please use EVAL.\r\n".to_string(),
+            "-ERR This server caches scripts but can't run them\r\n".to_string(),
+            "-ERR Error trying to load the RDB dump, check server logs.\r\n".to_string(),
+            "-ERR Error trying to save the snapshot, check server logs.\r\n".to_string(),
+            "-ERR Errors trying to SHUTDOWN. Check logs.\r\n".to_string(),
+            "-ERR Client names cannot contain spaces, newlines or special characters.\r\n"
+                .to_string(),
+            "-ERR No such client\r\n".to_string(),
+            "-ERR Client tracking takes RESP3, switch with HELLO 3\r\n".to_string(),
+            "-ERR PREFIX option requires BCAST mode to be enabled\r\n".to_string(),
+            "-ERR Protocol version is not an integer or out of range\r\n".to_string(),
+            "-NOPROTO unsupported protocol version\r\n".to_string(),
+            "-ERR Syntax error in HELLO option 'FOO'\r\n".to_string(),
+            "-WRONGPASS invalid username-password pair or user is disabled.\r\n".to_string(),
+            "-ERR The command has no key arguments\r\n".to_string(),
+            "-ERR Unknown option or number of arguments for CONFIG SET - 'maxmemory'\r\n"
+                .to_string(),
+            "-ERR CONFIG SET failed (possibly related to argument 'protected-mode') - argument \
+             must be 'yes' or 'no'\r\n"
+                .to_string(),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut write_buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::new(&mut write_buf);
+            RespValue::from(inputs[i].clone())
+                .write(&mut writer)
+                .unwrap();
+            assert_eq!(write_buf.get(), expects[i].as_bytes());
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// ===========================================================
+// Histogram
+// ===========================================================
+
+/// Buckets per power of two. Four keeps the reported percentiles within 25%
+/// of the actual latency.
+const SUB_BUCKETS: usize = 4;
+
+/// Latencies from 2^40 ns, about 18 minutes, on land in the last bucket
+const MAX_BITS: usize = 40;
+
+const BUCKETS: usize = (MAX_BITS - 1) * SUB_BUCKETS;
+
+/// Distribution of command execution times in nanoseconds, over fixed
+/// log-scaled buckets. Recording is a single atomic increment, so it can be
+/// done on every call without locking or allocating.
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+}
+
+impl Histogram {
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket(nanos)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Latency in nanoseconds that `percentile` percent of the recorded
+    /// calls didn't exceed, rounded up to the end of its bucket. `None`
+    /// before anything has been recorded.
+    pub(crate) fn percentile(&self, percentile: f64) -> Option<u64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0 * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return Some(upper_bound(i));
+            }
+        }
+
+        // Calls recorded while scanning can leave the rank out of reach
+        Some(upper_bound(BUCKETS - 1))
+    }
+
+    pub(crate) fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Bucket of a latency. Values below `SUB_BUCKETS` get a bucket each, above
+/// that every power of two is split into `SUB_BUCKETS` buckets.
+fn bucket(nanos: u64) -> usize {
+    let nanos = nanos.min((1 << MAX_BITS) - 1);
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+
+    let msb = 63 - nanos.leading_zeros() as usize;
+    let sub = (nanos >> (msb - 2)) as usize & (SUB_BUCKETS - 1);
+    (msb - 1) * SUB_BUCKETS + sub
+}
+
+/// Smallest latency that is past `bucket`
+fn upper_bound(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64 + 1;
+    }
+
+    let msb = bucket / SUB_BUCKETS + 1;
+    let sub = (bucket % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + sub + 1) << (msb - 2)
+}
+
+/// Renders nanoseconds as microseconds, the unit Redis reports latency
+/// percentiles in
+pub(crate) fn format_usec(nanos: u64) -> String {
+    format!("{:.3}", nanos as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let inputs = [0, 3, 4, 7, 8, 9, 10, 12, 15, 16, 1000, 1 << 50, u64::MAX];
+        let expects = [
+            (0, 1),
+            (3, 4),
+            (4, 5),
+            (7, 8),
+            (8, 10),
+            (8, 10),
+            (9, 12),
+            (10, 14),
+            (11, 16),
+            (12, 20),
+            (35, 1024),
+            (BUCKETS - 1, 1 << MAX_BITS),
+            (BUCKETS - 1, 1 << MAX_BITS),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let bucket = bucket(inputs[i]);
+            assert_eq!((bucket, upper_bound(bucket)), expects[i], "{}", inputs[i]);
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), None);
+
+        // 90 fast calls and 10 slow ones
+        for _ in 0..90 {
+            histogram.record(Duration::from_nanos(100));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(1));
+        }
+        assert_eq!(histogram.count(), 100);
+
+        let inputs = [0.0, 50.0, 90.0, 90.1, 99.0, 100.0];
+        let expects = [112, 112, 112, 1_048_576, 1_048_576, 1_048_576];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(histogram.percentile(inputs[i]), Some(expects[i]));
+        }
+
+        histogram.reset();
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(histogram.percentile(50.0), None);
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use log::error;
+
+use crate::{config::Config, script::Scripts, store::Value};
+
+/// Values whose estimated free effort is above this are handed over to the
+/// dropper thread, smaller ones are cheaper to free right away
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Freeing a string costs about as much as returning its pages, so its
+/// effort is counted in pages. A list costs an allocation per element.
+const PAGE_SIZE: usize = 4096;
+
+/// Something that was removed from the store, or the script cache, and is
+/// waiting to be freed
+pub enum Displaced<S> {
+    /// Removed by an explicit DEL
+    Deleted(Value),
+
+    /// Replaced by a write to the same key
+    Overwritten(Value),
+
+    /// The store of every shard, removed by FLUSHDB with an explicit ASYNC
+    /// or SYNC modifier, if any
+    Flushed { stores: Vec<S>, lazy: Option<bool> },
+
+    /// Every cached script, removed by SCRIPT FLUSH with an explicit ASYNC
+    /// or SYNC modifier, if any
+    ScriptsFlushed {
+        scripts: Scripts,
+        lazy: Option<bool>,
+    },
+}
+
+type Garbage = Box<dyn Send>;
+
+fn is_large(value: &Value) -> bool {
+    let effort = match value {
+        Value::Str(value) => value.stored_len().div_ceil(PAGE_SIZE),
+        Value::List(elements) => elements.len(),
+        Value::Hash(fields) => fields.len(),
+    };
+    effort > LAZYFREE_THRESHOLD
+}
+
+fn is_lazy<S>(displaced: &Displaced<S>, config: &Config) -> bool {
+    match displaced {
+        Displaced::Deleted(value) => config.lazyfree_lazy_user_del && is_large(value),
+        Displaced::Overwritten(value) => config.lazyfree_lazy_server_del && is_large(value),
+        Displaced::Flushed { lazy, .. } | Displaced::ScriptsFlushed { lazy, .. } => {
+            lazy.unwrap_or(config.lazyfree_lazy_user_flush)
+        }
+    }
+}
+
+/// Frees large values on a dedicated thread, so that neither the lock
+/// holder nor the connection that removed them pays for it
+pub struct LazyFree {
+    sender: mpsc::Sender<Garbage>,
+    pending: Arc<AtomicU64>,
+}
+
+impl LazyFree {
+    pub fn new() -> LazyFree {
+        let (sender, receiver) = mpsc::channel::<Garbage>();
+        let pending = Arc::new(AtomicU64::new(0));
+
+        let counter = pending.clone();
+        let res = thread::Builder::new()
+            .name("lazyfree".to_string())
+            .spawn(move || {
+                // Exits once the sender is gone
+                for garbage in receiver {
+                    drop(garbage);
+                    counter.fetch_sub(1, Ordering::Relaxed);
+                }
+            });
+        if let Err(err) = res {
+            // Sending fails without a receiver, so everything is freed inline
+            error!("Failed to start the lazyfree thread: {}", err);
+        }
+
+        LazyFree { sender, pending }
+    }
+
+    /// Number of objects waiting to be freed by the dropper thread
+    pub fn pending_objects(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Frees a displaced value or store, either inline or on the dropper
+    /// thread depending on the configuration and the size of the value.
+    /// Must be called after the database lock has been released.
+    pub fn free<S: Send + 'static>(&self, displaced: Displaced<S>, config: &Config) {
+        if !is_lazy(&displaced, config) {
+            return;
+        }
+
+        match displaced {
+            Displaced::Deleted(value) | Displaced::Overwritten(value) => {
+                self.free_lazily(Box::new(value))
+            }
+            Displaced::Flushed { stores, .. } => self.free_lazily(Box::new(stores)),
+            Displaced::ScriptsFlushed { scripts, .. } => self.free_lazily(Box::new(scripts)),
+        }
+    }
+
+    fn free_lazily(&self, garbage: Garbage) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(mpsc::SendError(garbage)) = self.sender.send(garbage) {
+            drop(garbage);
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    /// Reports on drop whether it was dropped on the calling thread
+    struct DropProbe(thread::ThreadId, mpsc::Sender<bool>);
+
+    impl Drop for DropProbe {
+        fn drop(&mut self) {
+            self.1.send(thread::current().id() == self.0).unwrap();
+        }
+    }
+
+    fn config(lazy_user_del: bool, lazy_server_del: bool, lazy_user_flush: bool) -> Config {
+        Config {
+            lazyfree_lazy_user_del: lazy_user_del,
+            lazyfree_lazy_server_del: lazy_server_del,
+            lazyfree_lazy_user_flush: lazy_user_flush,
+            ..Config::default()
+        }
+    }
+
+    fn wait_for_pending(lazyfree: &LazyFree, expected: u64) {
+        let start = Instant::now();
+        while lazyfree.pending_objects() != expected {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_flush() {
+        let lazyfree = LazyFree::new();
+        let config = config(false, false, false);
+
+        let inputs = [Some(false), Some(true)];
+        let expects = [true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (sender, receiver) = mpsc::channel();
+            let store = DropProbe(thread::current().id(), sender);
+            lazyfree.free(
+                Displaced::Flushed {
+                    stores: vec![store],
+                    lazy: inputs[i],
+                },
+                &config,
+            );
+            assert_eq!(receiver.recv().unwrap(), expects[i]);
+        }
+        wait_for_pending(&lazyfree, 0);
+    }
+
+    fn flushed(lazy: Option<bool>) -> Displaced<()> {
+        Displaced::Flushed {
+            stores: Vec::new(),
+            lazy,
+        }
+    }
+
+    #[test]
+    fn test_is_lazy() {
+        let small = Value::from(Bytes::from(vec![0; PAGE_SIZE]));
+        let large = Value::from(Bytes::from(vec![0; (LAZYFREE_THRESHOLD + 1) * PAGE_SIZE]));
+        // Lists are large by their number of elements, not their size
+        let long_list = Value::List(VecDeque::from(vec![Bytes::new(); LAZYFREE_THRESHOLD + 1]));
+        let short_list = Value::List(VecDeque::from([Bytes::from(vec![
+            0;
+            (LAZYFREE_THRESHOLD + 1)
+                * PAGE_SIZE
+        ])]));
+
+        let inputs = [
+            (config(true, true, false), Displaced::Deleted(large.clone())),
+            (
+                config(true, true, false),
+                Displaced::Overwritten(large.clone()),
+            ),
+            (config(true, true, false), Displaced::Deleted(small.clone())),
+            (
+                config(false, true, false),
+                Displaced::Deleted(large.clone()),
+            ),
+            (
+                config(true, false, false),
+                Displaced::Overwritten(large.clone()),
+            ),
+            (config(false, false, false), flushed(Some(true))),
+            (config(true, true, true), flushed(Some(false))),
+            (config(false, false, true), flushed(None)),
+            (config(false, false, false), flushed(None)),
+            (config(true, true, false), Displaced::Deleted(long_list)),
+            (config(true, true, false), Displaced::Deleted(short_list)),
+            (
+                config(false, false, true),
+                Displaced::ScriptsFlushed {
+                    scripts: Scripts::new(),
+                    lazy: None,
+                },
+            ),
+        ];
+        let expects = [
+            true, true, false, false, false, true, false, true, false, true, false, true,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (config, displaced) = &inputs[i];
+            assert_eq!(is_lazy(displaced, config), expects[i]);
+        }
+    }
+}
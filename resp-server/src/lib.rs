@@ -0,0 +1,27 @@
+mod client;
+mod codec;
+mod command;
+pub mod config;
+mod connection;
+mod crc64;
+mod db;
+mod error;
+mod latency;
+mod lazyfree;
+pub mod logfile;
+pub mod pidfile;
+mod reply;
+mod script;
+mod server;
+pub mod snapshot;
+mod store;
+#[cfg(test)]
+mod test_util;
+mod tracking;
+
+pub use client::ClientState;
+pub use connection::handle_request;
+pub use db::Database;
+pub use error::CommandError;
+pub use server::{Server, ServerBuilder};
+pub use store::{AccessTime, Entry, KvStore, Store, Str, Value};
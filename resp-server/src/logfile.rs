@@ -0,0 +1,83 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use tracing_subscriber::fmt::{MakeWriter, writer::MutexGuardWriter};
+
+// ===========================================================
+// LogFile
+// ===========================================================
+
+/// Log output appended to a file. Clones share the file, so the one given to
+/// the subscriber can be reopened through another one.
+#[derive(Clone, Debug)]
+pub struct LogFile {
+    path: PathBuf,
+
+    // A std mutex rather than parking_lot, for its MakeWriter implementation
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<LogFile> {
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(open(path)?)),
+        })
+    }
+
+    /// Opens the file at the configured path again, so that logging moves
+    /// over to a new file once the old one has been rotated away. The old
+    /// file keeps being written to if that fails.
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = open(&self.path)?;
+        *self.file.lock().unwrap_or_else(PoisonError::into_inner) = file;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = MutexGuardWriter<'a, File>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.file.make_writer()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io::Write, process};
+
+    use super::*;
+
+    #[test]
+    fn test_reopen() {
+        let path = env::temp_dir().join(format!("resp-server-test-{}.log", process::id()));
+        let rotated = path.with_extension("log.1");
+
+        let log_file = LogFile::open(&path).unwrap();
+        writeln!(log_file.make_writer(), "first").unwrap();
+
+        // Like logrotate, move the file away and ask for it to be reopened
+        fs::rename(&path, &rotated).unwrap();
+        writeln!(log_file.make_writer(), "second").unwrap();
+        log_file.clone().reopen().unwrap();
+        writeln!(log_file.make_writer(), "third").unwrap();
+
+        let inputs = [&rotated, &path];
+        let expects = ["first\nsecond\n", "third\n"];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(fs::read_to_string(inputs[i]).unwrap(), expects[i]);
+            fs::remove_file(inputs[i]).unwrap();
+        }
+    }
+}
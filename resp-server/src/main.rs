@@ -1,210 +1,250 @@
-use std::{collections::HashMap, sync::Arc};
-
-use bytes::BytesMut;
-use futures::SinkExt;
-use log::{debug, error, info};
-use parking_lot::Mutex;
-use resp::{
-    parser::RespParser,
-    types::{BulkString, RespReadable, RespValue, RespWritable},
-    writer::{RespWriter, WriteBuf},
+use std::{env, future, io, path::Path, process, sync::Arc, time::SystemTime};
+
+use log::{error, info};
+use resp_server::{
+    Database, Server,
+    config::{Config, LogFormat, LogLevel},
+    logfile::LogFile,
+    pidfile::PidFile,
+    snapshot::Snapshot,
 };
-use tokio::net::{TcpListener, TcpStream};
-use tokio_stream::StreamExt;
-use tokio_util::codec::{BytesCodec, Framed};
-
-#[derive(Debug)]
-enum Command {
-    Get { key: BulkString },
-    Set { key: BulkString, value: BulkString },
-    Del { key: BulkString },
+use tokio::runtime::{Builder, Runtime};
+use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter, reload};
+
+/// Changes the level of the installed subscriber
+type SetLogLevel = Box<dyn Fn(LogLevel) -> Result<(), reload::Error> + Send>;
+
+/// Filter for `loglevel`. RUST_LOG, or the older REDIS_LOG_LEVEL, take
+/// precedence over it.
+fn log_filter(level: LogLevel) -> EnvFilter {
+    EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_from_env("REDIS_LOG_LEVEL"))
+        .unwrap_or_else(|_| EnvFilter::new(level.filter()))
 }
 
-impl Command {
-    fn get(cmd: &[BulkString]) -> Option<Command> {
-        if cmd.len() != 2 {
-            None
-        } else {
-            Some(Command::Get {
-                key: cmd[1].clone(),
-            })
+/// Installs the global subscriber, writing to `log_file` if given and to
+/// stdout otherwise. Events from the `log` macros are bridged into it, so
+/// they are recorded inside the same spans.
+fn init_logging(format: LogFormat, level: LogLevel, log_file: Option<LogFile>) -> SetLogLevel {
+    // Escape codes only make sense on a terminal
+    let ansi =
+        log_file.is_none() && env::var("REDIS_LOG_STYLE").map_or(true, |style| style != "never");
+    let writer = match log_file {
+        Some(log_file) => BoxMakeWriter::new(log_file),
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(log_filter(level))
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => {
+            let builder = builder.with_ansi(ansi).with_filter_reloading();
+            let handle = builder.reload_handle();
+            builder.init();
+            Box::new(move |level| handle.reload(log_filter(level)))
         }
-    }
-
-    fn set(cmd: &[BulkString]) -> Option<Command> {
-        if cmd.len() != 3 {
-            None
-        } else {
-            Some(Command::Set {
-                key: cmd[1].clone(),
-                value: cmd[2].clone(),
-            })
+        LogFormat::Json => {
+            let builder = builder.json().with_filter_reloading();
+            let handle = builder.reload_handle();
+            builder.init();
+            Box::new(move |level| handle.reload(log_filter(level)))
         }
     }
+}
 
-    fn del(cmd: &[BulkString]) -> Option<Command> {
-        if cmd.len() != 2 {
-            None
-        } else {
-            Some(Command::Del {
-                key: cmd[1].clone(),
-            })
+/// Builds the runtime the server runs on, as selected by `io-threads`
+fn build_runtime(io_threads: Option<usize>) -> io::Result<Runtime> {
+    match io_threads {
+        Some(1) => {
+            info!("Running on a current-thread runtime");
+            Builder::new_current_thread().enable_all().build()
+        }
+        Some(threads) => {
+            info!(
+                "Running on a multi-threaded runtime with {} workers",
+                threads
+            );
+            Builder::new_multi_thread()
+                .worker_threads(threads)
+                .enable_all()
+                .build()
+        }
+        None => {
+            info!("Running on a multi-threaded runtime with one worker per core");
+            Builder::new_multi_thread().enable_all().build()
         }
     }
+}
 
-    fn from_cmd(cmd: &[BulkString]) -> Option<Command> {
-        let command = cmd.first()?;
+/// On every SIGHUP, reopens the log file so that it can be rotated and
+/// reloads the configuration
+#[cfg(unix)]
+async fn reload_on_hangup(db: Arc<Database>, log_file: Option<LogFile>, set_level: SetLogLevel) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading...");
+        if let Some(log_file) = &log_file {
+            match log_file.reopen() {
+                Ok(()) => info!("Reopened the log file"),
+                Err(err) => error!("Failed to reopen the log file: {}", err),
+            }
+        }
 
-        let command = command.value();
-        match &command[..] {
-            "GET" => Self::get(cmd),
-            "SET" => Self::set(cmd),
-            "DEL" => Self::del(cmd),
-            _ => None,
+        // A config file that doesn't load leaves everything as it was
+        match db.reload_config() {
+            Ok(config) => {
+                if let Err(err) = set_level(config.loglevel) {
+                    error!("Failed to change the log level: {}", err);
+                }
+            }
+            Err(err) => error!("Failed to reload the configuration: {}", err),
         }
     }
+}
 
-    fn handle(&self, db: &Arc<Database>, writer: &mut RespWriter<'_>) {
-        info!("Handle: {:?}", *self);
-
-        let mut db = db.kv_store.lock();
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {}", err);
+            future::pending::<()>().await;
+        }
+    };
 
-        let res = match *self {
-            Command::Get { ref key } => match db.get(key.value()) {
-                Some(v) => RespValue::Bulk(BulkString::new(v.clone())),
-                None => RespValue::None,
-            },
-            Command::Set { ref key, ref value } => {
-                let key = key.value();
-                let value = value.value();
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
 
-                db.insert(key.clone(), value.clone());
-                RespValue::Simple("OK".to_string())
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
-            Command::Del { ref key } => match db.remove(key.value()) {
-                Some(_) => RespValue::Simple("OK".to_string()),
-                None => RespValue::None,
-            },
-        };
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {}", err);
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
 
-        res.write(writer).unwrap();
+    tokio::select! {
+        _ = interrupt => info!("Received SIGINT scheduling shutdown..."),
+        _ = terminate => info!("Received SIGTERM scheduling shutdown..."),
     }
 }
 
-type KvStore = HashMap<String, String>;
-
-struct Database {
-    kv_store: Mutex<KvStore>,
-}
-
-async fn send_err(
-    transport: &mut Framed<TcpStream, BytesCodec>,
-    msg: String,
-    writer: &mut RespWriter<'_>,
-) {
-    error!("{}", msg);
-    let res = RespValue::Error(msg);
-    res.write(writer).unwrap();
-
-    // TODO: This should be handled better
-    let mut buf = BytesMut::with_capacity(writer.buffer().len());
-    buf.extend_from_slice(writer.buffer().get().as_slice());
-    if let Err(send_err) = transport.send(buf).await {
-        error!("Failed to send error response: {:?}", send_err);
+/// Verifies the snapshot at `path` and describes its contents, returning
+/// the exit code
+fn check_snapshot(path: &str) -> i32 {
+    match Snapshot::load(Path::new(path)) {
+        Ok(snapshot) => {
+            println!(
+                "{}: OK, {} keys, {} with an expiry, {} already expired",
+                path,
+                snapshot.entries.len(),
+                snapshot.expiring(),
+                snapshot.expired(SystemTime::now())
+            );
+            0
+        }
+        Err(err) => {
+            eprintln!("Snapshot check failed: {}", err);
+            1
+        }
     }
 }
 
-async fn handle_request(
-    transport: &mut Framed<TcpStream, BytesCodec>,
-    req_buf: BytesMut,
-    writer: &mut RespWriter<'_>,
-    db: &Arc<Database>,
-) {
-    let mut parser = RespParser::new(&req_buf);
-    let request = Vec::<BulkString>::parse(&mut parser);
-    if let Err(err) = request {
-        send_err(transport, format!("Error when parsing: {:?}", err), writer).await;
-        return;
-    }
-
-    let cmd = request.unwrap();
-    let command = Command::from_cmd(&cmd);
-    if command.is_none() {
-        send_err(
-            transport,
-            format!(
-                "Unknown command {:?} with args {:?}",
-                cmd.first(),
-                &cmd[1..]
-            ),
-            writer,
-        )
-        .await;
-        return;
-    }
-
-    command.unwrap().handle(db, writer);
-
-    // TODO: This should be handled better
-    let mut buf = BytesMut::with_capacity(writer.buffer().len());
-    buf.extend_from_slice(writer.buffer().get().as_slice());
-    if let Err(send_err) = transport.send(buf).await {
-        error!("Failed to send response: {:?}", send_err);
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [option, path] = args.as_slice() {
+        if option == "--check-snapshot" {
+            process::exit(check_snapshot(path));
+        }
     }
-}
 
-async fn handle_connection(stream: TcpStream, db: &Arc<Database>) {
-    let peer_addr = stream.peer_addr().unwrap();
-    debug!("Peer connected {:?}", peer_addr);
-    let mut transport = Framed::new(stream, BytesCodec::new());
+    let config = Config::from_args(args);
 
-    while let Some(result) = transport.next().await {
-        let mut write_buf = WriteBuf::new(Vec::new());
-        let mut writer = RespWriter::new(&mut write_buf);
+    // Logging is set up before a configuration error is reported, so that
+    // the error goes wherever logs go
+    let log_file = match config.as_ref().map(|config| config.logfile.as_deref()) {
+        Ok(Some(path)) => Some(LogFile::open(path)),
+        _ => None,
+    };
+    let (format, level) = config.as_ref().map_or(Default::default(), |config| {
+        (config.log_format, config.loglevel)
+    });
+    let set_level = init_logging(
+        format,
+        level,
+        log_file
+            .as_ref()
+            .and_then(|log_file| log_file.as_ref().ok())
+            .cloned(),
+    );
+
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Invalid configuration: {}", err);
+            process::exit(1);
+        }
+    };
 
-        if let Err(err) = result {
-            send_err(
-                &mut transport,
-                format!("Error when receiving: {:?}", err),
-                &mut writer,
-            )
-            .await;
+    let log_file = match log_file.transpose() {
+        Ok(log_file) => log_file,
+        Err(err) => {
+            error!("Failed to open the log file: {}", err);
+            process::exit(1);
+        }
+    };
 
-            continue;
+    let runtime = match build_runtime(config.io_threads) {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            error!("Failed to start the runtime: {}", err);
+            process::exit(1);
         }
+    };
+
+    runtime.block_on(async {
+        let pidfile_path = config.pidfile.clone();
+        let server = match Server::builder().config(config).build() {
+            Ok(server) => server,
+            Err(err) => {
+                error!("{}", err);
+                process::exit(1);
+            }
+        };
 
-        handle_request(&mut transport, result.unwrap(), &mut writer, db).await;
-    }
+        // Written only once the server listens, so that its presence means
+        // the server is up
+        let pidfile = match pidfile_path.as_deref().map(PidFile::create).transpose() {
+            Ok(pidfile) => pidfile,
+            Err(err) => {
+                error!("Failed to write the pidfile: {}", err);
+                process::exit(1);
+            }
+        };
 
-    debug!("Peer disconnected {:?}", peer_addr);
-}
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(
+            server.database().clone(),
+            log_file,
+            set_level,
+        ));
 
-#[tokio::main]
-async fn main() {
-    let env = env_logger::Env::default()
-        .filter_or("REDIS_LOG_LEVEL", "info")
-        .write_style_or("REDIS_LOG_STYLE", "always");
-    env_logger::init_from_env(env);
-
-    info!("Initializing key-value store");
-    let initial_db = KvStore::new();
-    let db = Arc::new(Database {
-        kv_store: Mutex::new(initial_db),
+        server.run(shutdown_signal()).await;
+        drop(pidfile);
     });
-
-    let listen_addr = "127.0.0.1:6379";
-    let listener = TcpListener::bind(listen_addr).await.unwrap();
-    info!("Listening on {}", listen_addr);
-
-    loop {
-        match listener.accept().await {
-            Err(err) => error!("Error when establishing connection: {:?}", err),
-            Ok((stream, _)) => {
-                let db = db.clone();
-                tokio::spawn(async move {
-                    handle_connection(stream, &db).await;
-                });
-            }
-        }
-    }
 }
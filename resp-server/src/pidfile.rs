@@ -0,0 +1,128 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use log::warn;
+
+// ===========================================================
+// PidFile
+// ===========================================================
+
+/// File holding the pid of the running server, for init systems. It is
+/// removed again when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the pid of this process to `path`. A pidfile left behind by a
+    /// process that is no longer running is replaced, one of a process that
+    /// is still running is not.
+    pub fn create(path: &Path) -> io::Result<PidFile> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let pid = contents.trim().parse::<u32>().ok();
+                // After a restart in a container the stale pid is likely to
+                // be the one this process got
+                if let Some(pid) = pid.filter(|pid| *pid != process::id() && is_running(*pid)) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "{} belongs to process {}, which is still running",
+                            path.display(),
+                            pid
+                        ),
+                    ));
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        fs::write(path, format!("{}\n", process::id()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Zero and negative pids address process groups
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+
+    // Signal 0 only checks whether the process exists, EPERM means it does
+    // but belongs to another user
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to check, a leftover pidfile is assumed to be stale
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_create() {
+        let path = env::temp_dir().join(format!("resp-server-test-{}.pid", process::id()));
+
+        // 999999999 is above the largest pid Linux hands out
+        let running = std::os::unix::process::parent_id();
+        let inputs = [
+            None,
+            Some("999999999\n".to_string()),
+            Some(format!("{}\n", process::id())),
+            Some("not a pid".to_string()),
+            Some(format!("{}\n", running)),
+        ];
+        let expects = [true, true, true, true, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            if let Some(contents) = &inputs[i] {
+                fs::write(&path, contents).unwrap();
+            }
+
+            match PidFile::create(&path) {
+                Ok(pidfile) => {
+                    assert!(expects[i], "replaced {:?}", inputs[i]);
+                    let contents = fs::read_to_string(&path).unwrap();
+                    assert_eq!(contents, format!("{}\n", process::id()));
+
+                    drop(pidfile);
+                    assert!(!path.exists());
+                }
+                Err(err) => {
+                    assert!(!expects[i], "{}", err);
+                    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+                    assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", running));
+                    fs::remove_file(&path).unwrap();
+                }
+            }
+        }
+    }
+}
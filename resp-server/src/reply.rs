@@ -0,0 +1,250 @@
+use std::sync::LazyLock;
+
+use bytes::Bytes;
+use resp::{
+    types::{BulkString, IntoResp, RespValue, RespWritable},
+    writer::{OutBuf, ProtocolVersion, RespWriter, WriteResult},
+};
+
+use crate::error::CommandError;
+
+// ===========================================================
+// Shared replies
+// ===========================================================
+
+const OK: &[u8] = b"+OK\r\n";
+const NULL: &[u8] = b"$-1\r\n";
+
+/// Integers below this have their serialized digits cached
+const CACHED_INTEGERS: usize = 10_000;
+
+/// `N\r\n` for every N below `CACHED_INTEGERS`, without a tag so that the
+/// same digits serve integer replies as well as bulk string and array
+/// headers
+static INTEGERS: LazyLock<Box<[Box<[u8]>]>> = LazyLock::new(|| {
+    (0..CACHED_INTEGERS)
+        .map(|n| format!("{}\r\n", n).into_bytes().into_boxed_slice())
+        .collect()
+});
+
+/// Writes `tag` followed by `n` from the cache, or returns false if `n`
+/// isn't cached
+fn write_cached<B: OutBuf>(writer: &mut RespWriter<'_, B>, tag: u8, n: i64) -> WriteResult<bool> {
+    let Some(digits) = usize::try_from(n).ok().and_then(|n| INTEGERS.get(n)) else {
+        return Ok(false);
+    };
+
+    writer.write_u8(tag)?;
+    writer.buffer().push_bytes(digits)?;
+    Ok(true)
+}
+
+// ===========================================================
+// Reply
+// ===========================================================
+
+/// What a handler replies with. The replies most commands send are written
+/// from preserialized bytes, without building a [`RespValue`] first.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Reply {
+    /// `+OK`
+    Ok,
+
+    /// The null bulk string, or null in RESP3
+    Null,
+
+    Integer(i64),
+    Bulk(Bytes),
+
+    /// Anything else
+    Value(RespValue),
+}
+
+impl From<RespValue> for Reply {
+    fn from(value: RespValue) -> Reply {
+        Reply::Value(value)
+    }
+}
+
+impl From<i64> for Reply {
+    fn from(value: i64) -> Reply {
+        Reply::Integer(value)
+    }
+}
+
+/// The integers 1 and 0, as Redis replies
+impl From<bool> for Reply {
+    fn from(value: bool) -> Reply {
+        Reply::Integer(value.into())
+    }
+}
+
+/// Counts and sizes, as a big number in the unlikely case that they are
+/// beyond the range of an integer
+impl From<u64> for Reply {
+    fn from(value: u64) -> Reply {
+        match i64::try_from(value) {
+            Ok(value) => Reply::Integer(value),
+            Err(_) => Reply::Value(RespValue::BigNumber(value.to_string())),
+        }
+    }
+}
+
+impl From<usize> for Reply {
+    fn from(value: usize) -> Reply {
+        Reply::from(value as u64)
+    }
+}
+
+impl From<Bytes> for Reply {
+    fn from(value: Bytes) -> Reply {
+        Reply::Bulk(value)
+    }
+}
+
+impl From<String> for Reply {
+    fn from(value: String) -> Reply {
+        Reply::Bulk(value.into())
+    }
+}
+
+/// A missing value replies with the null
+impl<T: Into<Reply>> From<Option<T>> for Reply {
+    fn from(value: Option<T>) -> Reply {
+        value.map_or(Reply::Null, Into::into)
+    }
+}
+
+impl<T: IntoResp> From<Vec<T>> for Reply {
+    fn from(values: Vec<T>) -> Reply {
+        Reply::Value(values.into_resp())
+    }
+}
+
+impl From<CommandError> for Reply {
+    fn from(err: CommandError) -> Reply {
+        Reply::Value(err.into())
+    }
+}
+
+impl RespWritable for Reply {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        match self {
+            Reply::Ok => writer.buffer().push_bytes(OK),
+            Reply::Null if writer.protocol() == ProtocolVersion::Resp2 => {
+                writer.buffer().push_bytes(NULL)
+            }
+            Reply::Null => writer.write_null(),
+            Reply::Integer(n) => {
+                if !write_cached(writer, b':', *n)? {
+                    writer.write_integer(*n)?;
+                }
+                Ok(())
+            }
+            Reply::Bulk(value) => {
+                if !write_cached(writer, b'$', value.len() as i64)? {
+                    return BulkString::new(value.clone()).write(writer);
+                }
+                writer.buffer().push_bytes(value)?;
+                writer.write_crlf()
+            }
+            Reply::Value(value) => value.write(writer),
+        }
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        match self {
+            Reply::Ok => OK.len(),
+            Reply::Null => RespValue::None.encoded_len(protocol),
+            Reply::Integer(n) => n.encoded_len(protocol),
+            Reply::Bulk(value) => (value.len() as i64).encoded_len(protocol) + value.len() + 2,
+            Reply::Value(value) => value.encoded_len(protocol),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use resp::writer::WriteBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let long = Bytes::from(vec![b'A'; CACHED_INTEGERS]);
+        let inputs = [
+            Reply::Ok,
+            Reply::Null,
+            Reply::Integer(0),
+            Reply::Integer(9_999),
+            Reply::Integer(10_000),
+            Reply::Integer(-1),
+            Reply::Bulk(Bytes::from("value")),
+            Reply::Bulk(Bytes::new()),
+            Reply::Bulk(long.clone()),
+            Reply::Value(RespValue::Simple("PONG".to_string())),
+            Reply::from(CommandError::Syntax),
+        ];
+        let expects = [
+            RespValue::Simple("OK".to_string()),
+            RespValue::None,
+            RespValue::from(0),
+            RespValue::from(9_999),
+            RespValue::from(10_000),
+            RespValue::from(-1),
+            RespValue::from("value"),
+            RespValue::from(""),
+            RespValue::from(long),
+            RespValue::Simple("PONG".to_string()),
+            RespValue::from(CommandError::Syntax),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+            for i in 0..inputs.len() {
+                let mut shared = WriteBuf::new(Vec::new());
+                inputs[i]
+                    .write(&mut RespWriter::with_protocol(&mut shared, protocol))
+                    .unwrap();
+                let mut built = WriteBuf::new(Vec::new());
+                expects[i]
+                    .write(&mut RespWriter::with_protocol(&mut built, protocol))
+                    .unwrap();
+                assert_eq!(shared.get(), built.get(), "{:?}", inputs[i]);
+                assert_eq!(inputs[i].encoded_len(protocol), shared.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_from() {
+        let inputs = [
+            Reply::from(true),
+            Reply::from(7u64),
+            Reply::from(u64::MAX),
+            Reply::from(3usize),
+            Reply::from("name".to_string()),
+            Reply::from(Some(Bytes::from("v"))),
+            Reply::from(None::<i64>),
+            Reply::from(vec![Some("a"), None]),
+        ];
+        let expects = [
+            Reply::Integer(1),
+            Reply::Integer(7),
+            Reply::Value(RespValue::BigNumber("18446744073709551615".to_string())),
+            Reply::Integer(3),
+            Reply::Bulk(Bytes::from("name")),
+            Reply::Bulk(Bytes::from("v")),
+            Reply::Null,
+            Reply::Value(RespValue::Array(vec![
+                RespValue::from("a"),
+                RespValue::None,
+            ])),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i], expects[i]);
+        }
+    }
+}
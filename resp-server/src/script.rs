@@ -0,0 +1,87 @@
+use std::{collections::HashMap, mem};
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+// ===========================================================
+// ScriptCache
+// ===========================================================
+
+/// Script bodies by the lowercase hex SHA1 of their body
+pub(crate) type Scripts = HashMap<String, Bytes>;
+
+/// Length of a SHA1 in hex
+const SHA_LEN: usize = 40;
+
+/// Scripts loaded with SCRIPT LOAD, shared by every connection. Only SCRIPT
+/// FLUSH empties it, neither FLUSHDB nor CONFIG RESETSTAT touch it.
+#[derive(Default)]
+pub(crate) struct ScriptCache {
+    scripts: RwLock<Scripts>,
+}
+
+impl ScriptCache {
+    /// Caches `body`, returning its SHA1. Loading a script again keeps the
+    /// cached copy.
+    pub(crate) fn load(&self, body: &[u8]) -> String {
+        let sha = sha1_smol::Sha1::from(body).digest().to_string();
+        self.scripts
+            .write()
+            .entry(sha.clone())
+            .or_insert_with(|| Bytes::copy_from_slice(body));
+        sha
+    }
+
+    /// Looks up a script by SHA1, which is matched ignoring case as in
+    /// Redis
+    pub(crate) fn get(&self, sha: &[u8]) -> Option<Bytes> {
+        if sha.len() != SHA_LEN {
+            return None;
+        }
+        let sha = String::from_utf8_lossy(sha).to_ascii_lowercase();
+        self.scripts.read().get(&sha).cloned()
+    }
+
+    pub(crate) fn contains(&self, sha: &[u8]) -> bool {
+        self.get(sha).is_some()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scripts.read().len()
+    }
+
+    /// Empties the cache, returning the scripts for the caller to free
+    pub(crate) fn flush(&self) -> Scripts {
+        mem::take(&mut *self.scripts.write())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let cache = ScriptCache::default();
+        let sha = cache.load(b"return 1");
+        assert_eq!(sha, "e0e1f9fabfc9d4800c877a703b823ac0578ff8db");
+        assert_eq!(cache.load(b"return 1"), sha);
+        assert_eq!(cache.len(), 1);
+
+        let inputs: [&[u8]; 4] = [
+            b"e0e1f9fabfc9d4800c877a703b823ac0578ff8db",
+            b"E0E1F9FABFC9D4800C877A703B823AC0578FF8DB",
+            b"e0e1f9fabfc9d4800c877a703b823ac0578ff8dc",
+            b"e0e1f9",
+        ];
+        let expects = [true, true, false, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(cache.contains(inputs[i]), expects[i], "{:?}", inputs[i]);
+        }
+
+        assert_eq!(cache.flush().len(), 1);
+        assert!(!cache.contains(sha.as_bytes()));
+    }
+}
@@ -0,0 +1,624 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use futures::future;
+use log::{error, info, warn};
+use socket2::SockRef;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio_util::task::TaskTracker;
+
+use crate::{
+    command,
+    config::{BindAddr, Config},
+    connection::{configure_socket, handle_connection},
+    db::Database,
+    snapshot::Snapshot,
+    store::{KvStore, Store},
+};
+
+/// How often the save points are checked
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before trying again after an automatic save failed
+const AUTOSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Share of every `hz` interval the active expiry may take
+const ACTIVE_EXPIRE_BUDGET_PERCENT: u32 = 25;
+
+// ===========================================================
+// ServerBuilder
+// ===========================================================
+
+/// Configures a [`Server`] before its listeners are bound
+pub struct ServerBuilder<S: Store = KvStore> {
+    config: Config,
+    addr: Option<SocketAddr>,
+    db: Option<Arc<Database<S>>>,
+}
+
+impl<S: Store> Default for ServerBuilder<S> {
+    fn default() -> ServerBuilder<S> {
+        ServerBuilder {
+            config: Config::default(),
+            addr: None,
+            db: None,
+        }
+    }
+}
+
+impl<S: Store> ServerBuilder<S> {
+    pub fn config(mut self, config: Config) -> ServerBuilder<S> {
+        self.config = config;
+        self
+    }
+
+    /// Listens on `addr` only, instead of the `bind` and `port` of the
+    /// configuration. Port 0 picks an ephemeral port, see
+    /// [`Server::local_addr`].
+    pub fn addr(mut self, addr: SocketAddr) -> ServerBuilder<S> {
+        self.addr = Some(addr);
+        self
+    }
+
+    /// Serves an existing database instead of a new, empty one. The
+    /// database determines the storage backend of the server, and keeps the
+    /// command renames of the configuration it was created with.
+    pub fn database<T: Store>(self, db: Arc<Database<T>>) -> ServerBuilder<T> {
+        ServerBuilder {
+            config: self.config,
+            addr: self.addr,
+            db: Some(db),
+        }
+    }
+
+    /// Binds the listeners and, unless a database was given, loads the
+    /// snapshot if there is one. Has to be called from within a Tokio
+    /// runtime.
+    pub fn build(self) -> io::Result<Server<S>> {
+        let mut config = self.config;
+        if let Some(addr) = self.addr {
+            config.bind = vec![BindAddr {
+                ip: addr.ip(),
+                optional: false,
+            }];
+            config.bind_configured = true;
+            config.port = addr.port();
+        }
+
+        command::check_renames(&config.rename_command)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        let listeners = listen(&config)?;
+        let db = match self.db {
+            Some(db) => {
+                db.set_config(config);
+                db
+            }
+            None => {
+                info!("Initializing key-value store");
+                Arc::new(Database::new(load_snapshot(&config)?, &config))
+            }
+        };
+
+        Ok(Server { listeners, db })
+    }
+}
+
+// ===========================================================
+// Server
+// ===========================================================
+
+pub struct Server<S: Store = KvStore> {
+    listeners: Vec<TcpListener>,
+    db: Arc<Database<S>>,
+}
+
+impl Server {
+    /// Builder of a server with the default, in-memory store. Another store
+    /// is used by passing a database with it to
+    /// [`ServerBuilder::database`].
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+}
+
+impl<S: Store> Server<S> {
+    /// Address of the first listener, with the actual port if it was bound
+    /// to port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect()
+    }
+
+    pub fn database(&self) -> &Arc<Database<S>> {
+        &self.db
+    }
+
+    /// Serves clients until `shutdown` resolves or a client sends SHUTDOWN.
+    /// Then stops accepting, lets every connection finish the command it is
+    /// running and send its replies, and returns. Connections still sending
+    /// after `shutdown-timeout` are left behind, to be dropped with the
+    /// runtime.
+    pub async fn run(self, shutdown: impl Future) {
+        let connections = TaskTracker::new();
+        let accept_loops: Vec<_> = self
+            .listeners
+            .into_iter()
+            .map(|listener| {
+                tokio::spawn(accept_loop(listener, self.db.clone(), connections.clone()))
+            })
+            .collect();
+        let autosave = tokio::spawn(autosave(self.db.clone()));
+        tokio::spawn(active_expire(self.db.clone()));
+
+        tokio::select! {
+            _ = shutdown => self.db.shutdown.cancel(),
+            _ = self.db.shutdown.cancelled() => {}
+        }
+
+        future::join_all(accept_loops).await;
+        connections.close();
+        let timeout = self.db.config().shutdown_timeout;
+        if tokio::time::timeout(Duration::from_secs(timeout), connections.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} connections still open after {} seconds, closing them",
+                connections.len(),
+                timeout
+            );
+        }
+
+        // Saved once every connection is done writing, so that nothing
+        // acknowledged is left out
+        let _ = autosave.await;
+        if self.db.persistence.needs_final_save(&self.db.config()) {
+            info!("Saving the final snapshot before exiting");
+            let db = self.db.clone();
+            match tokio::task::spawn_blocking(move || db.save()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => error!("Error trying to save the DB: {}", err),
+                Err(err) => error!("Saving the DB failed: {}", err),
+            }
+        }
+
+        info!("Redis is now ready to exit, bye bye...");
+    }
+}
+
+/// Creates a listener for every address in `bind`. Addresses marked as
+/// optional are skipped with a warning if they can't be bound, any other
+/// failure is returned naming the address.
+fn listen(config: &Config) -> io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(config.bind.len());
+    for bind in &config.bind {
+        let addr = SocketAddr::new(bind.ip, config.port);
+        match bind_listener(addr) {
+            Ok(listener) => {
+                info!("Listening on {}", addr);
+                listeners.push(listener);
+            }
+            Err(err) if bind.optional => warn!("Skipping {}: {}", addr, err),
+            Err(err) => {
+                return Err(io::Error::new(
+                    err.kind(),
+                    format!("Failed to bind {}: {}", addr, err),
+                ));
+            }
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "Failed to bind any of the configured addresses",
+        ));
+    }
+
+    Ok(listeners)
+}
+
+/// The store saved in the snapshot file, or an empty one if there is no
+/// file yet. A snapshot that can't be loaded is an error rather than a
+/// reason to start empty, since the next save would overwrite it.
+fn load_snapshot<S: Store>(config: &Config) -> io::Result<S> {
+    let path = config.snapshot_path();
+    if !path.try_exists()? {
+        return Ok(S::default());
+    }
+
+    let mut snapshot = Snapshot::load(&path).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to load {}: {}", path.display(), err),
+        )
+    })?;
+    snapshot.compress(config);
+    let store: S = snapshot.into_store(SystemTime::now());
+    info!(
+        "DB loaded from disk: {} keys from {}",
+        store.len(),
+        path.display()
+    );
+    Ok(store)
+}
+
+/// Saves the snapshot whenever a save point is reached, until the server
+/// shuts down. Saving blocks, so it runs on the blocking pool.
+async fn autosave<S: Store>(db: Arc<Database<S>>) {
+    let mut delay = AUTOSAVE_INTERVAL;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = db.shutdown.cancelled() => return,
+        }
+
+        delay = AUTOSAVE_INTERVAL;
+        if !db.persistence.is_due(&db.config(), SystemTime::now()) {
+            continue;
+        }
+
+        let saving = db.clone();
+        match tokio::task::spawn_blocking(move || saving.save()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                error!("Automatic save failed: {}", err);
+                delay = AUTOSAVE_RETRY_DELAY;
+            }
+            Err(err) => {
+                error!("Automatic save failed: {}", err);
+                delay = AUTOSAVE_RETRY_DELAY;
+            }
+        }
+    }
+}
+
+/// Removes keys that expired without being looked up, `hz` times a second
+/// until the server shuts down. Whatever a run has no time for is left to
+/// the next one.
+async fn active_expire<S: Store>(db: Arc<Database<S>>) {
+    loop {
+        let interval = Duration::from_secs(1) / db.config().hz;
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = db.shutdown.cancelled() => return,
+        }
+
+        let budget = interval * ACTIVE_EXPIRE_BUDGET_PERCENT / 100;
+        db.remove_expired_keys(SystemTime::now(), budget);
+    }
+}
+
+fn bind_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => {
+            // Otherwise `::` would also claim the IPv4 port and clash with a
+            // separate IPv4 listener
+            let socket = TcpSocket::new_v6()?;
+            SockRef::from(&socket).set_only_v6(true)?;
+            socket
+        }
+    };
+
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(511)
+}
+
+async fn accept_loop<S: Store>(
+    listener: TcpListener,
+    db: Arc<Database<S>>,
+    connections: TaskTracker,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = db.shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Err(err) => error!("Error when establishing connection: {:?}", err),
+            Ok((stream, _)) => {
+                // A connection keeps the configuration it was accepted with
+                let config = db.config();
+                configure_socket(&stream, &config);
+                let db = db.clone();
+                connections.spawn(async move {
+                    handle_connection(stream, &db, &config).await;
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{io::AsyncWriteExt, net::TcpStream, sync::oneshot};
+
+    use super::*;
+    use crate::test_util::{database, dispatch, expect_reply, read_to_close, request};
+
+    #[tokio::test]
+    async fn test_listen() {
+        // 192.0.2.0/24 is reserved for documentation and never assigned to
+        // a local interface
+        let inputs: &[&[&str]] = &[
+            &["127.0.0.1"],
+            &["127.0.0.1", "-192.0.2.1"],
+            &["127.0.0.1", "192.0.2.1"],
+            &["-192.0.2.1"],
+        ];
+        let expects = [Ok(1), Ok(1), Err("192.0.2.1"), Err("any")];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let config = Config {
+                bind: inputs[i]
+                    .iter()
+                    .map(|addr| BindAddr::parse(addr).unwrap())
+                    .collect(),
+                port: 0,
+                ..Config::default()
+            };
+
+            match (listen(&config), expects[i]) {
+                (Ok(listeners), Ok(count)) => assert_eq!(listeners.len(), count),
+                (Err(err), Err(msg)) => assert!(err.to_string().contains(msg), "{}", err),
+                (res, expected) => panic!("got {:?}, expected {:?}", res, expected),
+            }
+        }
+    }
+
+    fn local_server(db: Arc<Database>) -> Server {
+        Server::builder()
+            .addr("127.0.0.1:0".parse().unwrap())
+            .database(db)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_until_shutdown() {
+        let server = local_server(database(&[]));
+        let addr = server.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(server.run(stopped));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(&request(&[b"SET", b"key", b"value"]))
+            .await
+            .unwrap();
+        expect_reply(&mut client, b"+OK\r\n").await;
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$5\r\nvalue\r\n").await;
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_to_close(&mut client).await, b"");
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_active_expire() {
+        let db = database(&[("key", "value")]);
+        let server = local_server(db.clone());
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(server.run(stopped));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(&request(&[b"SET", b"expiring", b"value", b"PX", b"100"]))
+            .await
+            .unwrap();
+        expect_reply(&mut client, b"+OK\r\n").await;
+
+        // Removed without ever being looked up again, within a few runs of
+        // the active expiry at the default 10 per second
+        let removed = async {
+            while db.kv_store.read(b"expiring").entry(b"expiring").is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_millis(500), removed)
+            .await
+            .unwrap();
+        assert_eq!(db.kv_store.len(), 1);
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bind_from_config() {
+        // Port 0 from the configuration picks an ephemeral port too
+        let config = Config::from_args(["--bind", "127.0.0.1", "--port", "0"]).unwrap();
+        let server = Server::builder().config(config).build().unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(addr.ip().to_string(), "127.0.0.1");
+        assert_ne!(addr.port(), 0);
+
+        let running = tokio::spawn(server.run(future::pending::<()>()));
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut client, b"$-1\r\n").await;
+        running.abort();
+    }
+
+    #[tokio::test]
+    async fn test_bad_rename_command() {
+        // A configuration built directive by directive rather than through
+        // from_args is checked when the server is built
+        let mut config = Config::from_args(["--bind", "127.0.0.1", "--port", "0"]).unwrap();
+        config
+            .set("rename-command", &["nosuchcommand", "x"])
+            .unwrap();
+        let err = Server::builder().config(config).build().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "can't rename 'nosuchcommand': no such command"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_maxclients() {
+        let config = Config::from_args(["--maxclients", "1"]).unwrap();
+        let server = Server::builder()
+            .config(config)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let db = server.database().clone();
+        let running = tokio::spawn(server.run(future::pending::<()>()));
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        first.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut first, b"$-1\r\n").await;
+
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(
+            read_to_close(&mut second).await,
+            b"-ERR max number of clients reached\r\n"
+        );
+
+        // The slot is free again once the first client is gone
+        drop(first);
+        while !db.clients.list().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        third.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        expect_reply(&mut third, b"$-1\r\n").await;
+        running.abort();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        // A client that doesn't read its reply keeps the connection busy
+        // for longer than the socket buffers can absorb
+        let db = database(&[("key", &"A".repeat(64 * 1024 * 1024))]);
+        let config = Config::from_args(["--shutdown-timeout", "1"]).unwrap();
+        let server = Server::builder()
+            .config(config)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .database(db)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(server.run(stopped));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&request(&[b"GET", b"key"])).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let start = std::time::Instant::now();
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let dir =
+            std::env::temp_dir().join(format!("resp-server-test-{}-save", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config =
+            Config::from_args(["--dir", dir.to_str().unwrap(), "--save", "3600", "1"]).unwrap();
+        let build = || {
+            Server::builder()
+                .config(config.clone())
+                .addr("127.0.0.1:0".parse().unwrap())
+                .build()
+        };
+
+        // Keys and values that are neither text nor single lines
+        let inputs: &[(&[u8], &[u8])] = &[(b"crlf\r\n", b"\r\n\0"), (b"\xff\xfe", b"\x80")];
+        let server = build().unwrap();
+        let db = server.database().clone();
+        for (key, value) in inputs {
+            assert_eq!(dispatch(&request(&[b"SET", key, value]), &db), b"+OK\r\n");
+        }
+        assert_eq!(dispatch(&request(&[b"SAVE"]), &db), b"+OK\r\n");
+        assert_eq!(
+            db.persistence
+                .dirty
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+
+        let loaded = build().unwrap().database().clone();
+        for (key, value) in inputs {
+            let mut reply = format!("${}\r\n", value.len()).into_bytes();
+            reply.extend_from_slice(value);
+            reply.extend_from_slice(b"\r\n");
+            assert_eq!(dispatch(&request(&[b"GET", key]), &loaded), reply);
+        }
+
+        // With a save point configured, a write after the last save is saved
+        // on shutdown
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(server.run(stopped));
+        dispatch(&request(&[b"SET", b"late", b"v"]), &db);
+        stop.send(()).unwrap();
+        running.await.unwrap();
+        let loaded = build().unwrap().database().clone();
+        assert_eq!(
+            dispatch(&request(&[b"GET", b"late"]), &loaded),
+            b"$1\r\nv\r\n"
+        );
+
+        std::fs::write(config.snapshot_path(), b"RESPSNAP garbage").unwrap();
+        let err = build().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Failed to load"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_until_shutdown_command() {
+        let db = database(&[("key", "value")]);
+        let server = local_server(db.clone());
+        let addr = server.local_addr().unwrap();
+        let running = tokio::spawn(server.run(future::pending::<()>()));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&request(&[b"SHUTDOWN"])).await.unwrap();
+        assert_eq!(read_to_close(&mut client).await, b"");
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The database outlives the server
+        assert_eq!(db.kv_store.len(), 1);
+    }
+}
@@ -0,0 +1,595 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    config::Config,
+    crc64::crc64,
+    store::{Entry, Store, Str, Value},
+};
+
+// ===========================================================
+// Format
+// ===========================================================
+//
+// A snapshot is a header, a body and a footer:
+//
+//   header  "RESPSNAP", version (u32), body length (u64)
+//   body    entry count (u64), then per entry the key length (u32), key,
+//           type (u8), value and expiry in unix milliseconds (u64, 0 for
+//           none)
+//   footer  CRC64 of header and body
+//
+// A string value is its length (u32) and uncompressed data, a list its
+// element count (u32) and then each element as a string, and a hash its
+// field count (u32) and then each field and its value as strings.
+//
+// Integers are big endian, except for the checksum, which is little endian
+// as in RDB files.
+
+const MAGIC: &[u8; 8] = b"RESPSNAP";
+
+/// Version written by this server, and the only one it reads
+pub const VERSION: u32 = 2;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_HASH: u8 = 2;
+
+const HEADER_LEN: usize = MAGIC.len() + 4 + 8;
+const FOOTER_LEN: usize = 8;
+
+// ===========================================================
+// SnapshotError
+// ===========================================================
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The file could not be read
+    Read {
+        path: String,
+        reason: String,
+    },
+
+    /// The file could not be written, the previous snapshot is untouched
+    Write {
+        path: String,
+        reason: String,
+    },
+
+    /// The file doesn't start with the snapshot magic string
+    NotASnapshot,
+
+    UnsupportedVersion {
+        version: u32,
+    },
+
+    /// The file ends before the length its header declares
+    Truncated {
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The checksum doesn't match the contents
+    Checksum {
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The checksum matches but the body can't be decoded, which means it
+    /// was written wrongly rather than damaged afterwards
+    Malformed {
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Read { path, reason } => write!(f, "can't read '{}': {}", path, reason),
+            SnapshotError::Write { path, reason } => {
+                write!(f, "can't write '{}': {}", path, reason)
+            }
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported snapshot version {}, this server reads version {}",
+                version, VERSION
+            ),
+            SnapshotError::Truncated { expected, actual } => write!(
+                f,
+                "snapshot is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            SnapshotError::Checksum { expected, actual } => write!(
+                f,
+                "snapshot is corrupted: checksum is {:016x}, expected {:016x}",
+                actual, expected
+            ),
+            SnapshotError::Malformed { reason } => write!(f, "snapshot is malformed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// ===========================================================
+// Snapshot
+// ===========================================================
+
+/// Contents of a snapshot that passed every check
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub entries: Vec<(Bytes, Entry)>,
+}
+
+impl Snapshot {
+    /// Reads and verifies the snapshot at `path`
+    pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let data = fs::read(path).map_err(|err| SnapshotError::Read {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })?;
+        Snapshot::decode(&data)
+    }
+
+    /// Verifies the whole of `data` before decoding any of it, so that a
+    /// damaged snapshot is never partially applied
+    pub fn decode(data: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if data.is_empty() || !MAGIC.starts_with(&data[..data.len().min(MAGIC.len())]) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(SnapshotError::Truncated {
+                expected: (HEADER_LEN + FOOTER_LEN) as u64,
+                actual: data.len() as u64,
+            });
+        }
+
+        let mut header = Reader(&data[MAGIC.len()..HEADER_LEN]);
+        let version = header.u32()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+
+        let body_len = header.u64()?;
+        let expected = body_len.saturating_add((HEADER_LEN + FOOTER_LEN) as u64);
+        if (data.len() as u64) < expected {
+            return Err(SnapshotError::Truncated {
+                expected,
+                actual: data.len() as u64,
+            });
+        }
+        if data.len() as u64 > expected {
+            return Err(SnapshotError::Malformed {
+                reason: "trailing data after the checksum",
+            });
+        }
+
+        let (contents, footer) = data.split_at(data.len() - FOOTER_LEN);
+        let checksum = u64::from_le_bytes(footer.try_into().expect("footer is 8 bytes"));
+        let actual = crc64(0, contents);
+        if actual != checksum {
+            return Err(SnapshotError::Checksum {
+                expected: checksum,
+                actual,
+            });
+        }
+
+        let mut body = Reader(&contents[HEADER_LEN..]);
+        let count = body.u64()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let key = body.bytes()?;
+            let value = match body.u8()? {
+                TYPE_STRING => Value::from(body.bytes()?),
+                TYPE_LIST => {
+                    let len = body.u32()?;
+                    let mut elements = VecDeque::new();
+                    for _ in 0..len {
+                        elements.push_back(body.bytes()?);
+                    }
+                    Value::List(elements)
+                }
+                TYPE_HASH => {
+                    let len = body.u32()?;
+                    let mut fields = HashMap::new();
+                    for _ in 0..len {
+                        fields.insert(body.bytes()?, body.bytes()?);
+                    }
+                    Value::Hash(fields)
+                }
+                _ => {
+                    return Err(SnapshotError::Malformed {
+                        reason: "unknown value type",
+                    });
+                }
+            };
+            let expires_at = match body.u64()? {
+                0 => None,
+                millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+            };
+            entries.push((
+                key,
+                Entry {
+                    expires_at,
+                    ..Entry::new(value)
+                },
+            ));
+        }
+        if !body.0.is_empty() {
+            return Err(SnapshotError::Malformed {
+                reason: "data after the last entry",
+            });
+        }
+
+        Ok(Snapshot { entries })
+    }
+
+    /// Writes `data` to `path` through a temporary file in the same
+    /// directory, which is renamed over `path` once it is complete. A save
+    /// that fails halfway leaves the previous snapshot as it was.
+    pub fn write(path: &Path, data: &[u8]) -> Result<(), SnapshotError> {
+        let temp = path.with_file_name(format!("temp-{}.snap", process::id()));
+        let written = File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&temp, path));
+        written.map_err(|err| {
+            let _ = fs::remove_file(&temp);
+            SnapshotError::Write {
+                path: path.display().to_string(),
+                reason: err.to_string(),
+            }
+        })
+    }
+
+    /// Copies every entry of `store`, expired ones included. Copying the
+    /// data only bumps reference counts, so this is cheap enough to do under
+    /// the lock, leaving the encoding for after it is released.
+    pub fn of<S: Store>(store: &S) -> Snapshot {
+        Snapshot::of_stores(&[store])
+    }
+
+    /// Copies every entry of `stores`, as [`Snapshot::of`] does, into one
+    /// snapshot
+    pub fn of_stores<S: Store>(stores: &[&S]) -> Snapshot {
+        let len = stores.iter().map(|store| store.len()).sum();
+        let mut entries = Vec::with_capacity(len);
+        for store in stores {
+            store.scan(|key, entry| entries.push((key.clone(), entry.clone())));
+        }
+        Snapshot { entries }
+    }
+
+    /// Serializes every entry of `store`, expired ones included
+    pub fn encode<S: Store>(store: &S) -> Vec<u8> {
+        Snapshot::of(store).to_bytes()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&(self.entries.len() as u64).to_be_bytes());
+        for (key, entry) in &self.entries {
+            put_bytes(&mut body, key);
+            match &entry.value {
+                Value::Str(value) => {
+                    body.push(TYPE_STRING);
+                    put_bytes(&mut body, &value.data());
+                }
+                Value::List(elements) => {
+                    body.push(TYPE_LIST);
+                    body.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+                    for element in elements {
+                        put_bytes(&mut body, element);
+                    }
+                }
+                Value::Hash(fields) => {
+                    body.push(TYPE_HASH);
+                    body.extend_from_slice(&(fields.len() as u32).to_be_bytes());
+                    for (field, value) in fields {
+                        put_bytes(&mut body, field);
+                        put_bytes(&mut body, value);
+                    }
+                }
+            }
+            let millis = entry.expires_at.map_or(0, |expires_at| {
+                // An expiry at or before the epoch is long past, but 0
+                // means none
+                let millis = expires_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                u64::try_from(millis).unwrap_or(u64::MAX).max(1)
+            });
+            body.extend_from_slice(&millis.to_be_bytes());
+        }
+
+        let mut data = Vec::with_capacity(HEADER_LEN + body.len() + FOOTER_LEN);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        data.extend_from_slice(&body);
+        let checksum = crc64(0, &data);
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    /// Compresses the strings as the configuration says, snapshots hold them
+    /// uncompressed
+    pub fn compress(&mut self, config: &Config) {
+        if !config.value_compression {
+            return;
+        }
+        for (_, entry) in self.entries.iter_mut() {
+            if let Value::Str(value) = &entry.value {
+                let data = value.data();
+                entry.value = Value::Str(Str::compressed(data, config.value_compression_threshold));
+            }
+        }
+    }
+
+    /// Builds a store from the entries, leaving out those that have expired
+    /// at `now`. The others keep their expiry to the millisecond.
+    pub fn into_store<S: Store>(self, now: SystemTime) -> S {
+        let mut store = S::default();
+        for (key, entry) in self.entries {
+            if entry.expires_at.is_none_or(|expires_at| expires_at > now) {
+                store.insert(key, entry);
+            }
+        }
+        store
+    }
+
+    /// Number of entries that expire at some point
+    pub fn expiring(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at.is_some())
+            .count()
+    }
+
+    /// Number of entries that have already expired at `now`
+    pub fn expired(&self, now: SystemTime) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at.is_some_and(|at| at <= now))
+            .count()
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, data: &[u8]) {
+    // Values are far below 4 GiB, proto-max-bulk-len caps them at 512 MiB
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// Cursor over checksummed data, where running out of bytes means the
+/// writer produced an inconsistent body
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Malformed {
+                reason: "entry runs past the end of the body",
+            });
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Bytes, SnapshotError> {
+        let len = self.u32()? as usize;
+        Ok(Bytes::copy_from_slice(self.take(len)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{store::KvStore, test_util::store};
+
+    fn list() -> Value {
+        Value::List(VecDeque::from([
+            Bytes::from("a"),
+            Bytes::new(),
+            Bytes::from("\r\n"),
+        ]))
+    }
+
+    fn hash() -> Value {
+        Value::Hash(HashMap::from([
+            (Bytes::from("field"), Bytes::from("value")),
+            (Bytes::from("\r\n"), Bytes::new()),
+        ]))
+    }
+
+    fn snapshot() -> Vec<u8> {
+        let mut store: KvStore = store(&[("key", "value"), ("crlf\r\n", "\r\n\0")]);
+        store.insert(
+            Bytes::from("expiring"),
+            Entry {
+                expires_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+                ..Entry::new(Bytes::from_static(&[0xff, 0xfe]))
+            },
+        );
+        store.insert(Bytes::from("list"), Entry::new(list()));
+        store.insert(Bytes::from("hash"), Entry::new(hash()));
+        // Written uncompressed, so the format doesn't depend on the codec
+        store.insert(
+            Bytes::from("json"),
+            Entry::compressed(Bytes::from("{}".repeat(100)), 0),
+        );
+        Snapshot::encode(&store)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = snapshot();
+        let mut snapshot = Snapshot::decode(&data).unwrap();
+        snapshot.entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let expects = [
+            (Bytes::from("crlf\r\n"), Entry::new(Bytes::from("\r\n\0"))),
+            (
+                Bytes::from("expiring"),
+                Entry {
+                    expires_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+                    ..Entry::new(Bytes::from_static(&[0xff, 0xfe]))
+                },
+            ),
+            (Bytes::from("hash"), Entry::new(hash())),
+            (
+                Bytes::from("json"),
+                Entry::new(Bytes::from("{}".repeat(100))),
+            ),
+            (Bytes::from("key"), Entry::new(Bytes::from("value"))),
+            (Bytes::from("list"), Entry::new(list())),
+        ];
+        assert_eq!(snapshot.entries, expects);
+        assert_eq!(snapshot.expiring(), 1);
+        assert_eq!(snapshot.expired(SystemTime::now()), 1);
+
+        let empty = Snapshot::encode(&KvStore::new());
+        assert_eq!(Snapshot::decode(&empty).unwrap(), Snapshot::default());
+    }
+
+    #[test]
+    fn test_expiry_round_trip() {
+        // Every type with every kind of expiry. Expiries are saved to the
+        // millisecond, so a finer one loads truncated.
+        let now = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let future = now + Duration::from_secs(3600) + Duration::from_nanos(123_456_789);
+        let past = now - Duration::from_millis(1);
+
+        let inputs = [None, Some(future), Some(past), Some(now)];
+        let expects = [
+            Some(None),
+            Some(Some(now + Duration::from_millis(3_600_123))),
+            None,
+            None,
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for value in [Value::from(Bytes::from("value")), list(), hash()] {
+            for i in 0..inputs.len() {
+                let mut store = KvStore::new();
+                store.insert(
+                    Bytes::from("key"),
+                    Entry {
+                        expires_at: inputs[i],
+                        ..Entry::new(value.clone())
+                    },
+                );
+
+                let data = Snapshot::encode(&store);
+                let loaded: KvStore = Snapshot::decode(&data).unwrap().into_store(now);
+                let entry = loaded.entry(b"key");
+                assert_eq!(
+                    entry.map(|entry| entry.expires_at),
+                    expects[i],
+                    "{:?} {:?}",
+                    value,
+                    inputs[i]
+                );
+                assert!(entry.is_none_or(|entry| entry.value == value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = std::env::temp_dir().join(format!("resp-server-test-{}-snap", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.snap");
+
+        // Each write replaces the previous snapshot as a whole
+        for data in [snapshot(), Snapshot::encode(&KvStore::new())] {
+            Snapshot::write(&path, &data).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), data);
+        }
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        let missing = dir.join("missing").join("dump.snap");
+        assert!(matches!(
+            Snapshot::write(&missing, &snapshot()),
+            Err(SnapshotError::Write { path, .. }) if path == missing.display().to_string()
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_damaged() {
+        let data = snapshot();
+        let len = data.len() as u64;
+
+        let mut flipped = data.clone();
+        flipped[HEADER_LEN + 12] ^= 0x01;
+        let mut version = data.clone();
+        version[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&3u32.to_be_bytes());
+        let mut trailing = data.clone();
+        trailing.push(0);
+
+        let inputs = [
+            data[..data.len() - 1].to_vec(),
+            data[..HEADER_LEN - 1].to_vec(),
+            flipped.clone(),
+            version,
+            b"REDIS0011".to_vec(),
+            Vec::new(),
+            trailing,
+        ];
+        let expects = [
+            SnapshotError::Truncated {
+                expected: len,
+                actual: len - 1,
+            },
+            SnapshotError::Truncated {
+                expected: (HEADER_LEN + FOOTER_LEN) as u64,
+                actual: HEADER_LEN as u64 - 1,
+            },
+            SnapshotError::Checksum {
+                expected: crc64(0, &data[..data.len() - FOOTER_LEN]),
+                actual: crc64(0, &flipped[..data.len() - FOOTER_LEN]),
+            },
+            SnapshotError::UnsupportedVersion { version: 3 },
+            SnapshotError::NotASnapshot,
+            SnapshotError::NotASnapshot,
+            SnapshotError::Malformed {
+                reason: "trailing data after the checksum",
+            },
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(Snapshot::decode(&inputs[i]), Err(expects[i].clone()));
+        }
+    }
+}
@@ -0,0 +1,871 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    hash::{DefaultHasher, Hasher},
+    mem,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// ===========================================================
+// Str
+// ===========================================================
+
+/// A string value, held LZ4 compressed if that was worth it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Str {
+    /// The value as stored, compressed if `compressed` is set. Commands read
+    /// the value itself through [`Str::data`].
+    stored: Bytes,
+    compressed: bool,
+}
+
+impl Str {
+    pub fn new(data: Bytes) -> Str {
+        Str {
+            stored: data,
+            compressed: false,
+        }
+    }
+
+    /// Holds `data` compressed if it is longer than `threshold` and
+    /// compressing actually makes it smaller
+    pub fn compressed(data: Bytes, threshold: usize) -> Str {
+        if data.len() > threshold {
+            let compressed = lz4_flex::compress_prepend_size(&data);
+            if compressed.len() < data.len() {
+                return Str {
+                    stored: Bytes::from(compressed),
+                    compressed: true,
+                };
+            }
+        }
+
+        Str::new(data)
+    }
+
+    /// The value, decompressed if it is stored compressed. Call it after
+    /// releasing the database lock, decompressing a large value takes a
+    /// while.
+    pub fn data(&self) -> Bytes {
+        if !self.compressed {
+            return self.stored.clone();
+        }
+
+        let data = lz4_flex::decompress_size_prepended(&self.stored)
+            .expect("compressed values are only produced by Str::compressed");
+        Bytes::from(data)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Length as stored, compressed or not
+    pub fn stored_len(&self) -> usize {
+        self.stored.len()
+    }
+}
+
+// ===========================================================
+// Value
+// ===========================================================
+
+/// Value stored under a key, one variant per type. Commands create strings
+/// and hashes, no command creates lists yet. A command working on one type
+/// replies WRONGTYPE for the others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Str(Str),
+    List(VecDeque<Bytes>),
+
+    /// Fields and their values
+    Hash(HashMap<Bytes, Bytes>),
+}
+
+impl Value {
+    /// Bytes of data held, compressed strings counting as stored
+    pub fn stored_len(&self) -> usize {
+        match self {
+            Value::Str(value) => value.stored_len(),
+            Value::List(elements) => elements.iter().map(Bytes::len).sum(),
+            Value::Hash(fields) => fields
+                .iter()
+                .map(|(field, value)| field.len() + value.len())
+                .sum(),
+        }
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(data: Bytes) -> Value {
+        Value::Str(Str::new(data))
+    }
+}
+
+impl From<Str> for Value {
+    fn from(value: Str) -> Value {
+        Value::Str(value)
+    }
+}
+
+// ===========================================================
+// Entry
+// ===========================================================
+
+/// Wall clock time an entry was last accessed, in milliseconds since the
+/// epoch. It is updated through a shared reference, so that reads can
+/// update it under the read lock.
+#[derive(Debug, Default)]
+pub struct AccessTime(AtomicU64);
+
+impl AccessTime {
+    pub fn now() -> AccessTime {
+        AccessTime(AtomicU64::new(unix_millis(SystemTime::now())))
+    }
+
+    pub fn touch(&self) {
+        self.0
+            .store(unix_millis(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Clone for AccessTime {
+    fn clone(&self) -> AccessTime {
+        AccessTime(AtomicU64::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+/// What is stored under a key
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub value: Value,
+
+    /// Wall clock time the key expires at, so that it stays meaningful for a
+    /// backend that outlives the process
+    pub expires_at: Option<SystemTime>,
+
+    pub last_access: AccessTime,
+}
+
+impl Entry {
+    /// Entry that never expires
+    pub fn new(value: impl Into<Value>) -> Entry {
+        Entry {
+            value: value.into(),
+            expires_at: None,
+            last_access: AccessTime::now(),
+        }
+    }
+
+    /// String entry that never expires, compressed as [`Str::compressed`]
+    /// does
+    pub fn compressed(data: Bytes, threshold: usize) -> Entry {
+        Entry::new(Str::compressed(data, threshold))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        // The clock is only read for keys that can expire at all
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now())
+    }
+}
+
+/// Entries are equal if they hold the same value and expire at the same
+/// time. The access time is bookkeeping rather than content.
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.value == other.value && self.expires_at == other.expires_at
+    }
+}
+
+impl Eq for Entry {}
+
+// ===========================================================
+// Store
+// ===========================================================
+
+/// Storage backend of a [`Database`](crate::Database).
+///
+/// A store only has to keep entries. Expiry is handled by the provided
+/// methods, which commands use, so every backend treats expired keys the
+/// same way. The store sits behind the database lock, and a command holding
+/// the write guard has exclusive access to it, so operations spanning
+/// several keys are atomic without any help from the backend.
+pub trait Store: Default + Send + Sync + 'static {
+    /// Entry under `key`, even if it has expired
+    fn entry(&self, key: &[u8]) -> Option<&Entry>;
+
+    /// Entry under `key` for modification, even if it has expired
+    fn entry_mut(&mut self, key: &[u8]) -> Option<&mut Entry>;
+
+    /// Stores `entry` under `key`, returning the entry it replaced
+    fn insert(&mut self, key: Bytes, entry: Entry) -> Option<Entry>;
+
+    /// Removes the entry under `key` and returns it, even if it has expired
+    fn remove(&mut self, key: &[u8]) -> Option<Entry>;
+
+    /// Number of entries, expired ones included
+    fn len(&self) -> usize;
+
+    /// Visits every entry in no particular order, expired ones included
+    fn scan(&self, visit: impl FnMut(&Bytes, &Entry));
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Entry under `key`, unless it has expired
+    fn get(&self, key: &[u8]) -> Option<&Entry> {
+        self.entry(key).filter(|entry| !entry.is_expired())
+    }
+
+    /// Stores `value` under `key` without an expiry, returning the entry it
+    /// replaced, expired or not
+    fn set(&mut self, key: Bytes, value: Bytes) -> Option<Entry> {
+        self.insert(key, Entry::new(value))
+    }
+}
+
+/// The default, in-memory store
+pub type KvStore = HashMap<Bytes, Entry>;
+
+impl Store for KvStore {
+    fn entry(&self, key: &[u8]) -> Option<&Entry> {
+        HashMap::get(self, key)
+    }
+
+    fn entry_mut(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: Bytes, entry: Entry) -> Option<Entry> {
+        HashMap::insert(self, key, entry)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Entry> {
+        HashMap::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn scan(&self, mut visit: impl FnMut(&Bytes, &Entry)) {
+        self.iter().for_each(|(key, entry)| visit(key, entry));
+    }
+}
+
+// ===========================================================
+// Keyspace
+// ===========================================================
+
+/// Position of a key in a scan. SCAN walks the keys in the order of this
+/// hash rather than in the order of the store, which changes as keys come
+/// and go. The hasher has fixed keys, so a cursor stays valid for as long
+/// as the server runs.
+pub(crate) fn scan_position(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(key);
+    hasher.finish()
+}
+
+/// The store of a shard, along with the counts INFO reports about it, so that they don't
+/// take a scan, and indexes of the keys that expire and of every key by its
+/// scan position. Reads go to the store, every change goes through the
+/// keyspace to keep them right.
+pub(crate) struct Keyspace<S> {
+    store: S,
+
+    /// Every key, expired ones included, ordered by [`scan_position`], so
+    /// that SCAN continues from its cursor without walking the store
+    positions: BTreeSet<(u64, Bytes)>,
+
+    /// Keys with an expire time, including expired keys not removed yet,
+    /// ordered by when they expire
+    volatile: BTreeSet<(SystemTime, Bytes)>,
+
+    /// Sum of their expire times in milliseconds since the epoch, for the
+    /// average TTL
+    expires_sum: u128,
+}
+
+impl<S: Store> Keyspace<S> {
+    pub(crate) fn new(store: S) -> Keyspace<S> {
+        let mut keyspace = Keyspace {
+            store,
+            positions: BTreeSet::new(),
+            volatile: BTreeSet::new(),
+            expires_sum: 0,
+        };
+        keyspace.recount();
+        keyspace
+    }
+
+    /// Stores `entry` under `key`, returning the entry it replaced
+    pub(crate) fn insert(&mut self, key: Bytes, entry: Entry) -> Option<Entry> {
+        let expires_at = entry.expires_at;
+        let replaced = self.store.insert(key.clone(), entry);
+        match &replaced {
+            Some(replaced) => self.unindex(&key, replaced.expires_at),
+            None => {
+                self.positions.insert((scan_position(&key), key.clone()));
+            }
+        }
+        self.index(key, expires_at);
+        replaced
+    }
+
+    /// Removes the entry under `key` and returns it, even if it has expired
+    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<Entry> {
+        let removed = self.store.remove(key);
+        if let Some(removed) = &removed {
+            self.unindex(key, removed.expires_at);
+            self.positions
+                .remove(&(scan_position(key), Bytes::copy_from_slice(key)));
+        }
+        removed
+    }
+
+    /// Value under `key` for modification, even if it has expired. Changing
+    /// a value leaves the counts as they are.
+    pub(crate) fn value_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        self.store.entry_mut(key).map(|entry| &mut entry.value)
+    }
+
+    /// Changes the expire time of the entry under `key`, expired or not.
+    /// Returns false if there is no such entry.
+    pub(crate) fn set_expiry(&mut self, key: &[u8], expires_at: Option<SystemTime>) -> bool {
+        let Some(entry) = self.store.entry_mut(key) else {
+            return false;
+        };
+        let previous = mem::replace(&mut entry.expires_at, expires_at);
+        self.unindex(key, previous);
+        self.index(Bytes::copy_from_slice(key), expires_at);
+        true
+    }
+
+    /// Up to `limit` keys that have expired at `now`, those that expired
+    /// first first. Only expiring keys are looked at, so finding them takes
+    /// no scan of the store.
+    pub(crate) fn expired_keys(&self, now: SystemTime, limit: usize) -> Vec<Bytes> {
+        self.volatile
+            .iter()
+            .take_while(|(expires_at, _)| *expires_at <= now)
+            .take(limit)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    /// Walks the keys from scan position `cursor` on, in position order,
+    /// adding those that haven't expired to `keys`, until `budget` keys
+    /// have been walked. The keys sharing the position of the last one are
+    /// walked as well, since a cursor can't point between them. Expired
+    /// keys count against the budget, so that a batch costs the same
+    /// however many there are. Returns the position to continue from, or
+    /// `None` once past the last key.
+    fn scan_from(&self, cursor: u64, budget: &mut usize, keys: &mut Vec<Bytes>) -> Option<u64> {
+        let mut last = None;
+        for (position, key) in self.positions.range((cursor, Bytes::new())..) {
+            if *budget == 0 && last != Some(*position) {
+                return Some(*position);
+            }
+            *budget = budget.saturating_sub(1);
+            last = Some(*position);
+            if self.store.get(key).is_some() {
+                keys.push(key.clone());
+            }
+        }
+        None
+    }
+
+    pub(crate) fn expires(&self) -> usize {
+        self.volatile.len()
+    }
+
+    fn index(&mut self, key: Bytes, expires_at: Option<SystemTime>) {
+        if let Some(expires_at) = expires_at {
+            self.volatile.insert((expires_at, key));
+            self.expires_sum += u128::from(unix_millis(expires_at));
+        }
+    }
+
+    fn unindex(&mut self, key: &[u8], expires_at: Option<SystemTime>) {
+        if let Some(expires_at) = expires_at {
+            self.volatile
+                .remove(&(expires_at, Bytes::copy_from_slice(key)));
+            self.expires_sum -= u128::from(unix_millis(expires_at));
+        }
+    }
+
+    fn recount(&mut self) {
+        let (mut positions, mut volatile, mut expires_sum) = (BTreeSet::new(), BTreeSet::new(), 0);
+        self.store.scan(|key, entry| {
+            positions.insert((scan_position(key), key.clone()));
+            if let Some(expires_at) = entry.expires_at {
+                volatile.insert((expires_at, key.clone()));
+                expires_sum += u128::from(unix_millis(expires_at));
+            }
+        });
+        self.positions = positions;
+        self.volatile = volatile;
+        self.expires_sum = expires_sum;
+    }
+}
+
+impl<S> Deref for Keyspace<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.store
+    }
+}
+
+// ===========================================================
+// Shards
+// ===========================================================
+
+/// The keyspace is split into 2 to the power of this many shards
+const SHARD_BITS: u32 = 4;
+
+/// Index of the shard holding the keys at scan position `position`. Each
+/// shard holds a contiguous range of positions, so that SCAN walks the
+/// shards one after the other.
+fn shard_of(position: u64) -> usize {
+    (position >> (u64::BITS - SHARD_BITS)) as usize
+}
+
+/// First scan position of the shard at `index`
+fn shard_start(index: usize) -> u64 {
+    (index as u64) << (u64::BITS - SHARD_BITS)
+}
+
+fn shard_index(key: &[u8]) -> usize {
+    shard_of(scan_position(key))
+}
+
+/// The keyspace split into shards by the scan position of the keys, each
+/// behind its own lock, so that commands on keys of different shards don't
+/// wait on each other. A command on several keys locks every shard it needs
+/// at once with [`Shards::read_keys`] or [`Shards::write_keys`]. Those take
+/// the locks in shard order, so that two such commands can't deadlock.
+pub(crate) struct Shards<S> {
+    shards: Box<[RwLock<Keyspace<S>>]>,
+}
+
+impl<S: Store> Shards<S> {
+    pub(crate) fn new(store: S) -> Shards<S> {
+        Shards {
+            shards: Shards::split(store).into_iter().map(RwLock::new).collect(),
+        }
+    }
+
+    /// Moves the entries of `store` to a store per shard
+    fn split(mut store: S) -> Vec<Keyspace<S>> {
+        let mut keys = Vec::with_capacity(store.len());
+        store.scan(|key, _| keys.push(key.clone()));
+
+        let mut stores: Vec<S> = (0..1 << SHARD_BITS).map(|_| S::default()).collect();
+        for key in keys {
+            if let Some(entry) = store.remove(&key) {
+                stores[shard_index(&key)].insert(key, entry);
+            }
+        }
+        stores.into_iter().map(Keyspace::new).collect()
+    }
+
+    /// Read lock of the shard holding `key`
+    pub(crate) fn read(&self, key: &[u8]) -> RwLockReadGuard<'_, Keyspace<S>> {
+        self.shards[shard_index(key)].read()
+    }
+
+    /// Write lock of the shard holding `key`
+    pub(crate) fn write(&self, key: &[u8]) -> RwLockWriteGuard<'_, Keyspace<S>> {
+        self.shards[shard_index(key)].write()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn try_write(&self, key: &[u8]) -> Option<RwLockWriteGuard<'_, Keyspace<S>>> {
+        self.shards[shard_index(key)].try_write()
+    }
+
+    /// Read locks of the shards holding `keys`
+    pub(crate) fn read_keys<'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) -> ShardLocks<RwLockReadGuard<'_, Keyspace<S>>> {
+        self.lock(keys.into_iter().map(shard_index).collect(), RwLock::read)
+    }
+
+    /// Write locks of the shards holding `keys`
+    pub(crate) fn write_keys<'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k [u8]>,
+    ) -> ShardLocks<RwLockWriteGuard<'_, Keyspace<S>>> {
+        self.lock(keys.into_iter().map(shard_index).collect(), RwLock::write)
+    }
+
+    /// Read locks of every shard
+    pub(crate) fn read_all(&self) -> ShardLocks<RwLockReadGuard<'_, Keyspace<S>>> {
+        self.lock((0..self.shards.len()).collect(), RwLock::read)
+    }
+
+    /// Write locks of every shard
+    pub(crate) fn write_all(&self) -> ShardLocks<RwLockWriteGuard<'_, Keyspace<S>>> {
+        self.lock((0..self.shards.len()).collect(), RwLock::write)
+    }
+
+    /// Locks the shards at `indexes` with `lock`, in order
+    fn lock<'a, G>(
+        &'a self,
+        mut indexes: Vec<usize>,
+        lock: impl Fn(&'a RwLock<Keyspace<S>>) -> G,
+    ) -> ShardLocks<G> {
+        indexes.sort_unstable();
+        indexes.dedup();
+        ShardLocks {
+            locks: indexes
+                .into_iter()
+                .map(|index| (index, lock(&self.shards[index])))
+                .collect(),
+        }
+    }
+
+    /// Every shard, for work done a shard at a time
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &RwLock<Keyspace<S>>> {
+        self.shards.iter()
+    }
+
+    /// Number of keys, expired ones included. The shards are counted one
+    /// after the other, so the count may be off by writes made meanwhile.
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().len()).sum()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.read().is_empty())
+    }
+
+    pub(crate) fn expires(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().expires()).sum()
+    }
+
+    /// Average time left to live of the keys with an expire time, in
+    /// milliseconds. Expired keys that haven't been removed count as having
+    /// none left, so they bring it down.
+    pub(crate) fn avg_ttl(&self, now: SystemTime) -> u64 {
+        let (count, sum) = self.shards.iter().fold((0, 0), |(count, sum), shard| {
+            let shard = shard.read();
+            (
+                count + shard.volatile.len() as u128,
+                sum + shard.expires_sum,
+            )
+        });
+        if count == 0 {
+            return 0;
+        }
+        let avg = u64::try_from(sum / count).unwrap_or(u64::MAX);
+        avg.saturating_sub(unix_millis(now))
+    }
+
+    /// Visits every entry, a shard at a time under its read lock
+    pub(crate) fn scan(&self, mut visit: impl FnMut(&Bytes, &Entry)) {
+        for shard in self.shards.iter() {
+            shard.read().scan(&mut visit);
+        }
+    }
+
+    /// Keys from scan position `cursor` on, in position order, along with
+    /// the cursor to continue from, 0 once there are none left. The batch
+    /// ends after `count` keys, as [`Keyspace::scan_from`] counts them,
+    /// moving on to the next shard while it is short. Each shard is only
+    /// locked while its part of the batch is taken.
+    pub(crate) fn scan_from(&self, cursor: u64, count: usize) -> (Vec<Bytes>, u64) {
+        let mut keys = Vec::new();
+        let mut budget = count;
+        for index in shard_of(cursor)..self.shards.len() {
+            let from = cursor.max(shard_start(index));
+            let next = self.shards[index]
+                .read()
+                .scan_from(from, &mut budget, &mut keys);
+            if let Some(next) = next {
+                return (keys, next);
+            }
+        }
+        (keys, 0)
+    }
+}
+
+/// Locks of some of the shards, taken by [`Shards`]. Keys are looked up in
+/// the shard holding them, which has to be one of those locked.
+pub(crate) struct ShardLocks<G> {
+    /// Sorted by shard index
+    locks: Vec<(usize, G)>,
+}
+
+impl<S: Store, G: Deref<Target = Keyspace<S>>> ShardLocks<G> {
+    fn position(&self, key: &[u8]) -> usize {
+        self.locks
+            .binary_search_by_key(&shard_index(key), |(index, _)| *index)
+            .expect("the shard holding the key is locked")
+    }
+
+    /// The shard holding `key`
+    pub(crate) fn shard(&self, key: &[u8]) -> &Keyspace<S> {
+        &self.locks[self.position(key)].1
+    }
+
+    /// The stores of the locked shards, in order
+    pub(crate) fn stores(&self) -> Vec<&S> {
+        self.locks.iter().map(|(_, lock)| &***lock).collect()
+    }
+}
+
+impl<S: Store, G: DerefMut<Target = Keyspace<S>>> ShardLocks<G> {
+    /// The shard holding `key`, for modification
+    pub(crate) fn shard_mut(&mut self, key: &[u8]) -> &mut Keyspace<S> {
+        let position = self.position(key);
+        &mut self.locks[position].1
+    }
+
+    /// Swaps in another store, split into shards, returning the stores of
+    /// the current ones. Every shard has to be locked, so that no command
+    /// sees some shards of each.
+    pub(crate) fn replace(&mut self, store: S) -> Vec<S> {
+        assert_eq!(self.locks.len(), 1 << SHARD_BITS, "every shard is locked");
+        Shards::split(store)
+            .into_iter()
+            .zip(&mut self.locks)
+            .map(|(keyspace, (_, lock))| mem::replace(&mut **lock, keyspace).store)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use super::*;
+    use crate::{
+        config::Config,
+        db::Database,
+        test_util::{dispatch, request, store},
+    };
+
+    /// Second backend, so that the tests show commands only rely on the
+    /// trait
+    type OrderedStore = BTreeMap<Bytes, Entry>;
+
+    impl Store for OrderedStore {
+        fn entry(&self, key: &[u8]) -> Option<&Entry> {
+            BTreeMap::get(self, key)
+        }
+
+        fn entry_mut(&mut self, key: &[u8]) -> Option<&mut Entry> {
+            BTreeMap::get_mut(self, key)
+        }
+
+        fn insert(&mut self, key: Bytes, entry: Entry) -> Option<Entry> {
+            BTreeMap::insert(self, key, entry)
+        }
+
+        fn remove(&mut self, key: &[u8]) -> Option<Entry> {
+            BTreeMap::remove(self, key)
+        }
+
+        fn len(&self) -> usize {
+            BTreeMap::len(self)
+        }
+
+        fn scan(&self, mut visit: impl FnMut(&Bytes, &Entry)) {
+            self.iter().for_each(|(key, entry)| visit(key, entry));
+        }
+    }
+
+    /// A store with a key without expiry, one that has expired and one that
+    /// expires in an hour
+    fn expiring<S: Store>() -> S {
+        let now = SystemTime::now();
+        let mut store: S = store(&[("key", "value")]);
+        store.insert(
+            Bytes::from("expired"),
+            Entry {
+                expires_at: Some(now - Duration::from_secs(1)),
+                ..Entry::new(Bytes::from("old"))
+            },
+        );
+        store.insert(
+            Bytes::from("expiring"),
+            Entry {
+                expires_at: Some(now + Duration::from_secs(3600)),
+                ..Entry::new(Bytes::from("new"))
+            },
+        );
+        store
+    }
+
+    fn check_get<S: Store>() {
+        let store = expiring::<S>();
+
+        let inputs = ["key", "expired", "expiring", "missing"];
+        let expects = [Some("value"), None, Some("new"), None];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let entry = Store::get(&store, inputs[i].as_bytes());
+            assert_eq!(
+                entry.map(|entry| entry.value.clone()),
+                expects[i].map(|value| Value::from(Bytes::from(value)))
+            );
+        }
+
+        // Expired entries are still there until removed
+        assert!(store.entry(b"expired").is_some());
+        assert_eq!(Store::len(&store), 3);
+
+        let mut keys = Vec::new();
+        store.scan(|key, _| keys.push(key.clone()));
+        keys.sort();
+        assert_eq!(keys, ["expired", "expiring", "key"]);
+    }
+
+    #[test]
+    fn test_compressed() {
+        let json = Bytes::from(r#"{"id": 1, "tags": ["a", "b"]}"#.repeat(100));
+        // xorshift output, which LZ4 can't shrink
+        let mut state = 0x2545f4914f6cdd1d_u64;
+        let random: Bytes = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        let inputs = [
+            (json.clone(), 1024),
+            (json.clone(), json.len()),
+            (random.clone(), 1024),
+            (Bytes::new(), 0),
+        ];
+        let expects = [true, false, false, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (value, threshold) = inputs[i].clone();
+            let str = Str::compressed(value.clone(), threshold);
+            assert_eq!(str.is_compressed(), expects[i]);
+            assert_eq!(str.data(), value);
+            if str.is_compressed() {
+                assert!(str.stored_len() < value.len() / 5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        check_get::<KvStore>();
+        check_get::<OrderedStore>();
+    }
+
+    fn run_commands<S: Store>(inputs: &[&[&[u8]]]) -> Vec<Vec<u8>> {
+        let db = Arc::new(Database::new(expiring::<S>(), &Config::default()));
+        inputs
+            .iter()
+            .map(|args| dispatch(&request(args), &db))
+            .collect()
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let inputs: &[&[&[u8]]] = &[
+            &[b"GET", b"key"],
+            &[b"GET", b"expired"],
+            &[b"GET", b"expiring"],
+            &[b"DEL", b"expired"],
+            &[b"SET", b"expired", b"again"],
+            &[b"GET", b"expired"],
+            &[b"DEL", b"expiring"],
+            &[b"DEL", b"expiring"],
+            &[b"FLUSHDB"],
+            &[b"GET", b"key"],
+        ];
+        let expects: &[&[u8]] = &[
+            b"$5\r\nvalue\r\n",
+            b"$-1\r\n",
+            b"$3\r\nnew\r\n",
+            b":0\r\n",
+            b"+OK\r\n",
+            b"$5\r\nagain\r\n",
+            b":1\r\n",
+            b":0\r\n",
+            b"+OK\r\n",
+            b"$-1\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        assert_eq!(run_commands::<KvStore>(inputs), expects);
+        assert_eq!(run_commands::<OrderedStore>(inputs), expects);
+    }
+
+    #[test]
+    fn test_scan_from() {
+        let shards = Shards::new(expiring::<KvStore>());
+        for i in 0..100 {
+            let key = Bytes::from(format!("key:{}", i));
+            shards.write(&key).insert(key, Entry::new(Bytes::new()));
+        }
+        shards.write(b"key:0").remove(b"key:0");
+        // Setting a key again leaves it where it was
+        shards
+            .write(b"key:1")
+            .insert(Bytes::from("key:1"), Entry::new(Bytes::from("again")));
+
+        // Batches follow the positions across shards, and the expired key
+        // counts towards its batch without being returned
+        let (mut cursor, mut keys, mut taken) = (0, Vec::new(), 0);
+        loop {
+            let (batch, next) = shards.scan_from(cursor, 7);
+            assert!(batch.len() <= 7);
+            assert!(next == 0 || next > cursor);
+            assert!(batch.iter().all(|key| scan_position(key) >= cursor));
+            assert!(next == 0 || batch.iter().all(|key| scan_position(key) < next));
+            taken += 1;
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(taken, 101_usize.div_ceil(7));
+        let mut expected: Vec<Bytes> = (1..100)
+            .map(|i| Bytes::from(format!("key:{}", i)))
+            .chain([Bytes::from("key"), Bytes::from("expiring")])
+            .collect();
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        // Replacing the store rebuilds the indexes of every shard
+        let previous = shards.write_all().replace(store(&[("only", "v")]));
+        assert_eq!(previous.len(), 1 << SHARD_BITS);
+        assert_eq!(previous.iter().map(|store| store.len()).sum::<usize>(), 102);
+        assert_eq!(shards.scan_from(0, 10), (vec![Bytes::from("only")], 0));
+    }
+}
@@ -0,0 +1,109 @@
+//! Helpers shared by the unit tests of the server modules
+
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use resp::{
+    parser::RespParser,
+    types::RespReadable,
+    writer::{RespWriter, WriteBuf},
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    client::ClientState,
+    command,
+    config::Config,
+    connection::{configure_socket, handle_connection},
+    db::Database,
+    store::Store,
+};
+
+pub(crate) fn store<S: Store>(entries: &[(&str, &str)]) -> S {
+    let mut store = S::default();
+    for (k, v) in entries {
+        store.set(
+            Bytes::copy_from_slice(k.as_bytes()),
+            Bytes::copy_from_slice(v.as_bytes()),
+        );
+    }
+    store
+}
+
+pub(crate) fn database(entries: &[(&str, &str)]) -> Arc<Database> {
+    Arc::new(Database::new(store(entries), &Config::default()))
+}
+
+pub(crate) fn args<'a>(args: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    args.to_vec()
+}
+
+pub(crate) fn run<S: Store>(args: &[&[u8]], db: &Database<S>) -> Vec<u8> {
+    run_as(args, db, &mut ClientState::new(None))
+}
+
+/// Runs a command on behalf of `client`, so that what it changes about the
+/// client carries over to the next command
+pub(crate) fn run_as<S: Store>(
+    args: &[&[u8]],
+    db: &Database<S>,
+    client: &mut ClientState,
+) -> Vec<u8> {
+    let mut write_buf = WriteBuf::new(Vec::new());
+    let mut writer = RespWriter::with_protocol(&mut write_buf, client.protocol);
+    command::dispatch(args, db, &mut writer, client).unwrap();
+    write_buf.get().clone()
+}
+
+pub(crate) fn request(args: &[&[u8]]) -> Vec<u8> {
+    let mut req = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        req.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        req.extend_from_slice(arg);
+        req.extend_from_slice(b"\r\n");
+    }
+    req
+}
+
+pub(crate) fn dispatch<S: Store>(req: &[u8], db: &Arc<Database<S>>) -> Vec<u8> {
+    let mut parser = RespParser::new(req);
+    let args = Vec::<&[u8]>::parse(&mut parser).unwrap();
+    run(&args, db)
+}
+
+/// Serves a single connection with the given configuration
+pub(crate) async fn connect(db: Arc<Database>, config: Config) -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        configure_socket(&stream, &config);
+        handle_connection(stream, &db, &config).await;
+    });
+
+    TcpStream::connect(addr).await.unwrap()
+}
+
+/// Reads everything the server sends until it closes the connection
+pub(crate) async fn read_to_close(client: &mut TcpStream) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+
+    let mut res = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+    res
+}
+
+/// Reads exactly as many bytes as `expected` and compares them
+pub(crate) async fn expect_reply(client: &mut TcpStream, expected: &[u8]) {
+    use tokio::io::AsyncReadExt;
+
+    let mut res = vec![0; expected.len()];
+    tokio::time::timeout(Duration::from_secs(5), client.read_exact(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(res, expected);
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use bytes::Bytes;
+use log::warn;
+use parking_lot::Mutex;
+use resp::types::RespValue;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::client::ClientInfo;
+
+// ===========================================================
+// Tracking
+// ===========================================================
+
+/// Push messages queued for a connection beyond which it is disconnected.
+/// They are only queued while the connection is busy or its reply queue is
+/// full, so a client this far behind has stopped reading.
+pub(crate) const PUSH_QUEUE_LEN: usize = 1024;
+
+/// Where the invalidation messages of a client go. The connection writes
+/// them out between replies.
+#[derive(Clone)]
+pub(crate) struct PushSender {
+    pushes: mpsc::Sender<RespValue>,
+    client: Arc<ClientInfo>,
+}
+
+impl PushSender {
+    /// Queues `push`, killing the client instead if its queue is full
+    fn send(&self, push: RespValue) {
+        match self.pushes.try_send(push) {
+            // Closed only once the connection has gone away
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(
+                    "Killing client #{}: {} push messages are waiting to be sent",
+                    self.client.id, PUSH_QUEUE_LEN
+                );
+                self.client.kill();
+            }
+        }
+    }
+}
+
+pub(crate) type PushReceiver = mpsc::Receiver<RespValue>;
+
+/// Creates the push queue of `client`
+pub(crate) fn push_queue(client: Arc<ClientInfo>) -> (PushSender, PushReceiver) {
+    let (pushes, receiver) = mpsc::channel(PUSH_QUEUE_LEN);
+    (PushSender { pushes, client }, receiver)
+}
+
+/// How a client set up with CLIENT TRACKING ON learns about changed keys
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TrackingMode {
+    /// Invalidated once for every key it read since, after which the key is
+    /// forgotten until it is read again
+    Default,
+
+    /// Invalidated for every key written that starts with one of the
+    /// prefixes, any key if there are none, whether it read it or not
+    Broadcast { prefixes: Vec<Bytes> },
+}
+
+struct Tracker {
+    mode: TrackingMode,
+    pushes: PushSender,
+
+    /// Keys the client read, in the default mode, so that they can be
+    /// forgotten when it stops tracking
+    keys: HashSet<Bytes>,
+}
+
+#[derive(Default)]
+struct Table {
+    trackers: HashMap<u64, Tracker>,
+
+    /// Ids of the clients in the default mode that read each key
+    keys: HashMap<Bytes, HashSet<u64>>,
+}
+
+impl Table {
+    /// Forgets `key`, returning the ids of the clients that read it
+    fn remove_key(&mut self, key: &[u8]) -> HashSet<u64> {
+        let readers = self.keys.remove(key).unwrap_or_default();
+        for id in &readers {
+            if let Some(tracker) = self.trackers.get_mut(id) {
+                tracker.keys.remove(key);
+            }
+        }
+        readers
+    }
+
+    /// Forgets that client `id` read `keys`, and the keys nobody else read
+    fn forget(&mut self, id: u64, keys: HashSet<Bytes>) {
+        for key in keys {
+            if let Some(readers) = self.keys.get_mut(&key) {
+                readers.remove(&id);
+                if readers.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Queues an invalidation of its keys for each client in `invalidated`
+    fn push(&self, invalidated: HashMap<u64, Vec<Bytes>>) {
+        for (id, keys) in invalidated {
+            if let Some(tracker) = self.trackers.get(&id) {
+                tracker.pushes.send(invalidate(Some(keys)));
+            }
+        }
+    }
+}
+
+/// Clients tracking keys for client side caching, and the keys they read
+#[derive(Default)]
+pub(crate) struct Tracking {
+    table: Mutex<Table>,
+
+    /// Number of tracking clients, so that commands don't take the lock
+    /// while nobody tracks
+    trackers: AtomicUsize,
+}
+
+impl Tracking {
+    /// Starts sending invalidations to client `id` through `pushes`. A
+    /// client that tracks already switches to `mode`, keeping the keys it
+    /// read if it stays in the default mode.
+    pub(crate) fn enable(&self, id: u64, mode: TrackingMode, pushes: PushSender) {
+        let mut table = self.table.lock();
+        let mut keys = HashSet::new();
+        if let Some(previous) = table.trackers.remove(&id) {
+            if mode == TrackingMode::Default {
+                keys = previous.keys;
+            } else {
+                table.forget(id, previous.keys);
+            }
+        }
+        table.trackers.insert(id, Tracker { mode, pushes, keys });
+        self.trackers.store(table.trackers.len(), Ordering::Relaxed);
+    }
+
+    /// Stops tracking for client `id`, forgetting the keys it read
+    pub(crate) fn disable(&self, id: u64) {
+        let mut table = self.table.lock();
+        let Some(tracker) = table.trackers.remove(&id) else {
+            return;
+        };
+        table.forget(id, tracker.keys);
+        self.trackers.store(table.trackers.len(), Ordering::Relaxed);
+    }
+
+    /// Number of clients with tracking on
+    pub(crate) fn len(&self) -> usize {
+        self.trackers.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.len() > 0
+    }
+
+    /// Remembers that client `id` read `keys`, if it tracks them in the
+    /// default mode. Beyond `max_keys` keys, unless it is 0, other keys are
+    /// forgotten and invalidated for the clients that read them, since they
+    /// wouldn't hear about changes anymore. The keys just read only go if
+    /// there are more of them than `max_keys`.
+    pub(crate) fn remember(&self, id: u64, keys: &[&[u8]], max_keys: usize) {
+        let mut table = self.table.lock();
+        let Some(tracker) = table.trackers.get_mut(&id) else {
+            return;
+        };
+        if tracker.mode != TrackingMode::Default {
+            return;
+        }
+
+        let keys: HashSet<_> = keys.iter().map(|key| Bytes::copy_from_slice(key)).collect();
+        tracker.keys.extend(keys.iter().cloned());
+        for key in &keys {
+            table.keys.entry(key.clone()).or_default().insert(id);
+        }
+
+        let mut evicted = HashMap::<u64, Vec<Bytes>>::new();
+        while max_keys > 0 && table.keys.len() > max_keys {
+            let Some(key) = table
+                .keys
+                .keys()
+                .find(|key| !keys.contains(*key))
+                .or_else(|| table.keys.keys().next())
+                .cloned()
+            else {
+                break;
+            };
+            for id in table.remove_key(&key) {
+                evicted.entry(id).or_default().push(key.clone());
+            }
+        }
+        table.push(evicted);
+    }
+
+    /// Tells the clients that read `keys`, and the broadcasting clients
+    /// interested in them, that they changed
+    pub(crate) fn invalidate(&self, keys: &[&[u8]]) {
+        if !self.is_active() {
+            return;
+        }
+
+        let mut table = self.table.lock();
+        let mut invalidated = HashMap::<u64, Vec<Bytes>>::new();
+        for key in keys {
+            let key = Bytes::copy_from_slice(key);
+            for id in table.remove_key(&key) {
+                invalidated.entry(id).or_default().push(key.clone());
+            }
+            for (id, tracker) in &table.trackers {
+                if let TrackingMode::Broadcast { prefixes } = &tracker.mode {
+                    if prefixes.is_empty() || prefixes.iter().any(|p| key.starts_with(p)) {
+                        invalidated.entry(*id).or_default().push(key.clone());
+                    }
+                }
+            }
+        }
+        table.push(invalidated);
+    }
+
+    /// Tells every tracking client to drop everything it cached, for when
+    /// the whole keyspace is replaced
+    pub(crate) fn invalidate_all(&self) {
+        if !self.is_active() {
+            return;
+        }
+
+        let mut table = self.table.lock();
+        table.keys.clear();
+        for tracker in table.trackers.values_mut() {
+            tracker.keys.clear();
+            tracker.pushes.send(invalidate(None));
+        }
+    }
+}
+
+/// The RESP3 push telling a client to drop `keys` from its cache, or all of
+/// it for `None`
+fn invalidate(keys: Option<Vec<Bytes>>) -> RespValue {
+    let keys = match keys {
+        Some(keys) => RespValue::Array(keys.into_iter().map(RespValue::from).collect()),
+        None => RespValue::None,
+    };
+    RespValue::Push(vec![RespValue::from("invalidate"), keys])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::ClientState;
+
+    /// Invalidated keys received so far, `None` for a flush
+    fn received(pushes: &mut PushReceiver) -> Vec<Option<Vec<Bytes>>> {
+        let mut received = Vec::new();
+        while let Ok(push) = pushes.try_recv() {
+            let RespValue::Push(mut message) = push else {
+                panic!("not a push: {:?}", push);
+            };
+            assert_eq!(message[0], RespValue::from("invalidate"));
+            received.push(match message.remove(1) {
+                RespValue::Array(keys) => Some(
+                    keys.into_iter()
+                        .map(|key| match key {
+                            RespValue::Bulk(key) => Bytes::copy_from_slice(key.as_bytes()),
+                            key => panic!("not a key: {:?}", key),
+                        })
+                        .collect(),
+                ),
+                RespValue::None => None,
+                keys => panic!("not keys: {:?}", keys),
+            });
+        }
+        received
+    }
+
+    fn queue() -> (PushSender, PushReceiver) {
+        push_queue(ClientState::new(None).info().clone())
+    }
+
+    fn keys(keys: &[&'static str]) -> Option<Vec<Bytes>> {
+        Some(keys.iter().map(|key| Bytes::from(*key)).collect())
+    }
+
+    #[test]
+    fn test_default_mode() {
+        let tracking = Tracking::default();
+        let (tx, mut rx) = queue();
+        assert!(!tracking.is_active());
+
+        // Not tracking yet
+        tracking.remember(1, &[b"a"], 0);
+        tracking.enable(1, TrackingMode::Default, tx);
+        tracking.invalidate(&[b"a"]);
+        assert_eq!(received(&mut rx), vec![]);
+
+        tracking.remember(1, &[b"a", b"b"], 0);
+        tracking.invalidate(&[b"a", b"c"]);
+        assert_eq!(received(&mut rx), vec![keys(&["a"])]);
+
+        // Invalidated once until read again
+        tracking.invalidate(&[b"a", b"b"]);
+        tracking.invalidate(&[b"b"]);
+        assert_eq!(received(&mut rx), vec![keys(&["b"])]);
+
+        tracking.remember(1, &[b"a"], 0);
+        tracking.invalidate_all();
+        tracking.invalidate(&[b"a"]);
+        assert_eq!(received(&mut rx), vec![None]);
+
+        tracking.remember(1, &[b"a"], 0);
+        tracking.disable(1);
+        assert!(!tracking.is_active());
+        tracking.invalidate(&[b"a"]);
+        tracking.invalidate_all();
+        assert_eq!(received(&mut rx), vec![]);
+    }
+
+    #[test]
+    fn test_broadcast_mode() {
+        let tracking = Tracking::default();
+        let (all_tx, mut all_rx) = queue();
+        let (user_tx, mut user_rx) = queue();
+        tracking.enable(1, TrackingMode::Broadcast { prefixes: vec![] }, all_tx);
+        tracking.enable(
+            2,
+            TrackingMode::Broadcast {
+                prefixes: vec![Bytes::from("user:"), Bytes::from("session:")],
+            },
+            user_tx,
+        );
+        assert_eq!(tracking.len(), 2);
+
+        // Reads aren't remembered
+        tracking.remember(1, &[b"a"], 0);
+        tracking.invalidate(&[b"a", b"user:1"]);
+        tracking.invalidate(&[b"session:1"]);
+        tracking.invalidate(&[b"user"]);
+
+        assert_eq!(
+            received(&mut all_rx),
+            vec![
+                keys(&["a", "user:1"]),
+                keys(&["session:1"]),
+                keys(&["user"])
+            ]
+        );
+        assert_eq!(
+            received(&mut user_rx),
+            vec![keys(&["user:1"]), keys(&["session:1"])]
+        );
+    }
+
+    #[test]
+    fn test_max_keys() {
+        let tracking = Tracking::default();
+        let (tx, mut rx) = queue();
+        tracking.enable(1, TrackingMode::Default, tx);
+
+        tracking.remember(1, &[b"a", b"b"], 2);
+        assert_eq!(received(&mut rx), vec![]);
+
+        // One of the first two is forgotten, and invalidated for the reader
+        tracking.remember(1, &[b"c"], 2);
+        let evicted = received(&mut rx);
+        assert!(
+            evicted == vec![keys(&["a"])] || evicted == vec![keys(&["b"])],
+            "{:?}",
+            evicted
+        );
+
+        tracking.invalidate(&[b"c"]);
+        assert_eq!(received(&mut rx), vec![keys(&["c"])]);
+    }
+
+    #[test]
+    fn test_max_one_key() {
+        let tracking = Tracking::default();
+        let (tx, mut rx) = queue();
+        tracking.enable(1, TrackingMode::Default, tx);
+
+        // Each read evicts the key read before, never itself
+        tracking.remember(1, &[b"key:0"], 1);
+        for i in 1..100 {
+            let key = format!("key:{}", i);
+            tracking.remember(1, &[key.as_bytes()], 1);
+            let previous = Bytes::from(format!("key:{}", i - 1));
+            assert_eq!(received(&mut rx), vec![Some(vec![previous])]);
+        }
+
+        tracking.invalidate(&[b"key:99"]);
+        assert_eq!(received(&mut rx), vec![keys(&["key:99"])]);
+
+        // Reading more keys at once than fit keeps only some of them
+        tracking.remember(1, &[b"a", b"b"], 1);
+        assert_eq!(received(&mut rx).len(), 1);
+        assert_eq!(tracking.table.lock().keys.len(), 1);
+    }
+
+    #[test]
+    fn test_disable_forgets_keys() {
+        let tracking = Tracking::default();
+        let (gone_tx, mut gone_rx) = queue();
+        let (tx, mut rx) = queue();
+        tracking.enable(1, TrackingMode::Default, gone_tx);
+        tracking.enable(2, TrackingMode::Default, tx);
+        tracking.remember(1, &[b"a", b"b"], 3);
+        tracking.remember(2, &[b"b", b"c"], 3);
+        assert_eq!(tracking.table.lock().keys.len(), 3);
+
+        // Keys nobody else read go, the others stay for their readers
+        tracking.disable(1);
+        assert_eq!(tracking.table.lock().keys.len(), 2);
+
+        // So there is room for another without evicting anything
+        tracking.remember(2, &[b"d"], 3);
+        tracking.invalidate(&[b"a", b"b"]);
+        assert_eq!(received(&mut gone_rx), vec![]);
+        assert_eq!(received(&mut rx), vec![keys(&["b"])]);
+
+        // Switching to BCAST forgets the keys read, as turning tracking off
+        tracking.enable(2, TrackingMode::Broadcast { prefixes: vec![] }, queue().0);
+        assert_eq!(tracking.table.lock().keys.len(), 0);
+    }
+
+    #[test]
+    fn test_full_queue_kills_client() {
+        let tracking = Tracking::default();
+        let client = ClientState::new(None);
+        let (tx, mut rx) = push_queue(client.info().clone());
+        tracking.enable(
+            client.id(),
+            TrackingMode::Broadcast { prefixes: vec![] },
+            tx,
+        );
+
+        for _ in 0..PUSH_QUEUE_LEN {
+            tracking.invalidate(&[b"a"]);
+        }
+        assert!(!client.is_killed());
+
+        // The client stopped reading, so it is let go rather than queued
+        // for without bound
+        tracking.invalidate(&[b"a"]);
+        assert!(client.is_killed());
+        assert_eq!(received(&mut rx).len(), PUSH_QUEUE_LEN);
+    }
+}
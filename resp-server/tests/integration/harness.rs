@@ -0,0 +1,102 @@
+//! Starts a server in-process for tests that drive it with a real client
+
+use std::{net::SocketAddr, time::Duration};
+
+use redis::aio::MultiplexedConnection;
+use resp::client::RespClient;
+use resp_server::{Server, config::Config};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+/// A server listening on an ephemeral port of the loopback interface. It is
+/// shut down when dropped, or explicitly through [`TestServer::stop`].
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: Option<oneshot::Sender<()>>,
+    running: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::with_config(Config::default()).await
+    }
+
+    pub async fn with_config(config: Config) -> TestServer {
+        let server = Server::builder()
+            .config(config)
+            .addr("127.0.0.1:0".parse().unwrap())
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let (stop, stopped) = oneshot::channel::<()>();
+        let running = tokio::spawn(server.run(stopped));
+
+        TestServer {
+            addr,
+            stop: Some(stop),
+            running: Some(running),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn client(&self) -> redis::Client {
+        redis::Client::open(format!("redis://{}/", self.addr)).unwrap()
+    }
+
+    pub async fn connection(&self) -> MultiplexedConnection {
+        self.client()
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap()
+    }
+
+    /// A connection through the client of the resp crate, to cross-check
+    /// redis-rs with
+    pub async fn resp_client(&self) -> RespClient {
+        RespClient::connect(self.addr).await.unwrap()
+    }
+
+    /// A plain socket, for requests a well-behaved client would never send
+    pub async fn raw_connection(&self) -> TcpStream {
+        TcpStream::connect(self.addr).await.unwrap()
+    }
+
+    /// Stops the server and waits until every connection has been closed
+    pub async fn stop(mut self) {
+        self.stop.take().unwrap().send(()).unwrap();
+        let running = self.running.take().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// Sends `req` as is and returns everything the server sends until it
+/// closes the connection
+pub async fn send_raw_until_close(client: &mut TcpStream, req: &[u8]) -> Vec<u8> {
+    client.write_all(req).await.unwrap();
+
+    let mut res = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+    res
+}
@@ -0,0 +1,358 @@
+//! Tests that drive the server over the wire with redis-rs, and with the
+//! client of the resp crate where the two are cross-checked. Run with
+//! `cargo test -p resp-server --test integration`.
+
+mod harness;
+
+use std::{collections::HashSet, time::Duration};
+
+use harness::{TestServer, send_raw_until_close};
+use redis::{AsyncCommands, RedisResult, Value};
+use resp::{
+    client::ClientError,
+    parser::RespParser,
+    types::{BulkString, RespReadable, RespValue},
+};
+use resp_server::config::Config;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The reply of redis-rs as a value of the resp crate, or the code of the
+/// error it returned. Only the RESP2 replies are converted.
+fn from_redis(res: RedisResult<Value>) -> Result<RespValue, String> {
+    fn convert(value: Value) -> RespValue {
+        match value {
+            Value::Nil => RespValue::None,
+            Value::Int(i) => RespValue::Integer(i),
+            Value::BulkString(data) => RespValue::Bulk(BulkString::from(data)),
+            Value::Array(values) => RespValue::Array(values.into_iter().map(convert).collect()),
+            Value::SimpleString(s) => RespValue::Simple(s),
+            Value::Okay => RespValue::Simple("OK".to_string()),
+            value => panic!("not a RESP2 reply: {:?}", value),
+        }
+    }
+
+    res.map(convert)
+        .map_err(|err| err.code().unwrap_or_default().to_string())
+}
+
+#[tokio::test]
+async fn test_set_get_del() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let missing: Option<String> = con.get("key").await.unwrap();
+    assert_eq!(missing, None);
+
+    let () = con.set("key", "value").await.unwrap();
+    let value: String = con.get("key").await.unwrap();
+    assert_eq!(value, "value");
+
+    let _: Value = con.del("key").await.unwrap();
+    let value: Option<String> = con.get("key").await.unwrap();
+    assert_eq!(value, None);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn test_counters_and_append() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let value: i64 = con.incr("counter", 1).await.unwrap();
+    assert_eq!(value, 1);
+    let value: i64 = con.incr("counter", 41).await.unwrap();
+    assert_eq!(value, 42);
+    let value: i64 = con.decr("counter", 50).await.unwrap();
+    assert_eq!(value, -8);
+    let value: i64 = redis::cmd("DECR")
+        .arg("counter")
+        .query_async(&mut con)
+        .await
+        .unwrap();
+    assert_eq!(value, -9);
+
+    let () = con.set("text", "abc").await.unwrap();
+    let res: RedisResult<i64> = con.incr("text", 1).await;
+    assert!(res.is_err());
+    let value: String = con.get("text").await.unwrap();
+    assert_eq!(value, "abc");
+
+    let len: usize = con.append("text", "def").await.unwrap();
+    assert_eq!(len, 6);
+    let value: String = con.get("text").await.unwrap();
+    assert_eq!(value, "abcdef");
+
+    let count: usize = con.exists(&["counter", "text", "missing"]).await.unwrap();
+    assert_eq!(count, 2);
+}
+
+#[tokio::test]
+async fn test_clients_agree() {
+    // A server each, since the commands change what the next ones see
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+    let cross_server = TestServer::start().await;
+    let mut client = cross_server.resp_client().await;
+
+    let inputs: &[&[&[u8]]] = &[
+        &[b"GET", b"key"],
+        &[b"SET", b"key", b"\x00value\r\n"],
+        &[b"GET", b"key"],
+        &[b"SET", b"key", b"new", b"NX"],
+        &[b"SET", b"key", b"new", b"GET"],
+        &[b"DEL", b"key", b"other"],
+        &[b"DEL", b"key"],
+        &[b"GET"],
+        &[b"FOO", b"bar"],
+        &[b"CLIENT", b"GETNAME"],
+        &[b"INCR", b"counter"],
+        &[b"INCRBY", b"counter", b"9223372036854775807"],
+        &[b"INCR", b"key"],
+        &[b"APPEND", b"key", b"ed"],
+        &[b"EXISTS", b"key", b"counter", b"missing"],
+    ];
+
+    for args in inputs {
+        let mut cmd = redis::cmd(std::str::from_utf8(args[0]).unwrap());
+        for arg in &args[1..] {
+            cmd.arg(*arg);
+        }
+        let expect = from_redis(cmd.query_async(&mut con).await);
+        let res = client.command(args).await.map_err(|err| match err {
+            ClientError::Reply(err) => err.code.to_string(),
+            err => panic!("{}", err),
+        });
+        assert_eq!(res, expect, "{:?}", args);
+    }
+}
+
+#[tokio::test]
+async fn test_resp_client_pipeline() {
+    let server = TestServer::start().await;
+    let mut client = server.resp_client().await;
+
+    let keys: Vec<_> = (0..100).map(|i| format!("key:{}", i)).collect();
+    let values: Vec<_> = (0..100).map(|i| i.to_string()).collect();
+    let mut commands: Vec<Vec<&[u8]>> = Vec::new();
+    for i in 0..100 {
+        commands.push(vec![b"SET", keys[i].as_bytes(), values[i].as_bytes()]);
+    }
+    for key in &keys {
+        commands.push(vec![b"GET", key.as_bytes()]);
+    }
+    let commands: Vec<&[&[u8]]> = commands.iter().map(Vec::as_slice).collect();
+
+    let replies = client.pipeline(&commands).await.unwrap();
+    assert_eq!(replies.len(), 200);
+    for i in 0..100 {
+        assert_eq!(replies[i], RespValue::Simple("OK".to_string()));
+        assert_eq!(replies[100 + i], RespValue::from(values[i].as_str()));
+    }
+
+    // Error replies stay in their place
+    let replies = client
+        .pipeline(&[&[b"FOO"], &[b"GET", b"key:1"]])
+        .await
+        .unwrap();
+    assert!(matches!(replies[0], RespValue::Error(_)));
+    assert_eq!(replies[1], RespValue::from("1"));
+}
+
+#[tokio::test]
+async fn test_binary_values() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let value: Vec<u8> = (0..=255).collect();
+    let () = con.set(b"\x00key\r\n", &value).await.unwrap();
+    let res: Vec<u8> = con.get(b"\x00key\r\n").await.unwrap();
+    assert_eq!(res, value);
+}
+
+#[tokio::test]
+async fn test_pipeline() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let mut pipe = redis::pipe();
+    for i in 0..100 {
+        pipe.set(format!("key:{}", i), i).ignore();
+    }
+    for i in 0..100 {
+        pipe.get(format!("key:{}", i));
+    }
+    let values: Vec<i64> = pipe.query_async(&mut con).await.unwrap();
+    assert_eq!(values, (0..100).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_concurrent_connections_on_one_key() {
+    const CLIENTS: usize = 16;
+    const WRITES: usize = 200;
+
+    let server = TestServer::start().await;
+
+    let mut tasks = Vec::new();
+    for client in 0..CLIENTS {
+        let mut con = server.connection().await;
+        tasks.push(tokio::spawn(async move {
+            for i in 0..WRITES {
+                let value = format!("{}:{}", client, i);
+                let () = con.set("key", &value).await.unwrap();
+                let _: String = con.get("key").await.unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    // Whichever write came last, the value is one of them and intact
+    let mut con = server.connection().await;
+    let value: String = con.get("key").await.unwrap();
+    let last_writes: HashSet<_> = (0..CLIENTS)
+        .map(|client| format!("{}:{}", client, WRITES - 1))
+        .collect();
+    assert!(last_writes.contains(&value), "{}", value);
+}
+
+#[tokio::test]
+async fn test_large_value() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let value = vec![b'A'; 16 * 1024 * 1024];
+    let () = con.set("key", &value).await.unwrap();
+    let res: Vec<u8> = con.get("key").await.unwrap();
+    assert_eq!(res.len(), value.len());
+    assert!(res == value);
+}
+
+#[tokio::test]
+async fn test_unknown_command() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+
+    let res: RedisResult<()> = redis::cmd("FOO").arg("bar").query_async(&mut con).await;
+    assert!(res.is_err());
+
+    // The connection is still usable
+    let () = con.set("key", "value").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_protocol_errors_close_the_connection() {
+    let config = Config {
+        proto_max_bulk_len: 1024,
+        ..Config::default()
+    };
+    let server = TestServer::with_config(config).await;
+
+    let inputs: &[&[u8]] = &[
+        b"*1\r\n+GET\r\n",
+        b"*1\r\n$2048\r\n",
+        b"*1\r\n$-1\r\n",
+        b"*x\r\n",
+    ];
+    let expects: &[&[u8]] = &[
+        b"-ERR Protocol error: expected '$', got '+'\r\n",
+        b"-ERR Protocol error: invalid bulk length\r\n",
+        b"-ERR Protocol error: invalid bulk length\r\n",
+        b"-ERR Protocol error: invalid multibulk length\r\n",
+    ];
+
+    assert_eq!(inputs.len(), expects.len());
+    for i in 0..inputs.len() {
+        let mut client = server.raw_connection().await;
+        assert_eq!(
+            send_raw_until_close(&mut client, inputs[i]).await,
+            expects[i]
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_hello_handshake() {
+    let server = TestServer::start().await;
+    let mut client = server.raw_connection().await;
+
+    // What a RESP3 client sends on connect, pipelined with a version the
+    // server doesn't speak and a command whose reply differs per protocol
+    let req = b"*4\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$7\r\nSETNAME\r\n$3\r\ncli\r\n\
+                *2\r\n$5\r\nHELLO\r\n$1\r\n4\r\n\
+                *2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n";
+    client.write_all(req).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut res = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(res.starts_with(b"%7\r\n"), "{}", res.escape_ascii());
+    let mut parser = RespParser::new(&res);
+    let Ok(RespValue::Map(pairs)) = RespValue::parse(&mut parser) else {
+        panic!("not a map: {}", res.escape_ascii());
+    };
+    let field = |name: &str| {
+        pairs
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(field("server"), Some(RespValue::from("redis")));
+    assert_eq!(field("proto"), Some(RespValue::Integer(3)));
+    assert!(matches!(field("id"), Some(RespValue::Integer(_))));
+    assert_eq!(field("modules"), Some(RespValue::Array(vec![])));
+    assert_eq!(
+        parser.remaining(),
+        b"-NOPROTO unsupported protocol version\r\n_\r\n"
+    );
+}
+
+#[tokio::test]
+async fn test_redis_cli_handshake() {
+    let server = TestServer::start().await;
+    let mut client = server.raw_connection().await;
+
+    // What redis-cli 7 sends on connect, then a PING and an ECHO as typed
+    // at its prompt
+    let req = b"*2\r\n$7\r\nCOMMAND\r\n$4\r\nDOCS\r\n\
+                *1\r\n$4\r\nPING\r\n\
+                *2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n";
+    client.write_all(req).await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut res = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut res))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut parser = RespParser::new(&res);
+    let replies: Vec<RespValue> = (0..3)
+        .map(|_| RespValue::parse(&mut parser).unwrap())
+        .collect();
+    assert_eq!(
+        replies,
+        [
+            RespValue::Array(vec![]),
+            RespValue::Simple("PONG".to_string()),
+            RespValue::from("hello"),
+        ]
+    );
+    assert!(parser.remaining().is_empty());
+}
+
+#[tokio::test]
+async fn test_shutdown_closes_clients() {
+    let server = TestServer::start().await;
+    let mut con = server.connection().await;
+    let () = con.set("key", "value").await.unwrap();
+
+    let addr = server.addr();
+    server.stop().await;
+
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    assert!(con.get::<_, Option<String>>("key").await.is_err());
+}
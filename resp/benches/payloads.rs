@@ -0,0 +1,77 @@
+//! Deterministic payloads for the benchmarks, so that numbers from
+//! different runs and machines are comparable
+
+use resp::{
+    types::{BulkString, RespValue, RespWritable},
+    writer::{RespWriter, WriteBuf},
+};
+
+/// Small xorshift generator, seeded the same way on every run
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Rng {
+        Rng(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// `SET key value`, as sent by a client
+pub fn set_command() -> RespValue {
+    RespValue::Array(
+        ["SET", "key", "value"]
+            .into_iter()
+            .map(|arg| RespValue::Bulk(BulkString::new(arg)))
+            .collect(),
+    )
+}
+
+/// A bulk string of `len` pseudo-random bytes
+pub fn bulk(len: usize) -> RespValue {
+    RespValue::Bulk(BulkString::new(Rng::new().bytes(len)))
+}
+
+/// An array of `len` elements, alternating between integers and short bulk
+/// strings
+pub fn array(len: usize) -> RespValue {
+    let mut rng = Rng::new();
+    let values = (0..len)
+        .map(|i| match i % 2 {
+            0 => RespValue::Integer(rng.next() as i64),
+            _ => {
+                let len = 1 + rng.next() as usize % 32;
+                RespValue::Bulk(BulkString::new(rng.bytes(len)))
+            }
+        })
+        .collect();
+    RespValue::Array(values)
+}
+
+/// A simple string of `len` letters, as a frame
+pub fn line(len: usize) -> Vec<u8> {
+    let mut rng = Rng::new();
+    let letters: Vec<u8> = (0..len).map(|_| b'a' + (rng.next() % 26) as u8).collect();
+    [&b"+"[..], &letters, b"\r\n"].concat()
+}
+
+/// A simple string of `len` lone CRs, as a frame, the worst case for
+/// finding the end of a line
+pub fn lone_cr_line(len: usize) -> Vec<u8> {
+    [&b"+"[..], &vec![b'\r'; len], b"\n"].concat()
+}
+
+pub fn encode(value: &RespValue) -> Vec<u8> {
+    let mut buf = WriteBuf::new(Vec::new());
+    value.write(&mut RespWriter::new(&mut buf)).unwrap();
+    buf.get().clone()
+}
@@ -0,0 +1,134 @@
+//! Parser and writer benchmarks. Run with `cargo bench -p resp`, and compare
+//! a change against the current state with `-- --save-baseline main` before
+//! and `-- --baseline main` after it.
+
+mod payloads;
+
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use resp::{
+    parser::RespParser,
+    types::{BulkString, RespReadable, RespValue, RespWritable},
+    writer::{RespWriter, SegmentedBuf, WriteBuf},
+};
+
+/// The shapes every benchmark is run with
+fn shapes() -> [(&'static str, RespValue); 3] {
+    [
+        ("set_command", payloads::set_command()),
+        ("bulk_1mb", payloads::bulk(1024 * 1024)),
+        ("array_10k", payloads::array(10_000)),
+    ]
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, value) in shapes() {
+        let data = payloads::encode(&value);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut parser = RespParser::new(black_box(&data));
+                RespValue::parse(&mut parser).unwrap()
+            })
+        });
+    }
+
+    // Long lines, where finding the CRLF is most of the work
+    let lines = [
+        ("line_64kb", payloads::line(64 * 1024)),
+        ("lone_cr_64kb", payloads::lone_cr_line(64 * 1024)),
+    ];
+    for (name, data) in lines {
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut parser = RespParser::new(black_box(&data));
+                RespValue::parse(&mut parser).unwrap()
+            })
+        });
+    }
+
+    // The path requests take in the server
+    let data = payloads::encode(&payloads::set_command());
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("set_command_as_request", |b| {
+        b.iter(|| {
+            let mut parser = RespParser::new(black_box(&data));
+            Vec::<BulkString>::parse(&mut parser).unwrap()
+        })
+    });
+    group.finish();
+}
+
+/// The same shapes as `parse`, borrowing strings from the input instead of
+/// copying them out
+fn bench_parse_ref(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_ref");
+    for (name, value) in shapes() {
+        let data = payloads::encode(&value);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut parser = RespParser::new(black_box(&data));
+                parser.parse_ref().unwrap()
+            })
+        });
+    }
+
+    let data = payloads::encode(&payloads::set_command());
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("set_command_as_request", |b| {
+        b.iter(|| {
+            let mut parser = RespParser::new(black_box(&data));
+            Vec::<&[u8]>::parse(&mut parser).unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for (name, value) in shapes() {
+        let mut buf = WriteBuf::new(Vec::new());
+        group.throughput(Throughput::Bytes(payloads::encode(&value).len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                buf.clear();
+                black_box(&value)
+                    .write(&mut RespWriter::new(&mut buf))
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The same shapes as `write`, into a buffer that shares large payloads
+/// instead of copying them
+fn bench_write_segmented(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write_segmented");
+    for (name, value) in shapes() {
+        let mut buf = WriteBuf::new(SegmentedBuf::new());
+        group.throughput(Throughput::Bytes(payloads::encode(&value).len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                buf.clear();
+                black_box(&value)
+                    .write(&mut RespWriter::new(&mut buf))
+                    .unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_parse_ref,
+    bench_write,
+    bench_write_segmented
+);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Reads RESP from stdin, such as an AOF file or a captured stream, and
+//! prints each value as a line of JSON. Malformed frames are reported on
+//! stderr and skipped.
+//!
+//! ```sh
+//! cargo run -p resp --features json --example resp2json < appendonly.aof
+//! ```
+
+use std::{
+    io::{self, BufWriter, Read, Write},
+    process::ExitCode,
+};
+
+use resp::{
+    json::to_json,
+    parser::{ErrorRecovery, ParserConfig, RespParser},
+};
+
+fn main() -> ExitCode {
+    let mut data = Vec::new();
+    if let Err(err) = io::stdin().read_to_end(&mut data) {
+        eprintln!("resp2json: can't read stdin: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    let config = ParserConfig {
+        on_error: ErrorRecovery::Resync,
+        ..ParserConfig::default()
+    };
+    let mut parser = RespParser::new_with_config(&data, config);
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut status = ExitCode::SUCCESS;
+    for res in parser.iter_values() {
+        match res {
+            Ok(value) => {
+                if writeln!(out, "{}", to_json(&value)).is_err() {
+                    return ExitCode::FAILURE;
+                }
+            }
+            Err(err) => {
+                eprintln!("resp2json: {}", err);
+                status = ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if out.flush().is_err() {
+        return ExitCode::FAILURE;
+    }
+    status
+}
@@ -0,0 +1,104 @@
+#![no_main]
+
+//! Builds a well-formed frame, encodes it and then damages the encoding, so
+//! that the parser sees inputs that are close to valid rather than random
+//! noise.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use resp::{
+    parser::RespParser,
+    types::{BulkString, RespError, RespReadable, RespValue, RespWritable, parse_value_recursive},
+    writer::{RespWriter, WriteBuf},
+};
+
+#[derive(Arbitrary, Debug)]
+enum Frame {
+    None,
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Frame>),
+}
+
+impl Frame {
+    fn into_value(self) -> RespValue {
+        // Simple strings can't carry line breaks
+        let line = |s: String| s.replace(['\r', '\n'], "");
+        match self {
+            Frame::None => RespValue::None,
+            Frame::Simple(s) => RespValue::Simple(line(s)),
+            Frame::Error(s) => RespValue::Error(RespError::from(line(s))),
+            Frame::Integer(i) => RespValue::Integer(i),
+            Frame::Bulk(data) => RespValue::Bulk(BulkString::new(data)),
+            Frame::Array(frames) => {
+                RespValue::Array(frames.into_iter().map(Frame::into_value).collect())
+            }
+        }
+    }
+}
+
+/// Repeated duplications grow the input exponentially, which would only
+/// exhaust the fuzzer's memory and not the parser's
+const MAX_INPUT_LEN: usize = 64 * 1024;
+
+#[derive(Arbitrary, Debug)]
+enum Mutation {
+    Truncate(usize),
+    Remove(usize),
+    Insert(usize, u8),
+    Replace(usize, u8),
+    Duplicate(usize, usize),
+}
+
+impl Mutation {
+    fn apply(&self, data: &mut Vec<u8>) {
+        // Positions wrap around the current length
+        let at = |pos: usize, len: usize| if len == 0 { 0 } else { pos % len };
+        match *self {
+            Mutation::Truncate(len) => data.truncate(at(len, data.len() + 1)),
+            Mutation::Remove(pos) if !data.is_empty() => {
+                data.remove(at(pos, data.len()));
+            }
+            Mutation::Insert(pos, byte) => data.insert(at(pos, data.len() + 1), byte),
+            Mutation::Replace(pos, byte) if !data.is_empty() => {
+                let pos = at(pos, data.len());
+                data[pos] = byte;
+            }
+            Mutation::Duplicate(start, len) if !data.is_empty() => {
+                let start = at(start, data.len());
+                let end = start + at(len, data.len() - start + 1);
+                if data.len() + end - start <= MAX_INPUT_LEN {
+                    let chunk = data[start..end].to_vec();
+                    data.splice(start..start, chunk);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fuzz_target!(|input: (Frame, Vec<Mutation>)| {
+    let (frame, mutations) = input;
+    let value = frame.into_value();
+
+    let mut buf = WriteBuf::new(Vec::new());
+    value.write(&mut RespWriter::new(&mut buf)).unwrap();
+    let mut data = buf.get().clone();
+
+    for mutation in mutations.iter() {
+        mutation.apply(&mut data);
+    }
+
+    let mut parser = RespParser::new(&data);
+    let val = RespValue::parse(&mut parser);
+
+    let mut recursive = RespParser::new(&data);
+    // Compared as text, since a NaN double isn't equal to itself
+    assert_eq!(
+        format!("{:?}", val),
+        format!("{:?}", parse_value_recursive(&mut recursive))
+    );
+    assert_eq!(parser.consumed(), recursive.consumed());
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use resp::{
+    parser::RespParser,
+    types::{BulkString, RespReadable},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = RespParser::new(data);
+    let _ = Vec::<BulkString>::parse(&mut parser);
+});
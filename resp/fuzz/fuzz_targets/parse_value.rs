@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use resp::{
+    parser::RespParser,
+    types::{RespReadable, RespValue, parse_value_recursive},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = RespParser::new(data);
+    let val = RespValue::parse(&mut parser);
+
+    // The iterative parser has to agree with the recursive one it replaced
+    let mut recursive = RespParser::new(data);
+    // Compared as text, since a NaN double isn't equal to itself
+    assert_eq!(
+        format!("{:?}", val),
+        format!("{:?}", parse_value_recursive(&mut recursive))
+    );
+    assert_eq!(parser.consumed(), recursive.consumed());
+});
@@ -0,0 +1,362 @@
+//! Parsing out of a [`bytes::Buf`] whose data may be split across chunks,
+//! such as a chain of received segments. The end of the next value is found
+//! by walking the chunks, then the value is parsed in place if it lies within
+//! the first chunk, and otherwise out of a copy of just its own bytes.
+
+use std::io::IoSlice;
+
+use bytes::Buf;
+
+use crate::{
+    parser::{MAX_NESTING_DEPTH, ParseError, ParseErrorKind, ParseResult, RespParser},
+    types::RespReadable,
+};
+
+/// Parses the next value out of `buf`, which is advanced past the value only
+/// if it could be parsed. The chunks are found through
+/// [`Buf::chunks_vectored`], so a value straddling chunks is only seen whole
+/// in buffers that expose all their chunks there, like `Chain` and
+/// `VecDeque<u8>` do.
+pub fn parse_buf<T, B>(buf: &mut B) -> ParseResult<T>
+where
+    T: for<'a> RespReadable<'a>,
+    B: Buf,
+{
+    let (value, len) = {
+        let slices = chunks(buf);
+        let chunks: Vec<&[u8]> = slices.iter().map(|slice| &**slice).collect();
+        let end = match scan(&chunks) {
+            Scan::Complete(end) | Scan::Malformed(end) => end,
+            Scan::Incomplete(end) => {
+                return Err(ParseError::new(ParseErrorKind::Incomplete { needed: None }).at(end));
+            }
+        };
+
+        match chunks.first() {
+            Some(chunk) if chunk.len() >= end => parse_frame(&chunk[..end])?,
+            _ => {
+                let mut frame = Vec::with_capacity(end);
+                for chunk in chunks.iter() {
+                    let left = end - frame.len();
+                    frame.extend_from_slice(&chunk[..chunk.len().min(left)]);
+                }
+                parse_frame(&frame)?
+            }
+        }
+    };
+    buf.advance(len);
+
+    Ok(value)
+}
+
+/// Parses a value out of `frame`, returning it with the number of bytes it
+/// took up
+fn parse_frame<T: for<'a> RespReadable<'a>>(frame: &[u8]) -> ParseResult<(T, usize)> {
+    let mut parser = RespParser::new(frame);
+    let value = T::parse(&mut parser)?;
+    Ok((value, parser.consumed()))
+}
+
+/// Every chunk of `buf`, in order
+fn chunks<B: Buf>(buf: &B) -> Vec<IoSlice<'_>> {
+    let mut slices = vec![IoSlice::new(&[]); 16];
+    loop {
+        let n = buf.chunks_vectored(&mut slices);
+        if n < slices.len() {
+            slices.truncate(n);
+            return slices;
+        }
+        slices.resize(slices.len() * 2, IoSlice::new(&[]));
+    }
+}
+
+// ===========================================================
+// Scanning
+// ===========================================================
+
+/// Outcome of looking for the end of the next value
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Scan {
+    /// The value takes up this many bytes
+    Complete(usize),
+
+    /// The input ends inside the value after this many bytes
+    Incomplete(usize),
+
+    /// The value is malformed within this many bytes, parsing them gives the
+    /// error
+    Malformed(usize),
+}
+
+/// Reads bytes across chunk boundaries
+struct Segments<'a> {
+    chunks: &'a [&'a [u8]],
+    chunk: usize,
+    pos: usize,
+    consumed: usize,
+}
+
+impl<'a> Segments<'a> {
+    fn new(chunks: &'a [&'a [u8]]) -> Segments<'a> {
+        let mut segments = Segments {
+            chunks,
+            chunk: 0,
+            pos: 0,
+            consumed: 0,
+        };
+        segments.skip_empty();
+        segments
+    }
+
+    fn skip_empty(&mut self) {
+        while self.chunk < self.chunks.len() && self.pos == self.chunks[self.chunk].len() {
+            self.chunk += 1;
+            self.pos = 0;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.chunks.get(self.chunk).map(|chunk| chunk[self.pos])
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        self.consumed += 1;
+        self.skip_empty();
+        Some(b)
+    }
+
+    /// Skips `len` bytes, failing if the input ends before
+    fn skip(&mut self, mut len: usize) -> bool {
+        while len > 0 {
+            let Some(chunk) = self.chunks.get(self.chunk) else {
+                return false;
+            };
+            let n = len.min(chunk.len() - self.pos);
+            self.pos += n;
+            self.consumed += n;
+            len -= n;
+            self.skip_empty();
+        }
+        true
+    }
+
+    /// Reads up to and including the next CRLF into `line`, without the
+    /// CRLF. Fails if the input ends before.
+    fn read_line(&mut self, line: &mut Vec<u8>) -> bool {
+        line.clear();
+        while let Some(b) = self.next_byte() {
+            if b == b'\n' && line.last() == Some(&b'\r') {
+                line.pop();
+                return true;
+            }
+            line.push(b);
+        }
+        false
+    }
+}
+
+/// Reads a length, which may be negative here so that the null ones can be
+/// told apart. Lengths beyond i64::MAX can't be complete anyway, they are
+/// capped.
+fn parse_len(line: &[u8]) -> Option<i64> {
+    match crate::parser::read_u64(line) {
+        Ok(len) => Some(i64::try_from(len).unwrap_or(i64::MAX)),
+        Err(err) => match err.into_kind() {
+            ParseErrorKind::NegativeInteger { value } => Some(value),
+            _ => None,
+        },
+    }
+}
+
+/// Finds the end of the first value in `chunks`. Only the framing is
+/// checked, what is inside the lines is left to the parser.
+pub(crate) fn scan(chunks: &[&[u8]]) -> Scan {
+    let mut input = Segments::new(chunks);
+    let mut line = Vec::new();
+
+    // Values each open aggregate still expects, innermost last. Streamed
+    // aggregates are open until their `.`.
+    let mut open: Vec<Option<i64>> = vec![Some(1)];
+    while let Some(expected) = open.last_mut() {
+        if *expected == Some(0) {
+            open.pop();
+            continue;
+        }
+
+        let Some(tag) = input.peek() else {
+            return Scan::Incomplete(input.consumed);
+        };
+        if expected.is_none() && tag == b'.' {
+            input.next_byte();
+            if !input.read_line(&mut line) {
+                return Scan::Incomplete(input.consumed);
+            }
+            open.pop();
+            continue;
+        }
+        if let Some(n) = expected {
+            *n -= 1;
+        }
+
+        input.next_byte();
+        if !input.read_line(&mut line) {
+            return Scan::Incomplete(input.consumed);
+        }
+        let streamed = line == b"?";
+        match tag {
+            b'+' | b'-' | b':' | b'(' | b'_' => {}
+            b'$' if streamed => loop {
+                if input.peek().is_some_and(|b| b != b';') {
+                    return Scan::Malformed(input.consumed + 1);
+                }
+                input.next_byte();
+                if !input.read_line(&mut line) {
+                    return Scan::Incomplete(input.consumed);
+                }
+                match parse_len(&line) {
+                    Some(0) => break,
+                    Some(len) if len > 0 => {
+                        if !input.skip(len as usize + 2) {
+                            return Scan::Incomplete(input.consumed);
+                        }
+                    }
+                    _ => return Scan::Malformed(input.consumed),
+                }
+            },
+            b'$' => match parse_len(&line) {
+                Some(-1) => {}
+                Some(len) if len >= 0 => {
+                    if !input.skip(len as usize + 2) {
+                        return Scan::Incomplete(input.consumed);
+                    }
+                }
+                _ => return Scan::Malformed(input.consumed),
+            },
+            b'*' | b'~' | b'>' | b'%' | b'|' if streamed => open.push(None),
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let elements = match (tag, parse_len(&line)) {
+                    (b'*', Some(-1)) => 0,
+                    (_, Some(len)) if len >= 0 => len,
+                    _ => return Scan::Malformed(input.consumed),
+                };
+                // Maps and attributes hold pairs, and attributes are followed
+                // by the value they annotate
+                let elements = match tag {
+                    b'%' => elements.saturating_mul(2),
+                    b'|' => elements.saturating_mul(2).saturating_add(1),
+                    _ => elements,
+                };
+                open.push(Some(elements));
+            }
+            _ => return Scan::Malformed(input.consumed),
+        }
+
+        if open.len() > MAX_NESTING_DEPTH + 1 {
+            return Scan::Malformed(input.consumed);
+        }
+    }
+
+    Scan::Complete(input.consumed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        parser::kind_only,
+        types::{BulkString, RespValue},
+    };
+
+    #[test]
+    fn test_parse_buf_split() {
+        let inputs: [&[u8]; 10] = [
+            b"+OK\r\n",
+            b"$5\r\na\r\nbc\r\n",
+            b"$-1\r\n",
+            b"*-1\r\n",
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$0\r\n\r\n",
+            b"*2\r\n*1\r\n:1\r\n%1\r\n+a\r\n~0\r\n",
+            b"|1\r\n+k\r\n:1\r\n>2\r\n+m\r\n(12\r\n",
+            b"$?\r\n;2\r\nab\r\n;1\r\n\r\r\n;0\r\n",
+            b"*?\r\n:1\r\n%?\r\n+a\r\n_\r\n.\r\n.\r\n",
+            b"*1\r\n$4\r\nfoo\r\r\n",
+        ];
+
+        for input in inputs {
+            let mut data = input.to_vec();
+            // A second value follows, which has to be left in the buffer
+            data.extend_from_slice(b":7\r\n");
+            let expect = kind_only(RespValue::parse(&mut RespParser::new(input)));
+            assert!(expect.is_ok(), "{:?}", input.escape_ascii().to_string());
+
+            for i in 0..=data.len() {
+                for j in i..=data.len() {
+                    let mut buf = (&data[..i]).chain(&data[i..j]).chain(&data[j..]);
+                    let val = kind_only(parse_buf::<RespValue, _>(&mut buf));
+                    assert_eq!(
+                        val,
+                        expect,
+                        "{:?} split at {} and {}",
+                        data.escape_ascii().to_string(),
+                        i,
+                        j
+                    );
+                    assert_eq!(buf.remaining(), 4);
+                    assert_eq!(kind_only(parse_buf(&mut buf)), Ok(RespValue::Integer(7)));
+                }
+            }
+
+            // A truncated value is incomplete wherever it is cut, and stays
+            // in the buffer
+            for len in 0..input.len() {
+                for i in 0..=len {
+                    let mut buf = (&input[..i]).chain(&input[i..len]);
+                    let val = parse_buf::<RespValue, _>(&mut buf);
+                    assert!(
+                        val.as_ref().is_err_and(|err| err.is_incomplete()),
+                        "{:?} cut at {}: {:?}",
+                        input.escape_ascii().to_string(),
+                        len,
+                        val
+                    );
+                    assert_eq!(buf.remaining(), len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_buf() {
+        let data = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+        let mut buf = (&data[..9]).chain(&data[9..]);
+        assert_eq!(
+            parse_buf::<Vec<BulkString>, _>(&mut buf),
+            Ok(vec![BulkString::from("GET"), BulkString::from("k")])
+        );
+        assert!(!buf.has_remaining());
+
+        let inputs: [(&[u8], &[u8]); 4] = [
+            (b"*1\r\n$1\r\nab", b"\r\n"),
+            (b"*1\r\n$", b"x\r\n"),
+            (b"*1\r\n", b"?1\r\n"),
+            (b"$?\r\n;1\r\na\r\n", b"$0\r\n"),
+        ];
+        let expects = [
+            Err(ParseError::new(ParseErrorKind::MissingCRLF).at(9)),
+            Err(ParseError::new(ParseErrorKind::InvalidIntegerData { data: b'x' }).at(5)),
+            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'?' }).at(4)),
+            Err(ParseError::new(ParseErrorKind::InvalidChunk { tag: b'$' }).at(11)),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (first, second) = inputs[i];
+            let mut buf = first.chain(second);
+            assert_eq!(parse_buf::<RespValue, _>(&mut buf), expects[i]);
+            // Nothing is consumed on errors
+            assert_eq!(buf.remaining(), first.len() + second.len());
+        }
+    }
+}
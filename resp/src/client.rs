@@ -0,0 +1,357 @@
+//! A small async client, enabled with the `client` feature, for tests and
+//! tools that talk to a server without pulling in a full client library.
+//!
+//! Commands are written with [`AsyncRespWriter`] and replies read with
+//! [`RespReader`]. Replies come back in the order their commands were sent,
+//! so a batch of commands is pipelined by writing all of them before reading
+//! any reply. RESP3 pushes can arrive between replies at any time, and are
+//! handed to the receiver returned by [`RespClient::pushes`] instead.
+//!
+//! None of the futures are cancel safe: dropping one half way leaves the
+//! replies to its commands unread, and the connection has to be discarded.
+
+use std::{error, fmt, io};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc,
+};
+
+use crate::{
+    reader::{ReadError, RespReader},
+    types::{BulkString, RespError, RespValue},
+    writer::{AsyncRespWriter, WriteError},
+};
+
+// ===========================================================
+// ClientError
+// ===========================================================
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server replied with an error
+    Reply(RespError),
+
+    /// The server closed the connection
+    Closed,
+
+    /// A reply that doesn't have the shape the command calls for, e.g. a
+    /// SUBSCRIBE that isn't confirmed
+    UnexpectedReply(RespValue),
+
+    Read(ReadError),
+    Write(WriteError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Reply(err) => write!(f, "{}", err),
+            ClientError::Closed => write!(f, "connection closed by the server"),
+            ClientError::UnexpectedReply(value) => write!(f, "unexpected reply {:?}", value),
+            ClientError::Read(err) => write!(f, "{}", err),
+            ClientError::Write(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ClientError::Read(err) => Some(err),
+            ClientError::Write(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReadError> for ClientError {
+    fn from(err: ReadError) -> ClientError {
+        match err {
+            ReadError::Eof => ClientError::Closed,
+            err => ClientError::Read(err),
+        }
+    }
+}
+
+impl From<WriteError> for ClientError {
+    fn from(err: WriteError) -> ClientError {
+        ClientError::Write(err)
+    }
+}
+
+// ===========================================================
+// RespClient
+// ===========================================================
+
+pub struct RespClient<S = TcpStream> {
+    reader: RespReader<ReadHalf<S>>,
+    writer: AsyncRespWriter<WriteHalf<S>>,
+
+    /// Where pushes read while waiting for a reply go, if anywhere
+    pushes: Option<mpsc::UnboundedSender<RespValue>>,
+}
+
+impl RespClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<RespClient> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(RespClient::new(stream))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> RespClient<S> {
+    /// Creates a client on a connected stream
+    pub fn new(stream: S) -> RespClient<S> {
+        let (reader, writer) = tokio::io::split(stream);
+        RespClient {
+            reader: RespReader::new(reader),
+            writer: AsyncRespWriter::new(writer),
+            pushes: None,
+        }
+    }
+
+    /// Returns a receiver for the pushes the server sends from now on. Until
+    /// this is called, or once the receiver is dropped, pushes are
+    /// discarded. Calling it again replaces the earlier receiver.
+    pub fn pushes(&mut self) -> mpsc::UnboundedReceiver<RespValue> {
+        let (send, recv) = mpsc::unbounded_channel();
+        self.pushes = Some(send);
+        recv
+    }
+
+    /// Sends a command and waits for its reply. An error reply is returned
+    /// as `ClientError::Reply`.
+    pub async fn command(&mut self, args: &[&[u8]]) -> Result<RespValue, ClientError> {
+        self.writer.write_value(&args).await?;
+        self.writer.flush().await?;
+
+        match self.read_reply().await? {
+            RespValue::Error(err) => Err(ClientError::Reply(err)),
+            value => Ok(value),
+        }
+    }
+
+    /// Sends all of `commands` at once and then reads their replies, in the
+    /// same order. Error replies are returned among the others, as
+    /// `RespValue::Error`, so that one failed command doesn't hide the
+    /// replies to the rest.
+    pub async fn pipeline(&mut self, commands: &[&[&[u8]]]) -> Result<Vec<RespValue>, ClientError> {
+        for args in commands {
+            self.writer.write_value(args).await?;
+        }
+        self.writer.flush().await?;
+
+        let mut replies = Vec::with_capacity(commands.len());
+        for _ in 0..commands.len() {
+            replies.push(self.read_reply().await?);
+        }
+        Ok(replies)
+    }
+
+    /// Subscribes to `channels` and turns the client into a [`Subscription`]
+    /// that receives the messages published to them
+    pub async fn subscribe(mut self, channels: &[&[u8]]) -> Result<Subscription<S>, ClientError> {
+        let mut args = Vec::with_capacity(channels.len() + 1);
+        args.push(&b"SUBSCRIBE"[..]);
+        args.extend_from_slice(channels);
+        self.writer.write_value(&args).await?;
+        self.writer.flush().await?;
+
+        // One confirmation per channel, a push in RESP3
+        for _ in channels {
+            let value = self.reader.read_value().await?;
+            match pubsub_frame(&value) {
+                Some((b"subscribe", _)) => {}
+                _ => return Err(unexpected_reply(value)),
+            }
+        }
+
+        Ok(Subscription { client: self })
+    }
+
+    /// Reads the next value that isn't a push
+    async fn read_reply(&mut self) -> Result<RespValue, ClientError> {
+        loop {
+            match self.reader.read_value().await? {
+                RespValue::Push(values) => self.push(RespValue::Push(values)),
+                value => return Ok(value),
+            }
+        }
+    }
+
+    fn push(&mut self, value: RespValue) {
+        if let Some(pushes) = &self.pushes {
+            if pushes.send(value).is_err() {
+                self.pushes = None;
+            }
+        }
+    }
+}
+
+// ===========================================================
+// Subscription
+// ===========================================================
+
+/// A message published to a channel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub channel: BulkString,
+    pub payload: BulkString,
+}
+
+/// A client subscribed to channels, created with [`RespClient::subscribe`]
+pub struct Subscription<S = TcpStream> {
+    client: RespClient<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite> Subscription<S> {
+    /// Waits for the next message. Other frames, such as the confirmations
+    /// of later subscriptions, go to the push receiver of the client.
+    pub async fn next_message(&mut self) -> Result<Message, ClientError> {
+        loop {
+            let value = self.client.reader.read_value().await?;
+            if let Some((b"message", [channel, payload])) = pubsub_frame(&value) {
+                if let (RespValue::Bulk(channel), RespValue::Bulk(payload)) = (channel, payload) {
+                    return Ok(Message {
+                        channel: channel.clone(),
+                        payload: payload.clone(),
+                    });
+                }
+                return Err(unexpected_reply(value));
+            }
+            self.client.push(value);
+        }
+    }
+
+    pub fn into_client(self) -> RespClient<S> {
+        self.client
+    }
+}
+
+/// Splits a pub/sub frame, a push in RESP3 and an array in RESP2, into its
+/// kind and the rest of its elements
+fn pubsub_frame(value: &RespValue) -> Option<(&[u8], &[RespValue])> {
+    let (RespValue::Push(values) | RespValue::Array(values)) = value else {
+        return None;
+    };
+    match values.split_first() {
+        Some((RespValue::Bulk(kind), rest)) => Some((kind.as_bytes(), rest)),
+        _ => None,
+    }
+}
+
+fn unexpected_reply(value: RespValue) -> ClientError {
+    match value {
+        RespValue::Error(err) => ClientError::Reply(err),
+        value => ClientError::UnexpectedReply(value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_test::io::Builder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_command() {
+        let inputs: [&[&[u8]]; 3] = [&[b"PING"], &[b"GET", b"key"], &[b"FOO"]];
+        let requests: [&[u8]; 3] = [
+            b"*1\r\n$4\r\nPING\r\n",
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n",
+            b"*1\r\n$3\r\nFOO\r\n",
+        ];
+        let replies: [&[u8]; 3] = [
+            b"+PONG\r\n",
+            b"$5\r\nvalue\r\n",
+            b"-ERR unknown command 'FOO'\r\n",
+        ];
+        let expects = [
+            Ok(RespValue::Simple("PONG".to_string())),
+            Ok(RespValue::from("value")),
+            Err(RespError::err("unknown command 'FOO'")),
+        ];
+
+        assert_eq!(inputs.len(), requests.len());
+        assert_eq!(inputs.len(), replies.len());
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let stream = Builder::new().write(requests[i]).read(replies[i]).build();
+            let mut client = RespClient::new(stream);
+
+            let res = client.command(inputs[i]).await.map_err(|err| match err {
+                ClientError::Reply(err) => err,
+                err => panic!("{}", err),
+            });
+            assert_eq!(res, expects[i]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_with_pushes() {
+        // The commands go out in one write, and a push arrives between the
+        // replies
+        let stream = Builder::new()
+            .write(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n")
+            .read(b"+OK\r\n")
+            .read(b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n")
+            .read(b"$1\r\nv\r\n")
+            .build();
+        let mut client = RespClient::new(stream);
+        let mut pushes = client.pushes();
+
+        let replies = client
+            .pipeline(&[&[b"SET", b"k", b"v"], &[b"GET", b"k"]])
+            .await
+            .unwrap();
+        assert_eq!(
+            replies,
+            [RespValue::Simple("OK".to_string()), RespValue::from("v")]
+        );
+        assert_eq!(
+            pushes.try_recv().unwrap(),
+            RespValue::Push(vec![
+                RespValue::from("invalidate"),
+                RespValue::Array(vec![RespValue::from("k")]),
+            ])
+        );
+        assert!(pushes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        // RESP2 sends arrays, RESP3 pushes
+        let inputs: [&[u8]; 2] = [
+            b"*3\r\n$9\r\nsubscribe\r\n$2\r\nch\r\n:1\r\n\
+              *3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$5\r\nhello\r\n",
+            b">3\r\n$9\r\nsubscribe\r\n$2\r\nch\r\n:1\r\n\
+              >3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$5\r\nhello\r\n",
+        ];
+        let expect = Message {
+            channel: BulkString::from("ch"),
+            payload: BulkString::from("hello"),
+        };
+
+        for input in inputs {
+            let stream = Builder::new()
+                .write(b"*2\r\n$9\r\nSUBSCRIBE\r\n$2\r\nch\r\n")
+                .read(input)
+                .build();
+            let client = RespClient::new(stream);
+            let mut sub = client.subscribe(&[b"ch"]).await.unwrap();
+            assert_eq!(sub.next_message().await.unwrap(), expect);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closed() {
+        let stream = Builder::new().write(b"*1\r\n$4\r\nPING\r\n").build();
+        let mut client = RespClient::new(stream);
+        assert!(matches!(
+            client.command(&[b"PING"]).await,
+            Err(ClientError::Closed)
+        ));
+    }
+}
@@ -0,0 +1,742 @@
+//! `tokio-util` codecs, so that a stream can be wrapped in `Framed` and
+//! read and written as values. `RespCodec` reports protocol errors as
+//! `io::Error`s of kind `InvalidData`, with the `ParseError` as their inner
+//! error. `RespCommandCodec` reports them as `FrameError`s, which carry the
+//! messages Redis replies with.
+
+use std::{error, fmt, io, iter, mem, str};
+
+use bytes::{Buf, BytesMut};
+pub use tokio_util::codec::{Decoder, Encoder, Framed, FramedRead, FramedWrite};
+
+use crate::{
+    command::split_inline,
+    parser::{LineScan, ParseErrorKind, RespParser, find_crlf, parse_complete},
+    types::{BulkString, CommandFormatError, RespReadable, RespValue, RespWritable},
+    writer::{ProtocolVersion, RespWriter, WriteBuf, WriteError},
+};
+
+/// Default for the largest frame a codec buffers
+pub const DEFAULT_MAX_FRAME_LEN: usize = 512 * 1024 * 1024;
+
+// ===========================================================
+// RespCodec
+// ===========================================================
+
+/// Decodes any value and encodes values for the configured protocol.
+/// Between calls, data may only be appended to the buffer being decoded, as
+/// `Framed` does, since where the search for the end of an unfinished line
+/// stopped is remembered.
+#[derive(Debug)]
+pub struct RespCodec {
+    protocol: ProtocolVersion,
+    max_frame_len: usize,
+    line_scan: Option<LineScan>,
+}
+
+impl RespCodec {
+    pub fn new() -> RespCodec {
+        RespCodec::with_protocol(ProtocolVersion::Resp2)
+    }
+
+    pub fn with_protocol(protocol: ProtocolVersion) -> RespCodec {
+        RespCodec {
+            protocol,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            line_scan: None,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+
+    /// Sets the size of the largest frame that is decoded. Larger frames are
+    /// refused as soon as their length is known, before they are buffered.
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+impl Default for RespCodec {
+    fn default() -> RespCodec {
+        RespCodec::new()
+    }
+}
+
+impl Decoder for RespCodec {
+    type Item = RespValue;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<RespValue>> {
+        decode_frame(src, self.max_frame_len, &mut self.line_scan)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<RespValue>> {
+        let value = self.decode(src)?;
+        check_eof(value, src)
+    }
+}
+
+impl<T: RespWritable> Encoder<&T> for RespCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, value: &T, dst: &mut BytesMut) -> io::Result<()> {
+        encode_frame(value, dst, self.protocol)
+    }
+}
+
+// ===========================================================
+// RespCommandCodec
+// ===========================================================
+
+/// Limits on the commands `RespCommandCodec` accepts. They are checked as
+/// soon as the headers arrive, so an oversized payload is never buffered.
+/// The defaults are those of Redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandLimits {
+    /// Longest bulk string argument
+    pub max_bulk_len: usize,
+
+    /// Most arguments in a multibulk command
+    pub max_multibulk_len: usize,
+
+    /// Longest inline command, and longest header line of a multibulk one
+    pub max_inline_len: usize,
+
+    /// Most bytes buffered for a command that isn't complete yet
+    pub max_buffer_len: usize,
+}
+
+impl Default for CommandLimits {
+    fn default() -> CommandLimits {
+        CommandLimits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_inline_len: 64 * 1024,
+            max_buffer_len: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Error produced while splitting the input into commands. After any of
+/// these the stream can no longer be framed reliably, so the connection has
+/// to be closed.
+#[derive(Debug)]
+pub enum FrameError {
+    /// Malformed or oversized command, reported by Redis as
+    /// `-ERR Protocol error: <msg>` before it closes the connection
+    Protocol(String),
+
+    /// An incomplete command grew beyond `CommandLimits::max_buffer_len`
+    QueryBufferLimit {
+        len: usize,
+    },
+
+    Io(io::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            FrameError::QueryBufferLimit { len } => {
+                write!(f, "query buffer of {} bytes exceeds the limit", len)
+            }
+            FrameError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FrameError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> FrameError {
+        FrameError::Io(err)
+    }
+}
+
+impl From<FrameError> for io::Error {
+    fn from(err: FrameError) -> io::Error {
+        match err {
+            FrameError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Codec for the server side, decoding the commands sent by clients and
+/// encoding replies. Commands are arrays of bulk strings, or inline
+/// commands, a line of words the way `redis-cli` splits them, ended by a
+/// LF with or without a CR before it.
+#[derive(Debug, Default)]
+pub struct RespCommandCodec {
+    inner: RespCodec,
+    limits: CommandLimits,
+}
+
+impl RespCommandCodec {
+    pub fn new() -> RespCommandCodec {
+        RespCommandCodec::default()
+    }
+
+    pub fn with_limits(limits: CommandLimits) -> RespCommandCodec {
+        RespCommandCodec {
+            inner: RespCodec::new(),
+            limits,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.inner.protocol()
+    }
+
+    /// Sets the protocol replies are encoded for, e.g. after `HELLO 3`
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.inner.set_protocol(protocol);
+    }
+
+    pub fn limits(&self) -> &CommandLimits {
+        &self.limits
+    }
+
+    /// Splits the next complete command off `src` as it was sent, without
+    /// parsing its arguments, so that they can be borrowed from the frame
+    /// rather than copied. Blank inline lines are frames too.
+    pub fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, FrameError> {
+        match self.frame_len(src)? {
+            Some(len) => Ok(Some(src.split_to(len))),
+            None if src.len() > self.limits.max_buffer_len => {
+                Err(FrameError::QueryBufferLimit { len: src.len() })
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Finds the end of the line starting at `start`, returning the line
+    /// without its CRLF and the position right after it, or `None` if the
+    /// line is not complete yet.
+    fn line<'a>(
+        &self,
+        buf: &'a [u8],
+        start: usize,
+        too_big: &'static str,
+    ) -> Result<Option<(&'a [u8], usize)>, FrameError> {
+        match find_crlf(&buf[start..]) {
+            Some(end) => Ok(Some((&buf[start..start + end], start + end + 2))),
+            None if buf.len() - start > self.limits.max_inline_len => {
+                Err(FrameError::Protocol(too_big.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the length of the first complete command in `buf`, or `None`
+    /// if more data is needed.
+    fn frame_len(&self, buf: &[u8]) -> Result<Option<usize>, FrameError> {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        // Anything that is not a multibulk command is a single line. Like
+        // Redis, a lone LF ends it as well, for people typing commands into
+        // `nc` or `telnet`.
+        if buf[0] != b'*' {
+            return match memchr::memchr(b'\n', buf) {
+                Some(end) => Ok(Some(end + 1)),
+                None if buf.len() > self.limits.max_inline_len => {
+                    Err(FrameError::Protocol("too big inline request".to_string()))
+                }
+                None => Ok(None),
+            };
+        }
+
+        let Some((header, mut pos)) = self.line(buf, 1, "too big mbulk count string")? else {
+            return Ok(None);
+        };
+        let count = parse_len(header)
+            .filter(|count| *count <= self.limits.max_multibulk_len as i64)
+            .ok_or_else(|| FrameError::Protocol("invalid multibulk length".to_string()))?;
+
+        // Commands are flat arrays of bulk strings, so the nesting depth is
+        // bounded as well. Empty and null arrays carry no arguments.
+        for _ in 0..count.max(0) {
+            match buf.get(pos) {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(tag) => {
+                    return Err(FrameError::Protocol(format!(
+                        "expected '$', got '{}'",
+                        tag.escape_ascii()
+                    )));
+                }
+            }
+
+            let Some((header, end)) = self.line(buf, pos + 1, "too big bulk count string")? else {
+                return Ok(None);
+            };
+            let len = parse_len(header)
+                .filter(|len| (0..=self.limits.max_bulk_len as i64).contains(len))
+                .ok_or_else(|| FrameError::Protocol("invalid bulk length".to_string()))?;
+
+            pos = end + len as usize + 2;
+            if pos > buf.len() {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(pos))
+    }
+}
+
+fn parse_len(header: &[u8]) -> Option<i64> {
+    str::from_utf8(header).ok()?.parse().ok()
+}
+
+/// Parses a frame split off by `RespCommandCodec::decode_frame` into its
+/// arguments. Blank lines and empty or null arrays are `None`, as Redis
+/// ignores them.
+fn parse_frame(frame: &[u8]) -> Result<Option<Vec<BulkString>>, FrameError> {
+    let command = if frame.first() == Some(&b'*') {
+        let value = parse_complete::<RespValue>(frame)
+            .map_err(|err| FrameError::Protocol(err.kind().to_string()))?;
+        match value.into_command() {
+            Ok((name, args)) => iter::once(name).chain(args).collect(),
+            Err(CommandFormatError::Empty) => return Ok(None),
+            Err(err) => return Err(FrameError::Protocol(err.to_string())),
+        }
+    } else {
+        let line = frame.strip_suffix(b"\n").unwrap_or(frame);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        split_inline(line).map_err(|err| FrameError::Protocol(err.to_string()))?
+    };
+    Ok((!command.is_empty()).then_some(command))
+}
+
+impl Decoder for RespCommandCodec {
+    type Item = Vec<BulkString>;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Vec<BulkString>>, FrameError> {
+        while let Some(frame) = self.decode_frame(src)? {
+            if let Some(command) = parse_frame(&frame)? {
+                return Ok(Some(command));
+            }
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Vec<BulkString>>, FrameError> {
+        let command = self.decode(src)?;
+        Ok(check_eof(command, src)?)
+    }
+}
+
+impl<T: RespWritable> Encoder<&T> for RespCommandCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, value: &T, dst: &mut BytesMut) -> io::Result<()> {
+        self.inner.encode(value, dst)
+    }
+}
+
+// ===========================================================
+// Framing
+// ===========================================================
+
+/// Parses the next value out of `src`, leaving `src` as is until the value
+/// is complete. An incomplete line is searched for its end from where the
+/// last attempt left `line_scan`.
+fn decode_frame<T>(
+    src: &mut BytesMut,
+    max_frame_len: usize,
+    line_scan: &mut Option<LineScan>,
+) -> io::Result<Option<T>>
+where
+    T: for<'a> RespReadable<'a>,
+{
+    if src.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parser = RespParser::new(src);
+    parser.set_max_streamed_len(max_frame_len);
+    if let Some(scan) = line_scan.take() {
+        parser.resume_line_scan(scan);
+    }
+    let (value, len) = match T::parse(&mut parser) {
+        Ok(value) => (value, parser.consumed()),
+        Err(err) => match err.kind() {
+            ParseErrorKind::Incomplete { needed } => {
+                *line_scan = parser.line_scan();
+                let needed = needed.unwrap_or(0);
+                let len = src.len().saturating_add(needed);
+                if len > max_frame_len {
+                    return Err(frame_too_long(len, max_frame_len));
+                }
+                // A long payload is read into the buffer at once
+                src.reserve(needed);
+                return Ok(None);
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        },
+    };
+
+    if len > max_frame_len {
+        return Err(frame_too_long(len, max_frame_len));
+    }
+    src.advance(len);
+    Ok(Some(value))
+}
+
+fn frame_too_long(len: usize, max_frame_len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "frame of at least {} bytes exceeds the limit of {} bytes",
+            len, max_frame_len
+        ),
+    )
+}
+
+/// Fails if the stream ended with part of a value buffered
+fn check_eof<T>(value: Option<T>, src: &BytesMut) -> io::Result<Option<T>> {
+    if value.is_none() && !src.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("end of stream inside a value, after {} bytes", src.len()),
+        ));
+    }
+    Ok(value)
+}
+
+/// Writes `value` straight into `dst`, leaving nothing of it there if
+/// writing fails half way
+fn encode_frame<T: RespWritable>(
+    value: &T,
+    dst: &mut BytesMut,
+    protocol: ProtocolVersion,
+) -> io::Result<()> {
+    let mut buf = WriteBuf::new(mem::take(dst));
+    let start = buf.len();
+    let res = RespWriter::with_protocol(&mut buf, protocol).write_value(value);
+    if res.is_err() {
+        buf.truncate(start);
+    }
+    *dst = buf.into_inner();
+
+    res.map_err(|err| match err {
+        WriteError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::OutOfMemory, err),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_byte_at_a_time() {
+        let inputs: [&[u8]; 5] = [
+            b"+OK\r\n",
+            b"$5\r\na\r\nbc\r\n",
+            b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n",
+            b"%1\r\n+a\r\n*?\r\n:1\r\n.\r\n",
+            b"$?\r\n;2\r\nab\r\n;0\r\n",
+        ];
+        let expects = [
+            RespValue::Simple("OK".to_string()),
+            RespValue::from("a\r\nbc"),
+            RespValue::Array(vec![RespValue::from("GET"), RespValue::from("k")]),
+            RespValue::Map(vec![(
+                RespValue::Simple("a".to_string()),
+                RespValue::Array(vec![RespValue::Integer(1)]),
+            )]),
+            RespValue::from("ab"),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut codec = RespCodec::new();
+            let mut src = BytesMut::new();
+            for (j, b) in inputs[i].iter().enumerate() {
+                src.extend_from_slice(&[*b]);
+                let value = codec.decode(&mut src).unwrap();
+                if j + 1 < inputs[i].len() {
+                    assert_eq!(value, None, "{:?} after {} bytes", inputs[i], j + 1);
+                    assert_eq!(src.len(), j + 1);
+                } else {
+                    assert_eq!(value.as_ref(), Some(&expects[i]));
+                }
+            }
+            assert!(src.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decode_long_line() {
+        // The search resumes after every piece, some of which end in a CR
+        let line = [&b"+"[..], &b"a\r".repeat(4096), b"\r\n"].concat();
+        let mut codec = RespCodec::new();
+        let mut src = BytesMut::new();
+        for chunk in line.chunks(7) {
+            assert_eq!(codec.decode(&mut src).unwrap(), None);
+            src.extend_from_slice(chunk);
+        }
+        let value = codec.decode(&mut src).unwrap();
+        assert_eq!(value, Some(RespValue::Simple("a\r".repeat(4096))));
+        assert!(src.is_empty());
+
+        // The next frame is scanned from its own start
+        src.extend_from_slice(b"+b\r");
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        src.extend_from_slice(b"\n+c\r\n");
+        let values = [
+            codec.decode(&mut src).unwrap(),
+            codec.decode(&mut src).unwrap(),
+        ];
+        assert_eq!(
+            values,
+            [
+                Some(RespValue::Simple("b".to_string())),
+                Some(RespValue::Simple("c".to_string()))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_pipelined() {
+        let mut codec = RespCommandCodec::new();
+        let mut src =
+            BytesMut::from(&b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*1\r\n$4"[..]);
+
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(vec![BulkString::from("PING")])
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(vec![BulkString::from("GET"), BulkString::from("k")])
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert_eq!(src, &b"*1\r\n$4"[..]);
+
+        let err = codec.decode_eof(&mut src).unwrap_err();
+        assert!(
+            matches!(&err, FrameError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof),
+            "{:?}",
+            err
+        );
+
+        // Inline commands, with blank lines and empty arrays skipped
+        let mut src = BytesMut::from(&b"PING\r\n\r\n*0\r\n*-1\r\nGET 'a key'\n"[..]);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(vec![BulkString::from("PING")])
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(vec![BulkString::from("GET"), BulkString::from("a key")])
+        );
+        assert!(src.is_empty());
+
+        // Only arrays of bulk strings are commands
+        let mut src = BytesMut::from(&b"*1\r\n:1\r\n"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert!(
+            matches!(&err, FrameError::Protocol(msg) if msg == "expected '$', got ':'"),
+            "{:?}",
+            err
+        );
+        let mut src = BytesMut::from(&b"GET \"key\r\n"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Protocol error: unbalanced quotes in request"
+        );
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let mut codec = RespCodec::new();
+        codec.set_max_frame_len(16);
+
+        // Refused from the header, before the payload arrives
+        let mut src = BytesMut::from(&b"$100\r\n"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut src = BytesMut::from(&b"*4\r\n:1\r\n:2\r\n:3\r\n:4\r\n"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut src = BytesMut::from(&b"*2\r\n:1\r\n:2\r\n"[..]);
+        assert!(codec.decode(&mut src).unwrap().is_some());
+
+        let mut src = BytesMut::from(&b"?\r\n"[..]);
+        let err = codec.decode(&mut src).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.get_ref().is_some());
+    }
+
+    #[test]
+    fn test_encode() {
+        let value = RespValue::Array(vec![RespValue::None, RespValue::Integer(1)]);
+        let protocols = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+        let expects: [&[u8]; 2] = [b"+a\r\n*2\r\n$-1\r\n:1\r\n", b"+a\r\n*2\r\n_\r\n:1\r\n"];
+
+        for (protocol, expected) in protocols.into_iter().zip(expects) {
+            let mut codec = RespCodec::with_protocol(protocol);
+            let mut dst = BytesMut::from(&b"+a\r\n"[..]);
+            codec.encode(&value, &mut dst).unwrap();
+            assert_eq!(dst, expected);
+
+            // What was encoded decodes back
+            dst.advance(4);
+            assert_eq!(codec.decode(&mut dst).unwrap(), Some(value.clone()));
+        }
+    }
+
+    fn limited() -> RespCommandCodec {
+        RespCommandCodec::with_limits(CommandLimits {
+            max_bulk_len: 16,
+            max_multibulk_len: 4,
+            max_inline_len: 32,
+            max_buffer_len: 64,
+        })
+    }
+
+    #[test]
+    fn test_frame_len() {
+        let inputs = [
+            b"".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n*1\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$3\r\nke".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$3".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n".to_vec(),
+            b"*2\r".to_vec(),
+            b"*0\r\n".to_vec(),
+            b"*1\r\n$16\r\nAAAAAAAAAAAAAAAA\r\n".to_vec(),
+            b"*1\r\n$17\r\n".to_vec(),
+            b"*1\r\n$9999999999\r\n".to_vec(),
+            b"*1\r\n$-5\r\n\r\n".to_vec(),
+            b"*1\r\n$abc\r\n".to_vec(),
+            b"*x\r\n".to_vec(),
+            b"*1\r\n+GET\r\n".to_vec(),
+            b"PING\r\n".to_vec(),
+            b"PING".to_vec(),
+            [b"*1\r\n$", &[b'1'; 33][..]].concat(),
+            [b"*", &[b'1'; 33][..]].concat(),
+            [&[b'A'; 33][..]].concat(),
+            b"*4\r\n".to_vec(),
+            b"*5\r\n".to_vec(),
+            b"*9223372036854775807\r\n".to_vec(),
+            b"*9223372036854775808\r\n".to_vec(),
+            b"*-9223372036854775808\r\n".to_vec(),
+            b"*1\r\n$9223372036854775807\r\n".to_vec(),
+            b"*1\r\n*1\r\n$3\r\nGET\r\n".to_vec(),
+            b"GET foo\n".to_vec(),
+            b"PING\nPING\r\n".to_vec(),
+            b"*1\n$4\r\nPING\r\n".to_vec(),
+            b"*1\r\n$4\r\nPI\nG\r\n".to_vec(),
+        ];
+        let expects: &[Result<Option<usize>, &str>] = &[
+            Ok(None),
+            Ok(Some(22)),
+            Ok(Some(22)),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(None),
+            Ok(Some(4)),
+            Ok(Some(27)),
+            Err("invalid bulk length"),
+            Err("invalid bulk length"),
+            Err("invalid bulk length"),
+            Err("invalid bulk length"),
+            Err("invalid multibulk length"),
+            Err("expected '$', got '+'"),
+            Ok(Some(6)),
+            Ok(None),
+            Err("too big bulk count string"),
+            Err("too big mbulk count string"),
+            Err("too big inline request"),
+            Ok(None),
+            Err("invalid multibulk length"),
+            Err("invalid multibulk length"),
+            Err("invalid multibulk length"),
+            Ok(Some(23)),
+            Err("invalid bulk length"),
+            Err("expected '$', got '*'"),
+            Ok(Some(8)),
+            Ok(Some(5)),
+            Err("invalid multibulk length"),
+            Ok(Some(14)),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        let codec = limited();
+        for i in 0..inputs.len() {
+            let res = codec.frame_len(&inputs[i]).map_err(|err| match err {
+                FrameError::Protocol(msg) => msg,
+                err => panic!("unexpected error {:?}", err),
+            });
+            assert_eq!(
+                res.as_ref().copied().map_err(|msg| msg.as_str()),
+                expects[i]
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        let mut codec = limited();
+
+        let mut buf = BytesMut::from(&b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPI"[..]);
+        assert_eq!(
+            codec.decode_frame(&mut buf).unwrap().unwrap(),
+            &b"*1\r\n$4\r\nPING\r\n"[..]
+        );
+        assert!(codec.decode_frame(&mut buf).unwrap().is_none());
+        assert_eq!(buf, &b"*1\r\n$4\r\nPI"[..]);
+
+        buf.extend_from_slice(b"NG\r\n");
+        assert_eq!(
+            codec.decode_frame(&mut buf).unwrap().unwrap(),
+            &b"*1\r\n$4\r\nPING\r\n"[..]
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_query_buffer_limit() {
+        let mut codec = limited();
+
+        // Each bulk string is within limits, but the request as a whole
+        // can't be buffered
+        let mut buf = BytesMut::from(&b"*4\r\n"[..]);
+        for _ in 0..3 {
+            buf.extend_from_slice(b"$16\r\nAAAAAAAAAAAAAAAA\r\n");
+        }
+        assert!(matches!(
+            codec.decode_frame(&mut buf),
+            Err(FrameError::QueryBufferLimit { len: 73 })
+        ));
+    }
+}
@@ -0,0 +1,729 @@
+//! Commands as a server sees them: a name and arguments, either sent as a
+//! multibulk array or typed as an inline command, plus helpers to read the
+//! arguments. This is where what arrived on the wire becomes an invocation,
+//! before any command specific checks. [`RespCommand`] goes the other way
+//! and builds a request for a client to send.
+
+use std::{error, fmt, ops::Deref, str};
+
+use crate::{
+    parser::{ParseError, ParseErrorKind, ParseResult, RespParser, read_i64},
+    types::{BulkString, CommandFormatError, RespReadable, RespWritable},
+    writer::{OutBuf, ProtocolVersion, RespWriter, WriteResult},
+};
+
+// ===========================================================
+// Reading requests
+// ===========================================================
+
+/// Reads the words of a request, a multibulk array or otherwise a line
+/// holding an inline command
+pub fn read_request(parser: &mut RespParser<'_>) -> ParseResult<Vec<BulkString>> {
+    if parser.peek_first() == Some(&b'*') {
+        return Vec::<BulkString>::parse(parser);
+    }
+
+    let start = parser.consumed();
+    let line = parser.read_inline_line()?;
+    split_inline(line).map_err(|_| ParseError::new(ParseErrorKind::InvalidCmd).at(start))
+}
+
+/// An inline command with a quote that isn't closed, or closed in the middle
+/// of a word
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnbalancedQuotes;
+
+impl fmt::Display for UnbalancedQuotes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unbalanced quotes in request")
+    }
+}
+
+impl error::Error for UnbalancedQuotes {}
+
+/// Splits an inline command into words the way redis-cli and Redis do.
+/// Words are separated by whitespace and may be quoted. Double quotes take
+/// the escapes `\n`, `\r`, `\t`, `\b`, `\a` and `\xHH`, single quotes only
+/// `\'`.
+pub fn split_inline(line: &[u8]) -> Result<Vec<BulkString>, UnbalancedQuotes> {
+    let mut words = Vec::new();
+    let mut pos = 0;
+    loop {
+        while line.get(pos).is_some_and(|c| is_space(*c)) {
+            pos += 1;
+        }
+        if pos == line.len() {
+            return Ok(words);
+        }
+
+        let mut word = Vec::new();
+        let mut quote = None;
+        loop {
+            let c = line.get(pos).copied();
+            match (quote, c) {
+                (Some(_), None) => return Err(UnbalancedQuotes),
+                (None, None) => break,
+                (None, Some(c)) if is_space(c) => break,
+                (None, Some(b'"' | b'\'')) => quote = c,
+                (None, Some(c)) => word.push(c),
+                (Some(q), Some(c)) if c == q => {
+                    // The closing quote has to end the word
+                    if line.get(pos + 1).is_some_and(|c| !is_space(*c)) {
+                        return Err(UnbalancedQuotes);
+                    }
+                    pos += 1;
+                    break;
+                }
+                (Some(b'"'), Some(b'\\')) if pos + 1 < line.len() => {
+                    let (c, len) = unescape(&line[pos + 1..]);
+                    word.push(c);
+                    pos += len;
+                }
+                (Some(b'\''), Some(b'\\')) if line.get(pos + 1) == Some(&b'\'') => {
+                    word.push(b'\'');
+                    pos += 1;
+                }
+                (Some(_), Some(c)) => word.push(c),
+            }
+            pos += 1;
+        }
+        words.push(BulkString::new(word));
+    }
+}
+
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)
+}
+
+/// Reads the escape after a backslash, returning the byte it stands for and
+/// the number of bytes it took up
+fn unescape(escape: &[u8]) -> (u8, usize) {
+    let hex = |c: u8| (c as char).to_digit(16);
+    if let [b'x', hi, lo, ..] = escape {
+        if let (Some(hi), Some(lo)) = (hex(*hi), hex(*lo)) {
+            return ((hi * 16 + lo) as u8, 3);
+        }
+    }
+
+    let c = match escape[0] {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'b' => 0x08,
+        b'a' => 0x07,
+        c => c,
+    };
+    (c, 1)
+}
+
+// ===========================================================
+// RespCommand
+// ===========================================================
+
+/// A request to send, written as an array of bulk strings like clients
+/// send commands
+///
+/// ```
+/// use resp::command::RespCommand;
+///
+/// let cmd = RespCommand::new("SET").arg("key").arg(b"\x00value").arg(10);
+/// assert_eq!(cmd.args().len(), 4);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RespCommand {
+    args: Vec<BulkString>,
+}
+
+impl RespCommand {
+    pub fn new(name: impl Into<BulkString>) -> RespCommand {
+        RespCommand {
+            args: vec![name.into()],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<BulkString>) -> RespCommand {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args(&self) -> &[BulkString] {
+        &self.args
+    }
+
+    pub fn into_args(self) -> Vec<BulkString> {
+        self.args
+    }
+}
+
+impl RespWritable for RespCommand {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        self.args.write(writer)
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        self.args.encoded_len(protocol)
+    }
+}
+
+// ===========================================================
+// CommandName
+// ===========================================================
+
+/// Longest command name that is normalized without allocating
+pub const MAX_NAME_LEN: usize = 32;
+
+/// The name of a command in uppercase, so that names can be compared
+/// ignoring case. Names up to `MAX_NAME_LEN` bytes are kept on the stack.
+#[derive(Clone, Debug)]
+pub struct CommandName(Name);
+
+#[derive(Clone, Debug)]
+enum Name {
+    Inline { buf: [u8; MAX_NAME_LEN], len: usize },
+    Long(Vec<u8>),
+}
+
+impl CommandName {
+    pub fn new(name: &[u8]) -> CommandName {
+        let mut buf = [0; MAX_NAME_LEN];
+        match buf.get_mut(..name.len()) {
+            Some(upper) => {
+                upper.copy_from_slice(name);
+                upper.make_ascii_uppercase();
+                CommandName(Name::Inline {
+                    buf,
+                    len: name.len(),
+                })
+            }
+            None => CommandName(Name::Long(name.to_ascii_uppercase())),
+        }
+    }
+}
+
+impl Deref for CommandName {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Name::Inline { buf, len } => &buf[..*len],
+            Name::Long(name) => name,
+        }
+    }
+}
+
+impl PartialEq for CommandName {
+    fn eq(&self, other: &CommandName) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for CommandName {}
+
+// ===========================================================
+// CommandLine
+// ===========================================================
+
+/// A command name and its arguments, borrowed from the words of a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommandLine<'a> {
+    pub name: &'a [u8],
+    pub args: &'a [BulkString],
+}
+
+impl<'a> CommandLine<'a> {
+    /// Splits the words of a request into the name and the arguments
+    pub fn new(words: &'a [BulkString]) -> Result<CommandLine<'a>, CommandFormatError> {
+        match words.split_first() {
+            Some((name, args)) => Ok(CommandLine {
+                name: name.as_bytes(),
+                args,
+            }),
+            None => Err(CommandFormatError::Empty),
+        }
+    }
+
+    /// The name in uppercase
+    pub fn normalized_name(&self) -> CommandName {
+        CommandName::new(self.name)
+    }
+
+    /// Argument `n`, counting from 0 after the name
+    pub fn arg(&self, n: usize) -> Result<&'a [u8], ArgError> {
+        match self.args.get(n) {
+            Some(arg) => Ok(arg.as_bytes()),
+            None => Err(ArgError::Missing { index: n }),
+        }
+    }
+
+    pub fn arg_str(&self, n: usize) -> Result<&'a str, ArgError> {
+        str::from_utf8(self.arg(n)?).map_err(|_| ArgError::NotUtf8 { index: n })
+    }
+
+    pub fn arg_i64(&self, n: usize) -> Result<i64, ArgError> {
+        read_i64(self.arg(n)?).map_err(|_| ArgError::NotAnInteger { index: n })
+    }
+
+    /// Scans the arguments from `start` on for keyword options
+    pub fn options(
+        &self,
+        start: usize,
+        keywords: &'static [Keyword],
+    ) -> Result<Options<'a>, ArgError> {
+        let args = self.args.get(start..).unwrap_or_default();
+        scan_options(args, keywords).map_err(|err| err.offset(start))
+    }
+}
+
+// ===========================================================
+// Options
+// ===========================================================
+
+/// A keyword option such as `NX`, or `EX seconds` which takes a value.
+/// Keywords are matched ignoring case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keyword {
+    name: &'static str,
+    takes_value: bool,
+    group: Option<u8>,
+}
+
+impl Keyword {
+    pub const fn flag(name: &'static str) -> Keyword {
+        Keyword {
+            name,
+            takes_value: false,
+            group: None,
+        }
+    }
+
+    pub const fn with_value(name: &'static str) -> Keyword {
+        Keyword {
+            name,
+            takes_value: true,
+            group: None,
+        }
+    }
+
+    /// Puts the keyword in `group`. Keywords of the same group exclude each
+    /// other, like NX and XX, but each may be repeated.
+    pub const fn in_group(self, group: u8) -> Keyword {
+        Keyword {
+            group: Some(group),
+            ..self
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// The keywords found by `scan_options`. A keyword given more than once
+/// keeps its last value, as Redis does.
+#[derive(Clone, Debug)]
+pub struct Options<'a> {
+    keywords: &'static [Keyword],
+
+    /// For each keyword, whether it was found and its value
+    found: Vec<Option<Option<&'a [u8]>>>,
+}
+
+impl<'a> Options<'a> {
+    pub fn has(&self, name: &str) -> bool {
+        self.position(name).is_some_and(|i| self.found[i].is_some())
+    }
+
+    /// The value of a keyword that takes one
+    pub fn value(&self, name: &str) -> Option<&'a [u8]> {
+        self.position(name).and_then(|i| self.found[i].flatten())
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.keywords
+            .iter()
+            .position(|keyword| keyword.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Reads `args` as keyword options out of `keywords`. Anything else, a
+/// keyword without its value or two keywords that exclude each other is an
+/// error.
+pub fn scan_options<'a, A: AsRef<[u8]>>(
+    args: &'a [A],
+    keywords: &'static [Keyword],
+) -> Result<Options<'a>, ArgError> {
+    let mut found = vec![None; keywords.len()];
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.as_ref();
+        let Some(i) = keywords
+            .iter()
+            .position(|keyword| keyword.name.as_bytes().eq_ignore_ascii_case(arg))
+        else {
+            return Err(ArgError::UnknownOption { index });
+        };
+        let keyword = &keywords[i];
+
+        if let Some(group) = keyword.group {
+            let other = keywords
+                .iter()
+                .enumerate()
+                .find(|(j, other)| *j != i && other.group == Some(group) && found[*j].is_some());
+            if let Some((_, other)) = other {
+                return Err(ArgError::Conflict {
+                    first: other.name,
+                    second: keyword.name,
+                });
+            }
+        }
+
+        let value = if keyword.takes_value {
+            index += 1;
+            match args.get(index) {
+                Some(value) => Some(value.as_ref()),
+                None => return Err(ArgError::MissingValue { index: index - 1 }),
+            }
+        } else {
+            None
+        };
+        found[i] = Some(value);
+        index += 1;
+    }
+
+    Ok(Options { keywords, found })
+}
+
+// ===========================================================
+// ArgError
+// ===========================================================
+
+/// An argument that isn't what the command expects. Indexes count from the
+/// first argument after the name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArgError {
+    Missing {
+        index: usize,
+    },
+    NotAnInteger {
+        index: usize,
+    },
+    NotUtf8 {
+        index: usize,
+    },
+
+    /// The argument isn't one of the keywords the command takes
+    UnknownOption {
+        index: usize,
+    },
+
+    /// The keyword is the last argument, without its value
+    MissingValue {
+        index: usize,
+    },
+
+    /// Two keywords that exclude each other, such as NX and XX
+    Conflict {
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+impl ArgError {
+    /// Shifts the index of the error, for options scanned from `start` on
+    fn offset(self, start: usize) -> ArgError {
+        match self {
+            ArgError::Missing { index } => ArgError::Missing {
+                index: index + start,
+            },
+            ArgError::NotAnInteger { index } => ArgError::NotAnInteger {
+                index: index + start,
+            },
+            ArgError::NotUtf8 { index } => ArgError::NotUtf8 {
+                index: index + start,
+            },
+            ArgError::UnknownOption { index } => ArgError::UnknownOption {
+                index: index + start,
+            },
+            ArgError::MissingValue { index } => ArgError::MissingValue {
+                index: index + start,
+            },
+            err @ ArgError::Conflict { .. } => err,
+        }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing { index } => write!(f, "missing argument {}", index),
+            ArgError::NotAnInteger { .. } => write!(f, "value is not an integer or out of range"),
+            ArgError::NotUtf8 { index } => write!(f, "argument {} is not valid UTF-8", index),
+            ArgError::UnknownOption { .. } | ArgError::MissingValue { .. } => {
+                write!(f, "syntax error")
+            }
+            ArgError::Conflict { first, second } => {
+                write!(
+                    f,
+                    "{} and {} options at the same time are not compatible",
+                    first, second
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for ArgError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        parser::{ParserConfig, kind_only},
+        writer::WriteBuf,
+    };
+
+    #[test]
+    fn test_split_inline() {
+        let inputs: [&[u8]; 12] = [
+            b"",
+            b"  \t ",
+            b"GET key",
+            b"  SET  k   v  ",
+            b"SET k \"a b\"",
+            b"SET k \"\\x41\\n\\\"\\xZZ\"",
+            b"SET k 'it\\'s \\n'",
+            b"SET k \"\"",
+            b"SET k a\"b c\"",
+            b"SET k \"a",
+            b"SET k 'a",
+            b"SET k \"a\"b",
+        ];
+        let expects: [Result<&[&str], UnbalancedQuotes>; 12] = [
+            Ok(&[]),
+            Ok(&[]),
+            Ok(&["GET", "key"]),
+            Ok(&["SET", "k", "v"]),
+            Ok(&["SET", "k", "a b"]),
+            Ok(&["SET", "k", "A\n\"xZZ"]),
+            Ok(&["SET", "k", "it's \\n"]),
+            Ok(&["SET", "k", ""]),
+            Ok(&["SET", "k", "ab c"]),
+            Err(UnbalancedQuotes),
+            Err(UnbalancedQuotes),
+            Err(UnbalancedQuotes),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let expected = expects[i].map(|words| {
+                words
+                    .iter()
+                    .map(|word| BulkString::from(*word))
+                    .collect::<Vec<_>>()
+            });
+            assert_eq!(
+                split_inline(inputs[i]),
+                expected,
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let inputs: [&[u8]; 4] = [
+            b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n",
+            b"GET k\r\n",
+            b"GET \"k\r\n",
+            b"GET k",
+        ];
+        let expects = [
+            Ok(vec![BulkString::from("GET"), BulkString::from("k")]),
+            Ok(vec![BulkString::from("GET"), BulkString::from("k")]),
+            Err(ParseError::new(ParseErrorKind::InvalidCmd).at(0)),
+            Err(ParseError::new(ParseErrorKind::Incomplete { needed: None }).at(5)),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i]);
+            assert_eq!(read_request(&mut parser), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_read_request_lenient() {
+        // Inline commands end at a lone LF, multibulk requests still need
+        // their CRLFs, and a LF in a bulk payload is just another byte
+        let input = b"GET foo\n\
+            *2\r\n$3\r\nSET\r\n$3\r\na\nb\r\n\
+            PING\r\n\
+            *1\n$4\r\nPING\r\n";
+        let expects = [
+            Ok(vec![BulkString::from("GET"), BulkString::from("foo")]),
+            Ok(vec![BulkString::from("SET"), BulkString::from("a\nb")]),
+            Ok(vec![BulkString::from("PING")]),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'\n' }),
+        ];
+
+        let config = ParserConfig {
+            lenient_line_endings: true,
+            ..ParserConfig::default()
+        };
+        let mut parser = RespParser::new_with_config(input, config);
+        for expect in expects {
+            assert_eq!(kind_only(read_request(&mut parser)), expect);
+        }
+    }
+
+    #[test]
+    fn test_command_line() {
+        let words: Vec<BulkString> = ["set", "k", "-12", "\u{e9}", "x"]
+            .into_iter()
+            .map(BulkString::from)
+            .chain([BulkString::new(vec![0xff])])
+            .collect();
+        let command = CommandLine::new(&words).unwrap();
+
+        assert_eq!(command.name, b"set");
+        assert_eq!(&*command.normalized_name(), b"SET");
+        assert_eq!(command.normalized_name(), CommandName::new(b"SeT"));
+        assert_eq!(command.arg(0), Ok(&b"k"[..]));
+        assert_eq!(command.arg_i64(1), Ok(-12));
+        assert_eq!(command.arg_str(2), Ok("\u{e9}"));
+        assert_eq!(command.arg_i64(3), Err(ArgError::NotAnInteger { index: 3 }));
+        assert_eq!(command.arg_str(4), Err(ArgError::NotUtf8 { index: 4 }));
+        assert_eq!(command.arg(5), Err(ArgError::Missing { index: 5 }));
+        assert_eq!(CommandLine::new(&[]), Err(CommandFormatError::Empty));
+
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        assert_eq!(
+            &*CommandName::new(long.as_bytes()),
+            long.to_uppercase().as_bytes()
+        );
+    }
+
+    const SET_OPTIONS: &[Keyword] = &[
+        Keyword::flag("NX").in_group(0),
+        Keyword::flag("XX").in_group(0),
+        Keyword::with_value("EX").in_group(1),
+        Keyword::with_value("PX").in_group(1),
+        Keyword::flag("KEEPTTL").in_group(1),
+        Keyword::flag("GET"),
+    ];
+
+    #[test]
+    fn test_scan_options() {
+        let inputs: [&[&str]; 10] = [
+            &[],
+            &["nx", "EX", "10"],
+            &["GET", "px", "5", "Get"],
+            // Repeated keywords keep their last value
+            &["EX", "1", "ex", "2", "NX", "nx"],
+            &["NX", "XX"],
+            &["EX", "1", "KEEPTTL"],
+            &["PX", "1", "EX", "1"],
+            &["EX"],
+            &["NX", "EXAT", "1"],
+            &["1", "EX"],
+        ];
+        // Flags found and the value of EX and PX
+        type Found = (
+            Vec<&'static str>,
+            Option<&'static [u8]>,
+            Option<&'static [u8]>,
+        );
+        let expects: [Result<Found, ArgError>; 10] = [
+            Ok((vec![], None, None)),
+            Ok((vec!["NX"], Some(b"10"), None)),
+            Ok((vec!["GET"], None, Some(b"5"))),
+            Ok((vec!["NX"], Some(b"2"), None)),
+            Err(ArgError::Conflict {
+                first: "NX",
+                second: "XX",
+            }),
+            Err(ArgError::Conflict {
+                first: "EX",
+                second: "KEEPTTL",
+            }),
+            Err(ArgError::Conflict {
+                first: "PX",
+                second: "EX",
+            }),
+            Err(ArgError::MissingValue { index: 0 }),
+            Err(ArgError::UnknownOption { index: 1 }),
+            Err(ArgError::UnknownOption { index: 0 }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let found = scan_options(inputs[i], SET_OPTIONS).map(|options| {
+                let flags = ["NX", "XX", "KEEPTTL", "GET"]
+                    .into_iter()
+                    .filter(|name| options.has(name))
+                    .collect();
+                (flags, options.value("EX"), options.value("px"))
+            });
+            assert_eq!(found, expects[i], "{:?}", inputs[i]);
+        }
+
+        // Errors point at the arguments of the whole command
+        let words: Vec<BulkString> = ["SET", "k", "v", "NX", "EX"]
+            .into_iter()
+            .map(BulkString::from)
+            .collect();
+        let command = CommandLine::new(&words).unwrap();
+        assert_eq!(
+            command.options(2, SET_OPTIONS).unwrap_err(),
+            ArgError::MissingValue { index: 3 }
+        );
+        assert!(
+            command
+                .options(5, SET_OPTIONS)
+                .unwrap()
+                .found
+                .iter()
+                .all(Option::is_none)
+        );
+    }
+
+    #[test]
+    fn test_resp_command() {
+        let inputs = [
+            RespCommand::new("PING"),
+            RespCommand::new("SET")
+                .arg("key")
+                .arg(String::from("value")),
+            RespCommand::new("SET")
+                .arg(&b"k\r\n"[..])
+                .arg(b"\x00\xff")
+                .arg(Vec::from(&b""[..])),
+            RespCommand::new("INCRBY").arg("n").arg(-42).arg(i64::MAX),
+        ];
+        let expects: [&[u8]; 4] = [
+            b"*1\r\n$4\r\nPING\r\n",
+            b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n",
+            b"*4\r\n$3\r\nSET\r\n$3\r\nk\r\n\r\n$2\r\n\x00\xff\r\n$0\r\n\r\n",
+            b"*4\r\n$6\r\nINCRBY\r\n$1\r\nn\r\n$3\r\n-42\r\n$19\r\n9223372036854775807\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut buf = WriteBuf::new(Vec::new());
+            RespWriter::new(&mut buf).write_value(&inputs[i]).unwrap();
+            assert_eq!(buf.get(), expects[i]);
+            assert_eq!(
+                inputs[i].encoded_len(ProtocolVersion::Resp2),
+                expects[i].len()
+            );
+
+            let mut parser = RespParser::new(buf.get());
+            assert_eq!(
+                Vec::<BulkString>::parse(&mut parser).as_deref(),
+                Ok(inputs[i].args())
+            );
+            assert_eq!(parser.remaining(), b"");
+        }
+    }
+}
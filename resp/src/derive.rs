@@ -0,0 +1,95 @@
+//! Support for the code generated by `#[derive(RespReadable, RespWritable)]`,
+//! enabled with the `derive` feature. Not meant to be used directly.
+
+use crate::{
+    parser::{ParseError, ParseErrorKind, ParseResult, RespParser},
+    types::{self, Length, RespReadable},
+    writer::ProtocolVersion,
+};
+
+/// Parses a struct sent as an array of exactly `len` fields, which `fields`
+/// parses in order
+pub fn parse_array<'a, T>(
+    parser: &mut RespParser<'a>,
+    len: usize,
+    fields: impl FnOnce(&mut RespParser<'a>) -> ParseResult<T>,
+) -> ParseResult<T> {
+    let start = parser.consumed() + 1;
+    match Length::parse(parser, b'*')? {
+        Some(Length(found)) if found == len => parser.nested(fields),
+        Some(Length(found)) => Err(invalid_length(found).at(start)),
+        // The number of fields is known, so a streamed array has no use
+        None => Err(ParseError::new(ParseErrorKind::InvalidData).at(start)),
+    }
+}
+
+/// Parses a struct sent as a map, or the flat array of keys and values of
+/// RESP2. `field` parses the value of the field named `key`, and returns
+/// false for an unknown key, which fails the parse. Keys can be bulk or
+/// simple strings and come in any order.
+pub fn parse_map<'a>(
+    parser: &mut RespParser<'a>,
+    mut field: impl FnMut(&mut RespParser<'a>, &'a [u8]) -> ParseResult<bool>,
+) -> ParseResult<()> {
+    let tag = match parser.peek_first() {
+        Some(b'*') => b'*',
+        _ => b'%',
+    };
+    let start = parser.consumed() + 1;
+    let len = match Length::parse(parser, tag)? {
+        Some(Length(len)) if tag == b'%' => len,
+        Some(Length(len)) if len % 2 == 0 => len / 2,
+        Some(Length(len)) => return Err(invalid_length(len).at(start)),
+        None => return Err(ParseError::new(ParseErrorKind::InvalidData).at(start)),
+    };
+
+    parser.nested(|parser| {
+        for _ in 0..len {
+            let start = parser.consumed();
+            let key = match parser.peek_first() {
+                Some(b'+') => <&str>::parse(parser)?.as_bytes(),
+                _ => <&[u8]>::parse(parser)?,
+            };
+            if !field(parser, key)? {
+                return Err(ParseError::new(ParseErrorKind::InvalidData).at(start));
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// The value of a field that isn't an `Option`, which fails the parse if
+/// the map didn't have it
+pub fn required<T>(parser: &RespParser<'_>, value: Option<T>) -> ParseResult<T> {
+    value.ok_or_else(|| parser.error(ParseErrorKind::InvalidData))
+}
+
+/// Parses a `T` that may be null, either the null of RESP3 or the null bulk
+/// string or array of RESP2
+pub fn parse_nullable<'a, T: RespReadable<'a>>(
+    parser: &mut RespParser<'a>,
+) -> ParseResult<Option<T>> {
+    let data = parser.remaining();
+    if data.first() == Some(&b'_') {
+        types::read_null(parser)?;
+        Ok(None)
+    } else if data.starts_with(b"$-1\r\n") || data.starts_with(b"*-1\r\n") {
+        parser.read_bytes(5)?;
+        Ok(None)
+    } else {
+        T::parse(parser).map(Some)
+    }
+}
+
+pub fn array_header_len(len: usize) -> usize {
+    types::header_len(len)
+}
+
+pub fn map_header_len(len: usize, protocol: ProtocolVersion) -> usize {
+    types::map_header_len(len, protocol)
+}
+
+fn invalid_length(len: usize) -> ParseError {
+    ParseError::new(ParseErrorKind::InvalidLength { len: len as i64 })
+}
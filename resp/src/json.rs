@@ -0,0 +1,312 @@
+//! Conversion between [`RespValue`] and JSON, enabled with the `json`
+//! feature, for looking at captured traffic and writing test fixtures by
+//! hand.
+//!
+//! Simple and bulk strings become strings, integers and doubles numbers,
+//! booleans booleans, arrays arrays and null null. Anything JSON has no type
+//! for becomes an object with a single `$` key:
+//!
+//! - `{"$binary": "<base64>"}` for a bulk string that isn't UTF-8
+//! - `{"$error": "ERR message"}`
+//! - `{"$bignumber": "<digits>"}`
+//! - `{"$double": "inf"}`, `"-inf"` or `"nan"`
+//! - `{"$map": [[key, value], ...]}`, keeping the keys as values and in order
+//! - `{"$set": [...]}` and `{"$push": [...]}`
+//! - `{"$attributes": [[key, value], ...], "$value": value}`
+//!
+//! Strings convert back into bulk strings, so simple strings don't survive
+//! the round trip. Other objects convert into maps with bulk string keys.
+
+use std::{error, fmt, str};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde_json::{Map, Value};
+
+use crate::types::{BulkString, RespError, RespValue, parse_double};
+
+const BINARY: &str = "$binary";
+const ERROR: &str = "$error";
+const BIG_NUMBER: &str = "$bignumber";
+const DOUBLE: &str = "$double";
+const MAP: &str = "$map";
+const SET: &str = "$set";
+const PUSH: &str = "$push";
+const ATTRIBUTES: &str = "$attributes";
+const VALUE: &str = "$value";
+
+// ===========================================================
+// JsonError
+// ===========================================================
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsonError {
+    /// A `$` object whose contents have the wrong shape, e.g.
+    /// `{"$error": 1}`
+    InvalidObject { tag: &'static str },
+
+    /// The payload of a `$binary` object isn't valid base64
+    InvalidBase64,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::InvalidObject { tag } => write!(f, "malformed '{}' object", tag),
+            JsonError::InvalidBase64 => write!(f, "invalid base64 in '{}' object", BINARY),
+        }
+    }
+}
+
+impl error::Error for JsonError {}
+
+// ===========================================================
+// RespValue to JSON
+// ===========================================================
+
+pub fn to_json(value: &RespValue) -> Value {
+    match value {
+        RespValue::None => Value::Null,
+        RespValue::Simple(s) => Value::String(s.clone()),
+        RespValue::Error(err) => tagged(ERROR, Value::String(err.to_string())),
+        RespValue::Integer(i) => Value::from(*i),
+        RespValue::Boolean(b) => Value::Bool(*b),
+        RespValue::Double(d) => match serde_json::Number::from_f64(*d) {
+            Some(n) => Value::Number(n),
+            None if d.is_nan() => tagged(DOUBLE, Value::from("nan")),
+            None if *d > 0.0 => tagged(DOUBLE, Value::from("inf")),
+            None => tagged(DOUBLE, Value::from("-inf")),
+        },
+        RespValue::BigNumber(digits) => tagged(BIG_NUMBER, Value::String(digits.clone())),
+        RespValue::Bulk(bulk) => match str::from_utf8(bulk.as_bytes()) {
+            Ok(s) => Value::String(s.to_string()),
+            Err(_) => tagged(
+                BINARY,
+                Value::String(BASE64_STANDARD.encode(bulk.as_bytes())),
+            ),
+        },
+        RespValue::Array(values) => values_to_json(values),
+        RespValue::Map(pairs) => tagged(MAP, pairs_to_json(pairs)),
+        RespValue::Set(values) => tagged(SET, values_to_json(values)),
+        RespValue::Push(values) => tagged(PUSH, values_to_json(values)),
+        RespValue::Attributed(attributes, value) => Value::Object(Map::from_iter([
+            (ATTRIBUTES.to_string(), pairs_to_json(attributes)),
+            (VALUE.to_string(), to_json(value)),
+        ])),
+    }
+}
+
+fn tagged(tag: &str, value: Value) -> Value {
+    Value::Object(Map::from_iter([(tag.to_string(), value)]))
+}
+
+fn values_to_json(values: &[RespValue]) -> Value {
+    Value::Array(values.iter().map(to_json).collect())
+}
+
+fn pairs_to_json(pairs: &[(RespValue, RespValue)]) -> Value {
+    Value::Array(
+        pairs
+            .iter()
+            .map(|(key, value)| Value::Array(vec![to_json(key), to_json(value)]))
+            .collect(),
+    )
+}
+
+// ===========================================================
+// JSON to RespValue
+// ===========================================================
+
+pub fn from_json(value: &Value) -> Result<RespValue, JsonError> {
+    match value {
+        Value::Null => Ok(RespValue::None),
+        Value::Bool(b) => Ok(RespValue::Boolean(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Ok(RespValue::Integer(i)),
+            // Beyond the range of an integer
+            None if n.is_u64() => Ok(RespValue::BigNumber(n.to_string())),
+            None => Ok(RespValue::Double(n.as_f64().unwrap_or(f64::NAN))),
+        },
+        Value::String(s) => Ok(RespValue::Bulk(BulkString::from(s.as_str()))),
+        Value::Array(values) => Ok(RespValue::Array(values_from_json(values)?)),
+        Value::Object(object) => object_from_json(object),
+    }
+}
+
+fn object_from_json(object: &Map<String, Value>) -> Result<RespValue, JsonError> {
+    if let (1, Some((tag, value))) = (object.len(), object.iter().next()) {
+        match tag.as_str() {
+            BINARY => {
+                return BASE64_STANDARD
+                    .decode(string(BINARY, value)?)
+                    .map(|data| RespValue::Bulk(BulkString::new(data)))
+                    .map_err(|_| JsonError::InvalidBase64);
+            }
+            ERROR => return Ok(RespValue::Error(RespError::from(string(ERROR, value)?))),
+            BIG_NUMBER => {
+                return Ok(RespValue::BigNumber(string(BIG_NUMBER, value)?.to_string()));
+            }
+            DOUBLE => {
+                return parse_double(string(DOUBLE, value)?.as_bytes())
+                    .map(RespValue::Double)
+                    .ok_or(JsonError::InvalidObject { tag: DOUBLE });
+            }
+            MAP => return Ok(RespValue::Map(pairs_from_json(MAP, value)?)),
+            SET => return Ok(RespValue::Set(values_from_json(array(SET, value)?)?)),
+            PUSH => return Ok(RespValue::Push(values_from_json(array(PUSH, value)?)?)),
+            _ => {}
+        }
+    }
+    if let (2, Some(attributes), Some(value)) =
+        (object.len(), object.get(ATTRIBUTES), object.get(VALUE))
+    {
+        return Ok(RespValue::Attributed(
+            pairs_from_json(ATTRIBUTES, attributes)?,
+            Box::new(from_json(value)?),
+        ));
+    }
+
+    let pairs = object
+        .iter()
+        .map(|(key, value)| Ok((RespValue::from(key.as_str()), from_json(value)?)))
+        .collect::<Result<_, JsonError>>()?;
+    Ok(RespValue::Map(pairs))
+}
+
+fn values_from_json(values: &[Value]) -> Result<Vec<RespValue>, JsonError> {
+    values.iter().map(from_json).collect()
+}
+
+/// Pairs written as two-element arrays
+fn pairs_from_json(
+    tag: &'static str,
+    value: &Value,
+) -> Result<Vec<(RespValue, RespValue)>, JsonError> {
+    array(tag, value)?
+        .iter()
+        .map(|pair| match pair {
+            Value::Array(pair) if pair.len() == 2 => {
+                Ok((from_json(&pair[0])?, from_json(&pair[1])?))
+            }
+            _ => Err(JsonError::InvalidObject { tag }),
+        })
+        .collect()
+}
+
+fn string<'v>(tag: &'static str, value: &'v Value) -> Result<&'v str, JsonError> {
+    value.as_str().ok_or(JsonError::InvalidObject { tag })
+}
+
+fn array<'v>(tag: &'static str, value: &'v Value) -> Result<&'v [Value], JsonError> {
+    match value {
+        Value::Array(values) => Ok(values),
+        _ => Err(JsonError::InvalidObject { tag }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::types::ErrorCode;
+
+    #[test]
+    fn test_to_json() {
+        let inputs = [
+            RespValue::None,
+            RespValue::Simple("OK".to_string()),
+            RespValue::Error(RespError::syntax()),
+            RespValue::Integer(-7),
+            RespValue::Boolean(true),
+            RespValue::Double(1.5),
+            RespValue::Double(f64::NEG_INFINITY),
+            RespValue::BigNumber("18446744073709551616".to_string()),
+            RespValue::from("value"),
+            RespValue::Bulk(BulkString::new(&b"\xff\x00a"[..])),
+            RespValue::Array(vec![RespValue::from("GET"), RespValue::from("key")]),
+            RespValue::Map(vec![(RespValue::Integer(1), RespValue::None)]),
+            RespValue::Set(vec![RespValue::Integer(1)]),
+            RespValue::Push(vec![RespValue::from("message")]),
+            RespValue::Attributed(
+                vec![(RespValue::from("ttl"), RespValue::Integer(3))],
+                Box::new(RespValue::from("v")),
+            ),
+        ];
+        let expects = [
+            json!(null),
+            json!("OK"),
+            json!({"$error": "ERR syntax error"}),
+            json!(-7),
+            json!(true),
+            json!(1.5),
+            json!({"$double": "-inf"}),
+            json!({"$bignumber": "18446744073709551616"}),
+            json!("value"),
+            json!({"$binary": "/wBh"}),
+            json!(["GET", "key"]),
+            json!({"$map": [[1, null]]}),
+            json!({"$set": [1]}),
+            json!({"$push": ["message"]}),
+            json!({"$attributes": [["ttl", 3]], "$value": "v"}),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(to_json(&inputs[i]), expects[i]);
+            if i != 1 {
+                assert_eq!(from_json(&expects[i]), Ok(inputs[i].clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        let inputs = [
+            json!("OK"),
+            json!(18446744073709551615u64),
+            json!({"name": "worker", "tags": ["a"]}),
+            json!({"$error": "BUSYGROUP Consumer Group name already exists"}),
+            json!({"$unknown": 1}),
+            json!({"$double": "nan"}),
+            json!({"$double": "infinity"}),
+            json!({"$error": 1}),
+            json!({"$map": [[1]]}),
+            json!({"$binary": "not base64!"}),
+        ];
+        let expects = [
+            Ok(RespValue::from("OK")),
+            Ok(RespValue::BigNumber("18446744073709551615".to_string())),
+            Ok(RespValue::Map(vec![
+                (RespValue::from("name"), RespValue::from("worker")),
+                (
+                    RespValue::from("tags"),
+                    RespValue::Array(vec![RespValue::from("a")]),
+                ),
+            ])),
+            Ok(RespValue::Error(RespError::new(
+                ErrorCode::BusyGroup,
+                "Consumer Group name already exists",
+            ))),
+            Ok(RespValue::Map(vec![(
+                RespValue::from("$unknown"),
+                RespValue::Integer(1),
+            )])),
+            // NaN isn't equal to itself, so it is compared by its text
+            Ok(RespValue::Double(f64::NAN)),
+            Err(JsonError::InvalidObject { tag: "$double" }),
+            Err(JsonError::InvalidObject { tag: "$error" }),
+            Err(JsonError::InvalidObject { tag: "$map" }),
+            Err(JsonError::InvalidBase64),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                format!("{:?}", from_json(&inputs[i])),
+                format!("{:?}", expects[i]),
+                "{}",
+                inputs[i]
+            );
+        }
+    }
+}
@@ -1,3 +1,19 @@
+pub mod buf;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "tokio")]
+pub mod codec;
+pub mod command;
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod derive;
+#[cfg(feature = "json")]
+pub mod json;
+mod macros;
 pub mod parser;
+pub mod reader;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod trace;
 pub mod types;
 pub mod writer;
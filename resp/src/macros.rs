@@ -0,0 +1,63 @@
+/// Builds a [`RespValue::Array`](crate::types::RespValue::Array) out of
+/// anything that converts into a `RespValue`. Strings become bulk strings,
+/// `None` becomes null, and arrays nest by nesting the macro.
+///
+/// ```
+/// use resp::{resp_array, types::{BulkString, RespValue}};
+///
+/// let key = "counter".to_string();
+/// let value = resp_array!["SET", key, 42, resp_array![Some("a"), None::<i64>],];
+///
+/// assert_eq!(
+///     value,
+///     RespValue::Array(vec![
+///         RespValue::Bulk(BulkString::from("SET")),
+///         RespValue::Bulk(BulkString::from("counter")),
+///         RespValue::Integer(42),
+///         RespValue::Array(vec![
+///             RespValue::Bulk(BulkString::from("a")),
+///             RespValue::None,
+///         ]),
+///     ])
+/// );
+/// assert_eq!(resp_array![], RespValue::Array(vec![]));
+/// ```
+#[macro_export]
+macro_rules! resp_array {
+    ($($value:expr),* $(,)?) => {
+        $crate::types::RespValue::Array(::std::vec![
+            $($crate::types::RespValue::from($value)),*
+        ])
+    };
+}
+
+/// Builds a RESP3 [`RespValue::Map`](crate::types::RespValue::Map) out of
+/// `key => value` pairs, converted as in [`resp_array!`]. The pairs keep
+/// their order.
+///
+/// ```
+/// use resp::{resp_array, resp_map, types::{BulkString, RespValue}};
+///
+/// let value = resp_map! {
+///     "server" => "redis",
+///     "proto" => 3,
+///     "modules" => resp_array![],
+/// };
+///
+/// assert_eq!(
+///     value,
+///     RespValue::Map(vec![
+///         (RespValue::from("server"), RespValue::from("redis")),
+///         (RespValue::from("proto"), RespValue::Integer(3)),
+///         (RespValue::from("modules"), RespValue::Array(vec![])),
+///     ])
+/// );
+/// ```
+#[macro_export]
+macro_rules! resp_map {
+    ($($key:expr => $value:expr),* $(,)?) => {
+        $crate::types::RespValue::Map(::std::vec![
+            $(($crate::types::RespValue::from($key), $crate::types::RespValue::from($value))),*
+        ])
+    };
+}
@@ -1,23 +1,89 @@
+use std::{error, fmt, iter::FusedIterator, marker::PhantomData, sync::LazyLock};
+
+use memchr::memmem::Finder;
+
+use crate::types::{BulkString, RespReadable, RespValue, RespValueRef};
+
 // ===========================================================
 // ParseError, ParseErrorKind, ParseResult
 // ===========================================================
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ParseErrorKind {
-    InvalidTag { tag: u8 },
+    InvalidTag {
+        tag: u8,
+    },
+
+    /// The input ends in the middle of a value, and would parse with more
+    /// bytes appended. `needed` is the least number of bytes missing when
+    /// the parser can tell, which it can't while waiting for the end of a
+    /// line. Only ever returned for a prefix of a valid frame.
+    Incomplete {
+        needed: Option<usize>,
+    },
 
-    EmptyData,
+    /// A bulk string payload isn't followed by CRLF
     MissingCRLF,
-    MissingData { needed: usize },
-    ExtraData { extra: usize },
+
+    /// A bulk string payload is followed by `extra` bytes before the CRLF,
+    /// i.e. it is longer than its header says. Also `extra` bytes left over
+    /// after a value that should have taken up the whole input.
+    ExtraData {
+        extra: usize,
+    },
 
     InvalidData,
     InvalidUtf8Data,
-    InvalidIntegerData { data: u8 },
+
+    /// A RESP3 double that isn't a number, `inf`, `-inf` or `nan`
+    InvalidDouble,
+
+    InvalidIntegerData {
+        data: u8,
+    },
 
     IntegerOverflow,
 
-    InvalidLength { len: i64 },
+    /// An integer or length without any digits, e.g. `:\r\n` or `*-\r\n`
+    EmptyInteger,
+
+    InvalidLength {
+        len: i64,
+    },
+
+    /// A count or other integer that can't be negative is
+    NegativeInteger {
+        value: i64,
+    },
+
+    NestingTooDeep,
+
+    /// A chunk of a streamed bulk string doesn't start with `;`
+    InvalidChunk {
+        tag: u8,
+    },
+
+    /// A streamed bulk string adds up to more than `limit` bytes
+    TooLong {
+        limit: usize,
+    },
+
+    /// A line runs on for more than `limit` bytes without a CRLF
+    LineTooLong {
+        limit: usize,
+    },
+
+    /// A bulk string header announces more than `limit` bytes
+    BulkTooLong {
+        len: usize,
+        limit: usize,
+    },
+
+    /// An aggregate header announces more than `limit` elements
+    ArrayTooLong {
+        len: usize,
+        limit: usize,
+    },
 
     InvalidCmd,
 }
@@ -25,99 +91,611 @@ pub enum ParseErrorKind {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ParseError {
     kind: ParseErrorKind,
+
+    /// Position in the input of the parser where the error was detected
+    offset: Option<usize>,
+}
+
+impl ParseErrorKind {
+    /// Whether the error leaves it unknown where the next frame starts. A
+    /// server can reply to a fatal error, but has to close the connection
+    /// afterwards, since whatever it reads next may be the middle of a
+    /// frame. The other errors are confined to a value whose extent is
+    /// known.
+    ///
+    /// An incomplete frame counts as fatal, it only reaches the server if
+    /// the framing went wrong.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            ParseErrorKind::InvalidTag { .. }
+            | ParseErrorKind::Incomplete { .. }
+            | ParseErrorKind::MissingCRLF
+            | ParseErrorKind::ExtraData { .. }
+            | ParseErrorKind::InvalidIntegerData { .. }
+            | ParseErrorKind::IntegerOverflow
+            | ParseErrorKind::EmptyInteger
+            | ParseErrorKind::InvalidLength { .. }
+            | ParseErrorKind::NegativeInteger { .. }
+            | ParseErrorKind::NestingTooDeep
+            | ParseErrorKind::InvalidChunk { .. }
+            | ParseErrorKind::TooLong { .. }
+            | ParseErrorKind::LineTooLong { .. }
+            | ParseErrorKind::BulkTooLong { .. }
+            | ParseErrorKind::ArrayTooLong { .. } => true,
+            ParseErrorKind::InvalidData
+            | ParseErrorKind::InvalidUtf8Data
+            | ParseErrorKind::InvalidDouble
+            | ParseErrorKind::InvalidCmd => false,
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::InvalidTag { tag } => {
+                write!(f, "invalid type byte '{}'", tag.escape_ascii())
+            }
+            ParseErrorKind::Incomplete { needed: None } => write!(f, "unexpected end of input"),
+            ParseErrorKind::Incomplete {
+                needed: Some(needed),
+            } => write!(f, "unexpected end of input, need {} more bytes", needed),
+            ParseErrorKind::MissingCRLF => write!(f, "missing CRLF"),
+            ParseErrorKind::ExtraData { extra } => {
+                write!(f, "{} unexpected bytes after the value", extra)
+            }
+            ParseErrorKind::InvalidData => write!(f, "invalid data"),
+            ParseErrorKind::InvalidUtf8Data => write!(f, "invalid UTF-8"),
+            ParseErrorKind::InvalidDouble => write!(f, "invalid double"),
+            ParseErrorKind::InvalidIntegerData { data } => {
+                write!(f, "invalid integer character '{}'", data.escape_ascii())
+            }
+            ParseErrorKind::IntegerOverflow => write!(f, "integer out of range"),
+            ParseErrorKind::EmptyInteger => write!(f, "integer without digits"),
+            ParseErrorKind::InvalidLength { len } => write!(f, "invalid length {}", len),
+            ParseErrorKind::NegativeInteger { value } => {
+                write!(f, "negative integer {} where none is allowed", value)
+            }
+            ParseErrorKind::NestingTooDeep => write!(f, "nesting too deep"),
+            ParseErrorKind::InvalidChunk { tag } => {
+                write!(f, "expected ';' chunk header, got '{}'", tag.escape_ascii())
+            }
+            ParseErrorKind::TooLong { limit } => {
+                write!(f, "streamed string longer than {} bytes", limit)
+            }
+            ParseErrorKind::LineTooLong { limit } => {
+                write!(f, "line longer than {} bytes", limit)
+            }
+            ParseErrorKind::BulkTooLong { len, limit } => {
+                write!(
+                    f,
+                    "bulk string of {} bytes exceeds the limit of {}",
+                    len, limit
+                )
+            }
+            ParseErrorKind::ArrayTooLong { len, limit } => {
+                write!(
+                    f,
+                    "aggregate of {} elements exceeds the limit of {}",
+                    len, limit
+                )
+            }
+            ParseErrorKind::InvalidCmd => write!(f, "invalid command"),
+        }
+    }
 }
 
 impl ParseError {
     pub fn new(kind: ParseErrorKind) -> ParseError {
-        ParseError { kind }
+        ParseError { kind, offset: None }
     }
-}
 
-pub type ParseResult<T> = Result<T, ParseError>;
+    /// Sets the position in the input where the error was detected, unless
+    /// it is already known. Errors from the free functions, which only see
+    /// part of the input, get it from the parser that called them.
+    pub fn at(mut self, offset: usize) -> ParseError {
+        self.offset.get_or_insert(offset);
+        self
+    }
 
-// ===========================================================
-// Parser
-// ===========================================================
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
 
-pub fn read_i64(mut data: &[u8]) -> ParseResult<i64> {
-    if data.is_empty() {
-        return Ok(0);
+    pub fn into_kind(self) -> ParseErrorKind {
+        self.kind
+    }
+
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
     }
 
-    if data.len() > 20 {
-        return Err(ParseError::new(ParseErrorKind::IntegerOverflow));
+    /// Whether the input is a valid prefix of a longer frame, so that
+    /// parsing again once more bytes have arrived may succeed. Every other
+    /// error stays an error however many bytes follow.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ParseErrorKind::Incomplete { .. })
     }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
 
-    if data.len() == 20 && data[0] != b'+' && data[0] != b'-' {
-        return Err(ParseError::new(ParseErrorKind::IntegerOverflow));
+        Ok(())
     }
+}
 
-    let sign = match data.first().unwrap() {
-        b'+' => {
+impl error::Error for ParseError {}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// Drops the offset of an error, leaving what went wrong but not where. Tests
+/// compare results this way, so that expected errors don't have to spell out
+/// their offsets.
+pub fn kind_only<T>(res: ParseResult<T>) -> Result<T, ParseErrorKind> {
+    res.map_err(ParseError::into_kind)
+}
+
+// ===========================================================
+// Parser
+// ===========================================================
+
+pub fn read_i64(mut data: &[u8]) -> ParseResult<i64> {
+    let negative = match data.first() {
+        None => return Err(ParseError::new(ParseErrorKind::EmptyInteger)),
+        Some(b'+') => {
             data = &data[1..];
-            1
+            false
         }
-        b'-' => {
+        Some(b'-') => {
             data = &data[1..];
-            -1
+            true
         }
-        _ => 1,
+        Some(_) => false,
     };
+    if data.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::EmptyInteger));
+    }
 
-    const BASE: i64 = 10;
-    const MAX_OVER_BASE: i64 = i64::MAX / BASE;
+    // The magnitude is accumulated as a negative number, since the negative
+    // range of i64 is the larger one and holds i64::MIN
     let mut value: i64 = 0;
     for c in data.iter() {
         let digit = c.wrapping_sub(b'0');
-        if digit as i64 >= BASE {
+        if digit >= 10 {
             return Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
                 data: *c,
             }));
         }
 
-        if value >= MAX_OVER_BASE {
-            return Err(ParseError::new(ParseErrorKind::IntegerOverflow));
+        value = value
+            .checked_mul(10)
+            .and_then(|value| value.checked_sub(digit as i64))
+            .ok_or_else(|| ParseError::new(ParseErrorKind::IntegerOverflow))?;
+    }
+
+    if negative {
+        Ok(value)
+    } else {
+        value
+            .checked_neg()
+            .ok_or_else(|| ParseError::new(ParseErrorKind::IntegerOverflow))
+    }
+}
+
+/// Reads an integer that can't be negative, such as a length, an offset or
+/// a count, over the whole range of u64. Negative integers are refused with
+/// their value, except for `-0`.
+pub fn read_u64(data: &[u8]) -> ParseResult<u64> {
+    let digits = match data.first() {
+        Some(b'+') => &data[1..],
+        Some(b'-') => {
+            return match read_i64(data)? {
+                0 => Ok(0),
+                value => Err(ParseError::new(ParseErrorKind::NegativeInteger { value })),
+            };
+        }
+        _ => data,
+    };
+    if digits.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::EmptyInteger));
+    }
+
+    let mut value: u64 = 0;
+    for c in digits.iter() {
+        let digit = c.wrapping_sub(b'0');
+        if digit >= 10 {
+            return Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
+                data: *c,
+            }));
         }
 
-        value *= BASE;
-        value += digit as i64;
+        value = value
+            .checked_mul(10)
+            .and_then(|value| value.checked_add(digit as u64))
+            .ok_or_else(|| ParseError::new(ParseErrorKind::IntegerOverflow))?;
     }
 
-    Ok(sign * value)
+    Ok(value)
+}
+
+/// Reads a non-negative integer like `read_u64`, which also has to fit in a
+/// usize
+pub fn read_usize(data: &[u8]) -> ParseResult<usize> {
+    usize::try_from(read_u64(data)?).map_err(|_| ParseError::new(ParseErrorKind::IntegerOverflow))
 }
 
 pub fn read_str(data: &[u8]) -> ParseResult<String> {
-    unsafe { Ok(String::from_utf8_unchecked(data.to_vec())) }
+    String::from_utf8(data.to_vec()).map_err(|_| ParseError::new(ParseErrorKind::InvalidUtf8Data))
+}
+
+/// Searches for both bytes of the CRLF at once, so that lone CRs or LFs
+/// don't stop it any more than other bytes
+static CRLF: LazyLock<Finder<'static>> = LazyLock::new(|| Finder::new(b"\r\n"));
+
+/// Type bytes a frame can start with
+const FRAME_TAGS: &[u8] = b"+-:($_*%~>|";
+
+/// Most lines are headers that end within this many bytes, sooner than
+/// setting up the search would pay off
+const SHORT_LINE: usize = 32;
+
+/// Position of the first CRLF in `data`
+#[inline]
+pub fn find_crlf(data: &[u8]) -> Option<usize> {
+    let head = &data[..data.len().min(SHORT_LINE + 1)];
+    if let Some(pos) = head.windows(2).position(|w| w == b"\r\n") {
+        return Some(pos);
+    }
+    if data.len() <= SHORT_LINE + 1 {
+        return None;
+    }
+    find_crlf_long(&data[SHORT_LINE..]).map(|pos| SHORT_LINE + pos)
+}
+
+#[inline(never)]
+fn find_crlf_long(data: &[u8]) -> Option<usize> {
+    CRLF.find(data)
+}
+
+/// Where the search for the end of a line stopped when the input ran out.
+/// A parser over the same input with more data appended can resume the
+/// search there with `RespParser::resume_line_scan`, so that a long line
+/// arriving in many pieces isn't scanned from its start every time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineScan {
+    /// Position of the line in the input
+    start: usize,
+
+    /// Position up to which the line has no CRLF
+    end: usize,
+}
+
+/// A position in the input of a parser to go back to with
+/// `RespParser::rewind`, such as the start of a frame that may turn out to
+/// be incomplete
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+/// Default for the deepest nesting of aggregates the parser accepts.
+/// Aggregates are parsed recursively, so without a limit a long run of
+/// `*1\r\n` would overflow the stack.
+pub const MAX_NESTING_DEPTH: usize = 128;
+
+/// Default limit on the length of a bulk string, the same as the largest one
+/// Redis accepts. Readers make room for the payload once they have its
+/// header, so without a limit a header alone could make them allocate any
+/// amount.
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Default limit on the total length of a streamed bulk string, the same as
+/// for other bulk strings. Chunks are reassembled in memory, so without a
+/// limit a sender could make the parser buffer any amount.
+pub const MAX_STREAMED_LEN: usize = MAX_BULK_LEN;
+
+/// What to do about a frame that fails to parse, other than for being
+/// incomplete
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorRecovery {
+    /// Stop at the error. Where the next frame starts isn't known, so a
+    /// server replies with the error and closes the connection.
+    #[default]
+    Close,
+
+    /// Skip to where the next frame plausibly starts, with
+    /// `RespParser::resync`, and go on from there. Suits tools reading logs
+    /// or dumps, which would rather lose a frame than the rest of the input.
+    Resync,
+}
+
+/// Limits on what the parser accepts. A server sets them from its
+/// configuration, so that a hostile client can't make it buffer or allocate
+/// more than it allows. The defaults only limit nesting and bulk strings,
+/// anything else is accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Longest line, without its CRLF, such as a simple string or a header.
+    /// Longer lines fail once that many bytes are read, without waiting for
+    /// the CRLF.
+    pub max_line_len: usize,
+
+    /// Largest bulk string, refused from its header before any payload
+    pub max_bulk_len: usize,
+
+    /// Most elements in an aggregate, or pairs in a map, refused from its
+    /// header before anything is allocated
+    pub max_array_len: usize,
+
+    /// Deepest nesting of aggregates. Each level takes up stack in the
+    /// recursive parser, so a limit much above the default needs a larger
+    /// stack than the usual.
+    pub max_depth: usize,
+
+    /// Most bytes a streamed bulk string may add up to
+    pub max_streamed_len: usize,
+
+    /// Whether `Frames` stops at a malformed frame or skips it
+    pub on_error: ErrorRecovery,
+
+    /// Whether a lone LF ends an inline command as well as a CRLF, as Redis
+    /// allows for people typing commands into `nc` or `telnet`. Every other
+    /// line, the headers of multibulk requests included, still needs its
+    /// CRLF.
+    pub lenient_line_endings: bool,
+}
+
+impl Default for ParserConfig {
+    fn default() -> ParserConfig {
+        ParserConfig {
+            max_line_len: usize::MAX,
+            max_bulk_len: MAX_BULK_LEN,
+            max_array_len: usize::MAX,
+            max_depth: MAX_NESTING_DEPTH,
+            max_streamed_len: MAX_STREAMED_LEN,
+            on_error: ErrorRecovery::Close,
+            lenient_line_endings: false,
+        }
+    }
 }
 
 pub struct RespParser<'a> {
     pub(crate) data: &'a [u8],
+    pub(crate) depth: usize,
+    config: ParserConfig,
+
+    /// Length of the whole input
+    len: usize,
+
+    /// The search for the end of a line, where it stopped or where it is to
+    /// be resumed
+    line_scan: Option<LineScan>,
 }
 
 impl<'a> RespParser<'a> {
     pub fn new(data: &'a [u8]) -> RespParser<'a> {
-        RespParser { data }
+        RespParser::new_with_config(data, ParserConfig::default())
     }
 
-    fn split_line(&self) -> ParseResult<(&'a [u8], &'a [u8])> {
-        for i in 0..self.data.len() - 1 {
-            if self.data[i] == b'\r' && self.data[i + 1] == b'\n' {
-                return Ok((&self.data[0..i], &self.data[i + 2..]));
-            }
+    pub fn new_with_config(data: &'a [u8], config: ParserConfig) -> RespParser<'a> {
+        RespParser {
+            data,
+            depth: 0,
+            config,
+            len: data.len(),
+            line_scan: None,
+        }
+    }
+
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
+    }
+
+    /// Sets how deep aggregates may be nested
+    pub fn set_max_depth(&mut self, limit: usize) {
+        self.config.max_depth = limit;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.config.max_depth
+    }
+
+    /// Sets the most bytes a streamed bulk string may add up to
+    pub fn set_max_streamed_len(&mut self, limit: usize) {
+        self.config.max_streamed_len = limit;
+    }
+
+    pub fn max_streamed_len(&self) -> usize {
+        self.config.max_streamed_len
+    }
+
+    /// Number of input bytes consumed so far. After a value has been
+    /// parsed, it is the position right after the final LF of its frame.
+    pub fn consumed(&self) -> usize {
+        self.len - self.data.len()
+    }
+
+    /// Where the search for the end of the line being read stopped, if the
+    /// input ran out before it
+    pub fn line_scan(&self) -> Option<LineScan> {
+        self.line_scan
+    }
+
+    /// Resumes the search for the end of a line where a parser over a prefix
+    /// of this input stopped. It only applies to the line it was taken from.
+    pub fn resume_line_scan(&mut self, scan: LineScan) {
+        self.line_scan = Some(scan);
+    }
+
+    /// Input left after the bytes consumed so far
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Whether the whole input has been consumed
+    pub fn is_finished(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Remembers the current position, so that a parse that fails part way
+    /// can be undone with `rewind`
+    pub fn checkpoint(&self) -> Checkpoint<'a> {
+        Checkpoint {
+            data: self.data,
+            depth: self.depth,
+        }
+    }
+
+    /// Goes back to a checkpoint taken from this parser. The bytes consumed
+    /// since are read again by the next parse.
+    pub fn rewind(&mut self, checkpoint: Checkpoint<'a>) {
+        debug_assert!(checkpoint.data.len() <= self.len);
+        self.data = checkpoint.data;
+        self.depth = checkpoint.depth;
+    }
+
+    /// Checks that the input has been used up, as when it is supposed to
+    /// hold a single value. Any bytes left are an `ExtraData` error.
+    pub fn finish(self) -> ParseResult<()> {
+        match self.data.len() {
+            0 => Ok(()),
+            extra => Err(self.error(ParseErrorKind::ExtraData { extra })),
+        }
+    }
+
+    /// Skips to the next place a frame plausibly starts after a parse
+    /// error, which is a type byte right after a CRLF, and returns the
+    /// number of bytes discarded. Without one, the whole input is
+    /// discarded. Unless the input is used up, at least a byte is.
+    ///
+    /// It is a guess: the payload of a bulk string may look like the start
+    /// of a frame, and a frame may be skipped along with the broken one.
+    pub fn resync(&mut self) -> usize {
+        let skipped = CRLF
+            .find_iter(self.data)
+            .map(|pos| pos + 2)
+            .find(|&pos| {
+                self.data
+                    .get(pos)
+                    .is_some_and(|tag| FRAME_TAGS.contains(tag))
+            })
+            .unwrap_or(self.data.len());
+
+        self.data = &self.data[skipped..];
+        self.depth = 0;
+        self.line_scan = None;
+        skipped
+    }
+
+    /// Parses the next value without copying its strings out of the input
+    pub fn parse_ref(&mut self) -> ParseResult<RespValueRef<'a>> {
+        RespValueRef::parse(self)
+    }
+
+    /// Iterates over the values in the rest of the input, such as pipelined
+    /// replies
+    pub fn iter_values(&mut self) -> Frames<'_, 'a, RespValue> {
+        Frames::new(self)
+    }
+
+    /// Iterates over the commands in the rest of the input, arrays of bulk
+    /// strings as clients send them
+    pub fn iter_commands(&mut self) -> Frames<'_, 'a, Vec<BulkString>> {
+        Frames::new(self)
+    }
+
+    /// Creates an error detected at the current position
+    pub fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind).at(self.consumed())
+    }
+
+    /// Creates an error for input that ends too early, which is detected at
+    /// the end of the input
+    pub(crate) fn incomplete(&self, needed: Option<usize>) -> ParseError {
+        ParseError::new(ParseErrorKind::Incomplete { needed }).at(self.len)
+    }
+
+    /// Runs `parse` one nesting level deeper, failing if that would exceed
+    /// the depth limit
+    pub(crate) fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        self.enter()?;
+        let res = parse(self);
+        self.leave();
+        res
+    }
+
+    /// Goes one nesting level deeper, failing if that would exceed the depth
+    /// limit
+    pub(crate) fn enter(&mut self) -> ParseResult<()> {
+        if self.depth >= self.config.max_depth {
+            return Err(self.error(ParseErrorKind::NestingTooDeep));
         }
 
-        Err(ParseError::new(ParseErrorKind::MissingCRLF))
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Splits off the line at the start of the input. Only as far as the
+    /// longest line allowed and its CRLF is scanned.
+    fn split_line(&mut self) -> ParseResult<(&'a [u8], &'a [u8])> {
+        let limit = self.config.max_line_len;
+        let scanned = &self.data[..self.data.len().min(limit.saturating_add(2))];
+
+        let start = self.consumed();
+        let from = match self.line_scan {
+            Some(scan) if scan.start == start => (scan.end - start).min(scanned.len()),
+            _ => 0,
+        };
+        match find_crlf(&scanned[from..]) {
+            Some(end) => {
+                let end = from + end;
+                Ok((&self.data[..end], &self.data[end + 2..]))
+            }
+            // Past the limit, only a CR right at it can still end the line
+            None if scanned.len() > limit && scanned[limit..] != *b"\r" => {
+                Err(self.error(ParseErrorKind::LineTooLong { limit }))
+            }
+            None => {
+                // A CR at the end may be followed by the LF yet to come
+                self.line_scan = Some(LineScan {
+                    start,
+                    end: start + scanned.len().saturating_sub(1),
+                });
+                Err(self.incomplete(None))
+            }
+        }
     }
 
     pub fn peek_first(&self) -> Option<&u8> {
         self.data.first()
     }
 
+    /// Reads the type byte of the next value
+    pub fn read_tag(&mut self) -> ParseResult<u8> {
+        let (&tag, data) = self
+            .data
+            .split_first()
+            .ok_or_else(|| self.incomplete(None))?;
+        self.data = data;
+        Ok(tag)
+    }
+
     pub fn read_bytes(&mut self, len: usize) -> ParseResult<&'a [u8]> {
         if len > self.data.len() {
-            return Err(ParseError::new(ParseErrorKind::MissingData {
-                needed: len - self.data.len(),
-            }));
+            return Err(self.incomplete(Some(len - self.data.len())));
         }
         let (res, data) = self.data.split_at(len);
         self.data = data;
@@ -129,4 +707,518 @@ impl<'a> RespParser<'a> {
         self.data = data;
         Ok(line)
     }
+
+    /// Reads the line of an inline command. With `lenient_line_endings` it
+    /// ends at the first LF, and a CR right before it is dropped, otherwise
+    /// this is `read_line`.
+    pub fn read_inline_line(&mut self) -> ParseResult<&'a [u8]> {
+        if !self.config.lenient_line_endings {
+            return self.read_line();
+        }
+
+        let limit = self.config.max_line_len;
+        let scanned = &self.data[..self.data.len().min(limit.saturating_add(2))];
+        let Some(end) = memchr::memchr(b'\n', scanned) else {
+            if scanned.len() > limit && scanned[limit..] != *b"\r" {
+                return Err(self.error(ParseErrorKind::LineTooLong { limit }));
+            }
+            return Err(self.incomplete(None));
+        };
+
+        let line = &self.data[..end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.len() > limit {
+            return Err(self.error(ParseErrorKind::LineTooLong { limit }));
+        }
+        self.data = &self.data[end + 1..];
+        Ok(line)
+    }
+
+    /// Reads the CRLF that has to come next, as after a bulk string payload.
+    /// Bytes before a later CRLF are reported as `ExtraData`, which usually
+    /// means the sender got the length wrong.
+    pub fn read_crlf(&mut self) -> ParseResult<()> {
+        match self.data {
+            [b'\r', b'\n', data @ ..] => {
+                self.data = data;
+                Ok(())
+            }
+            [] | [b'\r'] => Err(self.incomplete(Some(2 - self.data.len()))),
+            _ => match find_crlf(self.data) {
+                Some(extra) => Err(self.error(ParseErrorKind::ExtraData { extra })),
+                None => Err(self.error(ParseErrorKind::MissingCRLF)),
+            },
+        }
+    }
+}
+
+/// Parses `data` as a single `T` that takes up all of it, rejecting
+/// trailing bytes instead of leaving them for the next parse
+pub fn parse_complete<'a, T: RespReadable<'a>>(data: &'a [u8]) -> ParseResult<T> {
+    let mut parser = RespParser::new(data);
+    let value = T::parse(&mut parser)?;
+    parser.finish()?;
+    Ok(value)
+}
+
+// ===========================================================
+// Frames
+// ===========================================================
+
+/// Iterator over the frames in the input of a parser, created with
+/// `RespParser::iter_values` or `RespParser::iter_commands`. It ends once the
+/// input is used up, or right after the first error. The frame that failed,
+/// a partial last one included, is left unconsumed, so that `consumed` is
+/// the length of the complete frames before it.
+///
+/// With `ErrorRecovery::Resync`, a malformed frame is reported and then
+/// skipped with `RespParser::resync`, and the iterator goes on. It still
+/// ends at a partial last frame.
+pub struct Frames<'p, 'a, T> {
+    parser: &'p mut RespParser<'a>,
+    failed: bool,
+    _frame: PhantomData<T>,
+}
+
+impl<'p, 'a, T> Frames<'p, 'a, T> {
+    fn new(parser: &'p mut RespParser<'a>) -> Frames<'p, 'a, T> {
+        Frames {
+            parser,
+            failed: false,
+            _frame: PhantomData,
+        }
+    }
+
+    /// Bytes of input taken up by the frames yielded so far, and whatever
+    /// the parser consumed before
+    pub fn consumed(&self) -> usize {
+        self.parser.consumed()
+    }
+
+    /// Input after the frames yielded so far
+    pub fn remaining(&self) -> &'a [u8] {
+        self.parser.remaining()
+    }
+}
+
+impl<'a, T: RespReadable<'a>> Iterator for Frames<'_, 'a, T> {
+    type Item = ParseResult<T>;
+
+    fn next(&mut self) -> Option<ParseResult<T>> {
+        if self.failed || self.parser.data.is_empty() {
+            return None;
+        }
+
+        let start = self.parser.checkpoint();
+        let res = T::parse(self.parser);
+        match &res {
+            Err(err)
+                if self.parser.config.on_error == ErrorRecovery::Resync && !err.is_incomplete() =>
+            {
+                // Resyncing moves on by at least a byte, so a frame
+                // that fails at its type byte is skipped too
+                self.parser.resync();
+                self.parser.depth = start.depth;
+            }
+            Err(_) => {
+                self.parser.rewind(start);
+                self.failed = true;
+            }
+            Ok(_) => {}
+        }
+        Some(res)
+    }
+}
+
+impl<'a, T: RespReadable<'a>> FusedIterator for Frames<'_, 'a, T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{BulkString, RespReadable};
+
+    #[test]
+    fn test_read_u64() {
+        let inputs: [&[u8]; 14] = [
+            b"0",
+            b"42",
+            b"+42",
+            b"-0",
+            b"9223372036854775807",
+            b"9223372036854775808",
+            b"18446744073709551615",
+            b"18446744073709551616",
+            b"-1",
+            b"-9223372036854775808",
+            b"-9223372036854775809",
+            b"",
+            b"+",
+            b"1a",
+        ];
+        let expects: [Result<u64, ParseErrorKind>; 14] = [
+            Ok(0),
+            Ok(42),
+            Ok(42),
+            Ok(0),
+            Ok(i64::MAX as u64),
+            Ok(i64::MAX as u64 + 1),
+            Ok(u64::MAX),
+            Err(ParseErrorKind::IntegerOverflow),
+            Err(ParseErrorKind::NegativeInteger { value: -1 }),
+            Err(ParseErrorKind::NegativeInteger { value: i64::MIN }),
+            Err(ParseErrorKind::IntegerOverflow),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'a' }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let val = kind_only(read_u64(inputs[i]));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+            if let Ok(value) = expects[i] {
+                assert_eq!(read_usize(inputs[i]), Ok(value as usize));
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_line() {
+        let inputs: [&[u8]; 6] = [b"", b"+", b"\r", b"\n", b"\r\n", b"ab\r\ncd"];
+        // The line and what follows it
+        type Split<'a> = (&'a [u8], &'a [u8]);
+        let expects: [Result<Split, ParseErrorKind>; 6] = [
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Ok((b"", b"")),
+            Ok((b"ab", b"cd")),
+        ];
+
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i]);
+            let val = kind_only(parser.read_line()).map(|line| (line, parser.remaining()));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let data = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n:1\r\n";
+        let frame_len = 22;
+
+        // Cut anywhere, the frame is incomplete, and rewinding leaves the
+        // parser where it started
+        for len in 0..frame_len {
+            let mut parser = RespParser::new(&data[..len]);
+            let start = parser.checkpoint();
+            let err = Vec::<BulkString>::parse(&mut parser).unwrap_err();
+            assert!(err.is_incomplete(), "{} {:?}", len, err);
+
+            parser.rewind(start);
+            assert_eq!(parser.consumed(), 0);
+            assert_eq!(parser.remaining(), &data[..len]);
+            assert_eq!(parser.is_finished(), len == 0);
+        }
+
+        // Low level reads can be undone as well
+        let mut parser = RespParser::new(data);
+        let start = parser.checkpoint();
+        assert_eq!(parser.read_tag(), Ok(b'*'));
+        assert_eq!(parser.read_line(), Ok(&b"2"[..]));
+        assert_eq!(parser.consumed(), 4);
+        parser.rewind(start);
+        assert_eq!(parser.consumed(), 0);
+
+        let request = Vec::<BulkString>::parse(&mut parser).unwrap();
+        assert_eq!(request, [BulkString::from("GET"), BulkString::from("key")]);
+        assert_eq!(parser.consumed(), frame_len);
+        assert!(!parser.is_finished());
+        assert_eq!(i64::parse(&mut parser), Ok(1));
+        assert!(parser.is_finished());
+    }
+
+    #[test]
+    fn test_read_inline_line() {
+        let inputs: [&[u8]; 8] = [
+            b"GET foo\n",
+            b"GET foo\r\n",
+            b"a\nb\r\n",
+            b"\n",
+            b"a\rb\n",
+            b"GET foo",
+            b"abcd\r\n",
+            b"abcde\n",
+        ];
+        // The line and what follows it, with lenient line endings
+        type Split<'a> = (&'a [u8], &'a [u8]);
+        let expects: [Result<Split, ParseErrorKind>; 8] = [
+            Ok((b"GET foo", b"")),
+            Ok((b"GET foo", b"")),
+            Ok((b"a", b"b\r\n")),
+            Ok((b"", b"")),
+            Ok((b"a\rb", b"")),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Ok((b"abcd", b"")),
+            Err(ParseErrorKind::LineTooLong { limit: 4 }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            // The last ones test the line limit
+            let config = ParserConfig {
+                max_line_len: if i < 6 { usize::MAX } else { 4 },
+                lenient_line_endings: true,
+                ..ParserConfig::default()
+            };
+            let mut parser = RespParser::new_with_config(inputs[i], config);
+            let val = kind_only(parser.read_inline_line()).map(|line| (line, parser.remaining()));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+
+        // Strict by default
+        let mut parser = RespParser::new(b"GET foo\n");
+        assert_eq!(
+            kind_only(parser.read_inline_line()),
+            Err(ParseErrorKind::Incomplete { needed: None })
+        );
+    }
+
+    #[test]
+    fn test_iter_values() {
+        let inputs: [&[u8]; 5] = [
+            b"",
+            b"+OK\r\n:1\r\n",
+            b"+OK\r\n*2\r\n:1\r\n",
+            b"+OK\r\n:1\r\n!garbage\r\n+never\r\n",
+            b"*1\r\n:x\r\n+OK\r\n",
+        ];
+        // What is yielded, then how much of the input is consumed
+        type Frames = (Vec<Result<RespValue, ParseErrorKind>>, usize);
+        let expects: [Frames; 5] = [
+            (vec![], 0),
+            (
+                vec![
+                    Ok(RespValue::Simple("OK".to_string())),
+                    Ok(RespValue::Integer(1)),
+                ],
+                9,
+            ),
+            (
+                vec![
+                    Ok(RespValue::Simple("OK".to_string())),
+                    Err(ParseErrorKind::Incomplete { needed: None }),
+                ],
+                5,
+            ),
+            (
+                vec![
+                    Ok(RespValue::Simple("OK".to_string())),
+                    Ok(RespValue::Integer(1)),
+                    Err(ParseErrorKind::InvalidTag { tag: b'!' }),
+                ],
+                9,
+            ),
+            (
+                vec![Err(ParseErrorKind::InvalidIntegerData { data: b'x' })],
+                0,
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i]);
+            let mut frames = parser.iter_values();
+            let values: Vec<_> = frames.by_ref().map(kind_only).collect();
+            assert_eq!(
+                (values, frames.consumed()),
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+            assert_eq!(frames.next(), None);
+            assert_eq!(frames.remaining(), &inputs[i][expects[i].1..]);
+        }
+    }
+
+    #[test]
+    fn test_iter_commands() {
+        let data = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n:1\r\n";
+        let mut parser = RespParser::new(data);
+        let commands: Vec<_> = parser.iter_commands().map(kind_only).collect();
+        assert_eq!(
+            commands,
+            [
+                Ok(vec![BulkString::from("PING")]),
+                Ok(vec![BulkString::from("GET"), BulkString::from("k")]),
+                Err(ParseErrorKind::InvalidTag { tag: b':' }),
+            ]
+        );
+        assert_eq!(parser.remaining(), b":1\r\n");
+    }
+
+    #[test]
+    fn test_parse_complete() {
+        let inputs: [&[u8]; 5] = [
+            b"+OK\r\n",
+            b"+OK\r\nx",
+            b"+OK\r\n+OK\r\n",
+            b"$3\r\nfoo\r\n\r\n",
+            b"+OK",
+        ];
+        let expects = [
+            Ok(RespValue::Simple("OK".to_string())),
+            Err(ParseError::new(ParseErrorKind::ExtraData { extra: 1 }).at(5)),
+            Err(ParseError::new(ParseErrorKind::ExtraData { extra: 5 }).at(5)),
+            Err(ParseError::new(ParseErrorKind::ExtraData { extra: 2 }).at(9)),
+            Err(ParseError::new(ParseErrorKind::Incomplete { needed: None }).at(3)),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(parse_complete::<RespValue>(inputs[i]), expects[i]);
+        }
+
+        // Parsing frame by frame leaves the stray bytes for the next parse
+        let mut parser = RespParser::new(inputs[1]);
+        assert_eq!(
+            RespValue::parse(&mut parser),
+            Ok(RespValue::Simple("OK".to_string()))
+        );
+        assert_eq!(parser.remaining(), b"x");
+    }
+
+    #[test]
+    fn test_resync() {
+        let inputs: [&[u8]; 6] = [
+            b"",
+            b"garbage",
+            b"!garbage\r\n+OK\r\n",
+            b"$3\r\nfoobar\r\n:1\r\n",
+            b"\r\n\r\n*1\r\n",
+            b"x\r\n",
+        ];
+        let expects = [0, 7, 10, 12, 4, 3];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i]);
+            assert_eq!(
+                parser.resync(),
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii()
+            );
+            assert_eq!(parser.consumed(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_iter_values_resync() {
+        let config = ParserConfig {
+            on_error: ErrorRecovery::Resync,
+            ..ParserConfig::default()
+        };
+        let inputs: [&[u8]; 3] = [
+            b":1\r\n!oops\r\n:2\r\n",
+            b"+OK\r\n*2\r\n$3\r\nfoo\r\n$x\r\nbar\r\n:1\r\n$3\r\nbaz\r\n",
+            b"!x\r\n+O",
+        ];
+        // What is yielded, then how much of the input is consumed
+        type Frames = (Vec<Result<RespValue, ParseErrorKind>>, usize);
+        let expects: [Frames; 3] = [
+            (
+                vec![
+                    Ok(RespValue::Integer(1)),
+                    Err(ParseErrorKind::InvalidTag { tag: b'!' }),
+                    Ok(RespValue::Integer(2)),
+                ],
+                inputs[0].len(),
+            ),
+            (
+                vec![
+                    Ok(RespValue::Simple("OK".to_string())),
+                    Err(ParseErrorKind::InvalidIntegerData { data: b'x' }),
+                    Ok(RespValue::Integer(1)),
+                    Ok(RespValue::Bulk(BulkString::from("baz"))),
+                ],
+                inputs[1].len(),
+            ),
+            // A partial last frame still ends the iteration
+            (
+                vec![
+                    Err(ParseErrorKind::InvalidTag { tag: b'!' }),
+                    Err(ParseErrorKind::Incomplete { needed: None }),
+                ],
+                4,
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new_with_config(inputs[i], config);
+            let mut frames = parser.iter_values();
+            let values: Vec<_> = frames.by_ref().map(kind_only).collect();
+            assert_eq!(
+                (values, frames.consumed()),
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_crlf() {
+        let inputs: [&[u8]; 7] = [
+            b"",
+            b"\r",
+            b"\n\r",
+            b"\r\n",
+            b"a\rb\r\r\n",
+            b"\r\r\r",
+            b"ab\n\r\n",
+        ];
+        let expects = [None, None, None, Some(0), Some(4), None, Some(3)];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(find_crlf(inputs[i]), expects[i], "{:?}", inputs[i]);
+        }
+    }
+
+    #[test]
+    fn test_resume_line_scan() {
+        let data = b":1\r\n+abc\rdef\r\n";
+
+        // Stops at the CR that may start the CRLF
+        let mut parser = RespParser::new(&data[..9]);
+        parser.read_line().unwrap();
+        assert!(kind_only(parser.read_line()).is_err());
+        let scan = parser.line_scan().unwrap();
+        assert_eq!(scan, LineScan { start: 4, end: 8 });
+
+        // Only the line the scan was taken from skips ahead
+        let mut parser = RespParser::new(data);
+        parser.resume_line_scan(scan);
+        assert_eq!(parser.read_line().unwrap(), b":1");
+        assert_eq!(parser.read_line().unwrap(), b"+abc\rdef");
+        assert!(parser.remaining().is_empty());
+    }
 }
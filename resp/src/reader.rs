@@ -0,0 +1,398 @@
+//! Reading values from a stream, either a blocking [`BufRead`] or, with the
+//! `tokio` feature, an `AsyncRead`
+
+use std::{
+    error, fmt,
+    io::{self, BufRead},
+};
+
+#[cfg(feature = "tokio")]
+use bytes::{Buf, BytesMut};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[cfg(feature = "tokio")]
+use crate::parser::{ParseErrorKind, ParserConfig};
+use crate::{
+    buf::{Scan, scan},
+    parser::{ParseError, RespParser},
+    types::{BulkString, RespReadable, RespValue},
+};
+
+// ===========================================================
+// ReadError
+// ===========================================================
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// The stream ended between two values
+    Eof,
+
+    /// The stream ended in the middle of a value, after `buffered` bytes of
+    /// it had been received
+    UnexpectedEof {
+        buffered: usize,
+    },
+
+    /// The input isn't valid RESP. The bytes of the value stay buffered.
+    Parse(ParseError),
+
+    Io(io::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Eof => write!(f, "end of stream"),
+            ReadError::UnexpectedEof { buffered } => {
+                write!(f, "end of stream inside a value, after {} bytes", buffered)
+            }
+            ReadError::Parse(err) => write!(f, "{}", err),
+            ReadError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReadError::Parse(err) => Some(err),
+            ReadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> ReadError {
+        ReadError::Io(err)
+    }
+}
+
+// ===========================================================
+// Blocking reads
+// ===========================================================
+
+pub fn read_value<R: BufRead>(reader: &mut R) -> Result<RespValue, ReadError> {
+    read(reader)
+}
+
+/// Reads a command sent by a client, an array of bulk strings
+pub fn read_command<R: BufRead>(reader: &mut R) -> Result<Vec<BulkString>, ReadError> {
+    read(reader)
+}
+
+/// Parses the next value from `reader`, blocking until it is complete.
+/// Only the bytes of the value are consumed from the reader, those after it
+/// stay there for the next read. A value that turns out to be invalid is
+/// left in the reader if it fit in its buffer, and consumed otherwise.
+pub fn read<T, R>(reader: &mut R) -> Result<T, ReadError>
+where
+    T: for<'a> RespReadable<'a>,
+    R: BufRead,
+{
+    // Start of a value that doesn't fit in the buffer of the reader
+    let mut frame = Vec::new();
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if available.is_empty() {
+            return Err(match frame.len() {
+                0 => ReadError::Eof,
+                buffered => ReadError::UnexpectedEof { buffered },
+            });
+        }
+
+        let end = match scan(&[&frame, available]) {
+            Scan::Complete(end) | Scan::Malformed(end) => end,
+            Scan::Incomplete(_) => {
+                // All of it belongs to the value
+                frame.extend_from_slice(available);
+                let len = available.len();
+                reader.consume(len);
+                continue;
+            }
+        };
+
+        let (value, len) = if frame.is_empty() {
+            let mut parser = RespParser::new(&available[..end]);
+            (
+                T::parse(&mut parser).map_err(ReadError::Parse)?,
+                parser.consumed(),
+            )
+        } else {
+            let taken = frame.len();
+            frame.extend_from_slice(&available[..end - taken]);
+            let mut parser = RespParser::new(&frame);
+            let value = T::parse(&mut parser).map_err(ReadError::Parse)?;
+            (value, parser.consumed() - taken)
+        };
+        reader.consume(len);
+        return Ok(value);
+    }
+}
+
+// ===========================================================
+// RespReader
+// ===========================================================
+
+/// Reads values from a stream, buffering what arrived past the last value
+/// for the next read. Room for a bulk string is made as soon as its header
+/// is read, up to the limit of the parser configuration.
+#[cfg(feature = "tokio")]
+pub struct RespReader<R> {
+    inner: R,
+    buf: BytesMut,
+    config: ParserConfig,
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead + Unpin> RespReader<R> {
+    pub fn new(inner: R) -> RespReader<R> {
+        RespReader::new_with_config(inner, ParserConfig::default())
+    }
+
+    pub fn new_with_config(inner: R, config: ParserConfig) -> RespReader<R> {
+        RespReader {
+            inner,
+            buf: BytesMut::new(),
+            config,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Bytes received but not read as a value yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub async fn read_value(&mut self) -> Result<RespValue, ReadError> {
+        self.read().await
+    }
+
+    /// Reads a command sent by a client, an array of bulk strings
+    pub async fn read_command(&mut self) -> Result<Vec<BulkString>, ReadError> {
+        self.read().await
+    }
+
+    /// Parses the next value, reading from the stream until it is complete
+    pub async fn read<T: for<'a> RespReadable<'a>>(&mut self) -> Result<T, ReadError> {
+        loop {
+            if !self.buf.is_empty() {
+                let mut parser = RespParser::new_with_config(&self.buf, self.config);
+                match T::parse(&mut parser) {
+                    Ok(value) => {
+                        let consumed = parser.consumed();
+                        self.buf.advance(consumed);
+                        return Ok(value);
+                    }
+                    Err(err) => match err.kind() {
+                        // A long payload is read into the buffer at once
+                        ParseErrorKind::Incomplete {
+                            needed: Some(needed),
+                        } => self.buf.reserve(*needed),
+                        ParseErrorKind::Incomplete { needed: None } => {}
+                        _ => return Err(ReadError::Parse(err)),
+                    },
+                }
+            }
+
+            if self.inner.read_buf(&mut self.buf).await? == 0 {
+                return Err(match self.buf.len() {
+                    0 => ReadError::Eof,
+                    buffered => ReadError::UnexpectedEof { buffered },
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufReader, Read},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[cfg(feature = "tokio")]
+    use tokio_test::io::Builder;
+
+    use super::*;
+    use crate::{parser::ParseErrorKind, writer::IoRespWriter};
+
+    /// Hands out the data in reads of at most `len` bytes
+    struct Trickle<'a> {
+        data: &'a [u8],
+        len: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.len.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_read_blocking() {
+        let data = b"*2\r\n$3\r\nGET\r\n$5\r\na\r\nbc\r\n:1\r\n+OK\r\n";
+        for len in 1..=data.len() {
+            // The buffer of the reader is smaller than a value
+            let mut reader = BufReader::with_capacity(len, Trickle { data, len });
+            assert_eq!(
+                read_command(&mut reader).unwrap(),
+                [BulkString::from("GET"), BulkString::from("a\r\nbc")]
+            );
+            assert_eq!(read_value(&mut reader).unwrap(), RespValue::Integer(1));
+            assert_eq!(
+                read_value(&mut reader).unwrap(),
+                RespValue::Simple("OK".to_string())
+            );
+            assert!(matches!(read_value(&mut reader), Err(ReadError::Eof)));
+        }
+
+        let mut reader = BufReader::with_capacity(4, &b"*2\r\n:1\r\n"[..]);
+        assert!(matches!(
+            read_value(&mut reader),
+            Err(ReadError::UnexpectedEof { buffered: 8 })
+        ));
+
+        let mut reader = &b":1\r\n?\r\n"[..];
+        assert_eq!(read_value(&mut reader).unwrap(), RespValue::Integer(1));
+        match read_value(&mut reader) {
+            Err(ReadError::Parse(err)) => {
+                assert_eq!(err.kind(), &ParseErrorKind::InvalidTag { tag: b'?' })
+            }
+            res => panic!("expected a parse error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_blocking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let values = [
+            RespValue::Array(vec![RespValue::from("SET"), RespValue::from("k")]),
+            RespValue::Bulk(BulkString::new(vec![b'x'; 100_000])),
+            RespValue::Integer(-1),
+            RespValue::None,
+        ];
+        let sent = values.clone();
+        let sender = std::thread::spawn(move || {
+            let mut writer = IoRespWriter::new(client);
+            for value in sent.iter() {
+                writer.write_value(value).unwrap();
+            }
+            writer.flush().unwrap();
+        });
+
+        let mut reader = BufReader::new(server);
+        for value in values.iter() {
+            assert_eq!(&read_value(&mut reader).unwrap(), value);
+        }
+        sender.join().unwrap();
+        assert!(matches!(read_value(&mut reader), Err(ReadError::Eof)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_read_value() {
+        // Values split across reads and several values in one read
+        let stream = Builder::new()
+            .read(b"*2\r\n$3\r")
+            .read(b"\nGET\r\n$1")
+            .read(b"\r\nk\r\n:1\r\n+OK")
+            .read(b"\r\n")
+            .build();
+        let mut reader = RespReader::new(stream);
+
+        assert_eq!(
+            reader.read_command().await.unwrap(),
+            [BulkString::from("GET"), BulkString::from("k")]
+        );
+        assert_eq!(reader.read_value().await.unwrap(), RespValue::Integer(1));
+        assert_eq!(
+            reader.read_value().await.unwrap(),
+            RespValue::Simple("OK".to_string())
+        );
+        assert!(matches!(reader.read_value().await, Err(ReadError::Eof)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_read_errors() {
+        let stream = Builder::new().read(b"*2\r\n$3\r\nGET\r\n").build();
+        let mut reader = RespReader::new(stream);
+        assert!(matches!(
+            reader.read_value().await,
+            Err(ReadError::UnexpectedEof { buffered: 13 })
+        ));
+
+        let stream = Builder::new().read(b":1\r\n?\r\n").build();
+        let mut reader = RespReader::new(stream);
+        assert_eq!(reader.read_value().await.unwrap(), RespValue::Integer(1));
+        match reader.read_value().await {
+            Err(ReadError::Parse(err)) => {
+                assert_eq!(err.kind(), &ParseErrorKind::InvalidTag { tag: b'?' })
+            }
+            res => panic!("expected a parse error, got {:?}", res),
+        }
+        assert_eq!(reader.buffer(), b"?\r\n");
+
+        let stream = Builder::new()
+            .read(b"$5\r\nab")
+            .read_error(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+            .build();
+        let mut reader = RespReader::new(stream);
+        match reader.read_value().await {
+            Err(ReadError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+            res => panic!("expected an I/O error, got {:?}", res),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_read_bulk_limit() {
+        let config = ParserConfig {
+            max_bulk_len: 4,
+            ..ParserConfig::default()
+        };
+        let stream = Builder::new()
+            .read(b"$4\r\nab")
+            .read(b"cd\r\n$5\r\n")
+            .build();
+        let mut reader = RespReader::new_with_config(stream, config);
+        assert_eq!(
+            reader.read_value().await.unwrap(),
+            RespValue::Bulk(BulkString::from("abcd"))
+        );
+
+        // Refused from the header, without making room for the payload
+        match reader.read_value().await {
+            Err(ReadError::Parse(err)) => assert_eq!(
+                err.kind(),
+                &ParseErrorKind::BulkTooLong { len: 5, limit: 4 }
+            ),
+            res => panic!("expected a parse error, got {:?}", res),
+        }
+        assert!(reader.buf.capacity() < 64);
+    }
+}
@@ -0,0 +1,861 @@
+//! Serde support, enabled with the `serde` feature. Values go through
+//! [`RespValue`]: structs and maps become maps, sequences and tuples become
+//! arrays, integers and booleans become integers, strings, floats and bytes
+//! become bulk strings, and `None` and `()` become null. Enum variants are
+//! written by name, with their data in a single-pair map.
+//!
+//! Maps are RESP3 types, a RESP2 writer flattens them into arrays, which
+//! don't deserialize back into structs.
+
+use std::{error, fmt};
+
+use ::serde::{
+    Deserialize, Serialize,
+    de::{
+        self, DeserializeOwned, IntoDeserializer, Visitor,
+        value::{MapDeserializer, SeqDeserializer},
+    },
+    forward_to_deserialize_any, ser,
+};
+
+use crate::{
+    parser::{ParseError, RespParser},
+    types::{BulkString, RespError, RespReadable, RespValue, RespWritable},
+    writer::{OutBuf, RespWriter, WriteError},
+};
+
+// ===========================================================
+// Error
+// ===========================================================
+
+#[derive(Debug)]
+pub enum Error {
+    /// The shape of the data has no RESP encoding, or the value doesn't fit
+    /// the type it is deserialized into
+    Message(String),
+
+    /// The value is an error reply
+    Reply(RespError),
+
+    Parse(ParseError),
+    Write(WriteError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Message(msg) => write!(f, "{}", msg),
+            Error::Reply(msg) => write!(f, "error reply: {}", msg),
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Write(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::Parse(err)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(err: WriteError) -> Error {
+        Error::Write(err)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// ===========================================================
+// Entry points
+// ===========================================================
+
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<RespValue> {
+    value.serialize(Serializer)
+}
+
+/// Serializes `value` and writes it in the protocol of `writer`
+pub fn to_writer<T: Serialize + ?Sized, B: OutBuf>(
+    writer: &mut RespWriter<'_, B>,
+    value: &T,
+) -> Result<()> {
+    Ok(to_value(value)?.write(writer)?)
+}
+
+pub fn from_value<T: DeserializeOwned>(value: RespValue) -> Result<T> {
+    T::deserialize(value)
+}
+
+/// Parses the next value of `parser` and deserializes it
+pub fn from_parser<T: DeserializeOwned>(parser: &mut RespParser<'_>) -> Result<T> {
+    from_value(RespValue::parse(parser)?)
+}
+
+// ===========================================================
+// Serializer
+// ===========================================================
+
+/// Serializes into a [`RespValue`]
+pub struct Serializer;
+
+fn bulk(data: impl Into<BulkString>) -> RespValue {
+    RespValue::Bulk(data.into())
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = RespValue;
+    type Error = Error;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeVariant<SerializeMap>;
+
+    fn serialize_bool(self, v: bool) -> Result<RespValue> {
+        Ok(v.into())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<RespValue> {
+        Ok(RespValue::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<RespValue> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<RespValue> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => Err(Error::Message(format!("{} is out of the integer range", v))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RespValue> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RespValue> {
+        Ok(bulk(v.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<RespValue> {
+        Ok(bulk(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RespValue> {
+        Ok(bulk(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<RespValue> {
+        Ok(bulk(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<RespValue> {
+        Ok(RespValue::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<RespValue> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RespValue> {
+        Ok(RespValue::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<RespValue> {
+        Ok(RespValue::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<RespValue> {
+        Ok(bulk(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<RespValue> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RespValue> {
+        Ok(RespValue::Map(vec![(bulk(variant), to_value(value)?)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec> {
+        Ok(SerializeVec {
+            values: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeVec>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeMap> {
+        Ok(SerializeMap {
+            pairs: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeMap>> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub struct SerializeVec {
+    values: Vec<RespValue>,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.values.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespValue> {
+        Ok(RespValue::Array(self.values))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<RespValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<RespValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    pairs: Vec<(RespValue, RespValue)>,
+
+    /// Key whose value comes next
+    key: Option<RespValue>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(to_value(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::Message("map value without a key".to_string()))?;
+        self.pairs.push((key, to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespValue> {
+        Ok(RespValue::Map(self.pairs))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.pairs.push((bulk(key), to_value(value)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespValue> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Data of an enum variant, which ends up as the value of a map with the
+/// variant name as its only key
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<RespValue> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(RespValue::Map(vec![(bulk(self.variant), value)]))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap> {
+    type Ok = RespValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<RespValue> {
+        let value = ser::SerializeMap::end(self.inner)?;
+        Ok(RespValue::Map(vec![(bulk(self.variant), value)]))
+    }
+}
+
+impl Serialize for RespValue {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap as _, SerializeSeq as _};
+
+        match self {
+            RespValue::None => serializer.serialize_none(),
+            RespValue::Simple(s) | RespValue::BigNumber(s) => serializer.serialize_str(s),
+            RespValue::Error(e) => Err(ser::Error::custom(format!("error reply: {}", e))),
+            RespValue::Integer(i) => serializer.serialize_i64(*i),
+            RespValue::Boolean(b) => serializer.serialize_bool(*b),
+            RespValue::Double(d) => serializer.serialize_f64(*d),
+            RespValue::Bulk(bulk) => match bulk.as_str() {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(bulk.as_bytes()),
+            },
+            RespValue::Array(values) | RespValue::Set(values) | RespValue::Push(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values.iter() {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            RespValue::Map(pairs) => {
+                let mut map = serializer.serialize_map(Some(pairs.len()))?;
+                for (key, value) in pairs.iter() {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            RespValue::Attributed(_, value) => value.serialize(serializer),
+        }
+    }
+}
+
+// ===========================================================
+// Deserializer
+// ===========================================================
+
+impl RespValue {
+    fn invalid_type(&self, exp: &dyn de::Expected) -> Error {
+        de::Error::invalid_type(de::Unexpected::Other(self.type_name()), exp)
+    }
+}
+
+/// Text of a string value, for types that are sent as strings
+fn text(value: &RespValue) -> Option<&str> {
+    match value {
+        RespValue::Simple(s) | RespValue::BigNumber(s) => Some(s),
+        RespValue::Bulk(bulk) => bulk.as_str().ok(),
+        _ => None,
+    }
+}
+
+impl<'de> de::Deserializer<'de> for RespValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            RespValue::None => visitor.visit_unit(),
+            RespValue::Simple(s) | RespValue::BigNumber(s) => visitor.visit_string(s),
+            RespValue::Error(e) => Err(Error::Reply(e)),
+            RespValue::Integer(i) => visitor.visit_i64(i),
+            RespValue::Boolean(b) => visitor.visit_bool(b),
+            RespValue::Double(d) => visitor.visit_f64(d),
+            RespValue::Bulk(bulk) => match String::from_utf8(bulk.into_bytes().into()) {
+                Ok(s) => visitor.visit_string(s),
+                Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+            },
+            RespValue::Array(values) | RespValue::Set(values) | RespValue::Push(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            RespValue::Map(pairs) => {
+                let mut map = MapDeserializer::new(pairs.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            RespValue::Attributed(_, value) => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            RespValue::Boolean(b) => visitor.visit_bool(b),
+            RespValue::Integer(0) => visitor.visit_bool(false),
+            RespValue::Integer(1) => visitor.visit_bool(true),
+            RespValue::Attributed(_, value) => value.deserialize_bool(visitor),
+            value => Err(value.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            RespValue::Double(d) => return visitor.visit_f64(d),
+            RespValue::Integer(i) => return visitor.visit_f64(i as f64),
+            _ => {}
+        }
+        match text(&self).map(str::parse) {
+            Some(Ok(v)) => visitor.visit_f64(v),
+            _ => match self {
+                RespValue::Attributed(_, value) => value.deserialize_f64(visitor),
+                value => Err(value.invalid_type(&visitor)),
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            RespValue::None => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            RespValue::Map(pairs) if pairs.len() == 1 => {
+                let (variant, value) = pairs.into_iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            RespValue::Attributed(_, value) => value.deserialize_enum(name, variants, visitor),
+            value if text(&value).is_some() => visitor.visit_enum(EnumDeserializer {
+                variant: value,
+                value: None,
+            }),
+            value => Err(value.invalid_type(&visitor)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Error> for RespValue {
+    type Deserializer = RespValue;
+
+    fn into_deserializer(self) -> RespValue {
+        self
+    }
+}
+
+/// Variant name of an enum, and its data unless it is a unit variant
+struct EnumDeserializer {
+    variant: RespValue,
+    value: Option<RespValue>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantDeserializer)> {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, VariantDeserializer { value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<RespValue>,
+}
+
+impl VariantDeserializer {
+    fn value(self) -> Result<RespValue> {
+        self.value
+            .ok_or_else(|| Error::Message("expected the data of an enum variant".to_string()))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(RespValue::None) => Ok(()),
+            Some(value) => Err(value.invalid_type(&"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.value()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.value()?, visitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for RespValue {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<RespValue, D::Error> {
+        deserializer.deserialize_any(RespValueVisitor)
+    }
+}
+
+struct RespValueVisitor;
+
+impl<'de> Visitor<'de> for RespValueVisitor {
+    type Value = RespValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a RESP value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<RespValue, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<RespValue, E> {
+        Ok(RespValue::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<RespValue, E> {
+        i64::try_from(v)
+            .map(RespValue::Integer)
+            .map_err(|_| E::custom(format!("{} is out of the integer range", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<RespValue, E> {
+        Ok(bulk(v.to_string()))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<RespValue, E> {
+        Ok(bulk(v))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<RespValue, E> {
+        Ok(bulk(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<RespValue, E> {
+        Ok(bulk(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<RespValue, E> {
+        Ok(bulk(v))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<RespValue, E> {
+        Ok(RespValue::None)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<RespValue, D::Error> {
+        RespValue::deserialize(deserializer)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<RespValue, E> {
+        Ok(RespValue::None)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<RespValue, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(RespValue::Array(values))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<RespValue, A::Error> {
+        let mut pairs = Vec::new();
+        while let Some(pair) = map.next_entry()? {
+            pairs.push(pair);
+        }
+        Ok(RespValue::Map(pairs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use ::serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::writer::{ProtocolVersion, WriteBuf};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Primary,
+        Replica { of: String, offset: u64 },
+        Sentinel(u16),
+        Unknown(String, Vec<u8>),
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Node {
+        id: String,
+        port: u16,
+        weight: f64,
+        tls: bool,
+        role: Role,
+        tags: Vec<String>,
+        parent: Option<Box<Node>>,
+        slots: BTreeMap<String, (i64, i64)>,
+        meta: (),
+    }
+
+    fn node(id: &str, role: Role) -> Node {
+        Node {
+            id: id.to_string(),
+            port: 6379,
+            weight: 0.5,
+            tls: false,
+            role,
+            tags: vec!["a".to_string(), "b c".to_string()],
+            parent: None,
+            slots: BTreeMap::from([("first".to_string(), (0, 5460))]),
+            meta: (),
+        }
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut child = node(
+            "child",
+            Role::Replica {
+                of: "root".to_string(),
+                offset: 1234,
+            },
+        );
+        child.parent = Some(Box::new(node("root", Role::Primary)));
+        let inputs = [
+            node("root", Role::Primary),
+            child,
+            node("sentinel", Role::Sentinel(26379)),
+            node("other", Role::Unknown("x".to_string(), vec![0, 0xff])),
+        ];
+
+        for input in inputs.iter() {
+            let mut buf = WriteBuf::new(Vec::new());
+            to_writer(
+                &mut RespWriter::with_protocol(&mut buf, ProtocolVersion::Resp3),
+                input,
+            )
+            .unwrap();
+
+            let mut parser = RespParser::new(buf.get());
+            let output: Node = from_parser(&mut parser).unwrap();
+            assert_eq!(&output, input);
+            assert!(parser.remaining().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_serde_encoding() {
+        let value = to_value(&node("n", Role::Sentinel(1))).unwrap();
+        let RespValue::Map(pairs) = value else {
+            panic!("struct serialized to {:?}", value);
+        };
+        let fields: Vec<_> = pairs
+            .iter()
+            .map(|(key, _)| String::try_from(key.clone()).unwrap())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "id", "port", "weight", "tls", "role", "tags", "parent", "slots", "meta"
+            ]
+        );
+        assert_eq!(pairs[1].1, RespValue::Integer(6379));
+        assert_eq!(pairs[2].1, RespValue::from("0.5"));
+        assert_eq!(pairs[3].1, RespValue::Integer(0));
+        assert_eq!(
+            pairs[4].1,
+            RespValue::Map(vec![(RespValue::from("Sentinel"), RespValue::Integer(1))])
+        );
+        assert_eq!(pairs[6].1, RespValue::None);
+
+        assert_eq!(
+            to_value(&Role::Primary).unwrap(),
+            RespValue::from("Primary")
+        );
+    }
+
+    #[test]
+    fn test_serde_errors() {
+        let inputs = [
+            to_value(&u64::MAX).map(|_| ()).unwrap_err(),
+            from_value::<u8>(RespValue::Integer(256))
+                .map(|_| ())
+                .unwrap_err(),
+            from_value::<bool>(RespValue::Integer(2))
+                .map(|_| ())
+                .unwrap_err(),
+            from_value::<Node>(RespValue::Array(vec![]))
+                .map(|_| ())
+                .unwrap_err(),
+            from_value::<i64>(RespValue::Error(RespError::err("no")))
+                .map(|_| ())
+                .unwrap_err(),
+            from_value::<Role>(RespValue::Integer(1))
+                .map(|_| ())
+                .unwrap_err(),
+            from_parser::<i64>(&mut RespParser::new(b":1"))
+                .map(|_| ())
+                .unwrap_err(),
+        ];
+        let expects = [
+            "18446744073709551615 is out of the integer range",
+            "invalid value: integer `256`, expected u8",
+            "invalid type: integer, expected a boolean",
+            "invalid length 0, expected struct Node with 9 elements",
+            "error reply: ERR no",
+            "invalid type: integer, expected enum Role",
+            "unexpected end of input at offset 2",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i].to_string(), expects[i]);
+        }
+    }
+}
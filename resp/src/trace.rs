@@ -0,0 +1,383 @@
+//! Annotated dumps of raw RESP for debugging, one line per protocol element
+//! with its offset, type, declared length and a preview of its payload,
+//! indented by nesting:
+//!
+//! ```text
+//!      0  array len=2
+//!      4    bulk len=3 "GET"
+//!     13    bulk len=3 "key"
+//! ```
+//!
+//! The input can hold several frames and end in a partial one. It isn't
+//! validated like the parser does, the dump goes on as far as it can make
+//! sense of the input and shows the rest in hex.
+
+use std::fmt::{self, Write};
+
+use crate::parser::{find_crlf, read_i64};
+
+/// Payload bytes shown before the preview is cut short
+const PREVIEW_LEN: usize = 64;
+
+/// Bytes of an undecodable region shown in hex
+const HEX_LEN: usize = 32;
+
+/// Formats `data` as a dump when displayed, so that it costs nothing unless
+/// it is actually logged
+pub struct Trace<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(data: &'a [u8]) -> Trace<'a> {
+        Trace { data }
+    }
+}
+
+/// An aggregate whose elements are still being dumped
+struct Open {
+    /// Elements left, `None` for a streamed aggregate ended by `.`, or a
+    /// streamed string ended by an empty chunk
+    remaining: Option<usize>,
+
+    /// Attributes belong to the value after them rather than counting as an
+    /// element of their own
+    attributes: bool,
+}
+
+impl fmt::Display for Trace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut open: Vec<Open> = Vec::new();
+        let mut pos = 0;
+        while pos < self.data.len() {
+            let rest = &self.data[pos..];
+            let tag = rest[0];
+            let Some(name) = tag_name(tag) else {
+                // Not the start of an element, so whatever was open can't
+                // be trusted to continue. Skip to the next line.
+                let len = find_crlf(rest).map_or(rest.len(), |i| i + 2);
+                line(f, pos, 0, "undecodable", None)?;
+                write_hex(f, &rest[..len])?;
+                open.clear();
+                pos += len;
+                continue;
+            };
+
+            let Some(line_len) = find_crlf(&rest[1..]) else {
+                line(f, pos, open.len(), name, None)?;
+                write_preview(f, &rest[1..])?;
+                return f.write_str(" (incomplete)\n");
+            };
+            let header = &rest[1..1 + line_len];
+            let header_len = line_len + 3;
+            let start = pos;
+
+            match tag {
+                b'$' | b'!' | b'=' | b';' => {
+                    if header == b"?" && tag == b'$' {
+                        line(f, pos, open.len(), name, Some("?"))?;
+                        f.write_char('\n')?;
+                        open.push(Open {
+                            remaining: None,
+                            attributes: false,
+                        });
+                        pos += header_len;
+                        continue;
+                    }
+                    let len = match read_i64(header) {
+                        Ok(-1) if tag == b'$' => {
+                            line(f, pos, open.len(), "null bulk", None)?;
+                            f.write_char('\n')?;
+                            pos += header_len;
+                            done(&mut open);
+                            continue;
+                        }
+                        Ok(len) if len >= 0 => len as usize,
+                        _ => {
+                            invalid_length(f, pos, open.len(), name, header)?;
+                            pos += header_len;
+                            continue;
+                        }
+                    };
+
+                    // The empty chunk ends a streamed string
+                    if tag == b';' && len == 0 {
+                        line(f, pos, open.len(), name, Some("0"))?;
+                        f.write_char('\n')?;
+                        pos += header_len;
+                        open.pop();
+                        done(&mut open);
+                        continue;
+                    }
+
+                    let payload = &rest[header_len..];
+                    line(f, pos, open.len(), name, Some(&len.to_string()))?;
+                    if payload.len() < len.saturating_add(2) {
+                        write_preview(f, &payload[..payload.len().min(len)])?;
+                        return writeln!(
+                            f,
+                            " (incomplete, {} of {} bytes)",
+                            payload.len().min(len),
+                            len
+                        );
+                    }
+                    write_preview(f, &payload[..len])?;
+                    if &payload[len..len + 2] == b"\r\n" {
+                        f.write_char('\n')?;
+                        pos += header_len + len + 2;
+                    } else {
+                        f.write_str(" (missing CRLF)\n")?;
+                        pos += header_len + len;
+                    }
+                    if tag != b';' {
+                        done(&mut open);
+                    }
+                }
+                b'*' | b'%' | b'~' | b'>' | b'|' => {
+                    pos += header_len;
+                    let pairs = tag == b'%' || tag == b'|';
+                    if header == b"?" {
+                        line(f, start, open.len(), name, Some("?"))?;
+                        f.write_char('\n')?;
+                        open.push(Open {
+                            remaining: None,
+                            attributes: tag == b'|',
+                        });
+                        continue;
+                    }
+                    let len = match read_i64(header) {
+                        Ok(-1) if tag == b'*' => {
+                            line(f, start, open.len(), "null array", None)?;
+                            f.write_char('\n')?;
+                            done(&mut open);
+                            continue;
+                        }
+                        Ok(len) if len >= 0 => len as usize,
+                        _ => {
+                            invalid_length(f, start, open.len(), name, header)?;
+                            continue;
+                        }
+                    };
+
+                    line(f, start, open.len(), name, Some(&len.to_string()))?;
+                    f.write_char('\n')?;
+                    let elements = if pairs { len.saturating_mul(2) } else { len };
+                    if elements > 0 {
+                        open.push(Open {
+                            remaining: Some(elements),
+                            attributes: tag == b'|',
+                        });
+                    } else if tag != b'|' {
+                        done(&mut open);
+                    }
+                }
+                b'.' => {
+                    let streamed = open.last().is_some_and(|open| open.remaining.is_none());
+                    if streamed {
+                        let attributes = open.pop().is_some_and(|open| open.attributes);
+                        line(f, pos, open.len(), name, None)?;
+                        f.write_char('\n')?;
+                        if !attributes {
+                            done(&mut open);
+                        }
+                    } else {
+                        line(f, pos, open.len(), name, None)?;
+                        f.write_str(" (not in a streamed aggregate)\n")?;
+                    }
+                    pos += header_len;
+                }
+                _ => {
+                    line(f, pos, open.len(), name, None)?;
+                    if !header.is_empty() || tag != b'_' {
+                        write_preview(f, header)?;
+                    }
+                    f.write_char('\n')?;
+                    pos += header_len;
+                    done(&mut open);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn tag_name(tag: u8) -> Option<&'static str> {
+    let name = match tag {
+        b'+' => "simple",
+        b'-' => "error",
+        b':' => "integer",
+        b',' => "double",
+        b'#' => "boolean",
+        b'_' => "null",
+        b'(' => "big number",
+        b'$' => "bulk",
+        b'!' => "blob error",
+        b'=' => "verbatim",
+        b';' => "chunk",
+        b'*' => "array",
+        b'%' => "map",
+        b'~' => "set",
+        b'>' => "push",
+        b'|' => "attributes",
+        b'.' => "end",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Counts a finished element against the aggregates it completes
+fn done(open: &mut Vec<Open>) {
+    while let Some(last) = open.last_mut() {
+        let Some(remaining) = &mut last.remaining else {
+            return;
+        };
+        *remaining -= 1;
+        if *remaining > 0 {
+            return;
+        }
+
+        let attributes = last.attributes;
+        open.pop();
+        if attributes {
+            return;
+        }
+    }
+}
+
+/// Writes the start of the line of an element, without a line break
+fn line(
+    f: &mut fmt::Formatter<'_>,
+    offset: usize,
+    depth: usize,
+    name: &str,
+    len: Option<&str>,
+) -> fmt::Result {
+    write!(
+        f,
+        "{:>6}  {:indent$}{}",
+        offset,
+        "",
+        name,
+        indent = depth * 2
+    )?;
+    if let Some(len) = len {
+        write!(f, " len={}", len)?;
+    }
+    Ok(())
+}
+
+fn invalid_length(
+    f: &mut fmt::Formatter<'_>,
+    offset: usize,
+    depth: usize,
+    name: &str,
+    header: &[u8],
+) -> fmt::Result {
+    line(f, offset, depth, name, None)?;
+    f.write_str(" invalid length")?;
+    write_hex(f, header)
+}
+
+/// Writes ` "payload"` with anything but printable ASCII escaped
+fn write_preview(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    f.write_str(" \"")?;
+    for &b in &data[..data.len().min(PREVIEW_LEN)] {
+        write!(f, "{}", b.escape_ascii())?;
+    }
+    f.write_char('"')?;
+    if data.len() > PREVIEW_LEN {
+        f.write_str("...")?;
+    }
+    Ok(())
+}
+
+/// Writes ` [0d 0a ...]` and ends the line
+fn write_hex(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    f.write_str(" [")?;
+    for (i, b) in data[..data.len().min(HEX_LEN)].iter().enumerate() {
+        if i > 0 {
+            f.write_char(' ')?;
+        }
+        write!(f, "{:02x}", b)?;
+    }
+    if data.len() > HEX_LEN {
+        f.write_str(" ...")?;
+    }
+    f.write_str("]\n")
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        let inputs: [&[u8]; 9] = [
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n+OK\r\n",
+            b"%1\r\n+a\r\n*?\r\n:1\r\n.\r\n_\r\n",
+            b"|1\r\n+ttl\r\n:3\r\n$1\r\nv\r\n",
+            b"$?\r\n;2\r\nab\r\n;0\r\n$-1\r\n",
+            b"*1\r\n$10\r\nabc",
+            b"$2\r\n\x00\xffxx+OK\r\n",
+            b"*x\r\n:1\r\n",
+            b"GET key\r\n+OK",
+            b"-ERR \"quoted\"\r\n.\r\n",
+        ];
+        let expects = [
+            "     0  array len=2\n\
+             \x20    4    bulk len=3 \"GET\"\n\
+             \x20   13    bulk len=3 \"key\"\n\
+             \x20   22  simple \"OK\"\n",
+            "     0  map len=1\n\
+             \x20    4    simple \"a\"\n\
+             \x20    8    array len=?\n\
+             \x20   12      integer \"1\"\n\
+             \x20   16    end\n\
+             \x20   19  null\n",
+            "     0  attributes len=1\n\
+             \x20    4    simple \"ttl\"\n\
+             \x20   10    integer \"3\"\n\
+             \x20   14  bulk len=1 \"v\"\n",
+            "     0  bulk len=?\n\
+             \x20    4    chunk len=2 \"ab\"\n\
+             \x20   12    chunk len=0\n\
+             \x20   16  null bulk\n",
+            "     0  array len=1\n\
+             \x20    4    bulk len=10 \"abc\" (incomplete, 3 of 10 bytes)\n",
+            "     0  bulk len=2 \"\\x00\\xff\" (missing CRLF)\n\
+             \x20    6  undecodable [78 78 2b 4f 4b 0d 0a]\n",
+            "     0  array invalid length [78]\n\
+             \x20    4  integer \"1\"\n",
+            "     0  undecodable [47 45 54 20 6b 65 79 0d 0a]\n\
+             \x20    9  simple \"OK\" (incomplete)\n",
+            "     0  error \"ERR \\\"quoted\\\"\"\n\
+             \x20   15  end (not in a streamed aggregate)\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(
+                Trace::new(inputs[i]).to_string(),
+                expects[i],
+                "{:?}",
+                inputs[i]
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_trace_any_input(data in prop::collection::vec(
+            prop_oneof![
+                Just(b'\r'), Just(b'\n'), Just(b'*'), Just(b'$'), Just(b';'), Just(b'.'),
+                Just(b'|'), Just(b'-'), Just(b'0'), Just(b'1'), Just(b'?'), any::<u8>(),
+            ],
+            0..64,
+        )) {
+            let _ = Trace::new(&data).to_string();
+        }
+    }
+}
@@ -1,12 +1,28 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter,
+    str::{self, Utf8Error},
+};
+
+use bytes::Bytes;
+
 use crate::{
     parser::{ParseError, ParseErrorKind, ParseResult, RespParser},
-    writer::{RespWriter, WriteBuf, WriteResult},
+    writer::{MIN_SHARED_LEN, OutBuf, ProtocolVersion, RespWriter, WriteBuf, WriteResult},
 };
 
 // ===========================================================
 // RespReadable, SimpleRespReadable, RespWritable, SimpleRespWritable
 // ===========================================================
 
+/// `#[derive(RespReadable, RespWritable)]` for structs, see the
+/// `resp-derive` crate
+#[cfg(feature = "derive")]
+pub use resp_derive::{RespReadable, RespWritable};
+
 pub trait RespReadable<'a>: Sized {
     fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self>;
 
@@ -21,13 +37,16 @@ pub trait SimpleRespReadable<'a>: Sized {
 
 impl<'a, T: SimpleRespReadable<'a>> RespReadable<'a> for T {
     fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
-        let tag = parser.read_bytes(1)?[0];
-        if !Self::can_parse(tag) {
-            return Err(ParseError::new(ParseErrorKind::InvalidTag { tag }));
+        if let Some(&tag) = parser.peek_first() {
+            if !Self::can_parse(tag) {
+                return Err(parser.error(ParseErrorKind::InvalidTag { tag }));
+            }
         }
+        parser.read_tag()?;
+        let start = parser.consumed();
         let data = parser.read_line()?;
 
-        Self::parse_raw(data)
+        Self::parse_raw(data).map_err(|err| err.at(start))
     }
 
     fn can_parse(tag: u8) -> bool {
@@ -36,26 +55,46 @@ impl<'a, T: SimpleRespReadable<'a>> RespReadable<'a> for T {
 }
 
 pub trait RespWritable: Sized {
-    fn write(&self, writer: &mut RespWriter<'_>) -> WriteResult;
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult;
+
+    /// Number of bytes `write` produces for `protocol`, which
+    /// `RespWriter::write_value` reserves in one go. Types that can't tell
+    /// cheaply return 0 and have the buffer grow as they are written.
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        0
+    }
 }
 
 pub trait SimpleRespWritable: Sized {
     const TAG: u8;
 
-    fn write_raw(&self, buf: &mut WriteBuf) -> WriteResult;
+    fn write_raw<B: OutBuf>(&self, buf: &mut WriteBuf<B>) -> WriteResult;
+
+    /// Number of bytes `write_raw` produces
+    fn raw_len(&self) -> usize;
 }
 
 impl<T> RespWritable for T
 where
     T: SimpleRespWritable,
 {
-    fn write(&self, writer: &mut RespWriter<'_>) -> WriteResult {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
         writer.write_u8(Self::TAG)?;
         self.write_raw(writer.buffer())?;
         writer.write_crlf()?;
 
         Ok(())
     }
+
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        self.raw_len() + 3
+    }
+}
+
+/// Length of the header of an aggregate or bulk string of `len`, from its
+/// tag to its CRLF
+pub(crate) fn header_len(len: usize) -> usize {
+    (len as i64).raw_len() + 3
 }
 
 // ===========================================================
@@ -72,441 +111,4364 @@ impl<'a> SimpleRespReadable<'a> for String {
     }
 }
 
+/// A simple or error string borrowed from the input
+impl<'a> SimpleRespReadable<'a> for &'a str {
+    const TAGS: &'a [u8] = b"+-";
+
+    fn parse_raw(data: &'a [u8]) -> ParseResult<Self> {
+        str::from_utf8(data).map_err(|_| ParseError::new(ParseErrorKind::InvalidUtf8Data))
+    }
+}
+
+/// Simple and error strings end at the first line break, so CR and LF are
+/// written as spaces, as Redis does. Otherwise a string holding client data
+/// could inject frames of its own.
 impl SimpleRespWritable for String {
     const TAG: u8 = b'+';
 
-    fn write_raw(&self, buf: &mut WriteBuf) -> WriteResult {
-        buf.push_bytes(self.as_bytes())
+    fn write_raw<B: OutBuf>(&self, buf: &mut WriteBuf<B>) -> WriteResult {
+        write_line(buf, self.as_bytes())
+    }
+
+    fn raw_len(&self) -> usize {
+        // Line breaks are replaced, not dropped
+        self.len()
     }
 }
 
+/// Writes `data` as the text of a simple or error string, with line breaks
+/// replaced by spaces
+pub(crate) fn write_line<B: OutBuf>(buf: &mut WriteBuf<B>, mut data: &[u8]) -> WriteResult {
+    while let Some(pos) = data.iter().position(|&b| b == b'\r' || b == b'\n') {
+        buf.push_bytes(&data[..pos])?;
+        buf.push_u8(b' ')?;
+        data = &data[pos + 1..];
+    }
+    buf.push_bytes(data)
+}
+
 // ===========================================================
-// Integer
+// RespError
 // ===========================================================
 
-// TODO: Use tuple structs to differentiate Length from Integers
+macro_rules! error_codes {
+    ($($(#[$doc:meta])* $variant:ident => $code:literal,)*) => {
+        /// Leading word of an error reply, which clients switch on to tell
+        /// errors apart without matching on the message
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum ErrorCode {
+            $($(#[$doc])* $variant,)*
 
-impl<'a> SimpleRespReadable<'a> for i64 {
-    const TAGS: &'a [u8] = b":*$";
+            /// Any other word, as it was received
+            Custom(String),
+        }
 
-    fn parse_raw(data: &'a [u8]) -> ParseResult<Self> {
-        crate::parser::read_i64(data)
-    }
+        impl ErrorCode {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(ErrorCode::$variant => $code,)*
+                    ErrorCode::Custom(code) => code,
+                }
+            }
+        }
+
+        /// Well-known codes get their own variant, anything else is kept
+        /// as a custom code
+        impl From<&str> for ErrorCode {
+            fn from(code: &str) -> ErrorCode {
+                match code {
+                    $($code => ErrorCode::$variant,)*
+                    code => ErrorCode::Custom(code.to_string()),
+                }
+            }
+        }
+    };
 }
 
-impl SimpleRespWritable for i64 {
-    const TAG: u8 = b':';
+error_codes! {
+    /// Generic error
+    Err => "ERR",
+    /// The key holds a value of another type than the command works on
+    WrongType => "WRONGTYPE",
+    NoAuth => "NOAUTH",
+    WrongPass => "WRONGPASS",
+    NoPerm => "NOPERM",
+    /// HELLO with a protocol version the server doesn't speak
+    NoProto => "NOPROTO",
+    /// Cluster redirection to the node owning the slot
+    Moved => "MOVED",
+    Ask => "ASK",
+    TryAgain => "TRYAGAIN",
+    ClusterDown => "CLUSTERDOWN",
+    CrossSlot => "CROSSSLOT",
+    /// The dataset is still being loaded
+    Loading => "LOADING",
+    /// A script or function is running
+    Busy => "BUSY",
+    BusyKey => "BUSYKEY",
+    /// XGROUP CREATE of a consumer group that already exists
+    BusyGroup => "BUSYGROUP",
+    NoGroup => "NOGROUP",
+    NoScript => "NOSCRIPT",
+    /// Write against a read-only replica
+    ReadOnly => "READONLY",
+    /// The transaction was discarded because of an earlier error
+    ExecAbort => "EXECABORT",
+    /// Out of memory
+    Oom => "OOM",
+    MasterDown => "MASTERDOWN",
+    NoReplicas => "NOREPLICAS",
+}
 
-    fn write_raw(&self, buf: &mut WriteBuf) -> WriteResult {
-        self.to_string().write_raw(buf)
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-// ===========================================================
-// BulkString
-// ===========================================================
+/// Error reply, `-CODE message`. The code is the first word of the reply
+/// and the message the rest of it, after a single space.
+///
+/// A custom code containing a space doesn't survive being written and
+/// parsed back, as the part after the space becomes part of the message.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RespError {
+    pub code: ErrorCode,
+    pub message: String,
+}
 
-// TODO: This is NOT a String, but rather byte array
+impl RespError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> RespError {
+        RespError {
+            code,
+            message: message.into(),
+        }
+    }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BulkString(String);
+    /// Generic `ERR` error
+    pub fn err(message: impl Into<String>) -> RespError {
+        RespError::new(ErrorCode::Err, message)
+    }
 
-impl BulkString {
-    pub fn new(s: String) -> BulkString {
-        BulkString(s)
+    pub fn wrong_type() -> RespError {
+        RespError::new(
+            ErrorCode::WrongType,
+            "Operation against a key holding the wrong kind of value",
+        )
     }
 
-    pub fn value(&self) -> &String {
-        &self.0
+    pub fn syntax() -> RespError {
+        RespError::err("syntax error")
     }
 
-    pub fn value_mut(&mut self) -> &mut String {
-        &mut self.0
+    /// An argument or a stored value is not a 64 bit signed integer
+    pub fn not_an_integer() -> RespError {
+        RespError::err("value is not an integer or out of range")
     }
-}
 
-impl<'a> RespReadable<'a> for BulkString {
-    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
-        match parser.peek_first() {
-            None => return Err(ParseError::new(ParseErrorKind::EmptyData)),
-            Some(tag) => {
-                if !Self::can_parse(*tag) {
-                    return Err(ParseError::new(ParseErrorKind::InvalidTag { tag: *tag }));
-                }
-            }
-        }
+    pub fn not_a_float() -> RespError {
+        RespError::err("value is not a valid float")
+    }
 
-        // Read length of the string
-        let length = i64::parse(parser)?;
-        if length < 0 {
-            return Err(ParseError::new(ParseErrorKind::InvalidLength {
-                len: length,
-            }));
-        }
-        // TODO: Check for max length
-        let length = length as usize;
+    pub fn wrong_arity(command: &str) -> RespError {
+        RespError::err(format!(
+            "wrong number of arguments for '{}' command",
+            command
+        ))
+    }
 
-        // Read the next line
-        let line = parser.read_line()?;
-        let line_len = line.len();
-        if line_len < length {
-            return Err(ParseError::new(ParseErrorKind::MissingData {
-                needed: length - line_len,
-            }));
-        }
+    /// A request that couldn't be read, for `reason`
+    pub fn protocol(reason: impl fmt::Display) -> RespError {
+        RespError::err(format!("Protocol error: {}", reason))
+    }
+}
 
-        // Truncate extra data
-        let s = crate::parser::read_str(&line[..length])?;
-        Ok(BulkString(s))
+/// Splits an error line into its code and message
+impl From<&str> for RespError {
+    fn from(line: &str) -> RespError {
+        let (code, message) = line.split_once(' ').unwrap_or((line, ""));
+        RespError::new(ErrorCode::from(code), message)
     }
+}
 
-    fn can_parse(tag: u8) -> bool {
-        tag == b'$'
+impl From<String> for RespError {
+    fn from(line: String) -> RespError {
+        RespError::from(line.as_str())
     }
 }
 
-impl RespWritable for BulkString {
-    fn write(&self, writer: &mut RespWriter<'_>) -> WriteResult {
-        // Tag + length
-        writer.write_u8(b'$')?;
-        (self.0.len() as i64).write_raw(writer.buffer())?;
-        writer.write_crlf()?;
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code.as_str())?;
+        if !self.message.is_empty() {
+            write!(f, " {}", self.message)?;
+        }
+        Ok(())
+    }
+}
 
-        // Value
-        self.0.write_raw(writer.buffer())?;
-        writer.write_crlf()?;
+impl std::error::Error for RespError {}
+
+impl<'a> SimpleRespReadable<'a> for RespError {
+    const TAGS: &'a [u8] = b"-";
+
+    fn parse_raw(data: &'a [u8]) -> ParseResult<Self> {
+        <&str>::parse_raw(data).map(RespError::from)
+    }
+}
+
+/// Line breaks are written as spaces, as in simple strings
+impl SimpleRespWritable for RespError {
+    const TAG: u8 = b'-';
 
+    fn write_raw<B: OutBuf>(&self, buf: &mut WriteBuf<B>) -> WriteResult {
+        write_line(buf, self.code.as_str().as_bytes())?;
+        if !self.message.is_empty() {
+            buf.push_u8(b' ')?;
+            write_line(buf, self.message.as_bytes())?;
+        }
         Ok(())
     }
+
+    fn raw_len(&self) -> usize {
+        match self.message.len() {
+            0 => self.code.as_str().len(),
+            len => self.code.as_str().len() + 1 + len,
+        }
+    }
 }
 
 // ===========================================================
-// Array
+// Integer
 // ===========================================================
 
-impl RespReadable<'_> for Vec<RespValue> {
-    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
-        let len = i64::parse(parser)?;
-        if len < 0 {
-            return Err(ParseError::new(ParseErrorKind::InvalidLength { len }));
-        }
+impl<'a> SimpleRespReadable<'a> for i64 {
+    const TAGS: &'a [u8] = b":";
+
+    fn parse_raw(data: &'a [u8]) -> ParseResult<Self> {
+        crate::parser::read_i64(data)
+    }
+}
+
+impl SimpleRespWritable for i64 {
+    const TAG: u8 = b':';
 
-        let mut vec = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            let value = RespValue::parse(parser)?;
-            vec.push(value);
+    fn write_raw<B: OutBuf>(&self, buf: &mut WriteBuf<B>) -> WriteResult {
+        // Formatted on the stack, the longest i64 has 20 characters
+        let mut digits = [0; 20];
+        let mut start = digits.len();
+        let mut n = self.unsigned_abs();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        if *self < 0 {
+            start -= 1;
+            digits[start] = b'-';
         }
 
-        Ok(vec)
+        buf.push_bytes(&digits[start..])
     }
 
-    fn can_parse(tag: u8) -> bool {
-        tag == b'*'
+    fn raw_len(&self) -> usize {
+        let digits = self.unsigned_abs().checked_ilog10().unwrap_or(0) as usize + 1;
+        digits + usize::from(*self < 0)
     }
 }
 
-impl RespWritable for Vec<RespValue> {
-    fn write(&self, writer: &mut RespWriter<'_>) -> WriteResult {
-        writer.write_u8(b'*')?;
-        self.len().to_string().write_raw(writer.buffer())?;
-        writer.write_crlf()?;
-
-        for value in self.iter() {
-            value.write(writer)?;
-        }
+/// Written as the integers 1 and 0, as Redis replies to commands such as
+/// EXISTS
+impl RespWritable for bool {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        i64::from(*self).write(writer)
+    }
 
-        Ok(())
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        4
     }
 }
 
-impl RespReadable<'_> for Vec<BulkString> {
-    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
-        let len = i64::parse(parser)?;
-        if len < 0 {
-            return Err(ParseError::new(ParseErrorKind::InvalidLength { len }));
+/// Counts and sizes. Those beyond the range of an integer are written as a
+/// big number, which RESP2 gets as a bulk string of its digits.
+impl RespWritable for u64 {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        match i64::try_from(*self) {
+            Ok(n) => n.write(writer),
+            Err(_) => write_big_number(writer, &self.to_string()),
         }
+    }
 
-        let mut vec = Vec::with_capacity(len as usize);
-        for _ in 0..len {
-            let value = BulkString::parse(parser)?;
-            vec.push(value);
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        match i64::try_from(*self) {
+            Ok(n) => n.encoded_len(protocol),
+            Err(_) => big_number_len(self.ilog10() as usize + 1, protocol),
         }
+    }
+}
 
-        Ok(vec)
+impl RespWritable for usize {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        (*self as u64).write(writer)
     }
 
-    fn can_parse(tag: u8) -> bool {
-        tag == b'*'
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        (*self as u64).encoded_len(protocol)
     }
 }
 
 // ===========================================================
-// RespValue
+// Big number
 // ===========================================================
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum RespValue {
-    /// Special case of $-1\r\n
-    None,
+/// Reads a RESP3 big number, an integer of any size, keeping its digits as
+/// received
+fn read_big_number<'a>(parser: &mut RespParser<'a>) -> ParseResult<&'a str> {
+    parser.read_tag()?;
+    let start = parser.consumed();
+    let line = parser.read_line()?;
+    let digits = match line {
+        [b'+' | b'-', digits @ ..] => digits,
+        digits => digits,
+    };
+    if digits.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::InvalidData).at(start));
+    }
+    if let Some(data) = digits.iter().find(|c| !c.is_ascii_digit()) {
+        return Err(ParseError::new(ParseErrorKind::InvalidIntegerData { data: *data }).at(start));
+    }
 
-    /// Simple String starting with `+`
-    Simple(String),
+    // Only ASCII is left by now
+    Ok(str::from_utf8(line).unwrap())
+}
 
-    /// Error String starting with `-`
-    Error(String),
+/// Writes the digits of a big number, as a bulk string in RESP2
+fn write_big_number<B: OutBuf>(writer: &mut RespWriter<'_, B>, digits: &str) -> WriteResult {
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => write_bulk(writer, digits.as_bytes()),
+        ProtocolVersion::Resp3 => {
+            writer.write_u8(b'(')?;
+            writer.buffer().push_bytes(digits.as_bytes())?;
+            writer.write_crlf()
+        }
+    }
+}
 
-    /// Signed 64 Bit Integer starting with `:`
-    Integer(i64),
+fn big_number_len(digits: usize, protocol: ProtocolVersion) -> usize {
+    match protocol {
+        ProtocolVersion::Resp2 => bulk_len(digits),
+        ProtocolVersion::Resp3 => digits + 3,
+    }
+}
 
-    /// Bulk String starting with `$`
-    Bulk(BulkString),
+// ===========================================================
+// Boolean and double
+// ===========================================================
 
-    /// Array of Values starting with `*`
-    Array(Vec<RespValue>),
+/// Reads a RESP3 boolean, `#t` or `#f`
+fn read_boolean(parser: &mut RespParser<'_>) -> ParseResult<bool> {
+    parser.read_tag()?;
+    let start = parser.consumed();
+    match parser.read_line()? {
+        b"t" => Ok(true),
+        b"f" => Ok(false),
+        _ => Err(ParseError::new(ParseErrorKind::InvalidData).at(start)),
+    }
 }
 
-impl RespReadable<'_> for RespValue {
-    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
-        match parser.peek_first() {
-            Some(b'+') => Ok(RespValue::Simple(String::parse(parser)?)),
-            Some(b'-') => Ok(RespValue::Error(String::parse(parser)?)),
-            Some(b':') => Ok(RespValue::Integer(i64::parse(parser)?)),
-            Some(b'$') => Ok(RespValue::Bulk(BulkString::parse(parser)?)),
-            Some(b'*') => Ok(RespValue::Array(Vec::<RespValue>::parse(parser)?)),
-            Some(tag) => Err(ParseError::new(ParseErrorKind::InvalidTag { tag: *tag })),
-            _ => Err(ParseError::new(ParseErrorKind::EmptyData)),
+/// Written as the integers 1 and 0 in RESP2, like `From<bool>` does
+fn write_boolean<B: OutBuf>(writer: &mut RespWriter<'_, B>, value: bool) -> WriteResult {
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => i64::from(value).write(writer),
+        ProtocolVersion::Resp3 => {
+            writer
+                .buffer()
+                .push_bytes(if value { b"#t" } else { b"#f" })?;
+            writer.write_crlf()
         }
     }
+}
 
-    fn can_parse(_tag: u8) -> bool {
-        true
+/// Reads a RESP3 double, including `inf`, `-inf` and `nan`
+fn read_double(parser: &mut RespParser<'_>) -> ParseResult<f64> {
+    parser.read_tag()?;
+    let start = parser.consumed();
+    let line = parser.read_line()?;
+    parse_double(line).ok_or_else(|| ParseError::new(ParseErrorKind::InvalidDouble).at(start))
+}
+
+pub(crate) fn parse_double(line: &[u8]) -> Option<f64> {
+    match line {
+        b"inf" | b"+inf" => return Some(f64::INFINITY),
+        b"-inf" => return Some(f64::NEG_INFINITY),
+        b"nan" | b"-nan" => return Some(f64::NAN),
+        _ => {}
+    }
+    // Rust also reads `infinity` and `NaN` in any case, which RESP3 doesn't
+    let numeric = |c: &u8| c.is_ascii_digit() || matches!(c, b'+' | b'-' | b'.' | b'e' | b'E');
+    if !line.iter().all(numeric) {
+        return None;
     }
+    str::from_utf8(line).ok()?.parse().ok()
 }
 
-impl RespWritable for RespValue {
-    fn write(&self, writer: &mut RespWriter<'_>) -> WriteResult {
-        match self {
-            RespValue::None => {
-                writer.write_u8(b'$')?;
-                writer.write_u8(b'-')?;
-                writer.write_u8(b'1')?;
-                writer.write_crlf()?;
+/// Longest text of a double, `-1.7976931348623157e308` and the like
+const MAX_DOUBLE_LEN: usize = 24;
 
-                Ok(())
-            }
-            RespValue::Simple(s) => Ok(s.write(writer)?),
-            RespValue::Error(e) => {
-                writer.write_u8(b'-')?;
-                e.write_raw(writer.buffer())?;
-                writer.write_crlf()?;
+/// Formats a double as the shortest text that reads back as the same value,
+/// switching to an exponent for very large and small values
+fn format_double(value: f64, buf: &mut [u8; MAX_DOUBLE_LEN]) -> &[u8] {
+    if value.is_nan() {
+        return b"nan";
+    }
+    let mut rest = &mut buf[..];
+    // Debug gives `inf` and `-inf` as RESP3 spells them
+    std::io::Write::write_fmt(&mut rest, format_args!("{:?}", value))
+        .expect("a double fits the buffer");
+    let len = MAX_DOUBLE_LEN - rest.len();
+    &buf[..len]
+}
 
-                Ok(())
-            }
-            RespValue::Integer(i) => Ok(i.write(writer)?),
-            RespValue::Bulk(bulk_string) => Ok(bulk_string.write(writer)?),
-            RespValue::Array(resp_values) => Ok(resp_values.write(writer)?),
+/// Written as a bulk string in RESP2, as Redis does
+fn write_double<B: OutBuf>(writer: &mut RespWriter<'_, B>, value: f64) -> WriteResult {
+    let mut buf = [0; MAX_DOUBLE_LEN];
+    let text = format_double(value, &mut buf);
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => write_bulk(writer, text),
+        ProtocolVersion::Resp3 => {
+            writer.write_u8(b',')?;
+            writer.buffer().push_bytes(text)?;
+            writer.write_crlf()
         }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+fn double_len(value: f64, protocol: ProtocolVersion) -> usize {
+    let len = format_double(value, &mut [0; MAX_DOUBLE_LEN]).len();
+    match protocol {
+        ProtocolVersion::Resp2 => bulk_len(len),
+        ProtocolVersion::Resp3 => len + 3,
+    }
+}
 
-    #[test]
-    fn test_parse_i64() {
-        let inputs = [
-            b":5\r\n".to_vec(),
-            b":1234\r\n".to_vec(),
-            b":32345678\r\n".to_vec(),
-            b":48713467133413751634\r\n".to_vec(),
-            b":48a71346713a\r\n".to_vec(),
-            b":48a\r\n".to_vec(),
-            b":48a".to_vec(),
-            b"48\r\n".to_vec(),
-            b"1234".to_vec(),
-            b":-5712346\r\n".to_vec(),
-            b":-1234567890123456789\r\n".to_vec(),
-            b":1234567890123456789\r\n".to_vec(),
-            b":12345678901234567890\r\n".to_vec(),
-            b":+1234567890123456789\r\n".to_vec(),
-            b":+12345678901234567890\r\n".to_vec(),
-            b":-12345678901234567890\r\n".to_vec(),
-        ];
-        let expects: &[ParseResult<i64>] = &[
-            Ok(5),
-            Ok(1234),
-            Ok(32345678),
-            Err(ParseError::new(ParseErrorKind::IntegerOverflow)),
-            Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
-                data: b'a',
-            })),
-            Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
-                data: b'a',
-            })),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'4' })),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'1' })),
-            Ok(-5712346),
-            Ok(-1234567890123456789),
-            Ok(1234567890123456789),
-            Err(ParseError::new(ParseErrorKind::IntegerOverflow)),
-            Ok(1234567890123456789),
-            Err(ParseError::new(ParseErrorKind::IntegerOverflow)),
-            Err(ParseError::new(ParseErrorKind::IntegerOverflow)),
-        ];
+// ===========================================================
+// BulkString
+// ===========================================================
 
-        assert_eq!(inputs.len(), expects.len());
-        for i in 0..inputs.len() {
-            let mut parser = RespParser::new(inputs[i].as_slice());
-            let val = i64::parse(&mut parser);
-            assert_eq!(val, expects[i]);
-        }
+/// Binary-safe string. The payload is an arbitrary byte array which is shared
+/// rather than copied when the value is cloned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BulkString(Bytes);
+
+impl BulkString {
+    pub fn new(value: impl Into<Bytes>) -> BulkString {
+        BulkString(value.into())
     }
 
-    #[test]
-    fn test_parse_simple() {
-        let inputs = [
-            b"+This is a simple string\r\n".to_vec(),
-            b"+This is another simple string that is longer than other simple string\r\n".to_vec(),
-            b"+GET\r\n".to_vec(),
-            b"Unknown tag\r\n".to_vec(),
-            b"-Unknown tag\r\n".to_vec(),
-            b"+Incomplete data".to_vec(),
-            b"+Incomplete data\r".to_vec(),
-        ];
-        let expects: &[ParseResult<String>] = &[
-            Ok("This is a simple string".to_string()),
-            Ok("This is another simple string that is longer than other simple string".to_string()),
-            Ok("GET".to_string()),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'U' })),
-            Ok("Unknown tag".to_string()),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-        ];
+    pub fn value(&self) -> &Bytes {
+        &self.0
+    }
 
-        assert_eq!(inputs.len(), expects.len());
-        for i in 0..inputs.len() {
-            let mut parser = RespParser::new(&inputs[i]);
-            let val = String::parse(&mut parser);
-            assert_eq!(val, expects[i]);
-        }
+    pub fn value_mut(&mut self) -> &mut Bytes {
+        &mut self.0
     }
 
-    #[test]
-    fn test_parse_error() {
-        let inputs = [
-            b"-This is a simple string\r\n".to_vec(),
-            b"-This is another simple string that is longer than other simple string\r\n".to_vec(),
-            b"-GET\r\n".to_vec(),
-            b"Unknown tag\r\n".to_vec(),
-            b"+Unknown tag\r\n".to_vec(),
-            b"-Incomplete data".to_vec(),
-            b"-Incomplete data\r".to_vec(),
-        ];
-        let expects: &[ParseResult<String>] = &[
-            Ok("This is a simple string".to_string()),
-            Ok("This is another simple string that is longer than other simple string".to_string()),
-            Ok("GET".to_string()),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'U' })),
-            Ok("Unknown tag".to_string()),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-        ];
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 
-        assert_eq!(inputs.len(), expects.len());
-        for i in 0..inputs.len() {
-            let mut parser = RespParser::new(&inputs[i]);
-            let val = String::parse(&mut parser);
-            assert_eq!(val, expects[i]);
-        }
+    pub fn into_bytes(self) -> Bytes {
+        self.0
     }
 
-    #[test]
-    fn test_parse_bulk() {
-        let inputs = [
-            b"$12\r\nHello, World\r\n".to_vec(),
-            b"$3\r\nGET\r\n".to_vec(),
-            b"$25\r\nAAAAAAAAAAAAAAAAAAAAAAAAA\r\n".to_vec(),
-            b"*3\r\nGET\r\n".to_vec(),
-            b"3\r\nGET\r\n".to_vec(),
-            b"$3\r\nGE\r\n".to_vec(),
+    /// The payload as text, for arguments such as numbers and cursors. Fails
+    /// if it isn't valid UTF-8, which nothing else about a bulk string
+    /// requires.
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self.0)
+    }
+
+    /// The payload as text for display, with invalid UTF-8 replaced
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+}
+
+impl AsRef<[u8]> for BulkString {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&str> for BulkString {
+    fn from(value: &str) -> BulkString {
+        BulkString::new(value.to_string())
+    }
+}
+
+impl From<String> for BulkString {
+    fn from(value: String) -> BulkString {
+        BulkString::new(value)
+    }
+}
+
+impl From<Vec<u8>> for BulkString {
+    fn from(value: Vec<u8>) -> BulkString {
+        BulkString::new(value)
+    }
+}
+
+impl From<&[u8]> for BulkString {
+    fn from(value: &[u8]) -> BulkString {
+        BulkString(Bytes::copy_from_slice(value))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for BulkString {
+    fn from(value: &[u8; N]) -> BulkString {
+        BulkString(Bytes::copy_from_slice(value))
+    }
+}
+
+/// The decimal text of the integer, as commands take numbers
+impl From<i64> for BulkString {
+    fn from(value: i64) -> BulkString {
+        BulkString::new(value.to_string())
+    }
+}
+
+impl From<Bytes> for BulkString {
+    fn from(value: Bytes) -> BulkString {
+        BulkString(value)
+    }
+}
+
+impl<'a> RespReadable<'a> for BulkString {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        match read_bulk(parser)? {
+            Some(data) => Ok(BulkString(Bytes::copy_from_slice(data))),
+            None => read_streamed_bulk(parser),
+        }
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'$'
+    }
+}
+
+/// Payload of a bulk string, borrowed from the input. A streamed bulk string
+/// arrives in chunks and has no contiguous payload to borrow, so it is
+/// rejected.
+impl<'a> RespReadable<'a> for &'a [u8] {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        match read_bulk(parser)? {
+            Some(data) => Ok(data),
+            None => Err(parser.error(ParseErrorKind::InvalidData)),
+        }
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'$'
+    }
+}
+
+/// Length in the header of a bulk string or aggregate, such as the 5 of
+/// `*5\r\n`. Headers aren't integers, so they are read by the caller that
+/// expects one, which knows the tag of the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Length(pub usize);
+
+impl Length {
+    /// Reads the header of a value starting with `tag`, e.g. `*` or `$`.
+    /// Returns `None` for the `?` length of a RESP3 streamed value. Negative
+    /// lengths are refused, the null ones are handled before, and so are
+    /// lengths above the limit of the parser for the type.
+    pub fn parse(parser: &mut RespParser<'_>, tag: u8) -> ParseResult<Option<Length>> {
+        if let Some(&found) = parser.peek_first() {
+            if found != tag {
+                return Err(parser.error(ParseErrorKind::InvalidTag { tag: found }));
+            }
+        }
+
+        parser.read_tag()?;
+        let start = parser.consumed();
+        let line = parser.read_line()?;
+        if line == b"?" {
+            return Ok(None);
+        }
+        let len = read_len(line).map_err(|err| err.at(start))?;
+
+        let config = parser.config();
+        let kind = if tag == b'$' {
+            let limit = config.max_bulk_len;
+            (len > limit).then_some(ParseErrorKind::BulkTooLong { len, limit })
+        } else {
+            let limit = config.max_array_len;
+            (len > limit).then_some(ParseErrorKind::ArrayTooLong { len, limit })
+        };
+        match kind {
+            Some(kind) => Err(ParseError::new(kind).at(start)),
+            None => Ok(Some(Length(len))),
+        }
+    }
+}
+
+/// Reads the digits of a length, refusing negative ones as invalid lengths
+fn read_len(line: &[u8]) -> ParseResult<usize> {
+    crate::parser::read_usize(line).map_err(|err| match err.into_kind() {
+        ParseErrorKind::NegativeInteger { value } => {
+            ParseError::new(ParseErrorKind::InvalidLength { len: value })
+        }
+        kind => ParseError::new(kind),
+    })
+}
+
+/// Reads the header and payload of a bulk string, or only the header if the
+/// string is streamed, in which case `None` is returned and the chunks come
+/// next
+fn read_bulk<'a>(parser: &mut RespParser<'a>) -> ParseResult<Option<&'a [u8]>> {
+    let Some(Length(length)) = Length::parse(parser, b'$')? else {
+        return Ok(None);
+    };
+
+    // The payload may contain anything, including CRLF, so it is read by
+    // length rather than by scanning for the line terminator
+    let data = parser.read_bytes(length)?;
+    parser.read_crlf()?;
+
+    Ok(Some(data))
+}
+
+/// Reads the chunks of a RESP3 streamed bulk string following its `$?`
+/// header. Each chunk is `;` with its length and payload, and `;0` ends the
+/// string. The chunks are joined into a single string of at most
+/// `max_streamed_len` bytes.
+fn read_streamed_bulk(parser: &mut RespParser<'_>) -> ParseResult<BulkString> {
+    let mut data = Vec::new();
+    loop {
+        if let Some(&tag) = parser.peek_first() {
+            if tag != b';' {
+                return Err(parser.error(ParseErrorKind::InvalidChunk { tag }));
+            }
+        }
+        parser.read_tag()?;
+        let start = parser.consumed();
+        let len = read_len(parser.read_line()?).map_err(|err| err.at(start))?;
+        if len == 0 {
+            return Ok(BulkString(data.into()));
+        }
+
+        if len > parser.max_streamed_len() - data.len() {
+            let limit = parser.max_streamed_len();
+            return Err(ParseError::new(ParseErrorKind::TooLong { limit }).at(start));
+        }
+        data.extend_from_slice(parser.read_bytes(len)?);
+        parser.read_crlf()?;
+    }
+}
+
+/// A bulk string that may be null, which is `$-1` in RESP2 and `_` in RESP3
+impl<'a> RespReadable<'a> for Option<BulkString> {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        match parser.peek_first() {
+            Some(b'_') => {
+                read_null(parser)?;
+                Ok(None)
+            }
+            Some(b'$') if parser.data.starts_with(b"$-1\r\n") => {
+                parser.read_bytes(5)?;
+                Ok(None)
+            }
+            _ => Ok(Some(BulkString::parse(parser)?)),
+        }
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'$' || tag == b'_'
+    }
+}
+
+/// A borrowed bulk string that may be null
+impl<'a> RespReadable<'a> for Option<&'a [u8]> {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        match parser.peek_first() {
+            Some(b'_') => {
+                read_null(parser)?;
+                Ok(None)
+            }
+            Some(b'$') if parser.data.starts_with(b"$-1\r\n") => {
+                parser.read_bytes(5)?;
+                Ok(None)
+            }
+            _ => Ok(Some(<&[u8]>::parse(parser)?)),
+        }
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'$' || tag == b'_'
+    }
+}
+
+/// Reads the RESP3 null, which has nothing between its tag and the CRLF
+pub(crate) fn read_null(parser: &mut RespParser<'_>) -> ParseResult<()> {
+    parser.read_tag()?;
+    let start = parser.consumed();
+    if !parser.read_line()?.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::InvalidData).at(start));
+    }
+
+    Ok(())
+}
+
+/// A value that may be missing, which is written as the null of the
+/// protocol
+impl<T: RespWritable> RespWritable for Option<T> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        match self {
+            Some(value) => value.write(writer),
+            None => write_null(writer),
+        }
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        match self {
+            Some(value) => value.encoded_len(protocol),
+            None => null_len(protocol),
+        }
+    }
+}
+
+/// Writes the null bulk string `$-1` in RESP2 and `_` in RESP3
+pub(crate) fn write_null<B: OutBuf>(writer: &mut RespWriter<'_, B>) -> WriteResult {
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => writer.buffer().push_bytes(b"$-1")?,
+        ProtocolVersion::Resp3 => writer.write_u8(b'_')?,
+    }
+    writer.write_crlf()
+}
+
+fn null_len(protocol: ProtocolVersion) -> usize {
+    match protocol {
+        ProtocolVersion::Resp2 => 5,
+        ProtocolVersion::Resp3 => 3,
+    }
+}
+
+/// Payloads of at least `MIN_SHARED_LEN` bytes are shared with the buffer
+/// rather than copied into it
+impl RespWritable for BulkString {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        if self.0.len() < MIN_SHARED_LEN {
+            return write_bulk(writer, &self.0);
+        }
+
+        write_bulk_header(writer, self.0.len())?;
+        writer.write_bytes_shared(self.0.clone())?;
+        writer.write_crlf()
+    }
+
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        bulk_len(self.0.len())
+    }
+}
+
+/// Text written as a bulk string, as `RespValue::from` makes of it. A
+/// `String` is written as a simple string instead.
+impl RespWritable for &str {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_bulk(writer, self.as_bytes())
+    }
+
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        bulk_len(self.len())
+    }
+}
+
+impl RespWritable for &[u8] {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_bulk(writer, self)
+    }
+
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        bulk_len(self.len())
+    }
+}
+
+impl RespWritable for Vec<u8> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_bulk(writer, self)
+    }
+
+    fn encoded_len(&self, _protocol: ProtocolVersion) -> usize {
+        bulk_len(self.len())
+    }
+}
+
+pub(crate) fn write_bulk<B: OutBuf>(writer: &mut RespWriter<'_, B>, data: &[u8]) -> WriteResult {
+    write_bulk_header(writer, data.len())?;
+    writer.buffer().push_bytes(data)?;
+    writer.write_crlf()
+}
+
+fn write_bulk_header<B: OutBuf>(writer: &mut RespWriter<'_, B>, len: usize) -> WriteResult {
+    writer.write_u8(b'$')?;
+    (len as i64).write_raw(writer.buffer())?;
+    writer.write_crlf()
+}
+
+/// Length of a bulk string with `len` bytes of payload
+fn bulk_len(len: usize) -> usize {
+    header_len(len) + len + 2
+}
+
+// ===========================================================
+// Aggregates
+// ===========================================================
+
+/// Parses an aggregate starting with `tag`: its length, then that many
+/// elements read by `element`
+fn parse_aggregate<'a, T>(
+    parser: &mut RespParser<'a>,
+    tag: u8,
+    mut element: impl FnMut(&mut RespParser<'a>) -> ParseResult<T>,
+) -> ParseResult<Vec<T>> {
+    let Some(Length(len)) = Length::parse(parser, tag)? else {
+        return parse_streamed_aggregate(parser, element);
+    };
+
+    // Every element takes up at least one byte of input, so a hostile
+    // header can't make the parser allocate more than the input could fill
+    let mut vec = Vec::with_capacity(len.min(parser.data.len()));
+    for _ in 0..len {
+        vec.push(element(parser)?);
+    }
+
+    Ok(vec)
+}
+
+/// Parses the elements of a RESP3 streamed aggregate following its `?`
+/// length, up to the `.` that ends it
+fn parse_streamed_aggregate<'a, T>(
+    parser: &mut RespParser<'a>,
+    mut element: impl FnMut(&mut RespParser<'a>) -> ParseResult<T>,
+) -> ParseResult<Vec<T>> {
+    let mut vec = Vec::new();
+    loop {
+        match parser.peek_first() {
+            Some(b'.') => {
+                read_end(parser)?;
+                return Ok(vec);
+            }
+            Some(_) => vec.push(element(parser)?),
+            None => return Err(parser.incomplete(None)),
+        }
+    }
+}
+
+/// Reads the `.` that ends a streamed aggregate
+fn read_end(parser: &mut RespParser<'_>) -> ParseResult<()> {
+    parser.read_tag()?;
+    let start = parser.consumed();
+    if !parser.read_line()?.is_empty() {
+        return Err(ParseError::new(ParseErrorKind::InvalidData).at(start));
+    }
+
+    Ok(())
+}
+
+/// Writes an aggregate that is sent with `tag` to RESP3 clients and as an
+/// array to RESP2 ones
+fn write_aggregate<B: OutBuf, T: RespWritable>(
+    writer: &mut RespWriter<'_, B>,
+    tag: u8,
+    values: &[T],
+) -> WriteResult {
+    write_aggregate_header(writer, tag, values.len())?;
+    for value in values.iter() {
+        value.write(writer)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the header of an aggregate of `len` elements, which RESP2 only
+/// has arrays for
+pub(crate) fn write_aggregate_header<B: OutBuf>(
+    writer: &mut RespWriter<'_, B>,
+    tag: u8,
+    len: usize,
+) -> WriteResult {
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => writer.write_u8(b'*')?,
+        ProtocolVersion::Resp3 => writer.write_u8(tag)?,
+    }
+    (len as i64).write_raw(writer.buffer())?;
+    writer.write_crlf()
+}
+
+fn aggregate_len<T: RespWritable>(values: &[T], protocol: ProtocolVersion) -> usize {
+    let elements: usize = values.iter().map(|value| value.encoded_len(protocol)).sum();
+    header_len(values.len()) + elements
+}
+
+// ===========================================================
+// Array
+// ===========================================================
+
+impl RespReadable<'_> for Vec<RespValue> {
+    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
+        parse_aggregate(parser, b'*', RespValue::parse)
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'*'
+    }
+}
+
+/// An array that may be null. RESP2 has a null array `*-1` besides the null
+/// bulk string, which some replies send instead, but both are the same null
+/// value, `_` in RESP3.
+impl<'a> RespReadable<'a> for Option<Vec<RespValue>> {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        match parser.peek_first() {
+            Some(b'_') => {
+                read_null(parser)?;
+                Ok(None)
+            }
+            Some(b'*') if parser.data.starts_with(b"*-1\r\n") => {
+                parser.read_bytes(5)?;
+                Ok(None)
+            }
+            _ => Ok(Some(Vec::<RespValue>::parse(parser)?)),
+        }
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'*' || tag == b'_'
+    }
+}
+
+impl<T: RespWritable> RespWritable for Vec<T> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_aggregate(writer, b'*', self)
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        aggregate_len(self, protocol)
+    }
+}
+
+impl<T: RespWritable> RespWritable for &[T] {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_aggregate(writer, b'*', self)
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        aggregate_len(self, protocol)
+    }
+}
+
+impl RespReadable<'_> for Vec<BulkString> {
+    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
+        parse_aggregate(parser, b'*', BulkString::parse)
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'*'
+    }
+}
+
+/// Array of bulk strings borrowed from the input, the form of a command and
+/// its arguments
+impl<'a> RespReadable<'a> for Vec<&'a [u8]> {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        parse_aggregate(parser, b'*', <&[u8]>::parse)
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'*'
+    }
+}
+
+// ===========================================================
+// Map
+// ===========================================================
+
+/// Pairs of a RESP3 map, in the order they were received. Keys aren't
+/// deduplicated.
+impl RespReadable<'_> for Vec<(RespValue, RespValue)> {
+    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
+        parse_pairs(parser, b'%')
+    }
+
+    fn can_parse(tag: u8) -> bool {
+        tag == b'%'
+    }
+}
+
+impl RespWritable for Vec<(RespValue, RespValue)> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_map(writer, self.len(), entries(self))
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        map_len(self.len(), entries(self), protocol)
+    }
+}
+
+impl<K: RespWritable, V: RespWritable, S: BuildHasher> RespWritable for HashMap<K, V, S> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_map(writer, self.len(), self)
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        map_len(self.len(), self, protocol)
+    }
+}
+
+impl<K: RespWritable, V: RespWritable> RespWritable for BTreeMap<K, V> {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        write_map(writer, self.len(), self)
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        map_len(self.len(), self, protocol)
+    }
+}
+
+/// Parses an aggregate of key-value pairs starting with `tag`
+fn parse_pairs(parser: &mut RespParser<'_>, tag: u8) -> ParseResult<Vec<(RespValue, RespValue)>> {
+    parse_aggregate(parser, tag, |parser| {
+        Ok((RespValue::parse(parser)?, RespValue::parse(parser)?))
+    })
+}
+
+/// The pairs of a map as references to their keys and values, the form
+/// every map iterates in
+fn entries(pairs: &[(RespValue, RespValue)]) -> impl Iterator<Item = (&RespValue, &RespValue)> {
+    pairs.iter().map(|(key, value)| (key, value))
+}
+
+/// Writes a map of `len` pairs. RESP2 has no maps, the pairs are sent
+/// flattened into an array.
+fn write_map<'v, B, K, V>(
+    writer: &mut RespWriter<'_, B>,
+    len: usize,
+    pairs: impl IntoIterator<Item = (&'v K, &'v V)>,
+) -> WriteResult
+where
+    B: OutBuf,
+    K: RespWritable + 'v,
+    V: RespWritable + 'v,
+{
+    write_map_header(writer, len)?;
+    write_pairs(writer, pairs)
+}
+
+/// Writes the header of a map of `len` pairs, a flat array of keys and
+/// values in RESP2
+pub(crate) fn write_map_header<B: OutBuf>(
+    writer: &mut RespWriter<'_, B>,
+    len: usize,
+) -> WriteResult {
+    match writer.protocol() {
+        ProtocolVersion::Resp2 => {
+            writer.write_u8(b'*')?;
+            (len as i64 * 2).write_raw(writer.buffer())?;
+        }
+        ProtocolVersion::Resp3 => {
+            writer.write_u8(b'%')?;
+            (len as i64).write_raw(writer.buffer())?;
+        }
+    }
+    writer.write_crlf()
+}
+
+fn map_len<'v, K, V>(
+    len: usize,
+    pairs: impl IntoIterator<Item = (&'v K, &'v V)>,
+    protocol: ProtocolVersion,
+) -> usize
+where
+    K: RespWritable + 'v,
+    V: RespWritable + 'v,
+{
+    map_header_len(len, protocol) + pairs_len(pairs, protocol)
+}
+
+/// Length of the header `write_map_header` writes
+pub(crate) fn map_header_len(len: usize, protocol: ProtocolVersion) -> usize {
+    match protocol {
+        ProtocolVersion::Resp2 => header_len(len * 2),
+        ProtocolVersion::Resp3 => header_len(len),
+    }
+}
+
+fn write_pairs<'v, B, K, V>(
+    writer: &mut RespWriter<'_, B>,
+    pairs: impl IntoIterator<Item = (&'v K, &'v V)>,
+) -> WriteResult
+where
+    B: OutBuf,
+    K: RespWritable + 'v,
+    V: RespWritable + 'v,
+{
+    for (key, value) in pairs {
+        key.write(writer)?;
+        value.write(writer)?;
+    }
+
+    Ok(())
+}
+
+fn pairs_len<'v, K, V>(
+    pairs: impl IntoIterator<Item = (&'v K, &'v V)>,
+    protocol: ProtocolVersion,
+) -> usize
+where
+    K: RespWritable + 'v,
+    V: RespWritable + 'v,
+{
+    pairs
+        .into_iter()
+        .map(|(key, value)| key.encoded_len(protocol) + value.encoded_len(protocol))
+        .sum()
+}
+
+// ===========================================================
+// RespValue
+// ===========================================================
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RespValue {
+    /// Null, the null bulk string `$-1` in RESP2 and `_` in RESP3. The
+    /// RESP2 null array `*-1` parses into it too.
+    None,
+
+    /// Simple String starting with `+`
+    Simple(String),
+
+    /// Error String starting with `-`
+    Error(RespError),
+
+    /// Signed 64 Bit Integer starting with `:`
+    Integer(i64),
+
+    /// RESP3 boolean, `#t` or `#f`. RESP2 clients get the integers 1 and 0.
+    Boolean(bool),
+
+    /// RESP3 floating point number starting with `,`, which may be `inf`,
+    /// `-inf` or `nan`. RESP2 clients get it as a bulk string. NaN isn't
+    /// equal to itself, so values holding one aren't either.
+    Double(f64),
+
+    /// RESP3 integer of any size starting with `(`, holding its digits and
+    /// optional sign. RESP2 clients get it as a bulk string.
+    BigNumber(String),
+
+    /// Bulk String starting with `$`
+    Bulk(BulkString),
+
+    /// Array of Values starting with `*`
+    Array(Vec<RespValue>),
+
+    /// RESP3 map of key-value pairs starting with `%`, written as a flat
+    /// array to RESP2 clients
+    Map(Vec<(RespValue, RespValue)>),
+
+    /// RESP3 set starting with `~`, in the order the elements were
+    /// received. RESP2 clients get an array.
+    Set(Vec<RespValue>),
+
+    /// RESP3 out-of-band message starting with `>`, such as a pub/sub
+    /// message, which can arrive between a request and its reply. RESP2
+    /// clients get an array.
+    Push(Vec<RespValue>),
+
+    /// Value preceded by a RESP3 attribute block starting with `|`, whose
+    /// key-value pairs annotate the value without being part of it. The
+    /// attributes are left out for RESP2 clients.
+    Attributed(Vec<(RespValue, RespValue)>, Box<RespValue>),
+}
+
+impl RespReadable<'_> for RespValue {
+    fn parse(parser: &mut RespParser<'_>) -> ParseResult<Self> {
+        parse_tree(parser)
+    }
+
+    fn can_parse(_tag: u8) -> bool {
+        true
+    }
+}
+
+impl<'a> Tree<'a> for RespValue {
+    fn parse_scalar(parser: &mut RespParser<'a>, tag: u8) -> ParseResult<Self> {
+        match tag {
+            b'+' => Ok(RespValue::Simple(String::parse(parser)?)),
+            b'-' => Ok(RespValue::Error(RespError::parse(parser)?)),
+            b':' => Ok(RespValue::Integer(i64::parse(parser)?)),
+            b'#' => Ok(RespValue::Boolean(read_boolean(parser)?)),
+            b',' => Ok(RespValue::Double(read_double(parser)?)),
+            b'(' => Ok(RespValue::BigNumber(read_big_number(parser)?.to_string())),
+            // The null bulk string is the only negative length accepted
+            b'$' | b'_' => match Option::<BulkString>::parse(parser)? {
+                Some(bulk) => Ok(RespValue::Bulk(bulk)),
+                None => Ok(RespValue::None),
+            },
+            tag => Err(parser.error(ParseErrorKind::InvalidTag { tag })),
+        }
+    }
+
+    fn null() -> Self {
+        RespValue::None
+    }
+
+    fn values(tag: u8, values: Vec<Self>) -> Self {
+        match tag {
+            b'~' => RespValue::Set(values),
+            b'>' => RespValue::Push(values),
+            _ => RespValue::Array(values),
+        }
+    }
+
+    fn map(pairs: Vec<(Self, Self)>) -> Self {
+        RespValue::Map(pairs)
+    }
+
+    fn attributed(attributes: Vec<(Self, Self)>, value: Self) -> Self {
+        RespValue::Attributed(attributes, Box::new(value))
+    }
+}
+
+/// The recursive parser `RespValue::parse` was before it got an explicit
+/// stack. It is kept while the property tests and fuzz targets check that
+/// both give the same results, and is not meant to be used otherwise.
+#[doc(hidden)]
+pub fn parse_value_recursive(parser: &mut RespParser<'_>) -> ParseResult<RespValue> {
+    fn values(parser: &mut RespParser<'_>, tag: u8) -> ParseResult<Vec<RespValue>> {
+        parser.nested(|parser| parse_aggregate(parser, tag, parse_value_recursive))
+    }
+    fn pairs(parser: &mut RespParser<'_>, tag: u8) -> ParseResult<Vec<(RespValue, RespValue)>> {
+        parser.nested(|parser| {
+            parse_aggregate(parser, tag, |parser| {
+                Ok((
+                    parse_value_recursive(parser)?,
+                    parse_value_recursive(parser)?,
+                ))
+            })
+        })
+    }
+
+    match parser.peek_first() {
+        Some(b'*') => parser.nested(|parser| {
+            if parser.data.starts_with(b"*-1\r\n") {
+                parser.read_bytes(5)?;
+                return Ok(RespValue::None);
+            }
+            let values = parse_aggregate(parser, b'*', parse_value_recursive)?;
+            Ok(RespValue::Array(values))
+        }),
+        Some(b'%') => Ok(RespValue::Map(pairs(parser, b'%')?)),
+        Some(b'~') => Ok(RespValue::Set(values(parser, b'~')?)),
+        Some(b'>') => Ok(RespValue::Push(values(parser, b'>')?)),
+        Some(b'|') => {
+            let attributes = pairs(parser, b'|')?;
+            let value = parser.nested(parse_value_recursive)?;
+            Ok(RespValue::Attributed(attributes, Box::new(value)))
+        }
+        Some(&tag) => RespValue::parse_scalar(parser, tag),
+        None => Err(parser.incomplete(None)),
+    }
+}
+
+impl RespWritable for RespValue {
+    fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+        match self {
+            RespValue::None => write_null(writer),
+            RespValue::Simple(s) => Ok(s.write(writer)?),
+            RespValue::Error(e) => e.write(writer),
+            RespValue::Integer(i) => Ok(i.write(writer)?),
+            RespValue::Boolean(b) => write_boolean(writer, *b),
+            RespValue::Double(d) => write_double(writer, *d),
+            RespValue::BigNumber(digits) => write_big_number(writer, digits),
+            RespValue::Bulk(bulk_string) => Ok(bulk_string.write(writer)?),
+            RespValue::Array(resp_values) => Ok(resp_values.write(writer)?),
+            RespValue::Map(pairs) => Ok(pairs.write(writer)?),
+            RespValue::Set(values) => write_aggregate(writer, b'~', values),
+            RespValue::Push(values) => write_aggregate(writer, b'>', values),
+            RespValue::Attributed(attributes, value) => {
+                if writer.protocol() == ProtocolVersion::Resp3 {
+                    writer.write_u8(b'|')?;
+                    (attributes.len() as i64).write_raw(writer.buffer())?;
+                    writer.write_crlf()?;
+                    write_pairs(writer, entries(attributes))?;
+                }
+                value.write(writer)
+            }
+        }
+    }
+
+    fn encoded_len(&self, protocol: ProtocolVersion) -> usize {
+        match self {
+            RespValue::None => null_len(protocol),
+            RespValue::Simple(s) => s.raw_len() + 3,
+            RespValue::Error(e) => e.encoded_len(protocol),
+            RespValue::Integer(i) => i.encoded_len(protocol),
+            RespValue::Boolean(_) => 4,
+            RespValue::Double(d) => double_len(*d, protocol),
+            RespValue::BigNumber(digits) => big_number_len(digits.len(), protocol),
+            RespValue::Bulk(bulk_string) => bulk_string.encoded_len(protocol),
+            RespValue::Array(resp_values) => resp_values.encoded_len(protocol),
+            RespValue::Map(pairs) => pairs.encoded_len(protocol),
+            RespValue::Set(values) | RespValue::Push(values) => aggregate_len(values, protocol),
+            RespValue::Attributed(attributes, value) => {
+                let attributes_len = match protocol {
+                    ProtocolVersion::Resp2 => 0,
+                    ProtocolVersion::Resp3 => {
+                        header_len(attributes.len()) + pairs_len(entries(attributes), protocol)
+                    }
+                };
+                attributes_len + value.encoded_len(protocol)
+            }
+        }
+    }
+}
+
+// ===========================================================
+// RespValueRef
+// ===========================================================
+
+/// Borrowed counterpart of [`RespValue`], whose strings point into the
+/// parsed input instead of being copied out of it. Parsing a value without
+/// aggregates allocates nothing, which suits callers that only look at a
+/// value before dropping it. Streamed bulk strings are rejected, as their
+/// chunks can't be borrowed as one payload.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RespValueRef<'a> {
+    None,
+    Simple(&'a str),
+
+    /// The whole line of an error reply, split into its code and message by
+    /// converting it into a [`RespError`]
+    Error(&'a str),
+
+    Integer(i64),
+    Boolean(bool),
+    Double(f64),
+    BigNumber(&'a str),
+    Bulk(&'a [u8]),
+    Array(Vec<RespValueRef<'a>>),
+    Map(Vec<(RespValueRef<'a>, RespValueRef<'a>)>),
+    Set(Vec<RespValueRef<'a>>),
+    Push(Vec<RespValueRef<'a>>),
+    Attributed(
+        Vec<(RespValueRef<'a>, RespValueRef<'a>)>,
+        Box<RespValueRef<'a>>,
+    ),
+}
+
+impl<'a> RespValueRef<'a> {
+    /// Copies the value into a [`RespValue`], which outlives the input
+    pub fn to_owned(&self) -> RespValue {
+        fn values(values: &[RespValueRef<'_>]) -> Vec<RespValue> {
+            values.iter().map(RespValueRef::to_owned).collect()
+        }
+        fn pairs(pairs: &[(RespValueRef<'_>, RespValueRef<'_>)]) -> Vec<(RespValue, RespValue)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect()
+        }
+
+        match self {
+            RespValueRef::None => RespValue::None,
+            RespValueRef::Simple(s) => RespValue::Simple(s.to_string()),
+            RespValueRef::Error(e) => RespValue::Error(RespError::from(*e)),
+            RespValueRef::Integer(i) => RespValue::Integer(*i),
+            RespValueRef::Boolean(b) => RespValue::Boolean(*b),
+            RespValueRef::Double(d) => RespValue::Double(*d),
+            RespValueRef::BigNumber(digits) => RespValue::BigNumber(digits.to_string()),
+            RespValueRef::Bulk(data) => RespValue::Bulk(BulkString::new(data.to_vec())),
+            RespValueRef::Array(elements) => RespValue::Array(values(elements)),
+            RespValueRef::Map(elements) => RespValue::Map(pairs(elements)),
+            RespValueRef::Set(elements) => RespValue::Set(values(elements)),
+            RespValueRef::Push(elements) => RespValue::Push(values(elements)),
+            RespValueRef::Attributed(attributes, value) => {
+                RespValue::Attributed(pairs(attributes), Box::new(RespValueRef::to_owned(value)))
+            }
+        }
+    }
+}
+
+impl<'a> RespReadable<'a> for RespValueRef<'a> {
+    fn parse(parser: &mut RespParser<'a>) -> ParseResult<Self> {
+        parse_tree(parser)
+    }
+
+    fn can_parse(_tag: u8) -> bool {
+        true
+    }
+}
+
+impl<'a> Tree<'a> for RespValueRef<'a> {
+    fn parse_scalar(parser: &mut RespParser<'a>, tag: u8) -> ParseResult<Self> {
+        match tag {
+            b'+' => Ok(RespValueRef::Simple(<&str>::parse(parser)?)),
+            b'-' => Ok(RespValueRef::Error(<&str>::parse(parser)?)),
+            b':' => Ok(RespValueRef::Integer(i64::parse(parser)?)),
+            b'#' => Ok(RespValueRef::Boolean(read_boolean(parser)?)),
+            b',' => Ok(RespValueRef::Double(read_double(parser)?)),
+            b'(' => Ok(RespValueRef::BigNumber(read_big_number(parser)?)),
+            b'$' | b'_' => match Option::<&[u8]>::parse(parser)? {
+                Some(data) => Ok(RespValueRef::Bulk(data)),
+                None => Ok(RespValueRef::None),
+            },
+            tag => Err(parser.error(ParseErrorKind::InvalidTag { tag })),
+        }
+    }
+
+    fn null() -> Self {
+        RespValueRef::None
+    }
+
+    fn values(tag: u8, values: Vec<Self>) -> Self {
+        match tag {
+            b'~' => RespValueRef::Set(values),
+            b'>' => RespValueRef::Push(values),
+            _ => RespValueRef::Array(values),
+        }
+    }
+
+    fn map(pairs: Vec<(Self, Self)>) -> Self {
+        RespValueRef::Map(pairs)
+    }
+
+    fn attributed(attributes: Vec<(Self, Self)>, value: Self) -> Self {
+        RespValueRef::Attributed(attributes, Box::new(value))
+    }
+}
+
+// ===========================================================
+// Tree parsing
+// ===========================================================
+
+/// Values that nest, owned or borrowed, built by `parse_tree`
+trait Tree<'a>: Sized {
+    /// Parses a value that isn't an aggregate, starting with `tag`
+    fn parse_scalar(parser: &mut RespParser<'a>, tag: u8) -> ParseResult<Self>;
+
+    fn null() -> Self;
+
+    /// Array, set or push, depending on `tag`
+    fn values(tag: u8, values: Vec<Self>) -> Self;
+
+    fn map(pairs: Vec<(Self, Self)>) -> Self;
+
+    fn attributed(attributes: Vec<(Self, Self)>, value: Self) -> Self;
+}
+
+/// Aggregate whose elements are being parsed. `len` is `None` for a
+/// streamed aggregate, which is open until its `.`.
+enum Open<T> {
+    /// Array, set or push
+    Values {
+        tag: u8,
+        values: Vec<T>,
+        len: Option<usize>,
+    },
+
+    /// Map or attribute block, with the key whose value comes next
+    Pairs {
+        tag: u8,
+        pairs: Vec<(T, T)>,
+        key: Option<T>,
+        len: Option<usize>,
+    },
+
+    /// Value annotated by an attribute block
+    Attributed {
+        attributes: Vec<(T, T)>,
+        value: Option<T>,
+    },
+}
+
+impl<T> Open<T> {
+    fn is_full(&self) -> bool {
+        match self {
+            Open::Values { values, len, .. } => *len == Some(values.len()),
+            Open::Pairs {
+                pairs, key, len, ..
+            } => key.is_none() && *len == Some(pairs.len()),
+            Open::Attributed { value, .. } => value.is_some(),
+        }
+    }
+
+    /// Whether the aggregate is streamed and the next element may be the
+    /// `.` that ends it
+    fn may_end(&self) -> bool {
+        matches!(
+            self,
+            Open::Values { len: None, .. }
+                | Open::Pairs {
+                    len: None,
+                    key: None,
+                    ..
+                }
+        )
+    }
+
+    fn push(&mut self, element: T) {
+        match self {
+            Open::Values { values, .. } => values.push(element),
+            Open::Pairs { pairs, key, .. } => match key.take() {
+                Some(key) => pairs.push((key, element)),
+                None => *key = Some(element),
+            },
+            Open::Attributed { value, .. } => *value = Some(element),
+        }
+    }
+}
+
+/// Parses a value keeping the aggregates it is nested in on a stack of its
+/// own rather than on the call stack, which only the depth limit bounds
+fn parse_tree<'a, T: Tree<'a>>(parser: &mut RespParser<'a>) -> ParseResult<T> {
+    let depth = parser.depth;
+    let res = parse_open(parser, &mut Vec::new());
+    // Aggregates left open by an error are closed all at once
+    parser.depth = depth;
+    res
+}
+
+fn parse_open<'a, T: Tree<'a>>(
+    parser: &mut RespParser<'a>,
+    open: &mut Vec<Open<T>>,
+) -> ParseResult<T> {
+    loop {
+        let value = match open.last() {
+            Some(top) if top.is_full() => close(parser, open),
+            Some(top) if top.may_end() && parser.peek_first() == Some(&b'.') => {
+                read_end(parser)?;
+                close(parser, open)
+            }
+            _ => start(parser, open)?,
+        };
+
+        if let Some(value) = value {
+            match open.last_mut() {
+                Some(top) => top.push(value),
+                None => return Ok(value),
+            }
+        }
+    }
+}
+
+/// Parses the next value if it is a scalar. For an aggregate only the
+/// header is read, and the aggregate is opened.
+fn start<'a, T: Tree<'a>>(
+    parser: &mut RespParser<'a>,
+    open: &mut Vec<Open<T>>,
+) -> ParseResult<Option<T>> {
+    let Some(&tag) = parser.peek_first() else {
+        return Err(parser.incomplete(None));
+    };
+    if !matches!(tag, b'*' | b'%' | b'~' | b'>' | b'|') {
+        return T::parse_scalar(parser, tag).map(Some);
+    }
+
+    parser.enter()?;
+    if tag == b'*' && parser.data.starts_with(b"*-1\r\n") {
+        parser.read_bytes(5)?;
+        parser.leave();
+        return Ok(Some(T::null()));
+    }
+
+    let len = Length::parse(parser, tag)?.map(|Length(len)| len);
+    // Every element takes up at least one byte of input, so a hostile
+    // header can't make the parser allocate more than the input could fill
+    let capacity = len.unwrap_or(0).min(parser.data.len());
+    open.push(match tag {
+        b'%' | b'|' => Open::Pairs {
+            tag,
+            pairs: Vec::with_capacity(capacity),
+            key: None,
+            len,
+        },
+        _ => Open::Values {
+            tag,
+            values: Vec::with_capacity(capacity),
+            len,
+        },
+    });
+    Ok(None)
+}
+
+/// Closes the innermost aggregate, whose elements are all parsed. An
+/// attribute block stays open for the value it annotates.
+fn close<'a, T: Tree<'a>>(parser: &mut RespParser<'a>, open: &mut Vec<Open<T>>) -> Option<T> {
+    let value = match open.pop()? {
+        Open::Values { tag, values, .. } => T::values(tag, values),
+        Open::Pairs {
+            tag: b'|', pairs, ..
+        } => {
+            open.push(Open::Attributed {
+                attributes: pairs,
+                value: None,
+            });
+            return None;
+        }
+        Open::Pairs { pairs, .. } => T::map(pairs),
+        Open::Attributed { attributes, value } => T::attributed(attributes, value?),
+    };
+    parser.leave();
+    Some(value)
+}
+
+// ===========================================================
+// Conversions
+// ===========================================================
+
+/// A value that doesn't convert to the Rust type asked for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError {
+    target: &'static str,
+    found: &'static str,
+}
+
+impl ConversionError {
+    fn new(target: &'static str, value: &RespValue) -> ConversionError {
+        ConversionError {
+            target,
+            found: value.type_name(),
+        }
+    }
+
+    /// Type the value was converted to
+    pub fn target(&self) -> &'static str {
+        self.target
+    }
+
+    /// Kind of value found instead, as named by `RespValue::type_name`
+    pub fn found(&self) -> &'static str {
+        self.found
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "can't convert {} to {}", self.found, self.target)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl RespValue {
+    /// Name of the kind of value, for messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RespValue::None => "null",
+            RespValue::Simple(_) => "simple string",
+            RespValue::Error(_) => "error",
+            RespValue::Integer(_) => "integer",
+            RespValue::Boolean(_) => "boolean",
+            RespValue::Double(_) => "double",
+            RespValue::BigNumber(_) => "big number",
+            RespValue::Bulk(_) => "bulk string",
+            RespValue::Array(_) => "array",
+            RespValue::Map(_) => "map",
+            RespValue::Set(_) => "set",
+            RespValue::Push(_) => "push",
+            RespValue::Attributed(_, value) => value.type_name(),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.unattributed(), RespValue::None)
+    }
+
+    /// Whether the value is the `+OK` status reply
+    pub fn is_ok(&self) -> bool {
+        matches!(self.unattributed(), RespValue::Simple(s) if s == "OK")
+    }
+
+    /// The error of an error reply
+    pub fn as_error(&self) -> Option<&RespError> {
+        match self.unattributed() {
+            RespValue::Error(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The value without the attributes it may have, which conversions
+    /// look through
+    fn into_unattributed(self) -> RespValue {
+        match self {
+            RespValue::Attributed(_, value) => value.into_unattributed(),
+            value => value,
+        }
+    }
+
+    fn unattributed(&self) -> &RespValue {
+        match self {
+            RespValue::Attributed(_, value) => value.unattributed(),
+            value => value,
+        }
+    }
+}
+
+impl From<i64> for RespValue {
+    fn from(value: i64) -> RespValue {
+        RespValue::Integer(value)
+    }
+}
+
+/// RESP2 has no booleans, Redis replies with the integers 1 and 0 instead
+impl From<bool> for RespValue {
+    fn from(value: bool) -> RespValue {
+        RespValue::Integer(value as i64)
+    }
+}
+
+impl From<&str> for RespValue {
+    fn from(value: &str) -> RespValue {
+        RespValue::Bulk(value.into())
+    }
+}
+
+impl From<String> for RespValue {
+    fn from(value: String) -> RespValue {
+        RespValue::Bulk(value.into())
+    }
+}
+
+impl From<Vec<u8>> for RespValue {
+    fn from(value: Vec<u8>) -> RespValue {
+        RespValue::Bulk(value.into())
+    }
+}
+
+impl From<Bytes> for RespValue {
+    fn from(value: Bytes) -> RespValue {
+        RespValue::Bulk(value.into())
+    }
+}
+
+impl From<BulkString> for RespValue {
+    fn from(value: BulkString) -> RespValue {
+        RespValue::Bulk(value)
+    }
+}
+
+impl From<Vec<RespValue>> for RespValue {
+    fn from(value: Vec<RespValue>) -> RespValue {
+        RespValue::Array(value)
+    }
+}
+
+impl<T: Into<RespValue>> From<Option<T>> for RespValue {
+    fn from(value: Option<T>) -> RespValue {
+        value.map_or(RespValue::None, Into::into)
+    }
+}
+
+/// Integers, and strings holding one
+impl TryFrom<RespValue> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(value: RespValue) -> Result<i64, ConversionError> {
+        let value = value.into_unattributed();
+        let parsed = match &value {
+            RespValue::Integer(i) => return Ok(*i),
+            RespValue::Simple(s) => s.parse().ok(),
+            RespValue::Bulk(bulk) => bulk.as_str().ok().and_then(|s| s.parse().ok()),
+            _ => None,
+        };
+        parsed.ok_or_else(|| ConversionError::new("i64", &value))
+    }
+}
+
+/// Doubles, integers, and strings holding a number
+impl TryFrom<RespValue> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: RespValue) -> Result<f64, ConversionError> {
+        let value = value.into_unattributed();
+        let parsed = match &value {
+            RespValue::Double(d) => return Ok(*d),
+            RespValue::Integer(i) => return Ok(*i as f64),
+            RespValue::Simple(s) => s.parse().ok(),
+            RespValue::Bulk(bulk) => bulk.as_str().ok().and_then(|s| s.parse().ok()),
+            _ => None,
+        };
+        parsed.ok_or_else(|| ConversionError::new("f64", &value))
+    }
+}
+
+/// Simple strings, big numbers and bulk strings that are valid UTF-8
+impl TryFrom<RespValue> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: RespValue) -> Result<String, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Simple(s) | RespValue::BigNumber(s) => Ok(s),
+            RespValue::Bulk(bulk) => match String::from_utf8(bulk.into_bytes().into()) {
+                Ok(s) => Ok(s),
+                Err(_) => Err(ConversionError {
+                    target: "String",
+                    found: "bulk string",
+                }),
+            },
+            value => Err(ConversionError::new("String", &value)),
+        }
+    }
+}
+
+/// Simple and bulk strings
+impl TryFrom<RespValue> for Vec<u8> {
+    type Error = ConversionError;
+
+    fn try_from(value: RespValue) -> Result<Vec<u8>, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Simple(s) => Ok(s.into_bytes()),
+            RespValue::Bulk(bulk) => Ok(bulk.into_bytes().into()),
+            value => Err(ConversionError::new("Vec<u8>", &value)),
+        }
+    }
+}
+
+/// Arrays, sets and pushes
+impl TryFrom<RespValue> for Vec<RespValue> {
+    type Error = ConversionError;
+
+    fn try_from(value: RespValue) -> Result<Vec<RespValue>, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Array(values) | RespValue::Set(values) | RespValue::Push(values) => {
+                Ok(values)
+            }
+            value => Err(ConversionError::new("Vec<RespValue>", &value)),
+        }
+    }
+}
+
+/// Null converts to `None`, anything else as `T` does. A blanket impl over
+/// `T` would overlap the one core has for `Option<RespValue>`.
+macro_rules! try_from_nullable {
+    ($($ty:ty),*) => {
+        $(
+            impl TryFrom<RespValue> for Option<$ty> {
+                type Error = ConversionError;
+
+                fn try_from(value: RespValue) -> Result<Option<$ty>, ConversionError> {
+                    match value.into_unattributed() {
+                        RespValue::None => Ok(None),
+                        value => <$ty>::try_from(value).map(Some),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+try_from_nullable!(i64, f64, String, Vec<u8>, Vec<RespValue>);
+
+/// A Rust type a [`RespValue`] converts to, such as the reply of a command.
+/// Unlike `TryFrom`, it covers containers of convertible types, e.g. the
+/// reply of HGETALL converts to a `HashMap<String, String>`.
+pub trait FromResp: Sized {
+    fn from_resp(value: RespValue) -> Result<Self, ConversionError>;
+}
+
+/// A Rust type that converts to a [`RespValue`], the counterpart of
+/// [`FromResp`]
+pub trait IntoResp {
+    fn into_resp(self) -> RespValue;
+}
+
+impl FromResp for RespValue {
+    fn from_resp(value: RespValue) -> Result<RespValue, ConversionError> {
+        Ok(value)
+    }
+}
+
+/// The types `TryFrom` already converts to
+macro_rules! from_resp_via_try_from {
+    ($($ty:ty),*) => {
+        $(
+            impl FromResp for $ty {
+                fn from_resp(value: RespValue) -> Result<$ty, ConversionError> {
+                    <$ty>::try_from(value)
+                }
+            }
+        )*
+    };
+}
+
+from_resp_via_try_from!(i64, f64, String, Vec<u8>);
+
+/// RESP3 booleans, the integers 1 and 0 which stand for them in RESP2, and
+/// the `+OK` status reply as true
+impl FromResp for bool {
+    fn from_resp(value: RespValue) -> Result<bool, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Boolean(b) => Ok(b),
+            RespValue::Integer(1) => Ok(true),
+            RespValue::Integer(0) => Ok(false),
+            RespValue::Simple(s) if s == "OK" => Ok(true),
+            value => Err(ConversionError::new("bool", &value)),
+        }
+    }
+}
+
+/// Simple and bulk strings, without copying a bulk string
+impl FromResp for Bytes {
+    fn from_resp(value: RespValue) -> Result<Bytes, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Simple(s) => Ok(Bytes::from(s)),
+            RespValue::Bulk(bulk) => Ok(bulk.into_bytes()),
+            value => Err(ConversionError::new("Bytes", &value)),
+        }
+    }
+}
+
+/// Null converts to `None`, anything else as `T` does
+impl<T: FromResp> FromResp for Option<T> {
+    fn from_resp(value: RespValue) -> Result<Option<T>, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::None => Ok(None),
+            value => T::from_resp(value).map(Some),
+        }
+    }
+}
+
+/// Arrays, sets and pushes whose every element converts to `T`
+impl<T: FromResp> FromResp for Vec<T> {
+    fn from_resp(value: RespValue) -> Result<Vec<T>, ConversionError> {
+        match value.into_unattributed() {
+            RespValue::Array(values) | RespValue::Set(values) | RespValue::Push(values) => {
+                values.into_iter().map(T::from_resp).collect()
+            }
+            value => Err(ConversionError::new("Vec", &value)),
+        }
+    }
+}
+
+/// Maps, and the flat arrays of keys and values RESP2 sends them as
+impl<K, V, H> FromResp for HashMap<K, V, H>
+where
+    K: FromResp + Eq + Hash,
+    V: FromResp,
+    H: BuildHasher + Default,
+{
+    fn from_resp(value: RespValue) -> Result<HashMap<K, V, H>, ConversionError> {
+        let pair = |(key, value)| Ok((K::from_resp(key)?, V::from_resp(value)?));
+        match value.into_unattributed() {
+            RespValue::Map(pairs) => pairs.into_iter().map(pair).collect(),
+            RespValue::Array(values) if values.len() % 2 == 0 => {
+                let mut values = values.into_iter();
+                iter::from_fn(|| Some((values.next()?, values.next()?)))
+                    .map(pair)
+                    .collect()
+            }
+            value => Err(ConversionError::new("HashMap", &value)),
+        }
+    }
+}
+
+/// Arrays of exactly as many elements as the tuple has, converted in order
+macro_rules! from_resp_tuple {
+    ($($len:literal => ($($name:ident),+)),*) => {
+        $(
+            impl<$($name: FromResp),+> FromResp for ($($name,)+) {
+                fn from_resp(value: RespValue) -> Result<($($name,)+), ConversionError> {
+                    match value.into_unattributed() {
+                        RespValue::Array(values) if values.len() == $len => {
+                            let mut values = values.into_iter();
+                            Ok(($($name::from_resp(values.next().unwrap())?,)+))
+                        }
+                        value => Err(ConversionError::new(
+                            concat!("tuple of ", $len),
+                            &value,
+                        )),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_resp_tuple!(
+    1 => (A),
+    2 => (A, B),
+    3 => (A, B, C),
+    4 => (A, B, C, D),
+    5 => (A, B, C, D, E),
+    6 => (A, B, C, D, E, F)
+);
+
+/// The types `From` already converts from
+macro_rules! into_resp_via_from {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoResp for $ty {
+                fn into_resp(self) -> RespValue {
+                    RespValue::from(self)
+                }
+            }
+        )*
+    };
+}
+
+into_resp_via_from!(
+    RespValue,
+    i64,
+    bool,
+    &str,
+    String,
+    Vec<u8>,
+    Bytes,
+    BulkString
+);
+
+/// A RESP3 double, which RESP2 clients get as a bulk string
+impl IntoResp for f64 {
+    fn into_resp(self) -> RespValue {
+        RespValue::Double(self)
+    }
+}
+
+impl<T: IntoResp> IntoResp for Option<T> {
+    fn into_resp(self) -> RespValue {
+        self.map_or(RespValue::None, T::into_resp)
+    }
+}
+
+impl<T: IntoResp> IntoResp for Vec<T> {
+    fn into_resp(self) -> RespValue {
+        RespValue::Array(self.into_iter().map(T::into_resp).collect())
+    }
+}
+
+/// A map, which RESP2 clients get as a flat array of keys and values
+impl<K: IntoResp, V: IntoResp, H> IntoResp for HashMap<K, V, H> {
+    fn into_resp(self) -> RespValue {
+        RespValue::Map(
+            self.into_iter()
+                .map(|(key, value)| (key.into_resp(), value.into_resp()))
+                .collect(),
+        )
+    }
+}
+
+macro_rules! into_resp_tuple {
+    ($(($($name:ident),+)),*) => {
+        $(
+            impl<$($name: IntoResp),+> IntoResp for ($($name,)+) {
+                #[allow(non_snake_case)]
+                fn into_resp(self) -> RespValue {
+                    let ($($name,)+) = self;
+                    RespValue::Array(vec![$($name.into_resp()),+])
+                }
+            }
+        )*
+    };
+}
+
+into_resp_tuple!(
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+);
+
+// ===========================================================
+// Comparisons
+// ===========================================================
+
+// Shorthands for checking replies, e.g. `reply == "OK"` or `reply == 5`.
+// Like the conversions, they look through attributes.
+
+/// Simple and bulk strings with the same bytes, not errors
+impl PartialEq<str> for RespValue {
+    fn eq(&self, other: &str) -> bool {
+        match self.unattributed() {
+            RespValue::Simple(s) => s == other,
+            RespValue::Bulk(bulk) => bulk.0 == other.as_bytes(),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq<i64> for RespValue {
+    fn eq(&self, other: &i64) -> bool {
+        matches!(self.unattributed(), RespValue::Integer(i) if i == other)
+    }
+}
+
+/// RESP3 booleans, and the integers 1 and 0 which stand for them as in
+/// `From<bool>`
+impl PartialEq<bool> for RespValue {
+    fn eq(&self, other: &bool) -> bool {
+        match self.unattributed() {
+            RespValue::Boolean(b) => b == other,
+            _ => *self == *other as i64,
+        }
+    }
+}
+
+impl PartialEq<&str> for RespValue {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+macro_rules! reflect_eq {
+    ($($ty:ty),*) => {
+        $(
+            impl PartialEq<RespValue> for $ty {
+                fn eq(&self, other: &RespValue) -> bool {
+                    other == self
+                }
+            }
+        )*
+    };
+}
+
+reflect_eq!(str, &str, i64, bool);
+
+// ===========================================================
+// Commands
+// ===========================================================
+
+/// A value that isn't a command, a non-empty array of bulk strings. It
+/// displays as the message of the protocol error Redis replies with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandFormatError {
+    /// The value isn't an array, `found` is its type byte
+    NotAnArray { found: u8 },
+
+    /// The array is empty or null, which clients send as keep-alives
+    Empty,
+
+    /// The element at `index`, counting the name as 0, isn't a bulk string
+    NotBulk { index: usize, found: u8 },
+}
+
+impl fmt::Display for CommandFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandFormatError::NotAnArray { found } => {
+                write!(f, "expected '*', got '{}'", found.escape_ascii())
+            }
+            CommandFormatError::Empty => write!(f, "empty command"),
+            CommandFormatError::NotBulk { found, .. } => {
+                write!(f, "expected '$', got '{}'", found.escape_ascii())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandFormatError {}
+
+impl RespValue {
+    /// Splits a command into its name and arguments, which are all bulk
+    /// strings, without copying them
+    pub fn as_command(&self) -> Result<(&[u8], &[RespValue]), CommandFormatError> {
+        let elements = match self.unattributed() {
+            RespValue::Array(elements) => elements,
+            RespValue::None => return Err(CommandFormatError::Empty),
+            value => return Err(CommandFormatError::NotAnArray { found: value.tag() }),
+        };
+        check_command(elements, |element| match element {
+            RespValue::Bulk(bulk) => Ok(bulk.as_bytes()),
+            element => Err(element.tag()),
+        })
+    }
+
+    /// Like `as_command`, taking the name and arguments out of the value
+    pub fn into_command(self) -> Result<(BulkString, Vec<BulkString>), CommandFormatError> {
+        self.as_command()?;
+        let RespValue::Array(elements) = self.into_unattributed() else {
+            unreachable!("checked by as_command");
+        };
+
+        let mut args = elements.into_iter().map(|element| match element {
+            RespValue::Bulk(bulk) => bulk,
+            _ => unreachable!("checked by as_command"),
+        });
+        let name = args.next().ok_or(CommandFormatError::Empty)?;
+        Ok((name, args.collect()))
+    }
+
+    /// The byte the value starts with on the wire, in RESP3
+    fn tag(&self) -> u8 {
+        match self {
+            RespValue::None => b'_',
+            RespValue::Simple(_) => b'+',
+            RespValue::Error(_) => b'-',
+            RespValue::Integer(_) => b':',
+            RespValue::Boolean(_) => b'#',
+            RespValue::Double(_) => b',',
+            RespValue::BigNumber(_) => b'(',
+            RespValue::Bulk(_) => b'$',
+            RespValue::Array(_) => b'*',
+            RespValue::Map(_) => b'%',
+            RespValue::Set(_) => b'~',
+            RespValue::Push(_) => b'>',
+            RespValue::Attributed(..) => b'|',
+        }
+    }
+}
+
+impl<'a> RespValueRef<'a> {
+    /// Borrowed counterpart of `RespValue::as_command`, the name points into
+    /// the input
+    pub fn as_command(&self) -> Result<(&'a [u8], &[RespValueRef<'a>]), CommandFormatError> {
+        let mut value = self;
+        while let RespValueRef::Attributed(_, inner) = value {
+            value = inner;
+        }
+        let elements = match value {
+            RespValueRef::Array(elements) => elements,
+            RespValueRef::None => return Err(CommandFormatError::Empty),
+            value => return Err(CommandFormatError::NotAnArray { found: value.tag() }),
+        };
+        check_command(elements, |element| match element {
+            RespValueRef::Bulk(data) => Ok(*data),
+            element => Err(element.tag()),
+        })
+    }
+
+    /// The bytes of a bulk string, e.g. an argument of a command
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            RespValueRef::Bulk(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            RespValueRef::None => b'_',
+            RespValueRef::Simple(_) => b'+',
+            RespValueRef::Error(_) => b'-',
+            RespValueRef::Integer(_) => b':',
+            RespValueRef::Boolean(_) => b'#',
+            RespValueRef::Double(_) => b',',
+            RespValueRef::BigNumber(_) => b'(',
+            RespValueRef::Bulk(_) => b'$',
+            RespValueRef::Array(_) => b'*',
+            RespValueRef::Map(_) => b'%',
+            RespValueRef::Set(_) => b'~',
+            RespValueRef::Push(_) => b'>',
+            RespValueRef::Attributed(..) => b'|',
+        }
+    }
+}
+
+/// Checks that every element is a bulk string, which `bulk` returns the
+/// bytes of, or the type byte of the element if it isn't one
+fn check_command<'v, 'a, T>(
+    elements: &'v [T],
+    bulk: impl Fn(&'v T) -> Result<&'a [u8], u8>,
+) -> Result<(&'a [u8], &'v [T]), CommandFormatError> {
+    let Some((name, args)) = elements.split_first() else {
+        return Err(CommandFormatError::Empty);
+    };
+    let name = bulk(name).map_err(|found| CommandFormatError::NotBulk { index: 0, found })?;
+    for (i, arg) in args.iter().enumerate() {
+        bulk(arg).map_err(|found| CommandFormatError::NotBulk {
+            index: i + 1,
+            found,
+        })?;
+    }
+
+    Ok((name, args))
+}
+
+// ===========================================================
+// Display
+// ===========================================================
+
+/// Writes `data` quoted the way redis-cli shows strings, with anything that
+/// isn't printable ASCII escaped so that binary data can't garble a terminal
+fn write_quoted(f: &mut fmt::Formatter<'_>, data: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &c in data.iter() {
+        match c {
+            b'\\' => f.write_str("\\\\")?,
+            b'"' => f.write_str("\\\"")?,
+            b'\n' => f.write_str("\\n")?,
+            b'\r' => f.write_str("\\r")?,
+            b'\t' => f.write_str("\\t")?,
+            0x07 => f.write_str("\\a")?,
+            0x08 => f.write_str("\\b")?,
+            b' '..=b'~' => write!(f, "{}", c as char)?,
+            _ => write!(f, "\\x{:02x}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for BulkString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quoted(f, &self.0)
+    }
+}
+
+/// Writes the elements of an aggregate numbered one per line, followed by
+/// `mark`. Lines after the first are indented by `indent`, and nested
+/// aggregates line up with the element they are in.
+fn write_elements<'a, T: 'a>(
+    f: &mut fmt::Formatter<'_>,
+    elements: &'a [T],
+    mark: char,
+    indent: usize,
+    mut element: impl FnMut(&mut fmt::Formatter<'_>, &'a T, usize) -> fmt::Result,
+) -> fmt::Result {
+    let width = elements.len().to_string().len();
+    for (i, value) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, "\n{:indent$}", "")?;
+        }
+        write!(f, "{:>width$}{} ", i + 1, mark)?;
+        element(f, value, indent + width + 2)?;
+    }
+
+    Ok(())
+}
+
+impl RespValue {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        match self {
+            RespValue::None => f.write_str("(nil)"),
+            RespValue::Simple(s) => f.write_str(s),
+            RespValue::Error(e) => write!(f, "(error) {}", e),
+            RespValue::Integer(i) => write!(f, "(integer) {}", i),
+            RespValue::Boolean(b) => write!(f, "({})", b),
+            RespValue::Double(d) => {
+                let mut buf = [0; MAX_DOUBLE_LEN];
+                let text = format_double(*d, &mut buf);
+                write!(f, "(double) {}", text.escape_ascii())
+            }
+            RespValue::BigNumber(digits) => write!(f, "(big number) {}", digits),
+            RespValue::Bulk(bulk) => write_quoted(f, bulk.as_bytes()),
+            RespValue::Array(values) | RespValue::Push(values) if values.is_empty() => {
+                f.write_str("(empty array)")
+            }
+            RespValue::Set(values) if values.is_empty() => f.write_str("(empty set)"),
+            RespValue::Map(pairs) if pairs.is_empty() => f.write_str("(empty hash)"),
+            RespValue::Array(values) | RespValue::Push(values) => {
+                write_elements(f, values, ')', indent, |f, value, indent| {
+                    value.fmt_indented(f, indent)
+                })
+            }
+            RespValue::Set(values) => write_elements(f, values, '~', indent, |f, value, indent| {
+                value.fmt_indented(f, indent)
+            }),
+            RespValue::Map(pairs) => {
+                write_elements(f, pairs, '#', indent, |f, (key, value), indent| {
+                    key.fmt_indented(f, indent)?;
+                    f.write_str(" => ")?;
+                    value.fmt_indented(f, indent)
+                })
+            }
+            // Attributes are metadata, the value is shown without them
+            RespValue::Attributed(_, value) => value.fmt_indented(f, indent),
+        }
+    }
+}
+
+/// Renders the value the way redis-cli shows replies, e.g. `(integer) 5`,
+/// `(nil)` or a numbered list for an array
+impl fmt::Display for RespValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{MAX_BULK_LEN, MAX_NESTING_DEPTH, ParserConfig, kind_only, parse_complete};
+
+    #[test]
+    fn test_parse_i64() {
+        let inputs = [
+            b":5\r\n".to_vec(),
+            b":1234\r\n".to_vec(),
+            b":32345678\r\n".to_vec(),
+            b":48713467133413751634\r\n".to_vec(),
+            b":48a71346713a\r\n".to_vec(),
+            b":48a\r\n".to_vec(),
+            b":48a".to_vec(),
+            b"48\r\n".to_vec(),
+            b"1234".to_vec(),
+            b":-5712346\r\n".to_vec(),
+            b":-1234567890123456789\r\n".to_vec(),
+            b":1234567890123456789\r\n".to_vec(),
+            b":12345678901234567890\r\n".to_vec(),
+            b":+1234567890123456789\r\n".to_vec(),
+            b":+12345678901234567890\r\n".to_vec(),
+            b":-12345678901234567890\r\n".to_vec(),
+            // Length headers aren't integers
+            b"*5\r\n".to_vec(),
+            b"$3\r\n".to_vec(),
+            b":\r\n".to_vec(),
+            b":+\r\n".to_vec(),
+            b":-\r\n".to_vec(),
+            b":9223372036854775807\r\n".to_vec(),
+            b":9223372036854775806\r\n".to_vec(),
+            b":9223372036854775800\r\n".to_vec(),
+            b":9223372036854775808\r\n".to_vec(),
+            b":-9223372036854775808\r\n".to_vec(),
+            b":-9223372036854775807\r\n".to_vec(),
+            b":-9223372036854775809\r\n".to_vec(),
+            b":+9223372036854775807\r\n".to_vec(),
+            b":00000000000000000000042\r\n".to_vec(),
+        ];
+        let expects: &[Result<i64, ParseErrorKind>] = &[
+            Ok(5),
+            Ok(1234),
+            Ok(32345678),
+            Err(ParseErrorKind::IntegerOverflow),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'a' }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'a' }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidTag { tag: b'4' }),
+            Err(ParseErrorKind::InvalidTag { tag: b'1' }),
+            Ok(-5712346),
+            Ok(-1234567890123456789),
+            Ok(1234567890123456789),
+            Err(ParseErrorKind::IntegerOverflow),
+            Ok(1234567890123456789),
+            Err(ParseErrorKind::IntegerOverflow),
+            Err(ParseErrorKind::IntegerOverflow),
+            Err(ParseErrorKind::InvalidTag { tag: b'*' }),
+            Err(ParseErrorKind::InvalidTag { tag: b'$' }),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::EmptyInteger),
+            Ok(i64::MAX),
+            Ok(i64::MAX - 1),
+            Ok(i64::MAX - 7),
+            Err(ParseErrorKind::IntegerOverflow),
+            Ok(i64::MIN),
+            Ok(i64::MIN + 1),
+            Err(ParseErrorKind::IntegerOverflow),
+            Ok(i64::MAX),
+            Ok(42),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i].as_slice());
+            let val = kind_only(i64::parse(&mut parser));
+            assert_eq!(val, expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_length() {
+        let inputs: [(&[u8], u8); 13] = [
+            (b"*5\r\n", b'*'),
+            (b"$0\r\n", b'$'),
+            (b"%2\r\n", b'%'),
+            (b"~?\r\n", b'~'),
+            (b"*-1\r\n", b'*'),
+            (b":5\r\n", b'*'),
+            (b"$3\r\n", b'*'),
+            (b"*x\r\n", b'*'),
+            (b"*\r\n", b'*'),
+            (b"$\r\n", b'$'),
+            (b"%-\r\n", b'%'),
+            // Bulk strings that long are refused by default
+            (b"*9223372036854775808\r\n", b'*'),
+            (b"$18446744073709551616\r\n", b'$'),
+        ];
+        let expects = [
+            Ok(Some(Length(5))),
+            Ok(Some(Length(0))),
+            Ok(Some(Length(2))),
+            Ok(None),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+            Err(ParseErrorKind::InvalidTag { tag: b':' }),
+            Err(ParseErrorKind::InvalidTag { tag: b'$' }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'x' }),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::EmptyInteger),
+            Err(ParseErrorKind::EmptyInteger),
+            // Past i64::MAX, though no payload that long will ever arrive
+            Ok(Some(Length(i64::MAX as usize + 1))),
+            Err(ParseErrorKind::IntegerOverflow),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (input, tag) = inputs[i];
+            let mut parser = RespParser::new(input);
+            assert_eq!(kind_only(Length::parse(&mut parser, tag)), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_big_number() {
+        let inputs = [
+            b"(48713467133413751634\r\n".to_vec(),
+            b"(-12345678901234567890\r\n".to_vec(),
+            b"(+12345678901234567890\r\n".to_vec(),
+            b"(0\r\n".to_vec(),
+            b"(3492890328409238509324850943850943825024385\r\n".to_vec(),
+            b"(48a71346713a\r\n".to_vec(),
+            b"(1.5\r\n".to_vec(),
+            b"(\r\n".to_vec(),
+            b"(-\r\n".to_vec(),
+            b"(--1\r\n".to_vec(),
+            b"(123".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::BigNumber("48713467133413751634".to_string())),
+            Ok(RespValue::BigNumber("-12345678901234567890".to_string())),
+            Ok(RespValue::BigNumber("+12345678901234567890".to_string())),
+            Ok(RespValue::BigNumber("0".to_string())),
+            Ok(RespValue::BigNumber(
+                "3492890328409238509324850943850943825024385".to_string(),
+            )),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'a' }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'.' }),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'-' }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_boolean_and_double() {
+        let inputs = [
+            b"#t\r\n".to_vec(),
+            b"#f\r\n".to_vec(),
+            b"#x\r\n".to_vec(),
+            b"#\r\n".to_vec(),
+            b"#true\r\n".to_vec(),
+            b",3.25\r\n".to_vec(),
+            b",-1\r\n".to_vec(),
+            b",1.5e-3\r\n".to_vec(),
+            b",+2E10\r\n".to_vec(),
+            b",inf\r\n".to_vec(),
+            b",-inf\r\n".to_vec(),
+            b",infinity\r\n".to_vec(),
+            b",NaN\r\n".to_vec(),
+            b",1.2.3\r\n".to_vec(),
+            b",12a\r\n".to_vec(),
+            b",\r\n".to_vec(),
+            b",3.14".to_vec(),
+            b"*2\r\n,1\r\n#t\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::Boolean(true)),
+            Ok(RespValue::Boolean(false)),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::InvalidData),
+            Ok(RespValue::Double(3.25)),
+            Ok(RespValue::Double(-1.0)),
+            Ok(RespValue::Double(0.0015)),
+            Ok(RespValue::Double(2e10)),
+            Ok(RespValue::Double(f64::INFINITY)),
+            Ok(RespValue::Double(f64::NEG_INFINITY)),
+            Err(ParseErrorKind::InvalidDouble),
+            Err(ParseErrorKind::InvalidDouble),
+            Err(ParseErrorKind::InvalidDouble),
+            Err(ParseErrorKind::InvalidDouble),
+            Err(ParseErrorKind::InvalidDouble),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Ok(RespValue::Array(vec![
+                RespValue::Double(1.0),
+                RespValue::Boolean(true),
+            ])),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+
+            let mut parser = RespParser::new(&inputs[i]);
+            let val_ref = kind_only(RespValueRef::parse(&mut parser).map(|val| val.to_owned()));
+            assert_eq!(val_ref, expects[i]);
+        }
+
+        // NaN isn't equal to itself
+        for input in [&b",nan\r\n"[..], b",-nan\r\n"] {
+            let mut parser = RespParser::new(input);
+            let val = RespValue::parse(&mut parser);
+            assert!(matches!(val, Ok(RespValue::Double(d)) if d.is_nan()));
+        }
+    }
+
+    #[test]
+    fn test_parse_simple() {
+        let inputs = [
+            b"+This is a simple string\r\n".to_vec(),
+            b"+This is another simple string that is longer than other simple string\r\n".to_vec(),
+            b"+GET\r\n".to_vec(),
+            b"Unknown tag\r\n".to_vec(),
+            b"-Unknown tag\r\n".to_vec(),
+            b"+Incomplete data".to_vec(),
+            b"+Incomplete data\r".to_vec(),
+            b"+\xff\xfe\r\n".to_vec(),
+            b"+\xed\xa0\x80\r\n".to_vec(),
+            b"".to_vec(),
+            b"+".to_vec(),
+            b"+x".to_vec(),
+            b"+\r".to_vec(),
+            b"+\r\n".to_vec(),
+        ];
+        let expects: &[Result<String, ParseErrorKind>] = &[
+            Ok("This is a simple string".to_string()),
+            Ok("This is another simple string that is longer than other simple string".to_string()),
+            Ok("GET".to_string()),
+            Err(ParseErrorKind::InvalidTag { tag: b'U' }),
+            Ok("Unknown tag".to_string()),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Ok("".to_string()),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(String::parse(&mut parser));
+            assert_eq!(val, expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let inputs = [
+            b"-This is a simple string\r\n".to_vec(),
+            b"-This is another simple string that is longer than other simple string\r\n".to_vec(),
+            b"-GET\r\n".to_vec(),
+            b"Unknown tag\r\n".to_vec(),
+            b"+Unknown tag\r\n".to_vec(),
+            b"-Incomplete data".to_vec(),
+            b"-Incomplete data\r".to_vec(),
+            b"-ERR \xc3\x28\r\n".to_vec(),
+            b"-ERR \xed\xbf\xbf\r\n".to_vec(),
+        ];
+        let expects: &[Result<String, ParseErrorKind>] = &[
+            Ok("This is a simple string".to_string()),
+            Ok("This is another simple string that is longer than other simple string".to_string()),
+            Ok("GET".to_string()),
+            Err(ParseErrorKind::InvalidTag { tag: b'U' }),
+            Ok("Unknown tag".to_string()),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            Err(ParseErrorKind::InvalidUtf8Data),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(String::parse(&mut parser));
+            assert_eq!(val, expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_resp_error() {
+        let inputs = [
+            b"-ERR syntax error\r\n".to_vec(),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_vec(),
+            b"-BUSYGROUP Consumer Group name already exists\r\n".to_vec(),
+            b"-MOVED 3999 127.0.0.1:6381\r\n".to_vec(),
+            b"-MYCODE something  odd\r\n".to_vec(),
+            b"-ERR\r\n".to_vec(),
+            b"-\r\n".to_vec(),
+            b"+OK\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespError, ParseErrorKind>] = &[
+            Ok(RespError::syntax()),
+            Ok(RespError::wrong_type()),
+            Ok(RespError::new(
+                ErrorCode::BusyGroup,
+                "Consumer Group name already exists",
+            )),
+            Ok(RespError::new(ErrorCode::Moved, "3999 127.0.0.1:6381")),
+            Ok(RespError::new(
+                ErrorCode::Custom("MYCODE".to_string()),
+                "something  odd",
+            )),
+            Ok(RespError::new(ErrorCode::Err, "")),
+            Ok(RespError::new(ErrorCode::Custom(String::new()), "")),
+            Err(ParseErrorKind::InvalidTag { tag: b'+' }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let err = kind_only(RespError::parse(&mut parser));
+            assert_eq!(err, expects[i]);
+
+            // Errors are written back the way they were received
+            if let Ok(err) = err {
+                let mut write_buf = WriteBuf::new(Vec::new());
+                err.write(&mut RespWriter::new(&mut write_buf)).unwrap();
+                assert_eq!(write_buf.get(), &inputs[i][..]);
+                assert_eq!(err.encoded_len(ProtocolVersion::Resp2), inputs[i].len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_bulk_string_conversions() {
+        let inputs = [
+            BulkString::from("héllo"),
+            BulkString::from("héllo".to_string()),
+            BulkString::from(vec![0xff, 0x00]),
+            BulkString::from(Bytes::from_static(b"")),
+        ];
+        let expects: &[(&[u8], Option<&str>, &str)] = &[
+            ("héllo".as_bytes(), Some("héllo"), "héllo"),
+            ("héllo".as_bytes(), Some("héllo"), "héllo"),
+            (&[0xff, 0x00], None, "\u{fffd}\0"),
+            (b"", Some(""), ""),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            // The length written is the length in bytes
+            let mut buf = WriteBuf::new(Vec::new());
+            inputs[i].write(&mut RespWriter::new(&mut buf)).unwrap();
+            let expected = [
+                format!("${}\r\n", expects[i].0.len()).as_bytes(),
+                expects[i].0,
+                b"\r\n",
+            ]
+            .concat();
+            assert_eq!(buf.get(), &expected);
+
+            assert_eq!(inputs[i].as_bytes(), expects[i].0);
+            assert_eq!(inputs[i].as_str().ok(), expects[i].1);
+            assert_eq!(inputs[i].to_string_lossy(), expects[i].2);
+            assert_eq!(inputs[i].clone().into_bytes(), expects[i].0);
+        }
+    }
+
+    #[test]
+    fn test_parse_bulk() {
+        let inputs = [
+            b"$12\r\nHello, World\r\n".to_vec(),
+            b"$3\r\nGET\r\n".to_vec(),
+            b"$25\r\nAAAAAAAAAAAAAAAAAAAAAAAAA\r\n".to_vec(),
+            b"*3\r\nGET\r\n".to_vec(),
+            b"3\r\nGET\r\n".to_vec(),
+            b"$3\r\nGE\r\n".to_vec(),
             b"$3\r\nGET2\r\n".to_vec(),
             b"$3\r\nGET".to_vec(),
             b"$3GET\r\n".to_vec(),
             b"$-1\r\n".to_vec(),
-            b"$-1234\r\n".to_vec(),
+            b"$-1234\r\n".to_vec(),
+            b"$4\r\nA\r\nB\r\n".to_vec(),
+            b"$3\r\n\x00\xff\xfe\r\n".to_vec(),
+            b"$3\r\nA\rB\r\n".to_vec(),
+            b"$3\r\nA\nB\r\n".to_vec(),
+            b"$4\r\n\r\n\r\n\r\n".to_vec(),
+        ];
+        let expects: &[Result<BulkString, ParseErrorKind>] = &[
+            Ok(BulkString::new("Hello, World")),
+            Ok(BulkString::new("GET")),
+            Ok(BulkString::new("AAAAAAAAAAAAAAAAAAAAAAAAA")),
+            Err(ParseErrorKind::InvalidTag { tag: b'*' }),
+            Err(ParseErrorKind::InvalidTag { tag: b'3' }),
+            Err(ParseErrorKind::MissingCRLF),
+            Err(ParseErrorKind::ExtraData { extra: 1 }),
+            Err(ParseErrorKind::Incomplete { needed: Some(2) }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'G' }),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+            Err(ParseErrorKind::InvalidLength { len: -1234 }),
+            Ok(BulkString::new("A\r\nB")),
+            Ok(BulkString::new(&b"\x00\xff\xfe"[..])),
+            Ok(BulkString::new("A\rB")),
+            Ok(BulkString::new("A\nB")),
+            Ok(BulkString::new("\r\n\r\n")),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(BulkString::parse(&mut parser));
+            assert_eq!(val, expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_array() {
+        let inputs = [
+            b"*-1\r\n".to_vec(),
+            b"*1\r\n+Simple\r\n".to_vec(),
+            b"*1\r\n:-7364\r\n".to_vec(),
+            b"*1\r\n-Error\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET\r\nkey\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET_SomeExtraData\r\n$3\r\nkey\r\n".to_vec(),
+            b"*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue_someextradata\r\n".to_vec(),
+            b"*3\r\n$3$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue_someextradata\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET_SomeExtraDatakey\r\n".to_vec(),
+            b"*2\r\n$-1\r\n:1\r\n".to_vec(),
+            b"*1\r\n$-2\r\n".to_vec(),
+            b"*2\r\n$1\r\n\xff\r\n+\xff\r\n".to_vec(),
+            b"*2\r\n$1\r\n\xff\r\n-\xed\xa0\x80\r\n".to_vec(),
+            b"*1\r\n*1\r\n$2\r\n\xc3\x28\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::None),
+            Ok(RespValue::Array(vec![RespValue::Simple(
+                "Simple".to_string(),
+            )])),
+            Ok(RespValue::Array(vec![RespValue::Integer(-7364)])),
+            Ok(RespValue::Array(vec![RespValue::Error(RespError::from(
+                "Error",
+            ))])),
+            Err(ParseErrorKind::InvalidTag { tag: b'k' }),
+            Err(ParseErrorKind::ExtraData { extra: 14 }),
+            Err(ParseErrorKind::ExtraData { extra: 14 }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'$' }),
+            Err(ParseErrorKind::ExtraData { extra: 17 }),
+            Ok(RespValue::Array(vec![
+                RespValue::None,
+                RespValue::Integer(1),
+            ])),
+            Err(ParseErrorKind::InvalidLength { len: -2 }),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            // Bulk strings are bytes, whether or not they are valid UTF-8
+            Ok(RespValue::Array(vec![RespValue::Array(vec![
+                RespValue::Bulk(BulkString::new(&b"\xc3\x28"[..])),
+            ])])),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            println!("Case {}", i + 1);
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(val, expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_null() {
+        let inputs = [
+            b"_\r\n".to_vec(),
+            b"$-1\r\n".to_vec(),
+            b"$3\r\nGET\r\n".to_vec(),
+            b"$0\r\n\r\n".to_vec(),
+            b"_x\r\n".to_vec(),
+            b"_\r".to_vec(),
+            b"$-2\r\n".to_vec(),
+        ];
+        let expects: &[Result<Option<BulkString>, ParseErrorKind>] = &[
+            Ok(None),
+            Ok(None),
+            Ok(Some(BulkString::new("GET"))),
+            Ok(Some(BulkString::new(""))),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidLength { len: -2 }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(Option::<BulkString>::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+
+            // Values read the same way
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            let expected = expects[i].clone().map(|bulk| match bulk {
+                Some(bulk) => RespValue::Bulk(bulk),
+                None => RespValue::None,
+            });
+            assert_eq!(val, expected);
+        }
+
+        let inputs = [
+            b"*-1\r\n".to_vec(),
+            b"_\r\n".to_vec(),
+            b"*0\r\n".to_vec(),
+            b"*1\r\n*-1\r\n".to_vec(),
+            b"*-1\r".to_vec(),
+            b"*-2\r\n".to_vec(),
+        ];
+        let expects: &[Result<Option<Vec<RespValue>>, ParseErrorKind>] = &[
+            Ok(None),
+            Ok(None),
+            Ok(Some(vec![])),
+            Ok(Some(vec![RespValue::None])),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidLength { len: -2 }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(Option::<Vec<RespValue>>::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+
+        // Our own nulls parse back in either protocol
+        for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+            let value = RespValue::Array(vec![RespValue::None, RespValue::Integer(1)]);
+            let data = encode_with(&value, protocol);
+            assert_eq!(RespValue::parse(&mut RespParser::new(&data)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let simple = |s: &str| RespValue::Simple(s.to_string());
+        let inputs = [
+            b"%0\r\n".to_vec(),
+            b"%1\r\n+key\r\n:1\r\n".to_vec(),
+            b"%2\r\n$1\r\na\r\n:1\r\n$1\r\na\r\n:2\r\n".to_vec(),
+            b"%1\r\n+outer\r\n%1\r\n+inner\r\n*1\r\n$-1\r\n".to_vec(),
+            b"%1\r\n%0\r\n%0\r\n".to_vec(),
+            b"%-1\r\n".to_vec(),
+            b"%1\r\n+key\r\n".to_vec(),
+            b"%1\r\n+key\r\n:1\r\n+extra\r\n".to_vec(),
+            b"%x\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::Map(vec![])),
+            Ok(RespValue::Map(vec![(simple("key"), RespValue::Integer(1))])),
+            // Duplicate keys are kept
+            Ok(RespValue::Map(vec![
+                (RespValue::Bulk(BulkString::new("a")), RespValue::Integer(1)),
+                (RespValue::Bulk(BulkString::new("a")), RespValue::Integer(2)),
+            ])),
+            Ok(RespValue::Map(vec![(
+                simple("outer"),
+                RespValue::Map(vec![(
+                    simple("inner"),
+                    RespValue::Array(vec![RespValue::None]),
+                )]),
+            )])),
+            Ok(RespValue::Map(vec![(
+                RespValue::Map(vec![]),
+                RespValue::Map(vec![]),
+            )])),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Ok(RespValue::Map(vec![(simple("key"), RespValue::Integer(1))])),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'x' }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_set() {
+        let inputs = [
+            b"~0\r\n".to_vec(),
+            b"~2\r\n$1\r\na\r\n:1\r\n".to_vec(),
+            b"~2\r\n+a\r\n+a\r\n".to_vec(),
+            b"~1\r\n~1\r\n*0\r\n".to_vec(),
+            b"~-1\r\n".to_vec(),
+            b"~2\r\n+a\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::Set(vec![])),
+            Ok(RespValue::Set(vec![
+                RespValue::Bulk(BulkString::new("a")),
+                RespValue::Integer(1),
+            ])),
+            // Elements aren't deduplicated
+            Ok(RespValue::Set(vec![
+                RespValue::Simple("a".to_string()),
+                RespValue::Simple("a".to_string()),
+            ])),
+            Ok(RespValue::Set(vec![RespValue::Set(vec![
+                RespValue::Array(vec![]),
+            ])])),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_push() {
+        let message = RespValue::Push(vec![
+            RespValue::Bulk(BulkString::new("message")),
+            RespValue::Bulk(BulkString::new("news")),
+            RespValue::Bulk(BulkString::new("hello")),
+        ]);
+        let invalidate = RespValue::Push(vec![
+            RespValue::Bulk(BulkString::new("invalidate")),
+            RespValue::Array(vec![RespValue::Bulk(BulkString::new("key"))]),
+        ]);
+
+        // What a client reads for GET key and INCR n, with pushes arriving
+        // before, between and after the replies
+        let data = [
+            encode_with(&message, ProtocolVersion::Resp3),
+            b"$5\r\nvalue\r\n".to_vec(),
+            encode_with(&invalidate, ProtocolVersion::Resp3),
+            encode_with(&message, ProtocolVersion::Resp3),
+            b":1\r\n".to_vec(),
+            encode_with(&invalidate, ProtocolVersion::Resp3),
+        ]
+        .concat();
+
+        let mut replies = Vec::new();
+        let mut pushes = Vec::new();
+        for value in RespParser::new(&data).iter_values() {
+            match value.unwrap() {
+                push @ RespValue::Push(_) => pushes.push(push),
+                reply => replies.push(reply),
+            }
+        }
+
+        assert_eq!(
+            replies,
+            [
+                RespValue::Bulk(BulkString::new("value")),
+                RespValue::Integer(1),
+            ]
+        );
+        assert_eq!(
+            pushes,
+            [&message, &invalidate, &message, &invalidate].map(Clone::clone)
+        );
+
+        let inputs = [b">-1\r\n".to_vec(), b">1\r\n".to_vec()];
+        let expects = [
+            ParseErrorKind::InvalidLength { len: -1 },
+            ParseErrorKind::Incomplete { needed: None },
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            assert_eq!(
+                kind_only(RespValue::parse(&mut parser)),
+                Err(expects[i].clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_attribute() {
+        let simple = |s: &str| RespValue::Simple(s.to_string());
+        let inputs = [
+            b"|1\r\n+ttl\r\n:3600\r\n$5\r\nvalue\r\n".to_vec(),
+            b"*2\r\n:1\r\n|1\r\n+a\r\n:1\r\n:2\r\n".to_vec(),
+            b"|0\r\n:1\r\n".to_vec(),
+            b"|1\r\n+a\r\n:1\r\n|1\r\n+b\r\n:2\r\n_\r\n".to_vec(),
+            b"|1\r\n+a\r\n:1\r\n".to_vec(),
+            b"|-1\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(RespValue::Attributed(
+                vec![(simple("ttl"), RespValue::Integer(3600))],
+                Box::new(RespValue::Bulk(BulkString::new("value"))),
+            )),
+            // The attribute isn't an element of its own
+            Ok(RespValue::Array(vec![
+                RespValue::Integer(1),
+                RespValue::Attributed(
+                    vec![(simple("a"), RespValue::Integer(1))],
+                    Box::new(RespValue::Integer(2)),
+                ),
+            ])),
+            Ok(RespValue::Attributed(
+                vec![],
+                Box::new(RespValue::Integer(1)),
+            )),
+            Ok(RespValue::Attributed(
+                vec![(simple("a"), RespValue::Integer(1))],
+                Box::new(RespValue::Attributed(
+                    vec![(simple("b"), RespValue::Integer(2))],
+                    Box::new(RespValue::None),
+                )),
+            )),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_streamed() {
+        let bulk = |s: &str| RespValue::Bulk(BulkString::new(s.to_string()));
+        let inputs = [
+            b"$?\r\n;4\r\nHell\r\n;5\r\no, Wo\r\n;3\r\nrld\r\n;0\r\n".to_vec(),
+            b"$?\r\n;0\r\n".to_vec(),
+            b"$?\r\n;4\r\na\r\nb\r\n;0\r\n".to_vec(),
+            b"*?\r\n:1\r\n$?\r\n;1\r\na\r\n;0\r\n*?\r\n.\r\n.\r\n".to_vec(),
+            b"%?\r\n+a\r\n:1\r\n+b\r\n:2\r\n.\r\n".to_vec(),
+            b"~?\r\n:1\r\n.\r\n".to_vec(),
+            b"$?\r\n;4\r\nHell\r\n$1\r\no\r\n;0\r\n".to_vec(),
+            b"$?\r\n;-1\r\n".to_vec(),
+            b"$?\r\n;x\r\n".to_vec(),
+            b"$?\r\n;2\r\nabc\r\n".to_vec(),
+            b"$?\r\n;4\r\nHell\r\n".to_vec(),
+            b"*?\r\n:1\r\n".to_vec(),
+            b"*?\r\n.x\r\n".to_vec(),
+        ];
+        let expects: &[Result<RespValue, ParseErrorKind>] = &[
+            Ok(bulk("Hello, World")),
+            Ok(bulk("")),
+            Ok(bulk("a\r\nb")),
+            Ok(RespValue::Array(vec![
+                RespValue::Integer(1),
+                bulk("a"),
+                RespValue::Array(vec![]),
+            ])),
+            Ok(RespValue::Map(vec![
+                (RespValue::Simple("a".to_string()), RespValue::Integer(1)),
+                (RespValue::Simple("b".to_string()), RespValue::Integer(2)),
+            ])),
+            Ok(RespValue::Set(vec![RespValue::Integer(1)])),
+            Err(ParseErrorKind::InvalidChunk { tag: b'$' }),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'x' }),
+            Err(ParseErrorKind::ExtraData { extra: 1 }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::InvalidData),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+            if val.is_ok() {
+                assert!(parser.remaining().is_empty());
+            }
+        }
+
+        // The limit applies to the chunks together
+        let data = b"$?\r\n;4\r\nHell\r\n;4\r\no, W\r\n;0\r\n";
+        let mut parser = RespParser::new(data);
+        parser.set_max_streamed_len(8);
+        assert_eq!(RespValue::parse(&mut parser), Ok(bulk("Hello, W")));
+
+        let mut parser = RespParser::new(data);
+        parser.set_max_streamed_len(7);
+        assert_eq!(
+            kind_only(RespValue::parse(&mut parser)),
+            Err(ParseErrorKind::TooLong { limit: 7 })
+        );
+    }
+
+    #[test]
+    fn test_parser_config() {
+        let config = ParserConfig {
+            max_line_len: 4,
+            max_bulk_len: 4,
+            max_array_len: 2,
+            ..ParserConfig::default()
+        };
+        let bulk = |s: &str| RespValue::Bulk(BulkString::from(s));
+        let inputs: [&[u8]; 12] = [
+            b"+OKAYS\r\n",
+            b"+OK",
+            b"+OKAY\r",
+            // Refused without waiting for the rest of the line
+            b"+OKAY!",
+            b":12345",
+            b"$4\r\nabcd\r\n",
+            b"$5\r\n",
+            b"*2\r\n:1\r\n:2\r\n",
+            b"*3\r\n",
+            b"%3\r\n",
+            b"*1\r\n~9\r\n",
+            b"$?\r\n;5\r\nabcde\r\n;0\r\n",
+        ];
+        let expects = [
+            Err(ParseErrorKind::LineTooLong { limit: 4 }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::LineTooLong { limit: 4 }),
+            Err(ParseErrorKind::LineTooLong { limit: 4 }),
+            Ok(bulk("abcd")),
+            Err(ParseErrorKind::BulkTooLong { len: 5, limit: 4 }),
+            Ok(RespValue::Array(vec![
+                RespValue::Integer(1),
+                RespValue::Integer(2),
+            ])),
+            Err(ParseErrorKind::ArrayTooLong { len: 3, limit: 2 }),
+            Err(ParseErrorKind::ArrayTooLong { len: 3, limit: 2 }),
+            Err(ParseErrorKind::ArrayTooLong { len: 9, limit: 2 }),
+            // Streamed strings have a limit of their own
+            Ok(bulk("abcde")),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new_with_config(inputs[i], config);
+            assert_eq!(
+                kind_only(RespValue::parse(&mut parser)),
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+
+            let mut parser = RespParser::new_with_config(inputs[i], config);
+            assert_eq!(kind_only(parse_value_recursive(&mut parser)), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_default_bulk_limit() {
+        let inputs = [
+            format!("${}\r\n", MAX_BULK_LEN),
+            format!("${}\r\n", MAX_BULK_LEN + 1),
+            "$4294967295\r\n".to_string(),
+            format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1),
+        ];
+        let expects = [
+            Err(ParseErrorKind::Incomplete {
+                needed: Some(MAX_BULK_LEN),
+            }),
+            Err(ParseErrorKind::BulkTooLong {
+                len: MAX_BULK_LEN + 1,
+                limit: MAX_BULK_LEN,
+            }),
+            Err(ParseErrorKind::BulkTooLong {
+                len: 4294967295,
+                limit: MAX_BULK_LEN,
+            }),
+            Err(ParseErrorKind::BulkTooLong {
+                len: MAX_BULK_LEN + 1,
+                limit: MAX_BULK_LEN,
+            }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(inputs[i].as_bytes());
+            assert_eq!(kind_only(RespValue::parse(&mut parser)), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_ref() {
+        let inputs = [
+            b"+OK\r\n".to_vec(),
+            b"-ERR x\r\n".to_vec(),
+            b":-5\r\n".to_vec(),
+            b"(123\r\n".to_vec(),
+            b"$3\r\na\r\n\r\n".to_vec(),
+            b"$-1\r\n".to_vec(),
+            b"*-1\r\n".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$0\r\n\r\n".to_vec(),
+            b"%1\r\n+a\r\n~1\r\n_\r\n".to_vec(),
+            b"|1\r\n+k\r\n:1\r\n>1\r\n+v\r\n".to_vec(),
+            b"$?\r\n;1\r\na\r\n;0\r\n".to_vec(),
+            b"+\xff\r\n".to_vec(),
+            b"*2\r\n$1\r\na\r\n".to_vec(),
         ];
-        let expects: &[ParseResult<BulkString>] = &[
-            Ok(BulkString("Hello, World".to_string())),
-            Ok(BulkString("GET".to_string())),
-            Ok(BulkString("AAAAAAAAAAAAAAAAAAAAAAAAA".to_string())),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'*' })),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'3' })),
-            Err(ParseError::new(ParseErrorKind::MissingData { needed: 1 })),
-            Ok(BulkString("GET".to_string())),
-            Err(ParseError::new(ParseErrorKind::MissingCRLF)),
-            Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
-                data: b'G',
-            })),
-            Err(ParseError::new(ParseErrorKind::InvalidLength { len: -1 })),
-            Err(ParseError::new(ParseErrorKind::InvalidLength {
-                len: -1234,
-            })),
+        let expects: &[Result<RespValueRef<'_>, ParseErrorKind>] = &[
+            Ok(RespValueRef::Simple("OK")),
+            Ok(RespValueRef::Error("ERR x")),
+            Ok(RespValueRef::Integer(-5)),
+            Ok(RespValueRef::BigNumber("123")),
+            Ok(RespValueRef::Bulk(b"a\r\n")),
+            Ok(RespValueRef::None),
+            Ok(RespValueRef::None),
+            Ok(RespValueRef::Array(vec![
+                RespValueRef::Bulk(b"GET"),
+                RespValueRef::Bulk(b""),
+            ])),
+            Ok(RespValueRef::Map(vec![(
+                RespValueRef::Simple("a"),
+                RespValueRef::Set(vec![RespValueRef::None]),
+            )])),
+            Ok(RespValueRef::Attributed(
+                vec![(RespValueRef::Simple("k"), RespValueRef::Integer(1))],
+                Box::new(RespValueRef::Push(vec![RespValueRef::Simple("v")])),
+            )),
+            Err(ParseErrorKind::InvalidData),
+            Err(ParseErrorKind::InvalidUtf8Data),
+            Err(ParseErrorKind::Incomplete { needed: None }),
         ];
 
         assert_eq!(inputs.len(), expects.len());
         for i in 0..inputs.len() {
             let mut parser = RespParser::new(&inputs[i]);
-            let val = BulkString::parse(&mut parser);
-            assert_eq!(val, expects[i]);
+            let val = kind_only(parser.parse_ref());
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+
+            // The owned value is what RespValue parses directly
+            if let Ok(val) = val {
+                assert!(parser.remaining().is_empty());
+                let mut parser = RespParser::new(&inputs[i]);
+                assert_eq!(Ok(val.to_owned()), RespValue::parse(&mut parser));
+            }
         }
+
+        let data = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n";
+        let mut parser = RespParser::new(data);
+        let args = Vec::<&[u8]>::parse(&mut parser).unwrap();
+        assert_eq!(args, [&b"SET"[..], b"k", b"v"]);
+        // The arguments point into the request
+        assert_eq!(args[0].as_ptr(), data[8..].as_ptr());
     }
 
     #[test]
-    fn test_parse_array() {
+    fn test_conversions() {
+        let bulk = |s: &str| RespValue::Bulk(BulkString::new(s.to_string()));
+        assert_eq!(RespValue::from(5), RespValue::Integer(5));
+        assert_eq!(RespValue::from(true), RespValue::Integer(1));
+        assert_eq!(RespValue::from("a"), bulk("a"));
+        assert_eq!(RespValue::from("a".to_string()), bulk("a"));
+        assert_eq!(RespValue::from(b"a".to_vec()), bulk("a"));
+        assert_eq!(RespValue::from(Some("a")), bulk("a"));
+        assert_eq!(RespValue::from(None::<i64>), RespValue::None);
+        assert_eq!(
+            RespValue::from(vec![RespValue::from(1), RespValue::from("a")]),
+            RespValue::Array(vec![RespValue::Integer(1), bulk("a")])
+        );
+
+        let attributed = RespValue::Attributed(vec![], Box::new(RespValue::Integer(7)));
+        assert_eq!(i64::try_from(RespValue::Integer(-3)), Ok(-3));
+        assert_eq!(i64::try_from(bulk("42")), Ok(42));
+        assert_eq!(i64::try_from(attributed), Ok(7));
+        assert_eq!(f64::try_from(bulk("1.5")), Ok(1.5));
+        assert_eq!(f64::try_from(RespValue::Integer(2)), Ok(2.0));
+        assert_eq!(String::try_from(bulk("héllo")), Ok("héllo".to_string()));
+        assert_eq!(
+            String::try_from(RespValue::Simple("OK".to_string())),
+            Ok("OK".to_string())
+        );
+        assert_eq!(Vec::<u8>::try_from(bulk("a")), Ok(b"a".to_vec()));
+        assert_eq!(
+            Vec::<RespValue>::try_from(RespValue::Set(vec![RespValue::None])),
+            Ok(vec![RespValue::None])
+        );
+        assert_eq!(Option::<i64>::try_from(RespValue::None), Ok(None));
+        assert_eq!(
+            Option::<String>::try_from(bulk("a")),
+            Ok(Some("a".to_string()))
+        );
+
         let inputs = [
-            b"*-1\r\n".to_vec(),
-            b"*1\r\n+Simple\r\n".to_vec(),
-            b"*1\r\n:-7364\r\n".to_vec(),
-            b"*1\r\n-Error\r\n".to_vec(),
-            b"*2\r\n$3\r\nGET\r\nkey\r\n".to_vec(),
-            b"*2\r\n$3\r\nGET_SomeExtraData\r\n$3\r\nkey\r\n".to_vec(),
-            b"*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue_someextradata\r\n".to_vec(),
-            b"*3\r\n$3$3\r\nset\r\n$3\r\nkey\r\n$5\r\nvalue_someextradata\r\n".to_vec(),
-            b"*2\r\n$3\r\nGET_SomeExtraDatakey\r\n".to_vec(),
+            i64::try_from(RespValue::None).map(|_| ()),
+            i64::try_from(bulk("4x")).map(|_| ()),
+            f64::try_from(RespValue::Array(vec![])).map(|_| ()),
+            String::try_from(RespValue::Bulk(BulkString::new(vec![0xff]))).map(|_| ()),
+            Vec::<u8>::try_from(RespValue::Integer(1)).map(|_| ()),
+            Vec::<RespValue>::try_from(RespValue::Map(vec![])).map(|_| ()),
+            Option::<i64>::try_from(RespValue::Error(RespError::from("ERR"))).map(|_| ()),
         ];
-        let expects: &[ParseResult<RespValue>] = &[
-            Err(ParseError::new(ParseErrorKind::InvalidLength { len: -1 })),
-            Ok(RespValue::Array(vec![RespValue::Simple(
-                "Simple".to_string(),
-            )])),
-            Ok(RespValue::Array(vec![RespValue::Integer(-7364)])),
-            Ok(RespValue::Array(vec![RespValue::Error(
-                "Error".to_string(),
-            )])),
-            Err(ParseError::new(ParseErrorKind::InvalidTag { tag: b'k' })),
-            Ok(RespValue::Array(vec![
-                RespValue::Bulk(BulkString("GET".to_string())),
-                RespValue::Bulk(BulkString("key".to_string())),
-            ])),
-            Ok(RespValue::Array(vec![
-                RespValue::Bulk(BulkString("set".to_string())),
-                RespValue::Bulk(BulkString("key".to_string())),
-                RespValue::Bulk(BulkString("value".to_string())),
+        let expects = [
+            "can't convert null to i64",
+            "can't convert bulk string to i64",
+            "can't convert array to f64",
+            "can't convert bulk string to String",
+            "can't convert integer to Vec<u8>",
+            "can't convert map to Vec<RespValue>",
+            "can't convert error to i64",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i].as_ref().unwrap_err().to_string(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_from_resp() {
+        let bulk = |s: &str| RespValue::Bulk(BulkString::new(s.to_string()));
+        let ok = RespValue::Simple("OK".to_string());
+
+        let inputs = [
+            RespValue::Boolean(false),
+            RespValue::Integer(1),
+            RespValue::Integer(0),
+            ok.clone(),
+            RespValue::Integer(2),
+            RespValue::Simple("QUEUED".to_string()),
+        ];
+        let expects = [
+            Ok(false),
+            Ok(true),
+            Ok(false),
+            Ok(true),
+            Err("can't convert integer to bool"),
+            Err("can't convert simple string to bool"),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let converted = bool::from_resp(inputs[i].clone()).map_err(|err| err.to_string());
+            assert_eq!(converted, expects[i].map_err(str::to_string));
+        }
+
+        // HGETALL in RESP2 and RESP3
+        let expected = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let flat = RespValue::Array(vec![bulk("a"), bulk("1"), bulk("b"), bulk("2")]);
+        let map = RespValue::Map(vec![
+            (bulk("a"), RespValue::Integer(1)),
+            (bulk("b"), RespValue::Integer(2)),
+        ]);
+        assert_eq!(
+            HashMap::<String, i64>::from_resp(flat),
+            Ok(expected.clone())
+        );
+        assert_eq!(HashMap::<String, i64>::from_resp(map), Ok(expected));
+
+        assert_eq!(
+            <(String, Option<i64>, bool)>::from_resp(RespValue::Array(vec![
+                bulk("a"),
+                RespValue::None,
+                ok,
             ])),
-            Err(ParseError::new(ParseErrorKind::InvalidIntegerData {
-                data: b'$',
-            })),
-            Err(ParseError::new(ParseErrorKind::EmptyData)),
+            Ok(("a".to_string(), None, true))
+        );
+        assert_eq!(
+            Vec::<Option<Bytes>>::from_resp(RespValue::Set(vec![bulk("a"), RespValue::None])),
+            Ok(vec![Some(Bytes::from("a")), None])
+        );
+
+        let errors = [
+            HashMap::<String, i64>::from_resp(RespValue::Array(vec![bulk("a")])).map(|_| ()),
+            HashMap::<String, i64>::from_resp(RespValue::Array(vec![bulk("a"), bulk("x")]))
+                .map(|_| ()),
+            <(i64, i64)>::from_resp(RespValue::Array(vec![RespValue::Integer(1)])).map(|_| ()),
+            Vec::<i64>::from_resp(RespValue::Array(vec![RespValue::None])).map(|_| ()),
+            Vec::<i64>::from_resp(bulk("a")).map(|_| ()),
+            Bytes::from_resp(RespValue::Integer(1)).map(|_| ()),
+        ];
+        let expects = [
+            "can't convert array to HashMap",
+            "can't convert bulk string to i64",
+            "can't convert array to tuple of 2",
+            "can't convert null to i64",
+            "can't convert bulk string to Vec",
+            "can't convert integer to Bytes",
+        ];
+
+        assert_eq!(errors.len(), expects.len());
+        for i in 0..errors.len() {
+            assert_eq!(errors[i].as_ref().unwrap_err().to_string(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_compare_primitives() {
+        let ok = RespValue::Simple("OK".to_string());
+        let bulk = RespValue::Bulk(BulkString::from("OK"));
+        assert!(ok == "OK");
+        assert!("OK" == ok);
+        assert!(bulk == "OK");
+        assert!(RespValue::Error(RespError::from("OK")) != "OK");
+        assert!(RespValue::Bulk(BulkString::new(vec![0xff])) != "\u{ff}");
+
+        let (one, zero) = (RespValue::Integer(1), RespValue::Integer(0));
+        assert!(one == 1);
+        assert!(1 == one);
+        assert!(RespValue::Bulk(BulkString::from("1")) != 1);
+        assert!(one == true);
+        assert!(zero == false);
+        assert!(RespValue::Integer(2) != true);
+
+        let attributed = RespValue::Attributed(vec![], Box::new(ok.clone()));
+        assert!(attributed == "OK");
+        assert!(ok.is_ok() && attributed.is_ok());
+        assert!(!RespValue::Simple("QUEUED".to_string()).is_ok());
+        assert!(RespValue::None.is_null() && !RespValue::Array(vec![]).is_null());
+        assert_eq!(
+            RespValue::Error(RespError::from("ERR no")).as_error(),
+            Some(&RespError::err("no"))
+        );
+        assert_eq!(ok.as_error(), None);
+    }
+
+    #[test]
+    fn test_as_command() {
+        let bulk = |s: &str| RespValue::Bulk(BulkString::from(s));
+        let inputs = [
+            RespValue::Array(vec![bulk("GET"), bulk("k")]),
+            RespValue::Attributed(vec![], Box::new(RespValue::Array(vec![bulk("PING")]))),
+            RespValue::Array(vec![]),
+            RespValue::None,
+            bulk("GET"),
+            RespValue::Map(vec![]),
+            RespValue::Array(vec![RespValue::Integer(1)]),
+            RespValue::Array(vec![
+                bulk("GET"),
+                bulk("k"),
+                RespValue::Simple("v".to_string()),
+            ]),
+        ];
+        let expects = [
+            Ok((&b"GET"[..], 1)),
+            Ok((&b"PING"[..], 0)),
+            Err(CommandFormatError::Empty),
+            Err(CommandFormatError::Empty),
+            Err(CommandFormatError::NotAnArray { found: b'$' }),
+            Err(CommandFormatError::NotAnArray { found: b'%' }),
+            Err(CommandFormatError::NotBulk {
+                index: 0,
+                found: b':',
+            }),
+            Err(CommandFormatError::NotBulk {
+                index: 2,
+                found: b'+',
+            }),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let command = inputs[i].as_command();
+            assert_eq!(command.map(|(name, args)| (name, args.len())), expects[i]);
+
+            // The borrowed and owned variants agree
+            let data = encode_with(&inputs[i], ProtocolVersion::Resp3);
+            let value = RespValueRef::parse(&mut RespParser::new(&data)).unwrap();
+            let command = value.as_command();
+            assert_eq!(command.map(|(name, args)| (name, args.len())), expects[i]);
+
+            let command = inputs[i].clone().into_command();
+            let command = command
+                .as_ref()
+                .map(|(name, args)| (name.as_bytes(), args.len()));
+            assert_eq!(command, expects[i].as_ref().map(|command| *command));
+        }
+
+        assert_eq!(
+            CommandFormatError::NotBulk {
+                index: 1,
+                found: b':'
+            }
+            .to_string(),
+            "expected '$', got ':'"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let bulk = |s: &[u8]| RespValue::Bulk(BulkString::new(s.to_vec()));
+        let inputs = [
+            RespValue::None,
+            RespValue::Simple("OK".to_string()),
+            RespValue::Error(RespError::from("ERR unknown command")),
+            RespValue::Integer(-5),
+            RespValue::Boolean(true),
+            RespValue::Double(-0.5),
+            RespValue::BigNumber("12345678901234567890".to_string()),
+            bulk(b"hello world"),
+            bulk(b"say \"hi\"\r\n\\"),
+            bulk(b"\x00\xff\x1b[2J"),
+            bulk("héllo".as_bytes()),
+            RespValue::Array(vec![]),
+            RespValue::Array(vec![
+                bulk(b"a"),
+                RespValue::Array(vec![RespValue::Integer(1), RespValue::None]),
+                bulk(b"b"),
+            ]),
+            RespValue::Array((0..10).map(RespValue::Integer).collect()),
+            RespValue::Map(vec![
+                (bulk(b"a"), RespValue::Integer(1)),
+                (bulk(b"b"), RespValue::Set(vec![bulk(b"x"), bulk(b"y")])),
+            ]),
+            RespValue::Attributed(
+                vec![(bulk(b"ttl"), RespValue::Integer(3600))],
+                Box::new(bulk(b"value")),
+            ),
+        ];
+        let expects = [
+            "(nil)",
+            "OK",
+            "(error) ERR unknown command",
+            "(integer) -5",
+            "(true)",
+            "(double) -0.5",
+            "(big number) 12345678901234567890",
+            "\"hello world\"",
+            "\"say \\\"hi\\\"\\r\\n\\\\\"",
+            "\"\\x00\\xff\\x1b[2J\"",
+            "\"h\\xc3\\xa9llo\"",
+            "(empty array)",
+            "1) \"a\"\n2) 1) (integer) 1\n   2) (nil)\n3) \"b\"",
+            " 1) (integer) 0\n 2) (integer) 1\n 3) (integer) 2\n 4) (integer) 3\n \
+             5) (integer) 4\n 6) (integer) 5\n 7) (integer) 6\n 8) (integer) 7\n \
+             9) (integer) 8\n10) (integer) 9",
+            "1# \"a\" => (integer) 1\n2# \"b\" => 1~ \"x\"\n   2~ \"y\"",
+            "\"value\"",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_eq!(inputs[i].to_string(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_write_i64() {
+        let inputs = [0, 7, 10, 1234567890, -1, -10, i64::MAX, i64::MIN];
+        let expects: &[&[u8]] = &[
+            b":0\r\n",
+            b":7\r\n",
+            b":10\r\n",
+            b":1234567890\r\n",
+            b":-1\r\n",
+            b":-10\r\n",
+            b":9223372036854775807\r\n",
+            b":-9223372036854775808\r\n",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut buf = WriteBuf::new(Vec::new());
+            inputs[i].write(&mut RespWriter::new(&mut buf)).unwrap();
+            assert_eq!(buf.get(), expects[i]);
+            assert_eq!(
+                inputs[i].encoded_len(ProtocolVersion::Resp2),
+                expects[i].len()
+            );
+        }
+    }
+
+    /// Checks what `value` writes for RESP2 and RESP3, and that its encoded
+    /// length agrees
+    fn assert_writes<T: RespWritable>(value: &T, resp2: &[u8], resp3: &[u8]) {
+        let protocols = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+        for (protocol, expected) in protocols.into_iter().zip([resp2, resp3]) {
+            let mut buf = WriteBuf::new(Vec::new());
+            value
+                .write(&mut RespWriter::with_protocol(&mut buf, protocol))
+                .unwrap();
+            assert_eq!(buf.get(), expected, "{:?}", protocol);
+            assert_eq!(
+                value.encoded_len(protocol),
+                expected.len(),
+                "{:?}",
+                protocol
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_option() {
+        assert_writes(&None::<i64>, b"$-1\r\n", b"_\r\n");
+        assert_writes(&Some(5i64), b":5\r\n", b":5\r\n");
+        assert_writes(&Some(None::<&str>), b"$-1\r\n", b"_\r\n");
+        assert_writes(
+            &vec![Some("a"), None],
+            b"*2\r\n$1\r\na\r\n$-1\r\n",
+            b"*2\r\n$1\r\na\r\n_\r\n",
+        );
+    }
+
+    #[test]
+    fn test_write_str() {
+        assert_writes(&"", b"$0\r\n\r\n", b"$0\r\n\r\n");
+        // Written by length, line breaks and all
+        assert_writes(&"a\r\nb", b"$4\r\na\r\nb\r\n", b"$4\r\na\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_write_bytes() {
+        assert_writes(
+            &&b"\xff\x00"[..],
+            b"$2\r\n\xff\x00\r\n",
+            b"$2\r\n\xff\x00\r\n",
+        );
+        assert_writes(&b"value".to_vec(), b"$5\r\nvalue\r\n", b"$5\r\nvalue\r\n");
+        assert_writes(&Vec::<u8>::new(), b"$0\r\n\r\n", b"$0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_write_bool() {
+        assert_writes(&true, b":1\r\n", b":1\r\n");
+        assert_writes(&false, b":0\r\n", b":0\r\n");
+    }
+
+    #[test]
+    fn test_write_boolean_and_double() {
+        let inputs = [
+            RespValue::Boolean(true),
+            RespValue::Boolean(false),
+            RespValue::Double(3.25),
+            RespValue::Double(-2.0),
+            RespValue::Double(1e300),
+            RespValue::Double(-2.2250738585072014e-308),
+            RespValue::Double(f64::INFINITY),
+            RespValue::Double(f64::NEG_INFINITY),
+            RespValue::Double(f64::NAN),
+        ];
+        let expects: &[(&[u8], &[u8])] = &[
+            (b":1\r\n", b"#t\r\n"),
+            (b":0\r\n", b"#f\r\n"),
+            (b"$4\r\n3.25\r\n", b",3.25\r\n"),
+            (b"$4\r\n-2.0\r\n", b",-2.0\r\n"),
+            (b"$5\r\n1e300\r\n", b",1e300\r\n"),
+            (
+                b"$24\r\n-2.2250738585072014e-308\r\n",
+                b",-2.2250738585072014e-308\r\n",
+            ),
+            (b"$3\r\ninf\r\n", b",inf\r\n"),
+            (b"$4\r\n-inf\r\n", b",-inf\r\n"),
+            (b"$3\r\nnan\r\n", b",nan\r\n"),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            assert_writes(&inputs[i], expects[i].0, expects[i].1);
+        }
+    }
+
+    #[test]
+    fn test_resp3_round_trip() {
+        let inputs: [&[u8]; 5] = [
+            b"_\r\n",
+            b"%2\r\n#t\r\n,1.5\r\n+k\r\n(123456789012345678901234567890\r\n",
+            b"%1\r\n$4\r\nlist\r\n*3\r\n:1\r\n,-inf\r\n#f\r\n",
+            b"%1\r\n+outer\r\n%2\r\n+a\r\n*2\r\n:1\r\n_\r\n+b\r\n*1\r\n%1\r\n,0.25\r\n*0\r\n",
+            b"*2\r\n%0\r\n~1\r\n#t\r\n",
+        ];
+
+        for input in inputs {
+            let value: RespValue = parse_complete(input).unwrap();
+            assert_eq!(
+                encode_with(&value, ProtocolVersion::Resp3),
+                input,
+                "{:?}",
+                input.escape_ascii().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_unsigned() {
+        assert_writes(&0u64, b":0\r\n", b":0\r\n");
+        assert_writes(
+            &(i64::MAX as u64),
+            b":9223372036854775807\r\n",
+            b":9223372036854775807\r\n",
+        );
+        assert_writes(
+            &(i64::MAX as u64 + 1),
+            b"$19\r\n9223372036854775808\r\n",
+            b"(9223372036854775808\r\n",
+        );
+        assert_writes(
+            &u64::MAX,
+            b"$20\r\n18446744073709551615\r\n",
+            b"(18446744073709551615\r\n",
+        );
+        assert_writes(&42usize, b":42\r\n", b":42\r\n");
+    }
+
+    #[test]
+    fn test_write_slice() {
+        let values: &[i64] = &[1, 2];
+        assert_writes(&values, b"*2\r\n:1\r\n:2\r\n", b"*2\r\n:1\r\n:2\r\n");
+        assert_writes(
+            &vec![vec!["a"], vec![]],
+            b"*2\r\n*1\r\n$1\r\na\r\n*0\r\n",
+            b"*2\r\n*1\r\n$1\r\na\r\n*0\r\n",
+        );
+        assert_writes(&Vec::<RespValue>::new(), b"*0\r\n", b"*0\r\n");
+    }
+
+    #[test]
+    fn test_write_maps() {
+        let map = BTreeMap::from([("b", 2i64), ("a", 1)]);
+        assert_writes(
+            &map,
+            b"*4\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n:2\r\n",
+            b"%2\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n:2\r\n",
+        );
+        assert_writes(&BTreeMap::<&str, i64>::new(), b"*0\r\n", b"%0\r\n");
+
+        // A single pair, since the order of a hash map is arbitrary
+        let map = HashMap::from([("k", Some("v"))]);
+        assert_writes(
+            &map,
+            b"*2\r\n$1\r\nk\r\n$1\r\nv\r\n",
+            b"%1\r\n$1\r\nk\r\n$1\r\nv\r\n",
+        );
+    }
+
+    #[test]
+    fn test_write_line_breaks() {
+        let inputs = [
+            RespValue::Simple("a\r\n+OK".to_string()),
+            RespValue::Error(RespError::from("ERR\rx\ny")),
+            RespValue::Simple("\r\n".to_string()),
+        ];
+        let expects: &[&[u8]] = &[b"+a  +OK\r\n", b"-ERR x y\r\n", b"+  \r\n"];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut buf = WriteBuf::new(Vec::new());
+            inputs[i].write(&mut RespWriter::new(&mut buf)).unwrap();
+            assert_eq!(buf.get(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_write_per_protocol() {
+        let inputs = [
+            RespValue::None,
+            RespValue::Array(vec![RespValue::None]),
+            RespValue::Integer(1),
+            RespValue::Map(vec![]),
+            RespValue::Set(vec![RespValue::Integer(1), RespValue::None]),
+            RespValue::Push(vec![RespValue::Simple("message".to_string())]),
+            RespValue::Attributed(
+                vec![(RespValue::Simple("a".to_string()), RespValue::Integer(1))],
+                Box::new(RespValue::Integer(2)),
+            ),
+            RespValue::BigNumber("-12345678901234567890".to_string()),
+            RespValue::Map(vec![
+                (RespValue::Simple("a".to_string()), RespValue::None),
+                (
+                    RespValue::Simple("b".to_string()),
+                    RespValue::Map(vec![(RespValue::Integer(1), RespValue::Integer(2))]),
+                ),
+            ]),
+        ];
+        let expects: &[(&[u8], &[u8])] = &[
+            (b"$-1\r\n", b"_\r\n"),
+            (b"*1\r\n$-1\r\n", b"*1\r\n_\r\n"),
+            (b":1\r\n", b":1\r\n"),
+            (b"*0\r\n", b"%0\r\n"),
+            (b"*2\r\n:1\r\n$-1\r\n", b"~2\r\n:1\r\n_\r\n"),
+            (b"*1\r\n+message\r\n", b">1\r\n+message\r\n"),
+            (b":2\r\n", b"|1\r\n+a\r\n:1\r\n:2\r\n"),
+            (
+                b"$21\r\n-12345678901234567890\r\n",
+                b"(-12345678901234567890\r\n",
+            ),
+            (
+                b"*4\r\n+a\r\n$-1\r\n+b\r\n*2\r\n:1\r\n:2\r\n",
+                b"%2\r\n+a\r\n_\r\n+b\r\n%1\r\n:1\r\n:2\r\n",
+            ),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let protocols = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+            let expects = [expects[i].0, expects[i].1];
+            for (protocol, expected) in protocols.into_iter().zip(expects) {
+                let mut buf = WriteBuf::new(Vec::new());
+                inputs[i]
+                    .write(&mut RespWriter::with_protocol(&mut buf, protocol))
+                    .unwrap();
+                assert_eq!(buf.get(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_error_offset() {
+        let inputs = [
+            b"*2\r\n$3\r\nGET\r\nkey\r\n".to_vec(),
+            b":12a\r\n".to_vec(),
+            b"*1\r\n$3\r\nGET".to_vec(),
+            b"$3\r\nGETX".to_vec(),
+            b"*2\r\n:1\r\n*-5\r\n".to_vec(),
+        ];
+        let expects = [
+            "invalid type byte 'k' at offset 13",
+            "invalid integer character 'a' at offset 1",
+            "unexpected end of input, need 2 more bytes at offset 11",
+            "missing CRLF at offset 7",
+            "invalid length -5 at offset 9",
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let err = RespValue::parse(&mut parser).unwrap_err();
+            assert_eq!(err.to_string(), expects[i]);
+        }
+    }
+
+    #[test]
+    fn test_parse_nesting_depth() {
+        let nested = |depth: usize| [b"*1\r\n".repeat(depth), b":1\r\n".to_vec()].concat();
+
+        let inputs = [
+            nested(1),
+            nested(MAX_NESTING_DEPTH),
+            nested(MAX_NESTING_DEPTH + 1),
+            nested(1_000_000),
+        ];
+        let expects = [true, true, false, false];
+
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(RespValue::parse(&mut parser));
+            match expects[i] {
+                true => assert!(val.is_ok()),
+                false => assert_eq!(val, Err(ParseErrorKind::NestingTooDeep)),
+            }
+        }
+
+        // Maps, sets and pushes count as well
+        let input = b"%1\r\n+k\r\n~1\r\n>1\r\n*1\r\n:1\r\n";
+        let depths = [4, 3, 0];
+        let expects = [true, false, false];
+        for (depth, expected) in depths.into_iter().zip(expects) {
+            let mut parser = RespParser::new(input);
+            parser.set_max_depth(depth);
+            let val = kind_only(RespValue::parse(&mut parser));
+            match expected {
+                true => assert!(val.is_ok()),
+                false => assert_eq!(val, Err(ParseErrorKind::NestingTooDeep)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_deep_nesting_iteratively() {
+        // Far deeper than the call stack would allow a recursive parser to go
+        let depth = 10_000;
+        let input = [b"*1\r\n~1\r\n".repeat(depth / 2), b":1\r\n".to_vec()].concat();
+
+        let mut parser = RespParser::new(&input);
+        parser.set_max_depth(depth);
+        let mut val = RespValue::parse(&mut parser).unwrap();
+        assert_eq!(parser.consumed(), input.len());
+
+        let mut parser = RespParser::new(&input);
+        parser.set_max_depth(depth);
+        assert!(RespValueRef::parse(&mut parser).is_ok());
+
+        // Dropping is recursive, so the value is taken apart by hand
+        let mut levels = 0;
+        while let RespValue::Array(mut values) | RespValue::Set(mut values) = val {
+            val = values.pop().unwrap();
+            levels += 1;
+        }
+        assert_eq!(levels, depth);
+        assert_eq!(val, RespValue::Integer(1));
+    }
+
+    #[test]
+    fn test_parse_hostile_array_headers() {
+        let inputs = [
+            b"*1000000000\r\n".to_vec(),
+            b"*4611686018427387904\r\n:1\r\n".to_vec(),
+            b"*4611686018427387904\r\n$1\r\na\r\n".to_vec(),
+        ];
+
+        for input in inputs.iter() {
+            let mut parser = RespParser::new(input);
+            assert_eq!(
+                kind_only(RespValue::parse(&mut parser)),
+                Err(ParseErrorKind::Incomplete { needed: None })
+            );
+
+            let mut parser = RespParser::new(input);
+            assert!(Vec::<BulkString>::parse(&mut parser).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_consecutive_frames() {
+        let first = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        let second = b"+OK\r\n";
+        let data = [&first[..], second, b"$5\r\nval"].concat();
+
+        let mut parser = RespParser::new(&data);
+        assert_eq!(parser.consumed(), 0);
+        let value = RespValue::parse(&mut parser).unwrap();
+        assert_eq!(
+            value,
+            RespValue::Array(vec![
+                RespValue::Bulk(BulkString::new("GET")),
+                RespValue::Bulk(BulkString::new("key")),
+            ])
+        );
+        assert_eq!(parser.consumed(), first.len());
+
+        // What is left parses on its own, as a codec would do with the
+        // rest of its buffer
+        let (frame, rest) = data.split_at(parser.consumed());
+        assert_eq!(frame, first);
+        assert_eq!(rest, parser.remaining());
+        let mut parser = RespParser::new(rest);
+        let value = RespValue::parse(&mut parser).unwrap();
+        assert_eq!(value, RespValue::Simple("OK".to_string()));
+        assert_eq!(parser.consumed(), second.len());
+
+        // A failed parse leaves the parser somewhere within the frame, so
+        // it restarts from the last frame boundary
+        let rest = parser.remaining();
+        assert!(RespValue::parse(&mut parser).unwrap_err().is_incomplete());
+        assert_eq!(rest, b"$5\r\nval");
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let inputs = [
+            b"".to_vec(),
+            b"*".to_vec(),
+            b"*2\r".to_vec(),
+            b"*2\r\n".to_vec(),
+            b"*2\r\n$3\r\nGE".to_vec(),
+            b"*2\r\n$3\r\nGET".to_vec(),
+            b"*2\r\n$3\r\nGET\r".to_vec(),
+            b"*2\r\n$3\r\nGET\r\n$".to_vec(),
+            // Complete, and malformed whatever follows
+            b"*2\r\n$3\r\nGETX".to_vec(),
+            b"*2\r\n$3\r\nGET\rX".to_vec(),
+            b"*2\r\n+GET\r\n".to_vec(),
+            b"*x\r\n".to_vec(),
+            b"*1\r\n$-1\r\n".to_vec(),
+        ];
+        let expects: &[Result<Vec<BulkString>, ParseErrorKind>] = &[
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::Incomplete { needed: Some(1) }),
+            Err(ParseErrorKind::Incomplete { needed: Some(2) }),
+            Err(ParseErrorKind::Incomplete { needed: Some(1) }),
+            Err(ParseErrorKind::Incomplete { needed: None }),
+            Err(ParseErrorKind::MissingCRLF),
+            Err(ParseErrorKind::MissingCRLF),
+            Err(ParseErrorKind::InvalidTag { tag: b'+' }),
+            Err(ParseErrorKind::InvalidIntegerData { data: b'x' }),
+            Err(ParseErrorKind::InvalidLength { len: -1 }),
         ];
 
         assert_eq!(inputs.len(), expects.len());
         for i in 0..inputs.len() {
-            println!("Case {}", i + 1);
             let mut parser = RespParser::new(&inputs[i]);
+            let val = kind_only(Vec::<BulkString>::parse(&mut parser));
+            assert_eq!(
+                val,
+                expects[i],
+                "{:?}",
+                inputs[i].escape_ascii().to_string()
+            );
+        }
+    }
+
+    // ===========================================================
+    // Round trips
+    // ===========================================================
+
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::writer::{RespWriter, WriteBuf};
+
+    /// Simple and error strings can't contain line breaks
+    const LINE: &str = "[^\r\n]*";
+
+    fn value() -> impl Strategy<Value = RespValue> {
+        let leaf = prop_oneof![
+            Just(RespValue::None),
+            LINE.prop_map(RespValue::Simple),
+            LINE.prop_map(|line| RespValue::Error(RespError::from(line))),
+            any::<i64>().prop_map(RespValue::Integer),
+            vec(any::<u8>(), 0..64).prop_map(|data| RespValue::Bulk(BulkString::new(data))),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            vec(inner, 0..8).prop_map(RespValue::Array)
+        })
+    }
+
+    /// A code followed by a space and no message is written back without
+    /// the space
+    fn is_canonical_error(line: &str) -> bool {
+        line.find(' ').map(|pos| pos + 1) != Some(line.len())
+    }
+
+    /// Frames in the one encoding the writer produces, built by hand so that
+    /// they don't depend on the writer
+    fn canonical_frame() -> impl Strategy<Value = Vec<u8>> {
+        let leaf = prop_oneof![
+            Just(b"$-1\r\n".to_vec()),
+            LINE.prop_map(|s| format!("+{}\r\n", s).into_bytes()),
+            LINE.prop_filter("empty message", |s| is_canonical_error(s))
+                .prop_map(|s| format!("-{}\r\n", s).into_bytes()),
+            any::<i64>().prop_map(|i| format!(":{}\r\n", i).into_bytes()),
+            vec(any::<u8>(), 0..64).prop_map(|data| {
+                [format!("${}\r\n", data.len()).as_bytes(), &data, b"\r\n"].concat()
+            }),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            vec(inner, 0..8).prop_map(|frames| {
+                [
+                    format!("*{}\r\n", frames.len()).into_bytes(),
+                    frames.concat(),
+                ]
+                .concat()
+            })
+        })
+    }
+
+    /// Values with RESP3 types, which only round trip through RESP3
+    fn value3() -> impl Strategy<Value = RespValue> {
+        let leaf = prop_oneof![
+            Just(RespValue::None),
+            LINE.prop_map(RespValue::Simple),
+            any::<i64>().prop_map(RespValue::Integer),
+            any::<bool>().prop_map(RespValue::Boolean),
+            // NaN wouldn't compare equal after the round trip
+            any::<f64>()
+                .prop_filter("NaN", |d| !d.is_nan())
+                .prop_map(RespValue::Double),
+            "[+-]?[0-9]{1,40}".prop_map(RespValue::BigNumber),
+            vec(any::<u8>(), 0..64).prop_map(|data| RespValue::Bulk(BulkString::new(data))),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..8).prop_map(RespValue::Array),
+                vec(inner.clone(), 0..8).prop_map(RespValue::Set),
+                vec(inner.clone(), 0..8).prop_map(RespValue::Push),
+                (vec((inner.clone(), inner.clone()), 0..4), inner.clone()).prop_map(
+                    |(attributes, value)| RespValue::Attributed(attributes, Box::new(value))
+                ),
+                vec((inner.clone(), inner), 0..8).prop_map(RespValue::Map),
+            ]
+        })
+    }
+
+    fn encode(value: &RespValue) -> Vec<u8> {
+        encode_with(value, ProtocolVersion::Resp2)
+    }
+
+    fn encode_with(value: &RespValue, protocol: ProtocolVersion) -> Vec<u8> {
+        let mut buf = WriteBuf::new(Vec::new());
+        value
+            .write(&mut RespWriter::with_protocol(&mut buf, protocol))
+            .unwrap();
+        buf.get().clone()
+    }
+
+    /// Converts `value` to a RESP value, writes and parses it, and converts
+    /// it back
+    fn convert_round_trip<T: IntoResp + FromResp>(
+        value: T,
+        protocol: ProtocolVersion,
+    ) -> Result<T, ConversionError> {
+        let data = encode_with(&value.into_resp(), protocol);
+        T::from_resp(parse_complete(&data).unwrap())
+    }
+
+    /// Asserts that `value` survives the conversions and the wire in both
+    /// protocols
+    macro_rules! prop_assert_converts {
+        ($value:expr) => {
+            for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+                prop_assert_eq!(
+                    convert_round_trip($value.clone(), protocol),
+                    Ok($value.clone())
+                );
+            }
+        };
+    }
+
+    proptest! {
+        #[test]
+        fn test_convert_scalars(
+            i in any::<i64>(),
+            b in any::<bool>(),
+            d in any::<f64>().prop_filter("NaN", |d| !d.is_nan()),
+            s in any::<String>(),
+            data in vec(any::<u8>(), 0..64),
+        ) {
+            prop_assert_converts!(i);
+            prop_assert_converts!(b);
+            prop_assert_converts!(d);
+            prop_assert_converts!(s);
+            prop_assert_converts!(Bytes::from(data.clone()));
+            prop_assert_converts!(data);
+        }
+
+        #[test]
+        fn test_convert_containers(
+            options in vec(proptest::option::of(any::<String>()), 0..8),
+            ints in vec(any::<i64>(), 0..8),
+            map in proptest::collection::hash_map(any::<String>(), any::<i64>(), 0..8),
+            tuple in (any::<i64>(), any::<String>(), any::<bool>()),
+            nested in vec((any::<String>(), proptest::option::of(any::<i64>())), 0..8),
+        ) {
+            prop_assert_converts!(options);
+            prop_assert_converts!(ints);
+            prop_assert_converts!(map);
+            prop_assert_converts!(tuple);
+            prop_assert_converts!(nested);
+            prop_assert_converts!((1i64,));
+            prop_assert_converts!((1i64, 2i64, 3i64, 4i64, 5i64, 6i64));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_write_parse_round_trip(value in value(), rest in vec(any::<u8>(), 0..16)) {
+            let data = [encode(&value), rest.clone()].concat();
+
+            let mut parser = RespParser::new(&data);
+            prop_assert_eq!(RespValue::parse(&mut parser), Ok(value));
+            prop_assert_eq!(parser.remaining(), &rest[..]);
+            prop_assert_eq!(parser.consumed(), data.len() - rest.len());
+        }
+
+        #[test]
+        fn test_parse_write_round_trip(frame in canonical_frame()) {
+            let value: RespValue = parse_complete(&frame).unwrap();
+            prop_assert_eq!(encode(&value), frame);
+        }
+
+        #[test]
+        fn test_map_round_trip(value in value3()) {
+            let data = encode_with(&value, ProtocolVersion::Resp3);
+            prop_assert_eq!(parse_complete(&data), Ok(value));
+
+            for len in 0..data.len() {
+                let mut parser = RespParser::new(&data[..len]);
+                prop_assert!(RespValue::parse(&mut parser).unwrap_err().is_incomplete());
+            }
+        }
+
+        #[test]
+        fn test_parse_matches_recursive(
+            value in value3(),
+            damage in vec((any::<prop::sample::Index>(), any::<u8>()), 0..4),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let mut data = encode_with(&value, ProtocolVersion::Resp3);
+            for (pos, byte) in damage {
+                let pos = pos.index(data.len());
+                data[pos] = byte;
+            }
+            data.truncate(cut.index(data.len() + 1));
+
+            let mut parser = RespParser::new(&data);
             let val = RespValue::parse(&mut parser);
-            assert_eq!(val, expects[i]);
+            let mut recursive = RespParser::new(&data);
+            prop_assert_eq!(&val, &parse_value_recursive(&mut recursive));
+            prop_assert_eq!(parser.consumed(), recursive.consumed());
+
+            let mut parser = RespParser::new(&data);
+            let val_ref = RespValueRef::parse(&mut parser).map(|val| val.to_owned());
+            prop_assert_eq!(val_ref, val);
+        }
+
+        #[test]
+        fn test_encoded_len(value in value(), value3 in value3()) {
+            for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+                prop_assert_eq!(value.encoded_len(protocol), encode_with(&value, protocol).len());
+                prop_assert_eq!(value3.encoded_len(protocol), encode_with(&value3, protocol).len());
+            }
+        }
+
+        #[test]
+        fn test_parse_any_input(data in vec(
+            prop_oneof![
+                Just(b'\r'), Just(b'\n'), Just(b'*'), Just(b'$'), Just(b'%'), Just(b'+'),
+                Just(b'-'), Just(b'0'), Just(b'1'), Just(b'?'), Just(0xed), any::<u8>(),
+            ],
+            0..64,
+        )) {
+            let mut parser = RespParser::new(&data);
+            let _ = RespValue::parse(&mut parser);
+            prop_assert!(parser.consumed() <= data.len());
+        }
+
+        #[test]
+        fn test_prefix_is_incomplete(frame in canonical_frame()) {
+            for len in 0..frame.len() {
+                let mut parser = RespParser::new(&frame[..len]);
+                let err = RespValue::parse(&mut parser).unwrap_err();
+                prop_assert!(err.is_incomplete(), "{} for {:?}", err, &frame[..len]);
+            }
         }
     }
 }
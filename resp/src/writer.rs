@@ -1,4 +1,13 @@
-use crate::types::RespWritable;
+use std::{
+    error, fmt,
+    io::{self, IoSlice},
+};
+
+use bytes::{Bytes, BytesMut};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::types::{self, RespWritable, SimpleRespWritable};
 
 // ===========================================================
 // WriteErrorKind, WriteError, WriteResult
@@ -7,6 +16,45 @@ use crate::types::RespWritable;
 #[derive(Debug)]
 pub enum WriteError {
     AllocationError,
+
+    /// A push would have grown a buffer created with `WriteBuf::with_limit`
+    /// to `needed` bytes, beyond its `limit`
+    BufferFull {
+        needed: usize,
+        limit: usize,
+    },
+
+    /// Sending the written data failed
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::AllocationError => write!(f, "failed to allocate the write buffer"),
+            WriteError::BufferFull { needed, limit } => write!(
+                f,
+                "write buffer limit of {} bytes exceeded, {} needed",
+                limit, needed
+            ),
+            WriteError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            WriteError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::Io(err)
+    }
 }
 
 pub type WriteResult<T = ()> = Result<T, WriteError>;
@@ -15,68 +63,1362 @@ pub type WriteResult<T = ()> = Result<T, WriteError>;
 // WriteBuf, RespWriter
 // ===========================================================
 
-pub struct WriteBuf(Vec<u8>);
+/// Storage a `WriteBuf` writes into. Besides `Vec<u8>` it is implemented
+/// for `BytesMut`, so that a frame can be written straight into the buffer of
+/// a codec and sent without copying it, and for `SegmentedBuf`, which keeps
+/// large payloads out of its buffer.
+pub trait OutBuf: Sized {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn capacity(&self) -> usize;
+
+    /// Makes room for `additional` more bytes, failing if they can't be
+    /// allocated
+    fn try_reserve(&mut self, additional: usize) -> WriteResult;
+
+    fn extend_from_slice(&mut self, data: &[u8]);
+
+    /// Appends `data`, which storage that can hold on to it instead of
+    /// copying it overrides
+    fn extend_from_bytes(&mut self, data: Bytes) {
+        self.extend_from_slice(&data);
+    }
+
+    /// Inserts `data` at `at`, moving what follows back
+    fn insert_from_slice(&mut self, at: usize, data: &[u8]);
+
+    fn truncate(&mut self, len: usize);
+
+    fn shrink_to(&mut self, min_capacity: usize);
+}
+
+/// Storage that holds all of its data in one slice
+pub trait ContiguousBuf: OutBuf {
+    fn split_off(&mut self, at: usize) -> Self;
+
+    fn as_slice(&self) -> &[u8];
+}
+
+impl OutBuf for Vec<u8> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity()
+    }
+
+    fn try_reserve(&mut self, additional: usize) -> WriteResult {
+        self.try_reserve(additional)
+            .map_err(|_| WriteError::AllocationError)
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        self.extend_from_slice(data);
+    }
+
+    fn insert_from_slice(&mut self, at: usize, data: &[u8]) {
+        self.extend_from_slice(data);
+        self[at..].rotate_right(data.len());
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.truncate(len);
+    }
+
+    fn shrink_to(&mut self, min_capacity: usize) {
+        self.shrink_to(min_capacity);
+    }
+}
+
+impl ContiguousBuf for Vec<u8> {
+    fn split_off(&mut self, at: usize) -> Self {
+        self.split_off(at)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+}
+
+impl OutBuf for BytesMut {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity()
+    }
+
+    /// `BytesMut` has no fallible allocation, so this aborts like any other
+    /// failed allocation would
+    fn try_reserve(&mut self, additional: usize) -> WriteResult {
+        self.reserve(additional);
+        Ok(())
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        self.extend_from_slice(data);
+    }
+
+    fn insert_from_slice(&mut self, at: usize, data: &[u8]) {
+        self.extend_from_slice(data);
+        self[at..].rotate_right(data.len());
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.truncate(len);
+    }
+
+    /// `BytesMut` can't give memory back, its capacity is left as is
+    fn shrink_to(&mut self, _min_capacity: usize) {}
+}
+
+impl ContiguousBuf for BytesMut {
+    fn split_off(&mut self, at: usize) -> Self {
+        self.split_off(at)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Payloads at least this long are handed to the buffer as shared `Bytes`
+/// rather than copied, see `RespWriter::write_bytes_shared`
+pub const MIN_SHARED_LEN: usize = 16 * 1024;
+
+/// Output made of an inline buffer, which takes headers, CRLFs and small
+/// values, with large `Bytes` payloads kept as segments of their own in
+/// between. Sending it with `write_vectored` over `as_io_slices` copies those
+/// payloads neither into the buffer nor out of it.
+#[derive(Debug, Default)]
+pub struct SegmentedBuf {
+    inline: Vec<u8>,
+
+    /// Shared payloads in order, each with the length `inline` had when it
+    /// was appended
+    shared: Vec<(usize, Bytes)>,
+    shared_len: usize,
+}
+
+impl SegmentedBuf {
+    pub fn new() -> SegmentedBuf {
+        SegmentedBuf::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> SegmentedBuf {
+        SegmentedBuf {
+            inline: Vec::with_capacity(capacity),
+            ..SegmentedBuf::default()
+        }
+    }
+
+    /// Number of shared payloads
+    pub fn segments(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// The data in order, for `write_vectored`. None of the slices is empty.
+    pub fn as_io_slices(&self) -> Vec<IoSlice<'_>> {
+        let mut slices = Vec::with_capacity(self.shared.len() * 2 + 1);
+        let mut pos = 0;
+        for (at, data) in &self.shared {
+            if *at > pos {
+                slices.push(IoSlice::new(&self.inline[pos..*at]));
+            }
+            slices.push(IoSlice::new(data));
+            pos = *at;
+        }
+        if pos < self.inline.len() {
+            slices.push(IoSlice::new(&self.inline[pos..]));
+        }
+        slices
+    }
+
+    /// Copies the data into a single buffer
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.len());
+        for slice in self.as_io_slices() {
+            vec.extend_from_slice(&slice);
+        }
+        vec
+    }
+}
+
+impl OutBuf for SegmentedBuf {
+    fn len(&self) -> usize {
+        self.inline.len() + self.shared_len
+    }
+
+    /// Capacity of the inline buffer
+    fn capacity(&self) -> usize {
+        self.inline.capacity()
+    }
+
+    /// Reserves at most `MIN_SHARED_LEN` bytes, since the room asked for
+    /// ahead of a large value is mostly taken by shared payloads
+    fn try_reserve(&mut self, additional: usize) -> WriteResult {
+        self.inline
+            .try_reserve(additional.min(MIN_SHARED_LEN))
+            .map_err(|_| WriteError::AllocationError)
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        self.inline.extend_from_slice(data);
+    }
+
+    fn extend_from_bytes(&mut self, data: Bytes) {
+        if data.is_empty() {
+            return;
+        }
+        self.shared_len += data.len();
+        self.shared.push((self.inline.len(), data));
+    }
+
+    fn insert_from_slice(&mut self, at: usize, data: &[u8]) {
+        // Find how many shared bytes come before `at`, splitting the payload
+        // it falls into
+        let mut before = 0;
+        let mut i = 0;
+        while i < self.shared.len() {
+            let (pos, payload) = &mut self.shared[i];
+            let start = *pos + before;
+            if start >= at {
+                break;
+            }
+            if start + payload.len() > at {
+                let rest = payload.split_off(at - start);
+                let pos = *pos;
+                before += at - start;
+                i += 1;
+                self.shared.insert(i, (pos, rest));
+                break;
+            }
+            before += payload.len();
+            i += 1;
+        }
+
+        self.inline.insert_from_slice(at - before, data);
+        for (pos, _) in &mut self.shared[i..] {
+            *pos += data.len();
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        let mut kept = 0;
+        let mut shared_len = 0;
+        for (pos, payload) in &mut self.shared {
+            let start = *pos + shared_len;
+            if start >= len {
+                break;
+            }
+            payload.truncate(len - start);
+            shared_len += payload.len();
+            kept += 1;
+        }
+
+        self.shared.truncate(kept);
+        self.shared_len = shared_len;
+        self.inline.truncate(len - shared_len);
+    }
+
+    fn shrink_to(&mut self, min_capacity: usize) {
+        self.inline.shrink_to(min_capacity);
+    }
+}
+
+pub struct WriteBuf<B = Vec<u8>> {
+    data: B,
+    limit: Option<usize>,
+}
 
 impl WriteBuf {
-    pub fn new(data: Vec<u8>) -> WriteBuf {
-        WriteBuf(data)
+    /// Creates an empty buffer that can take `capacity` bytes before
+    /// reallocating
+    pub fn with_capacity(capacity: usize) -> WriteBuf {
+        WriteBuf::new(Vec::with_capacity(capacity))
+    }
+}
+
+impl<B: OutBuf> WriteBuf<B> {
+    pub fn new(data: B) -> WriteBuf<B> {
+        WriteBuf { data, limit: None }
+    }
+
+    /// Creates a buffer that refuses to grow beyond `limit` bytes. Pushes
+    /// that would exceed it fail with `WriteError::BufferFull` and leave the
+    /// buffer as it was.
+    pub fn with_limit(data: B, limit: usize) -> WriteBuf<B> {
+        WriteBuf {
+            data,
+            limit: Some(limit),
+        }
     }
 
+    /// Length of the data written so far. It doubles as a watermark: passing
+    /// it to `truncate` later drops whatever was written in between.
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.data.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.data.is_empty()
     }
 
-    pub fn get(&self) -> &Vec<u8> {
-        &self.0
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
     }
 
-    pub fn get_mut(&mut self) -> &mut Vec<u8> {
-        &mut self.0
+    /// Removes all data from the buffer while keeping its capacity, so the
+    /// buffer can be reused without reallocating
+    pub fn clear(&mut self) {
+        self.data.truncate(0);
+    }
+
+    /// Shrinks the capacity of the buffer to at least `min_capacity` bytes
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.data.shrink_to(min_capacity);
+    }
+
+    /// Drops the data past `len`, such as a value that failed half way
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    /// Returns the written data without copying it
+    pub fn into_inner(self) -> B {
+        self.data
+    }
+
+    pub fn get(&self) -> &B {
+        &self.data
+    }
+
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.data
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    fn check_limit(&self, additional: usize) -> WriteResult {
+        if let Some(limit) = self.limit {
+            let needed = self.data.len().saturating_add(additional);
+            if needed > limit {
+                return Err(WriteError::BufferFull { needed, limit });
+            }
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) -> WriteResult {
+        self.check_limit(additional)?;
+        self.data.try_reserve(additional)
     }
 
     pub fn push_u8(&mut self, b: u8) -> WriteResult {
-        self.0
-            .try_reserve(1)
-            .map_err(|_| WriteError::AllocationError)?;
-        self.0.push(b);
+        self.reserve(1)?;
+        self.data.extend_from_slice(&[b]);
         Ok(())
     }
 
     pub fn push_bytes(&mut self, data: &[u8]) -> WriteResult {
-        self.0
-            .try_reserve(data.len())
-            .map_err(|_| WriteError::AllocationError)?;
-        self.0.extend_from_slice(data);
+        self.reserve(data.len())?;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Appends `data`, which storage such as `SegmentedBuf` keeps as is
+    /// instead of copying it
+    pub fn push_shared(&mut self, data: Bytes) -> WriteResult {
+        self.check_limit(data.len())?;
+        self.data.extend_from_bytes(data);
+        Ok(())
+    }
+
+    /// Inserts `data` at `at`, moving what follows back
+    fn insert_bytes(&mut self, at: usize, data: &[u8]) -> WriteResult {
+        self.reserve(data.len())?;
+        self.data.insert_from_slice(at, data);
         Ok(())
     }
 }
 
-pub struct RespWriter<'a> {
-    buf: &'a mut WriteBuf,
+impl<B: ContiguousBuf> WriteBuf<B> {
+    /// Splits the buffer in two at `at`. The buffer keeps `[0, at)` and the
+    /// returned one, which has the same limit, holds `[at, len)`.
+    pub fn split_off(&mut self, at: usize) -> WriteBuf<B> {
+        WriteBuf {
+            data: self.data.split_off(at),
+            limit: self.limit,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
 }
 
-impl RespWriter<'_> {
-    pub fn new(buf: &mut WriteBuf) -> RespWriter<'_> {
-        RespWriter { buf }
+impl WriteBuf<BytesMut> {
+    /// Takes the data written so far, e.g. a frame to hand to a codec,
+    /// leaving the buffer empty with the rest of its capacity
+    pub fn split(&mut self) -> BytesMut {
+        self.data.split()
     }
 
-    pub fn buffer(&mut self) -> &mut WriteBuf {
+    pub fn freeze(self) -> Bytes {
+        self.data.freeze()
+    }
+}
+
+/// Version of the protocol the peer speaks. The same value can have a
+/// different encoding in each version, e.g. the null value is `$-1\r\n` in
+/// RESP2 and `_\r\n` in RESP3.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+pub struct RespWriter<'a, B = Vec<u8>> {
+    buf: &'a mut WriteBuf<B>,
+    protocol: ProtocolVersion,
+}
+
+impl<'a, B: OutBuf> RespWriter<'a, B> {
+    /// Creates a writer that encodes values for RESP2
+    pub fn new(buf: &'a mut WriteBuf<B>) -> RespWriter<'a, B> {
+        RespWriter::with_protocol(buf, ProtocolVersion::Resp2)
+    }
+
+    pub fn with_protocol(buf: &'a mut WriteBuf<B>, protocol: ProtocolVersion) -> RespWriter<'a, B> {
+        RespWriter { buf, protocol }
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+
+    pub fn buffer(&mut self) -> &mut WriteBuf<B> {
         self.buf
     }
 
+    /// Writes `value`, reserving the space it takes up at once rather than
+    /// growing the buffer piece by piece. If that fails half way, what was
+    /// written of the value is dropped, so the buffer never ends in a
+    /// partial frame. The other methods leave whatever they wrote before
+    /// failing, for the caller to truncate.
     pub fn write_value<T: RespWritable>(&mut self, value: &T) -> WriteResult {
-        value.write(self)
+        let start = self.buf.len();
+        let res = self
+            .buf
+            .reserve(value.encoded_len(self.protocol))
+            .and_then(|()| value.write(self));
+        if res.is_err() {
+            self.buf.truncate(start);
+        }
+        res
     }
 
     pub fn write_u8(&mut self, value: u8) -> WriteResult {
         self.buf.push_u8(value)
     }
 
+    /// Writes `data` as is, like `push_bytes` on the buffer, except that a
+    /// `SegmentedBuf` keeps a reference to it rather than a copy. Meant for
+    /// payloads of at least `MIN_SHARED_LEN` bytes, below that copying is
+    /// cheaper than another segment.
+    pub fn write_bytes_shared(&mut self, data: Bytes) -> WriteResult {
+        self.buf.push_shared(data)
+    }
+
     pub fn write_crlf(&mut self) -> WriteResult {
         self.buf.push_bytes(b"\r\n")
     }
+
+    /// Writes a simple string such as `OK`. Line breaks are written as
+    /// spaces, since they would end the string.
+    pub fn write_simple(&mut self, s: &str) -> WriteResult {
+        self.write_u8(b'+')?;
+        types::write_line(self.buf, s.as_bytes())?;
+        self.write_crlf()
+    }
+
+    /// Writes an error reply such as `ERR syntax error`, with line breaks
+    /// written as spaces
+    pub fn write_error(&mut self, msg: &str) -> WriteResult {
+        self.write_u8(b'-')?;
+        types::write_line(self.buf, msg.as_bytes())?;
+        self.write_crlf()
+    }
+
+    pub fn write_integer(&mut self, n: i64) -> WriteResult {
+        n.write(self)
+    }
+
+    pub fn write_bulk(&mut self, data: &[u8]) -> WriteResult {
+        types::write_bulk(self, data)
+    }
+
+    /// Writes the null bulk string `$-1` in RESP2, or the null in RESP3
+    pub fn write_null(&mut self) -> WriteResult {
+        types::write_null(self)
+    }
+
+    /// Starts an array of `len` elements, which have to be written next.
+    /// A large reply can be written element by element this way, without
+    /// building all of it first.
+    pub fn write_array_header(&mut self, len: usize) -> WriteResult {
+        types::write_aggregate_header(self, b'*', len)
+    }
+
+    /// Starts a map of `len` pairs, each of which has to be written next as
+    /// its key followed by its value. RESP2 gets a flat array of both.
+    pub fn write_map_header(&mut self, len: usize) -> WriteResult {
+        types::write_map_header(self, len)
+    }
+
+    /// Writes the RESP2 null array `*-1`, which some replies such as a timed
+    /// out blocking pop send instead of the null bulk string. RESP3 has a
+    /// single null.
+    pub fn write_null_array(&mut self) -> WriteResult {
+        match self.protocol {
+            ProtocolVersion::Resp2 => self.buf.push_bytes(b"*-1\r\n"),
+            ProtocolVersion::Resp3 => self.buf.push_bytes(b"_\r\n"),
+        }
+    }
+
+    /// Starts a bulk string whose length isn't known up front, so that a
+    /// large reply can be written piece by piece without assembling it
+    /// first. Nothing else may be written until the string is finished.
+    pub fn begin_streamed_bulk(&mut self) -> WriteResult<StreamedBulk<'_, 'a, B>> {
+        let start = self.buf.len();
+        if self.protocol == ProtocolVersion::Resp3 {
+            self.buf.push_bytes(b"$?\r\n")?;
+        }
+
+        Ok(StreamedBulk {
+            writer: self,
+            start,
+        })
+    }
+}
+
+/// Bulk string written in chunks, started with
+/// `RespWriter::begin_streamed_bulk`. RESP3 peers get each chunk as it is
+/// written. RESP2 has no streamed strings, so there the chunks are written
+/// as a plain payload, and `finish` puts the length in front of it.
+///
+/// A string that isn't finished leaves an incomplete frame in the buffer.
+pub struct StreamedBulk<'w, 'a, B = Vec<u8>> {
+    writer: &'w mut RespWriter<'a, B>,
+
+    /// Position in the buffer where the string starts
+    start: usize,
+}
+
+impl<B: OutBuf> StreamedBulk<'_, '_, B> {
+    pub fn write_chunk(&mut self, data: &[u8]) -> WriteResult {
+        // An empty chunk would end the string
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if self.writer.protocol() == ProtocolVersion::Resp3 {
+            self.writer.write_u8(b';')?;
+            (data.len() as i64).write_raw(self.writer.buffer())?;
+            self.writer.write_crlf()?;
+        }
+        self.writer.buffer().push_bytes(data)?;
+        if self.writer.protocol() == ProtocolVersion::Resp3 {
+            self.writer.write_crlf()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> WriteResult {
+        let protocol = self.writer.protocol();
+        let buf = self.writer.buffer();
+        match protocol {
+            ProtocolVersion::Resp2 => {
+                let header = format!("${}\r\n", buf.len() - self.start);
+                buf.reserve(header.len() + 2)?;
+                buf.insert_bytes(self.start, header.as_bytes())?;
+                buf.push_bytes(b"\r\n")
+            }
+            ProtocolVersion::Resp3 => buf.push_bytes(b";0\r\n"),
+        }
+    }
+}
+
+/// A buffer is a write target like any other, which is handy for an
+/// `IoRespWriter` in tests
+impl<B: OutBuf> io::Write for WriteBuf<B> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.push_bytes(data)
+            .map_err(|_| io::ErrorKind::OutOfMemory)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// ===========================================================
+// IoRespWriter
+// ===========================================================
+
+/// Default amount of buffered output that makes `IoRespWriter` and
+/// `AsyncRespWriter` flush on their own
+pub const DEFAULT_FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Writes values to a blocking [`io::Write`] such as a `TcpStream`. Values
+/// are buffered until `flush` is called, or until the buffer reaches the
+/// flush threshold.
+pub struct IoRespWriter<W> {
+    inner: W,
+    buf: WriteBuf,
+    protocol: ProtocolVersion,
+    flush_threshold: usize,
+}
+
+impl<W: io::Write> IoRespWriter<W> {
+    /// Creates a writer that encodes values for RESP2
+    pub fn new(inner: W) -> IoRespWriter<W> {
+        IoRespWriter::with_protocol(inner, ProtocolVersion::Resp2)
+    }
+
+    pub fn with_protocol(inner: W, protocol: ProtocolVersion) -> IoRespWriter<W> {
+        IoRespWriter {
+            inner,
+            buf: WriteBuf::new(Vec::new()),
+            protocol,
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+
+    /// Sets the amount of buffered output at which a write flushes. With 0,
+    /// every write is flushed right away.
+    pub fn set_flush_threshold(&mut self, threshold: usize) {
+        self.flush_threshold = threshold;
+    }
+
+    pub fn flush_threshold(&self) -> usize {
+        self.flush_threshold
+    }
+
+    /// Bytes written but not flushed yet
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the underlying writer. Output that wasn't flushed is lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub fn write_value<T: RespWritable>(&mut self, value: &T) -> WriteResult {
+        write_buffered(&mut self.buf, self.protocol, value)?;
+        self.flush_if_full()
+    }
+
+    /// Starts an array of `len` elements, which have to be written next.
+    /// The elements are sent as they are written, so a large array never
+    /// has to be held in memory as a whole.
+    pub fn write_array_header(&mut self, len: usize) -> WriteResult {
+        RespWriter::with_protocol(&mut self.buf, self.protocol).write_array_header(len)?;
+        self.flush_if_full()
+    }
+
+    /// Starts a map of `len` pairs, each of which has to be written next as
+    /// its key followed by its value. RESP2 gets a flat array of both.
+    pub fn write_map_header(&mut self, len: usize) -> WriteResult {
+        RespWriter::with_protocol(&mut self.buf, self.protocol).write_map_header(len)?;
+        self.flush_if_full()
+    }
+
+    /// Sends everything buffered and flushes the underlying writer
+    pub fn flush(&mut self) -> WriteResult {
+        self.inner.write_all(&self.buf.data)?;
+        self.buf.clear();
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    fn flush_if_full(&mut self) -> WriteResult {
+        if self.buf.len() >= self.flush_threshold {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends `value` to `buf`, leaving nothing of it there if writing fails
+/// half way
+fn write_buffered<T: RespWritable>(
+    buf: &mut WriteBuf,
+    protocol: ProtocolVersion,
+    value: &T,
+) -> WriteResult {
+    let start = buf.len();
+    let res = RespWriter::with_protocol(buf, protocol).write_value(value);
+    if res.is_err() {
+        buf.truncate(start);
+    }
+    res
+}
+
+// ===========================================================
+// AsyncRespWriter
+// ===========================================================
+
+/// Writes values to an [`AsyncWrite`], enabled with the `tokio` feature.
+/// Values are buffered until `flush` is called, or until the buffer reaches
+/// the flush threshold, so that a long run of replies is sent as it goes
+/// instead of piling up in memory.
+#[cfg(feature = "tokio")]
+pub struct AsyncRespWriter<W> {
+    inner: W,
+    buf: WriteBuf,
+    protocol: ProtocolVersion,
+    flush_threshold: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> AsyncRespWriter<W> {
+    /// Creates a writer that encodes values for RESP2
+    pub fn new(inner: W) -> AsyncRespWriter<W> {
+        AsyncRespWriter::with_protocol(inner, ProtocolVersion::Resp2)
+    }
+
+    pub fn with_protocol(inner: W, protocol: ProtocolVersion) -> AsyncRespWriter<W> {
+        AsyncRespWriter {
+            inner,
+            buf: WriteBuf::new(Vec::new()),
+            protocol,
+            flush_threshold: DEFAULT_FLUSH_THRESHOLD,
+        }
+    }
+
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+
+    /// Sets the amount of buffered output at which a write flushes. With 0,
+    /// every write is flushed right away.
+    pub fn set_flush_threshold(&mut self, threshold: usize) {
+        self.flush_threshold = threshold;
+    }
+
+    pub fn flush_threshold(&self) -> usize {
+        self.flush_threshold
+    }
+
+    /// Bytes written but not flushed yet
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the underlying writer. Output that wasn't flushed is lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub async fn write_value<T: RespWritable>(&mut self, value: &T) -> WriteResult {
+        let start = self.buf.len();
+        if let Err(err) = RespWriter::with_protocol(&mut self.buf, self.protocol).write_value(value)
+        {
+            // A value that failed half way isn't sent
+            self.buf.data.truncate(start);
+            return Err(err);
+        }
+
+        self.flush_if_full().await
+    }
+
+    /// Starts an array of `len` elements, which have to be written next.
+    /// The elements are sent as they are written, so a large array never
+    /// has to be held in memory as a whole.
+    pub async fn write_array_header(&mut self, len: usize) -> WriteResult {
+        RespWriter::with_protocol(&mut self.buf, self.protocol).write_array_header(len)?;
+        self.flush_if_full().await
+    }
+
+    /// Starts a map of `len` pairs, each of which has to be written next as
+    /// its key followed by its value. RESP2 gets a flat array of both.
+    pub async fn write_map_header(&mut self, len: usize) -> WriteResult {
+        RespWriter::with_protocol(&mut self.buf, self.protocol).write_map_header(len)?;
+        self.flush_if_full().await
+    }
+
+    /// Sends everything buffered and flushes the underlying writer
+    pub async fn flush(&mut self) -> WriteResult {
+        self.inner.write_all(&self.buf.data).await?;
+        self.buf.clear();
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    async fn flush_if_full(&mut self) -> WriteResult {
+        if self.buf.len() >= self.flush_threshold {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{BulkString, RespError, RespValue};
+
+    #[test]
+    fn test_write_buf_limit() {
+        let mut buf = WriteBuf::with_limit(Vec::new(), 4);
+        assert!(buf.push_bytes(b"abc").is_ok());
+        assert!(buf.push_u8(b'd').is_ok());
+        assert!(matches!(
+            buf.push_u8(b'e'),
+            Err(WriteError::BufferFull {
+                needed: 5,
+                limit: 4
+            })
+        ));
+        assert!(matches!(
+            buf.push_bytes(b"ef"),
+            Err(WriteError::BufferFull {
+                needed: 6,
+                limit: 4
+            })
+        ));
+        assert_eq!(buf.get(), b"abcd");
+    }
+
+    #[test]
+    fn test_write_value_reserves_once() {
+        let value = RespValue::Array((0..1000).map(RespValue::Integer).collect());
+        let mut buf = WriteBuf::new(Vec::new());
+        RespWriter::new(&mut buf).write_value(&value).unwrap();
+        assert_eq!(buf.capacity(), buf.len());
+
+        // A value that can't fit is refused before any of it is written
+        let mut buf = WriteBuf::with_limit(Vec::new(), 100);
+        assert!(matches!(
+            RespWriter::new(&mut buf).write_value(&value),
+            Err(WriteError::BufferFull { limit: 100, .. })
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_write_value_at_limit() {
+        /// Doesn't tell its length, so it is written without reserving
+        struct Pair;
+
+        impl RespWritable for Pair {
+            fn write<B: OutBuf>(&self, writer: &mut RespWriter<'_, B>) -> WriteResult {
+                writer.write_array_header(2)?;
+                writer.write_integer(1)?;
+                writer.write_integer(2)
+            }
+        }
+
+        // Up to the limit exactly, then a frame past it is refused whole
+        let mut buf = WriteBuf::with_limit(Vec::new(), 9);
+        let mut writer = RespWriter::new(&mut buf);
+        writer.write_value(&1i64).unwrap();
+        writer.write_value(&"OK".to_string()).unwrap();
+        assert!(matches!(
+            writer.write_value(&2i64),
+            Err(WriteError::BufferFull {
+                needed: 13,
+                limit: 9
+            })
+        ));
+        assert_eq!(buf.get(), b":1\r\n+OK\r\n");
+
+        // A frame that runs into the limit half way is rolled back
+        let mut buf = WriteBuf::with_limit(Vec::new(), 12);
+        let mut writer = RespWriter::new(&mut buf);
+        writer.write_value(&1i64).unwrap();
+        assert!(matches!(
+            writer.write_value(&Pair),
+            Err(WriteError::BufferFull {
+                needed: 13,
+                limit: 12
+            })
+        ));
+        assert_eq!(buf.get(), b":1\r\n");
+    }
+
+    #[test]
+    fn test_write_streamed_bulk() {
+        let protocols = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+        let expects: [&[u8]; 2] = [
+            b"+OK\r\n$12\r\nHello, World\r\n",
+            b"+OK\r\n$?\r\n;5\r\nHello\r\n;7\r\n, World\r\n;0\r\n",
+        ];
+
+        for (protocol, expected) in protocols.into_iter().zip(expects) {
+            let mut buf = WriteBuf::new(Vec::new());
+            let mut writer = RespWriter::with_protocol(&mut buf, protocol);
+            writer.write_value(&"OK".to_string()).unwrap();
+
+            let mut bulk = writer.begin_streamed_bulk().unwrap();
+            bulk.write_chunk(b"Hello").unwrap();
+            bulk.write_chunk(b"").unwrap();
+            bulk.write_chunk(b", World").unwrap();
+            bulk.finish().unwrap();
+
+            assert_eq!(buf.get(), expected);
+        }
+
+        // The length is put in front of the RESP2 payload within the limit
+        let mut buf = WriteBuf::with_limit(Vec::new(), 8);
+        let mut writer = RespWriter::new(&mut buf);
+        let mut bulk = writer.begin_streamed_bulk().unwrap();
+        bulk.write_chunk(b"abc").unwrap();
+        assert!(matches!(bulk.finish(), Err(WriteError::BufferFull { .. })));
+    }
+
+    #[test]
+    fn test_write_null_array() {
+        let protocols = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+        let expects: [&[u8]; 2] = [b"*-1\r\n", b"_\r\n"];
+
+        for (protocol, expected) in protocols.into_iter().zip(expects) {
+            let mut buf = WriteBuf::new(Vec::new());
+            RespWriter::with_protocol(&mut buf, protocol)
+                .write_null_array()
+                .unwrap();
+            assert_eq!(buf.get(), expected);
+        }
+    }
+
+    #[test]
+    fn test_write_bytes_mut() {
+        let values = [
+            RespValue::Array(vec![RespValue::from("SET"), RespValue::Integer(-12)]),
+            RespValue::Map(vec![(RespValue::from("k"), RespValue::None)]),
+        ];
+
+        for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+            let mut vec_buf = WriteBuf::new(Vec::new());
+            let mut bytes_buf = WriteBuf::new(BytesMut::new());
+            for value in values.iter() {
+                value
+                    .write(&mut RespWriter::with_protocol(&mut vec_buf, protocol))
+                    .unwrap();
+                value
+                    .write(&mut RespWriter::with_protocol(&mut bytes_buf, protocol))
+                    .unwrap();
+            }
+            let mut writer = RespWriter::with_protocol(&mut bytes_buf, protocol);
+            let mut bulk = writer.begin_streamed_bulk().unwrap();
+            bulk.write_chunk(b"abc").unwrap();
+            bulk.finish().unwrap();
+            let mut writer = RespWriter::with_protocol(&mut vec_buf, protocol);
+            let mut bulk = writer.begin_streamed_bulk().unwrap();
+            bulk.write_chunk(b"abc").unwrap();
+            bulk.finish().unwrap();
+
+            assert_eq!(bytes_buf.as_slice(), vec_buf.as_slice());
+
+            // The frame is taken out without copying, the buffer is reused
+            let frame = bytes_buf.split();
+            assert_eq!(&frame[..], vec_buf.as_slice());
+            assert!(bytes_buf.is_empty());
+            bytes_buf.push_bytes(b"+OK\r\n").unwrap();
+            assert_eq!(bytes_buf.freeze(), Bytes::from_static(b"+OK\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_write_shorthands() {
+        type Shorthand = fn(&mut RespWriter<'_>) -> WriteResult;
+        let inputs: [Shorthand; 9] = [
+            |w| w.write_simple("OK"),
+            |w| w.write_simple("two\r\nlines"),
+            |w| w.write_error("ERR syntax error"),
+            |w| w.write_integer(-42),
+            |w| w.write_bulk(b"a\r\nb"),
+            |w| w.write_null(),
+            |w| {
+                w.write_array_header(2)?;
+                w.write_integer(1)?;
+                w.write_bulk(b"x")
+            },
+            |w| w.write_array_header(0),
+            |w| {
+                w.write_map_header(1)?;
+                w.write_bulk(b"k")?;
+                w.write_null()
+            },
+        ];
+        let expects = [
+            RespValue::Simple("OK".to_string()),
+            RespValue::Simple("two\r\nlines".to_string()),
+            RespValue::Error(RespError::syntax()),
+            RespValue::Integer(-42),
+            RespValue::from("a\r\nb"),
+            RespValue::None,
+            RespValue::Array(vec![RespValue::Integer(1), RespValue::from("x")]),
+            RespValue::Array(vec![]),
+            RespValue::Map(vec![(RespValue::from("k"), RespValue::None)]),
+        ];
+
+        assert_eq!(inputs.len(), expects.len());
+        for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+            for i in 0..inputs.len() {
+                let mut written = WriteBuf::new(Vec::new());
+                inputs[i](&mut RespWriter::with_protocol(&mut written, protocol)).unwrap();
+                let mut built = WriteBuf::new(Vec::new());
+                RespWriter::with_protocol(&mut built, protocol)
+                    .write_value(&expects[i])
+                    .unwrap();
+                assert_eq!(
+                    written.get(),
+                    built.get(),
+                    "{:?} {:?}",
+                    protocol,
+                    expects[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_segmented() {
+        let large = Bytes::from(vec![b'x'; MIN_SHARED_LEN * 4]);
+        let values = [
+            RespValue::Array(vec![
+                RespValue::from("small"),
+                RespValue::Bulk(BulkString::new(large.clone())),
+                RespValue::Integer(7),
+            ]),
+            RespValue::Bulk(BulkString::new(large.clone())),
+        ];
+
+        for protocol in [ProtocolVersion::Resp2, ProtocolVersion::Resp3] {
+            let mut vec_buf = WriteBuf::new(Vec::new());
+            let mut segmented = WriteBuf::new(SegmentedBuf::new());
+            for value in values.iter() {
+                RespWriter::with_protocol(&mut vec_buf, protocol)
+                    .write_value(value)
+                    .unwrap();
+                RespWriter::with_protocol(&mut segmented, protocol)
+                    .write_value(value)
+                    .unwrap();
+            }
+            let mut writer = RespWriter::with_protocol(&mut segmented, protocol);
+            let mut bulk = writer.begin_streamed_bulk().unwrap();
+            bulk.write_chunk(b"abc").unwrap();
+            bulk.finish().unwrap();
+            let mut writer = RespWriter::with_protocol(&mut vec_buf, protocol);
+            let mut bulk = writer.begin_streamed_bulk().unwrap();
+            bulk.write_chunk(b"abc").unwrap();
+            bulk.finish().unwrap();
+
+            // The payloads are sent from where they already were
+            assert_eq!(segmented.len(), vec_buf.len());
+            assert_eq!(segmented.get().to_vec(), vec_buf.as_slice());
+            assert_eq!(segmented.get().segments(), 2);
+            assert!(segmented.capacity() < large.len());
+            let slices = segmented.get().as_io_slices();
+            assert!(slices.iter().all(|slice| !slice.is_empty()));
+            assert!(slices.iter().any(|slice| slice.as_ptr() == large.as_ptr()));
+
+            let mark = vec_buf.len() - 10;
+            segmented.truncate(mark);
+            vec_buf.truncate(mark);
+            assert_eq!(segmented.get().to_vec(), vec_buf.as_slice());
+        }
+
+        // Data put in front of a payload, and cutting into one
+        let inputs: [(usize, usize); 5] = [(0, 8), (2, 8), (3, 8), (3, 6), (4, 7)];
+        let expects: [&[u8]; 5] = [b"--ab1234", b"ab--1234", b"ab1--234", b"ab1--2", b"ab12--3"];
+        assert_eq!(inputs.len(), expects.len());
+        for i in 0..inputs.len() {
+            let (at, len) = inputs[i];
+            let mut buf = SegmentedBuf::new();
+            buf.extend_from_slice(b"ab");
+            buf.extend_from_bytes(Bytes::from_static(b"12"));
+            buf.extend_from_bytes(Bytes::from_static(b"34"));
+            buf.insert_from_slice(at, b"--");
+            buf.truncate(len);
+            assert_eq!(buf.to_vec(), expects[i], "{:?}", inputs[i]);
+            assert_eq!(buf.len(), expects[i].len());
+        }
+
+        // Shared payloads count towards the limit
+        let mut buf = WriteBuf::with_limit(SegmentedBuf::new(), 8);
+        let mut writer = RespWriter::new(&mut buf);
+        writer
+            .write_bytes_shared(Bytes::from_static(b"12345"))
+            .unwrap();
+        assert!(matches!(
+            writer.write_bytes_shared(Bytes::from_static(b"6789")),
+            Err(WriteError::BufferFull {
+                needed: 9,
+                limit: 8
+            })
+        ));
+        assert_eq!(buf.len(), 5);
+    }
+
+    #[test]
+    fn test_write_buf_lifecycle() {
+        let mut buf = WriteBuf::with_capacity(64);
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 64);
+
+        buf.push_bytes(b":1\r\n").unwrap();
+        let mark = buf.len();
+        buf.push_bytes(b"+half").unwrap();
+        buf.truncate(mark);
+        assert_eq!(buf.as_slice(), b":1\r\n");
+
+        buf.push_bytes(b":2\r\n").unwrap();
+        let tail = buf.split_off(mark);
+        assert_eq!(buf.as_slice(), b":1\r\n");
+        assert_eq!(tail.as_slice(), b":2\r\n");
+        assert_eq!(buf.into_inner(), b":1\r\n");
+
+        // The limit carries over to the split off part
+        let mut buf = WriteBuf::with_limit(b"abcd".to_vec(), 4);
+        let mut tail = buf.split_off(2);
+        assert!(tail.push_bytes(b"ef").is_ok());
+        assert!(matches!(
+            tail.push_u8(b'g'),
+            Err(WriteError::BufferFull { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_buf_clear_keeps_capacity() {
+        let mut buf = WriteBuf::new(Vec::new());
+        buf.push_bytes(&[0; 1024]).unwrap();
+        let capacity = buf.capacity();
+
+        buf.clear();
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), capacity);
+
+        buf.push_bytes(&[0; 1024]).unwrap();
+        assert_eq!(buf.capacity(), capacity);
+
+        buf.clear();
+        buf.shrink_to(16);
+        assert!(buf.capacity() < capacity);
+    }
+
+    #[test]
+    fn test_io_writer_streams_aggregates() {
+        let mut writer =
+            IoRespWriter::with_protocol(io::Cursor::new(Vec::new()), ProtocolVersion::Resp3);
+        writer.write_array_header(2).unwrap();
+        writer.write_value(&1i64).unwrap();
+        writer.write_map_header(1).unwrap();
+        writer.write_value(&BulkString::from("k")).unwrap();
+        writer.write_value(&RespValue::None).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            writer.into_inner().into_inner(),
+            b"*2\r\n:1\r\n%1\r\n$1\r\nk\r\n_\r\n"
+        );
+
+        /// Counts what it is sent, keeping none of it
+        #[derive(Default)]
+        struct Counter {
+            len: usize,
+            largest_write: usize,
+        }
+
+        impl io::Write for Counter {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                self.len += data.len();
+                self.largest_write = self.largest_write.max(data.len());
+                Ok(data.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // A million elements go out in chunks of about the flush threshold,
+        // with no more than that held at any time
+        const LEN: i64 = 1_000_000;
+        let mut writer = IoRespWriter::new(Counter::default());
+        writer.write_array_header(LEN as usize).unwrap();
+        for i in 0..LEN {
+            writer.write_value(&i).unwrap();
+            assert!(writer.buffered() < DEFAULT_FLUSH_THRESHOLD);
+        }
+        writer.flush().unwrap();
+
+        let expected =
+            b"*1000000\r\n".len() + (0..LEN).map(|i| i.to_string().len() + 3).sum::<usize>();
+        let counter = writer.into_inner();
+        assert_eq!(counter.len, expected);
+        assert!(counter.largest_write < DEFAULT_FLUSH_THRESHOLD + 32);
+    }
+
+    #[test]
+    fn test_io_writer_error() {
+        struct Broken;
+
+        impl io::Write for Broken {
+            fn write(&mut self, _data: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut writer = IoRespWriter::new(Broken);
+        writer.write_value(&1i64).unwrap();
+        let err = writer.flush().unwrap_err();
+        assert!(matches!(&err, WriteError::Io(err) if err.kind() == io::ErrorKind::BrokenPipe));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_writer_buffers_until_flush() {
+        use tokio_test::io::Builder;
+
+        use crate::types::{BulkString, RespValue};
+
+        let stream = Builder::new()
+            .write(b"+OK\r\n:1\r\n$1\r\na\r\n")
+            .write(b"_\r\n")
+            .build();
+        let mut writer = AsyncRespWriter::new(stream);
+        writer.write_value(&"OK".to_string()).await.unwrap();
+        writer.write_value(&1i64).await.unwrap();
+        writer.write_value(&BulkString::from("a")).await.unwrap();
+        assert_eq!(writer.buffered(), 16);
+        writer.flush().await.unwrap();
+        assert_eq!(writer.buffered(), 0);
+
+        writer.set_protocol(ProtocolVersion::Resp3);
+        writer.write_value(&RespValue::None).await.unwrap();
+        writer.flush().await.unwrap();
+
+        // Past the threshold every write is sent on its own
+        let stream = Builder::new().write(b":1\r\n").write(b":2\r\n").build();
+        let mut writer = AsyncRespWriter::new(stream);
+        writer.set_flush_threshold(4);
+        writer.write_value(&1i64).await.unwrap();
+        writer.write_value(&2i64).await.unwrap();
+        assert_eq!(writer.buffered(), 0);
+
+        // Including the header of an array, ahead of its elements
+        let stream = Builder::new()
+            .write(b"*2\r\n")
+            .write(b":1\r\n")
+            .write(b":2\r\n")
+            .build();
+        let mut writer = AsyncRespWriter::new(stream);
+        writer.set_flush_threshold(4);
+        writer.write_array_header(2).await.unwrap();
+        writer.write_value(&1i64).await.unwrap();
+        writer.write_value(&2i64).await.unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_writer_concurrent_writes() {
+        use std::sync::Arc;
+
+        use tokio::sync::Mutex;
+
+        use crate::{
+            parser::RespParser,
+            types::{RespReadable, RespValue},
+        };
+
+        // Replies and out-of-band messages are written by different tasks
+        let writer = Arc::new(Mutex::new(AsyncRespWriter::new(Vec::new())));
+        let tasks: Vec<_> = (0..2)
+            .map(|task| {
+                let writer = writer.clone();
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        let value = RespValue::Array(vec![task.into(), i.into()]);
+                        let mut writer = writer.lock().await;
+                        writer.write_value(&value).await.unwrap();
+                        if i % 7 == 0 {
+                            writer.flush().await.unwrap();
+                        }
+                        drop(writer);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let mut writer = Arc::try_unwrap(writer).ok().unwrap().into_inner();
+        writer.flush().await.unwrap();
+        let out = writer.into_inner();
+
+        // Every value arrives whole, and those of each task in order
+        let mut next = [0, 0];
+        let mut parser = RespParser::new(&out);
+        while !parser.remaining().is_empty() {
+            let value = Vec::<RespValue>::parse(&mut parser).unwrap();
+            let [RespValue::Integer(task), RespValue::Integer(i)] = value[..] else {
+                panic!("unexpected value {:?}", value);
+            };
+            assert_eq!(i, next[task as usize]);
+            next[task as usize] += 1;
+        }
+        assert_eq!(next, [100, 100]);
+    }
 }
@@ -0,0 +1,154 @@
+#![cfg(feature = "derive")]
+
+use resp::{
+    parser::{ParseErrorKind, kind_only, parse_complete},
+    types::{BulkString, RespReadable, RespWritable},
+    writer::{ProtocolVersion, RespWriter, WriteBuf},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, RespReadable, RespWritable)]
+struct Heartbeat {
+    id: i64,
+    name: String,
+    note: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, RespReadable, RespWritable)]
+struct Range(i64, i64);
+
+#[derive(Clone, Debug, PartialEq, Eq, RespReadable, RespWritable)]
+#[resp(map)]
+struct Job<'a> {
+    queue: &'a [u8],
+    #[resp(rename = "max-retries")]
+    max_retries: i64,
+    args: Vec<BulkString>,
+    range: Option<Range>,
+    last: Heartbeat,
+}
+
+fn encode_with<T: RespWritable>(value: &T, protocol: ProtocolVersion) -> Vec<u8> {
+    let mut buf = WriteBuf::new(Vec::new());
+    RespWriter::with_protocol(&mut buf, protocol)
+        .write_value(value)
+        .unwrap();
+    buf.get().clone()
+}
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat {
+        id: 7,
+        name: "worker".to_string(),
+        note: None,
+    }
+}
+
+#[test]
+fn test_derive_array() {
+    let inputs = [
+        heartbeat(),
+        Heartbeat {
+            note: Some("busy".to_string()),
+            ..heartbeat()
+        },
+    ];
+    let expects: [&[u8]; 2] = [
+        b"*3\r\n:7\r\n+worker\r\n_\r\n",
+        b"*3\r\n:7\r\n+worker\r\n+busy\r\n",
+    ];
+
+    assert_eq!(inputs.len(), expects.len());
+    for i in 0..inputs.len() {
+        let data = encode_with(&inputs[i], ProtocolVersion::Resp3);
+        assert_eq!(data, expects[i]);
+        assert_eq!(inputs[i].encoded_len(ProtocolVersion::Resp3), data.len());
+        assert_eq!(parse_complete::<Heartbeat>(&data), Ok(inputs[i].clone()));
+    }
+
+    let data = encode_with(&Range(-1, 5), ProtocolVersion::Resp2);
+    assert_eq!(data, b"*2\r\n:-1\r\n:5\r\n");
+    assert_eq!(parse_complete::<Range>(&data), Ok(Range(-1, 5)));
+}
+
+#[test]
+fn test_derive_map() {
+    let job = Job {
+        queue: b"emails",
+        max_retries: 3,
+        args: vec![BulkString::from("a")],
+        range: Some(Range(0, 10)),
+        last: heartbeat(),
+    };
+
+    let inputs = [ProtocolVersion::Resp2, ProtocolVersion::Resp3];
+    let expects: [&[u8]; 2] = [
+        b"*10\r\n$5\r\nqueue\r\n$6\r\nemails\r\n$11\r\nmax-retries\r\n:3\r\n\
+          $4\r\nargs\r\n*1\r\n$1\r\na\r\n$5\r\nrange\r\n*2\r\n:0\r\n:10\r\n\
+          $4\r\nlast\r\n*3\r\n:7\r\n+worker\r\n$-1\r\n",
+        b"%5\r\n$5\r\nqueue\r\n$6\r\nemails\r\n$11\r\nmax-retries\r\n:3\r\n\
+          $4\r\nargs\r\n*1\r\n$1\r\na\r\n$5\r\nrange\r\n*2\r\n:0\r\n:10\r\n\
+          $4\r\nlast\r\n*3\r\n:7\r\n+worker\r\n_\r\n",
+    ];
+
+    assert_eq!(inputs.len(), expects.len());
+    for i in 0..inputs.len() {
+        let data = encode_with(&job, inputs[i]);
+        assert_eq!(data, expects[i]);
+        assert_eq!(job.encoded_len(inputs[i]), data.len());
+        assert_eq!(parse_complete::<Job>(&data), Ok(job.clone()));
+    }
+
+    // Keys in another order, as simple strings, and a missing optional field
+    let data = b"%4\r\n+last\r\n*3\r\n:1\r\n+w\r\n_\r\n+args\r\n*0\r\n\
+                 +max-retries\r\n:0\r\n+queue\r\n$1\r\nq\r\n";
+    let expect = Job {
+        queue: b"q",
+        max_retries: 0,
+        args: Vec::new(),
+        range: None,
+        last: Heartbeat {
+            id: 1,
+            name: "w".to_string(),
+            note: None,
+        },
+    };
+    assert_eq!(parse_complete::<Job>(data), Ok(expect));
+}
+
+#[test]
+fn test_derive_parse_errors() {
+    let inputs: [&[u8]; 6] = [
+        b"*2\r\n:7\r\n+worker\r\n",
+        b"*3\r\n:7\r\n:1\r\n_\r\n",
+        b"*3\r\n:7\r\n+wor",
+        b"%1\r\n$5\r\nqueue\r\n$1\r\nq\r\n",
+        b"%1\r\n$4\r\nname\r\n$1\r\nq\r\n",
+        b"*3\r\n$5\r\nqueue\r\n$1\r\nq\r\n",
+    ];
+    let expects = [
+        Err(ParseErrorKind::InvalidLength { len: 2 }),
+        Err(ParseErrorKind::InvalidTag { tag: b':' }),
+        Err(ParseErrorKind::Incomplete { needed: None }),
+        // Missing the other fields
+        Err(ParseErrorKind::InvalidData),
+        // Unknown key
+        Err(ParseErrorKind::InvalidData),
+        Err(ParseErrorKind::InvalidLength { len: 3 }),
+    ];
+
+    assert_eq!(inputs.len(), expects.len());
+    for i in 0..inputs.len() {
+        let res = if i < 3 {
+            kind_only(parse_complete::<Heartbeat>(inputs[i])).map(|_| ())
+        } else {
+            kind_only(parse_complete::<Job>(inputs[i])).map(|_| ())
+        };
+        assert_eq!(res, expects[i], "{:?}", inputs[i]);
+    }
+}
+
+#[test]
+fn test_derive_compile_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
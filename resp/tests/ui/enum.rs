@@ -0,0 +1,9 @@
+use resp::types::RespWritable;
+
+#[derive(RespWritable)]
+enum Reply {
+    Ok,
+    Value(i64),
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use resp::types::RespReadable;
+
+#[derive(RespReadable)]
+#[resp(map)]
+struct Pair(String, i64);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use resp::types::RespWritable;
+
+#[derive(RespWritable)]
+struct Entry {
+    #[resp(rename = "entry-id")]
+    id: i64,
+}
+
+fn main() {}
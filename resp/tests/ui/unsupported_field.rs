@@ -0,0 +1,9 @@
+use resp::types::{RespReadable, RespWritable};
+
+#[derive(RespReadable, RespWritable)]
+struct Sample {
+    name: String,
+    ratio: f64,
+}
+
+fn main() {}